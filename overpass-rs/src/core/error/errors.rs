@@ -236,7 +236,10 @@ pub enum BocError {
     TotalSizeTooLarge,
     CellDataTooLarge,
     TooManyReferences,
-    InvalidReference { from: usize, to: usize },
+    InvalidReference {
+        from: usize,
+        to: usize,
+    },
     InvalidRoot(usize),
     InvalidMerkleProof,
     InvalidPrunedBranch,
@@ -244,6 +247,11 @@ pub enum BocError {
     DeserializationError(String),
     CycleDetected,
     MaxDepthExceeded,
+    Malformed {
+        offset: u64,
+        cell: Option<usize>,
+        reason: String,
+    },
 }
 
 impl fmt::Display for BocError {
@@ -264,6 +272,20 @@ impl fmt::Display for BocError {
             BocError::MaxDepthExceeded => write!(f, "Max depth exceeded"),
             BocError::SerializationError(err) => write!(f, "Serialization error: {}", err),
             BocError::DeserializationError(err) => write!(f, "Deserialization error: {}", err),
+            BocError::Malformed {
+                offset,
+                cell: Some(cell),
+                reason,
+            } => write!(
+                f,
+                "Malformed BOC at offset {} in cell {}: {}",
+                offset, cell, reason
+            ),
+            BocError::Malformed {
+                offset,
+                cell: None,
+                reason,
+            } => write!(f, "Malformed BOC at offset {}: {}", offset, reason),
         }
     }
 }
@@ -272,6 +294,24 @@ impl std::error::Error for CellError {}
 impl std::error::Error for ZkProofError {}
 impl std::error::Error for BocError {}
 
+impl From<ovp_cell::CellError> for BocError {
+    fn from(err: ovp_cell::CellError) -> Self {
+        BocError::SerializationError(err.to_string())
+    }
+}
+
+impl From<ovp_cell::CellError> for SystemError {
+    fn from(err: ovp_cell::CellError) -> Self {
+        SystemError::new(SystemErrorType::SerializationError, err.to_string())
+    }
+}
+
+impl From<BocError> for SystemError {
+    fn from(err: BocError) -> Self {
+        SystemError::new(SystemErrorType::SerializationError, err.to_string())
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -0,0 +1,630 @@
+// ./src/core/hierarchy/intermediate/closure_i.rs
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::intermediate::destination_contract::DestinationContract;
+use crate::core::hierarchy::intermediate::settlement_i::FinalChannelState;
+use crate::core::hierarchy::root::global_tree_manager::IntermediateProofVerifier;
+use crate::core::types::boc::BOC;
+use crate::core::zkps::proof::ZkProof;
+use std::collections::{HashMap, VecDeque};
+
+type ChannelId = [u8; 32];
+
+const CLOSURE_DOMAIN: &[u8] = b"ovp:channel-closure";
+
+/// A request to close a channel at the state in `boc`. Either participant
+/// may submit one, but only for a state the other parties signed as well;
+/// the other side can answer with a counter-claim until the dispute window
+/// ends.
+#[derive(Debug, Clone)]
+pub struct ChannelClosureRequest {
+    pub channel_id: ChannelId,
+    /// Sum of the balances in `boc`; paid out to the destination contract.
+    pub final_balance: u64,
+    /// Serialized [`BOC`] holding the channel's [`FinalChannelState`].
+    pub boc: Vec<u8>,
+    /// Proof of the state, committing to the BOC hash as its `merkle_root`.
+    pub proof: ZkProof,
+    /// Destination-contract party that signed the request.
+    pub party_id: String,
+    /// Signature over [`closure_message`].
+    pub signature: Vec<u8>,
+    /// Signatures over the same message by other parties, by party. With
+    /// the submitter's they must reach the destination's threshold, and at
+    /// least one is needed when the channel has more than one party.
+    pub co_signatures: HashMap<String, Vec<u8>>,
+}
+
+/// Bytes a party signs to close `channel_id` at the state hashing to
+/// `state_hash` with sequence number `sequence`.
+pub fn closure_message(channel_id: &ChannelId, sequence: u64, state_hash: &[u8; 32]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(CLOSURE_DOMAIN.len() + 72);
+    message.extend_from_slice(CLOSURE_DOMAIN);
+    message.extend_from_slice(channel_id);
+    message.extend_from_slice(&sequence.to_le_bytes());
+    message.extend_from_slice(state_hash);
+    message
+}
+
+/// The claim currently standing for a closing channel.
+#[derive(Debug, Clone)]
+pub struct PendingClosure {
+    pub request: ChannelClosureRequest,
+    /// Nonce of the claimed final state; counter-claims must exceed it.
+    pub sequence_number: u64,
+    pub opened_at: u64,
+    /// Counter-claims are accepted strictly before this time.
+    pub dispute_deadline: u64,
+    /// Number of counter-claims that replaced an earlier claim.
+    pub counter_claims: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinalizedClosure {
+    pub channel_id: ChannelId,
+    pub sequence_number: u64,
+    pub final_balance: u64,
+    pub party_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum ClosureOutcome {
+    Finalized(FinalizedClosure),
+    /// Settling into the destination contract failed. The closure stays
+    /// queued and is retried on the next call.
+    Failed {
+        channel_id: ChannelId,
+        error: SystemError,
+    },
+}
+
+struct ClosableChannel {
+    destination: DestinationContract,
+    dispute_window: u64,
+}
+
+/// Processes channel closures in dispute-deadline order.
+///
+/// A valid closure opens a dispute window of the channel's configured
+/// length. During the window any party may replace the claim with one at a
+/// higher sequence number; the deadline does not move, so a closure cannot
+/// be held open indefinitely. Once the window has passed, the standing claim
+/// is settled into the channel's destination contract.
+pub struct ClosureProcessor<V: IntermediateProofVerifier> {
+    verifier: V,
+    channels: HashMap<ChannelId, ClosableChannel>,
+    closing_channels: HashMap<ChannelId, PendingClosure>,
+    closure_queue: VecDeque<ChannelId>,
+}
+
+impl<V: IntermediateProofVerifier> ClosureProcessor<V> {
+    pub fn new(verifier: V) -> Self {
+        Self {
+            verifier,
+            channels: HashMap::new(),
+            closing_channels: HashMap::new(),
+            closure_queue: VecDeque::new(),
+        }
+    }
+
+    /// Makes a channel closable. Closure requests must be signed by one of
+    /// the destination contract's parties.
+    pub fn register_channel(
+        &mut self,
+        channel_id: ChannelId,
+        destination: DestinationContract,
+        dispute_window: u64,
+    ) -> Result<(), SystemError> {
+        if self.channels.contains_key(&channel_id) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Channel is already registered".to_string(),
+            ));
+        }
+        if destination.parties().is_empty() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Destination contract has no parties".to_string(),
+            ));
+        }
+        self.channels.insert(
+            channel_id,
+            ClosableChannel {
+                destination,
+                dispute_window,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn destination(&self, channel_id: &ChannelId) -> Option<&DestinationContract> {
+        self.channels
+            .get(channel_id)
+            .map(|channel| &channel.destination)
+    }
+
+    pub fn pending(&self, channel_id: &ChannelId) -> Option<&PendingClosure> {
+        self.closing_channels.get(channel_id)
+    }
+
+    /// Channels awaiting finalization, earliest deadline first.
+    pub fn closure_queue(&self) -> impl Iterator<Item = &ChannelId> {
+        self.closure_queue.iter()
+    }
+
+    /// Checks a closure's BOC, proof and signatures. Returns the sequence
+    /// number of the claimed state.
+    ///
+    /// A state signed by only one side could carry any sequence number, so
+    /// the submitter's signature alone is never enough when the channel has
+    /// a counterparty.
+    pub fn validate_closure(&self, request: &ChannelClosureRequest) -> Result<u64, SystemError> {
+        let channel = self.channels.get(&request.channel_id).ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NotFound,
+                "Channel is not registered".to_string(),
+            )
+        })?;
+        if channel.destination.settled {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidState,
+                "Channel is already settled".to_string(),
+            ));
+        }
+
+        let boc = BOC::deserialize(&request.boc)?;
+        let state = FinalChannelState::from_boc(&boc)?;
+        if state.channel_id != request.channel_id {
+            return Err(SystemError::new(
+                SystemErrorType::StateDataMismatch,
+                "Closure state belongs to a different channel".to_string(),
+            ));
+        }
+        let total = state
+            .balances
+            .values()
+            .try_fold(0u64, |total, balance| total.checked_add(*balance));
+        if total != Some(request.final_balance) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidAmount,
+                "Final balance does not match the closing state".to_string(),
+            ));
+        }
+
+        let state_hash = boc.compute_hash();
+        request.proof.verify_internally()?;
+        self.verifier.verify(&request.proof, &state_hash)?;
+
+        if request.co_signatures.contains_key(&request.party_id) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidSignature,
+                "Submitter cannot co-sign its own closure".to_string(),
+            ));
+        }
+        let parties = channel.destination.parties();
+        let message = closure_message(&request.channel_id, state.nonce, &state_hash);
+        let signatures = std::iter::once((&request.party_id, &request.signature))
+            .chain(request.co_signatures.iter());
+        for (party_id, signature) in signatures {
+            let key = parties.get(party_id).ok_or_else(|| {
+                SystemError::new(
+                    SystemErrorType::InvalidSignature,
+                    "Closure is not signed by a channel party".to_string(),
+                )
+            })?;
+            if !key.verify(&message, signature) {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidSignature,
+                    "Closure signature does not verify".to_string(),
+                ));
+            }
+        }
+        let required = channel.destination.threshold().max(parties.len().min(2));
+        if 1 + request.co_signatures.len() < required {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidSignature,
+                format!("Closure state needs {} party signatures", required),
+            ));
+        }
+        Ok(state.nonce)
+    }
+
+    /// Opens a closure, or replaces the standing claim with a counter-claim
+    /// at a higher sequence number while the dispute window is open.
+    pub fn submit_closure(
+        &mut self,
+        request: ChannelClosureRequest,
+        now: u64,
+    ) -> Result<(), SystemError> {
+        let sequence_number = self.validate_closure(&request)?;
+        let channel_id = request.channel_id;
+
+        if let Some(pending) = self.closing_channels.get_mut(&channel_id) {
+            if now >= pending.dispute_deadline {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidState,
+                    "Dispute window has closed".to_string(),
+                ));
+            }
+            if sequence_number <= pending.sequence_number {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidSequence,
+                    format!(
+                        "Counter-claim must exceed sequence number {}",
+                        pending.sequence_number
+                    ),
+                ));
+            }
+            pending.request = request;
+            pending.sequence_number = sequence_number;
+            pending.counter_claims += 1;
+            return Ok(());
+        }
+
+        let dispute_deadline = now.saturating_add(self.channels[&channel_id].dispute_window);
+        let position = self
+            .closure_queue
+            .iter()
+            .position(|queued| self.closing_channels[queued].dispute_deadline > dispute_deadline)
+            .unwrap_or(self.closure_queue.len());
+        self.closure_queue.insert(position, channel_id);
+        self.closing_channels.insert(
+            channel_id,
+            PendingClosure {
+                request,
+                sequence_number,
+                opened_at: now,
+                dispute_deadline,
+                counter_claims: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Settles every closure whose dispute window has passed by `now` into
+    /// its destination contract, in deadline order. A closure leaves the
+    /// queue only once it has settled; one that fails is reported and kept
+    /// for the next call without holding back the ones behind it.
+    pub fn process_expired(&mut self, now: u64) -> Vec<ClosureOutcome> {
+        let expired = self
+            .closure_queue
+            .iter()
+            .take_while(|queued| self.closing_channels[*queued].dispute_deadline <= now)
+            .count();
+        let mut outcomes = Vec::with_capacity(expired);
+        let mut retained = VecDeque::new();
+
+        for channel_id in self.closure_queue.drain(..expired) {
+            let pending = &self.closing_channels[&channel_id];
+            let channel = self.channels.get_mut(&channel_id).unwrap();
+            if let Err(e) = channel
+                .destination
+                .settle_unilaterally(pending.request.final_balance)
+            {
+                outcomes.push(ClosureOutcome::Failed {
+                    channel_id,
+                    error: SystemError::new(SystemErrorType::InvalidState, e.to_string()),
+                });
+                retained.push_back(channel_id);
+                continue;
+            }
+
+            let pending = self.closing_channels.remove(&channel_id).unwrap();
+            outcomes.push(ClosureOutcome::Finalized(FinalizedClosure {
+                channel_id,
+                sequence_number: pending.sequence_number,
+                final_balance: pending.request.final_balance,
+                party_id: pending.request.party_id,
+            }));
+        }
+
+        // Failed closures keep their place ahead of the unexpired ones.
+        retained.append(&mut self.closure_queue);
+        self.closure_queue = retained;
+        outcomes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::hierarchy::intermediate::destination_contract::PartyKey;
+    use ed25519_dalek::{Signer, SigningKey};
+    use std::collections::BTreeMap;
+
+    /// Accepts any proof that commits to the expected value, reading the
+    /// commitment from `merkle_root`.
+    struct AcceptCommitted;
+
+    impl IntermediateProofVerifier for AcceptCommitted {
+        fn verify(&self, proof: &ZkProof, commitment: &[u8; 32]) -> Result<(), SystemError> {
+            if proof.merkle_root != *commitment {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidProof,
+                    "wrong commitment".to_string(),
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    fn keys() -> (SigningKey, SigningKey) {
+        (
+            SigningKey::from_bytes(&[1; 32]),
+            SigningKey::from_bytes(&[2; 32]),
+        )
+    }
+
+    fn processor(channel: u8, dispute_window: u64) -> ClosureProcessor<AcceptCommitted> {
+        let (alice, bob) = keys();
+        let parties = HashMap::from([
+            (
+                "alice".to_string(),
+                PartyKey::Ed25519(alice.verifying_key().to_bytes()),
+            ),
+            (
+                "bob".to_string(),
+                PartyKey::Ed25519(bob.verifying_key().to_bytes()),
+            ),
+        ]);
+        let destination =
+            DestinationContract::with_parties(format!("channel-{}", channel), 0, parties, 2)
+                .unwrap();
+        let mut processor = ClosureProcessor::new(AcceptCommitted);
+        processor
+            .register_channel([channel; 32], destination, dispute_window)
+            .unwrap();
+        processor
+    }
+
+    fn closure(
+        channel: u8,
+        nonce: u64,
+        balances: [u64; 2],
+        party: &str,
+        key: &SigningKey,
+    ) -> ChannelClosureRequest {
+        let boc = FinalChannelState {
+            channel_id: [channel; 32],
+            nonce,
+            balances: BTreeMap::from([([1; 32], balances[0]), ([2; 32], balances[1])]),
+        }
+        .to_boc()
+        .unwrap();
+        let state_hash = boc.compute_hash();
+        ChannelClosureRequest {
+            channel_id: [channel; 32],
+            final_balance: balances.iter().sum(),
+            boc: boc.serialize().unwrap(),
+            proof: ZkProof::new(vec![1], vec![nonce], state_hash.to_vec(), 0),
+            party_id: party.to_string(),
+            signature: key
+                .sign(&closure_message(&[channel; 32], nonce, &state_hash))
+                .to_bytes()
+                .to_vec(),
+            co_signatures: HashMap::new(),
+        }
+    }
+
+    fn co_sign(
+        mut request: ChannelClosureRequest,
+        party: &str,
+        key: &SigningKey,
+    ) -> ChannelClosureRequest {
+        let boc = BOC::deserialize(&request.boc).unwrap();
+        let nonce = FinalChannelState::from_boc(&boc).unwrap().nonce;
+        let message = closure_message(&request.channel_id, nonce, &boc.compute_hash());
+        request
+            .co_signatures
+            .insert(party.to_string(), key.sign(&message).to_bytes().to_vec());
+        request
+    }
+
+    /// A closure of a two-party channel signed by `party` and co-signed by
+    /// the other.
+    fn mutual(channel: u8, nonce: u64, balances: [u64; 2], party: &str) -> ChannelClosureRequest {
+        let (alice, bob) = keys();
+        let (key, other, other_key) = match party {
+            "alice" => (alice, "bob", bob),
+            _ => (bob, "alice", alice),
+        };
+        co_sign(
+            closure(channel, nonce, balances, party, &key),
+            other,
+            &other_key,
+        )
+    }
+
+    fn finalized(outcomes: Vec<ClosureOutcome>) -> Vec<FinalizedClosure> {
+        outcomes
+            .into_iter()
+            .map(|outcome| match outcome {
+                ClosureOutcome::Finalized(finalized) => finalized,
+                ClosureOutcome::Failed { error, .. } => panic!("closure failed: {:?}", error),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_unilateral_close_settles_after_dispute_window() {
+        let mut processor = processor(1, 100);
+        processor
+            .submit_closure(mutual(1, 5, [70, 30], "alice"), 1_000)
+            .unwrap();
+
+        assert!(processor.process_expired(1_099).is_empty());
+        assert!(!processor.destination(&[1; 32]).unwrap().settled);
+
+        let finalized = finalized(processor.process_expired(1_100));
+        assert_eq!(finalized.len(), 1);
+        assert_eq!(finalized[0].sequence_number, 5);
+        let destination = processor.destination(&[1; 32]).unwrap();
+        assert!(destination.settled);
+        assert_eq!(destination.get_balance(), 100);
+        assert!(processor
+            .submit_closure(mutual(1, 6, [70, 30], "alice"), 1_200)
+            .is_err());
+    }
+
+    #[test]
+    fn test_counter_claim_needs_higher_sequence_within_window() {
+        let mut processor = processor(1, 100);
+        processor
+            .submit_closure(mutual(1, 5, [90, 10], "alice"), 0)
+            .unwrap();
+
+        assert!(processor
+            .submit_closure(mutual(1, 5, [10, 90], "bob"), 50)
+            .is_err());
+        processor
+            .submit_closure(mutual(1, 7, [40, 60], "bob"), 50)
+            .unwrap();
+        assert!(processor
+            .submit_closure(mutual(1, 8, [40, 60], "bob"), 100)
+            .is_err());
+
+        let pending = processor.pending(&[1; 32]).unwrap();
+        assert_eq!(pending.sequence_number, 7);
+        assert_eq!(pending.counter_claims, 1);
+        assert_eq!(pending.dispute_deadline, 100);
+
+        let finalized = finalized(processor.process_expired(100));
+        assert_eq!(finalized[0].party_id, "bob");
+    }
+
+    #[test]
+    fn test_invalid_closures_are_rejected() {
+        let (alice, bob) = keys();
+        let mut processor = processor(1, 100);
+
+        let mut wrong_signer = closure(1, 1, [50, 50], "alice", &bob);
+        assert!(processor.validate_closure(&wrong_signer).is_err());
+        wrong_signer.party_id = "mallory".to_string();
+        assert!(processor.validate_closure(&wrong_signer).is_err());
+
+        let mut inflated = mutual(1, 1, [50, 50], "alice");
+        inflated.final_balance = 101;
+        assert!(processor.validate_closure(&inflated).is_err());
+
+        let mut unbound = mutual(1, 1, [50, 50], "alice");
+        unbound.proof.merkle_root = vec![0; 32];
+        assert!(processor.validate_closure(&unbound).is_err());
+
+        assert!(processor
+            .submit_closure(closure(2, 1, [50, 50], "alice", &alice), 0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_closures_finalize_in_deadline_order() {
+        let (_, bob) = keys();
+        let mut processor = processor(1, 300);
+        let parties = HashMap::from([(
+            "bob".to_string(),
+            PartyKey::Ed25519(bob.verifying_key().to_bytes()),
+        )]);
+        let destination =
+            DestinationContract::with_parties("channel-2".to_string(), 0, parties, 1).unwrap();
+        processor
+            .register_channel([2; 32], destination, 100)
+            .unwrap();
+
+        processor
+            .submit_closure(mutual(1, 1, [1, 1], "alice"), 0)
+            .unwrap();
+        processor
+            .submit_closure(closure(2, 1, [1, 1], "bob", &bob), 10)
+            .unwrap();
+        assert_eq!(
+            processor.closure_queue().copied().collect::<Vec<_>>(),
+            vec![[2; 32], [1; 32]]
+        );
+
+        let finalized = finalized(processor.process_expired(300));
+        let order: Vec<ChannelId> = finalized.iter().map(|f| f.channel_id).collect();
+        assert_eq!(order, vec![[2; 32], [1; 32]]);
+    }
+
+    #[test]
+    fn test_failed_settlement_stays_queued() {
+        let (_, bob) = keys();
+        let mut processor = processor(1, 100);
+        let parties = HashMap::from([(
+            "bob".to_string(),
+            PartyKey::Ed25519(bob.verifying_key().to_bytes()),
+        )]);
+        let destination =
+            DestinationContract::with_parties("channel-2".to_string(), 0, parties, 1).unwrap();
+        processor
+            .register_channel([2; 32], destination, 100)
+            .unwrap();
+        processor
+            .submit_closure(mutual(1, 1, [1, 1], "alice"), 0)
+            .unwrap();
+        processor
+            .submit_closure(closure(2, 1, [2, 2], "bob", &bob), 10)
+            .unwrap();
+
+        // Channel 1 settles through another path before its closure does.
+        processor
+            .channels
+            .get_mut(&[1; 32])
+            .unwrap()
+            .destination
+            .settled = true;
+        let outcomes = processor.process_expired(200);
+        assert!(matches!(
+            &outcomes[..],
+            [
+                ClosureOutcome::Failed { channel_id, .. },
+                ClosureOutcome::Finalized(FinalizedClosure { final_balance: 4, .. }),
+            ] if *channel_id == [1; 32]
+        ));
+        assert!(processor.pending(&[1; 32]).is_some());
+        assert!(processor.pending(&[2; 32]).is_none());
+        assert_eq!(
+            processor.closure_queue().copied().collect::<Vec<_>>(),
+            vec![[1; 32]]
+        );
+
+        processor
+            .channels
+            .get_mut(&[1; 32])
+            .unwrap()
+            .destination
+            .settled = false;
+        let finalized = finalized(processor.process_expired(200));
+        assert_eq!(finalized[0].channel_id, [1; 32]);
+        assert_eq!(processor.closure_queue().count(), 0);
+    }
+
+    #[test]
+    fn test_self_signed_closure_needs_counterparty() {
+        let (alice, bob) = keys();
+        let mut processor = processor(1, 100);
+
+        // Alice alone signs a state with the highest possible nonce.
+        let forged = closure(1, u64::MAX, [100, 0], "alice", &alice);
+        assert!(processor.validate_closure(&forged).is_err());
+        assert!(processor.submit_closure(forged.clone(), 0).is_err());
+
+        // Nor can she co-sign it herself or have it counter-signed by a
+        // stranger.
+        let self_signed = co_sign(forged.clone(), "alice", &alice);
+        assert!(processor.validate_closure(&self_signed).is_err());
+        let stranger = SigningKey::from_bytes(&[3; 32]);
+        assert!(processor
+            .validate_closure(&co_sign(forged.clone(), "mallory", &stranger))
+            .is_err());
+
+        // It cannot displace a mutually signed claim either.
+        processor
+            .submit_closure(mutual(1, 5, [50, 50], "bob"), 0)
+            .unwrap();
+        assert!(processor.submit_closure(forged.clone(), 10).is_err());
+        assert_eq!(processor.pending(&[1; 32]).unwrap().sequence_number, 5);
+
+        let co_signed = co_sign(forged, "bob", &bob);
+        assert_eq!(processor.validate_closure(&co_signed).unwrap(), u64::MAX);
+    }
+}
@@ -1,44 +1,223 @@
 // ./src/core/hierarchy/intermediate/destination_contract.rs
 
+use ed25519_dalek::{Signature as Ed25519Signature, Verifier, VerifyingKey};
+use secp256k1::{ecdsa, Message, PublicKey, Secp256k1};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 
+const SETTLEMENT_DOMAIN: &[u8] = b"ovp:destination-settlement";
+
+/// Public key a party signs settlements with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartyKey {
+    Ed25519([u8; 32]),
+    /// Compressed SEC1 encoding.
+    Secp256k1([u8; 33]),
+}
+
+impl PartyKey {
+    fn validate(&self) -> Result<(), &'static str> {
+        match self {
+            PartyKey::Ed25519(key) => VerifyingKey::from_bytes(key)
+                .map(|_| ())
+                .map_err(|_| "Invalid ed25519 public key"),
+            PartyKey::Secp256k1(key) => PublicKey::from_slice(key)
+                .map(|_| ())
+                .map_err(|_| "Invalid secp256k1 public key"),
+        }
+    }
+
+    /// Ed25519 signs `message` directly; secp256k1 signs its SHA-256 digest
+    /// with a compact 64-byte ECDSA signature.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            PartyKey::Ed25519(key) => {
+                let (Ok(key), Ok(signature)) = (
+                    VerifyingKey::from_bytes(key),
+                    Ed25519Signature::from_slice(signature),
+                ) else {
+                    return false;
+                };
+                key.verify(message, &signature).is_ok()
+            }
+            PartyKey::Secp256k1(key) => {
+                let (Ok(key), Ok(signature)) = (
+                    PublicKey::from_slice(key),
+                    ecdsa::Signature::from_compact(signature),
+                ) else {
+                    return false;
+                };
+                let digest = Message::from_digest(Sha256::digest(message).into());
+                Secp256k1::verification_only()
+                    .verify_ecdsa(&digest, &signature, &key)
+                    .is_ok()
+            }
+        }
+    }
+}
+
+/// Destination of a channel's funds on settlement.
+///
+/// Settling requires valid signatures over [`settlement_message`](Self::settlement_message)
+/// from at least `threshold` of the registered parties. Every balance change
+/// bumps the settlement nonce, which is part of the message, so signatures
+/// collected for an earlier balance are discarded and cannot be replayed
+/// once the balance returns to the same value.
 #[derive(Debug, Clone)]
 pub struct DestinationContract {
     pub channel_id: String,
     pub balance: u64,
-    pub signatures: HashMap<String, Vec<u8>>,
+    parties: HashMap<String, PartyKey>,
+    threshold: usize,
+    /// Verified signatures, by party.
+    signatures: HashMap<String, Vec<u8>>,
+    /// Only ever increases.
+    settlement_nonce: u64,
     pub settled: bool,
     pub submit_root: bool,
 }
 
 impl DestinationContract {
+    /// A contract with no parties. Parties must be registered before it can
+    /// be settled.
     pub fn new(channel_id: String, initial_balance: u64) -> Self {
         Self {
             channel_id,
             balance: initial_balance,
+            parties: HashMap::new(),
+            threshold: 1,
             signatures: HashMap::new(),
+            settlement_nonce: 0,
             settled: false,
             submit_root: false,
         }
     }
 
+    /// A contract settled by any `threshold` of `parties`.
+    pub fn with_parties(
+        channel_id: String,
+        initial_balance: u64,
+        parties: HashMap<String, PartyKey>,
+        threshold: usize,
+    ) -> Result<Self, &'static str> {
+        let mut contract = Self::new(channel_id, initial_balance);
+        for (party_id, key) in parties {
+            contract.register_party(party_id, key)?;
+        }
+        contract.set_threshold(threshold)?;
+        Ok(contract)
+    }
+
+    pub fn register_party(&mut self, party_id: String, key: PartyKey) -> Result<(), &'static str> {
+        if self.settled {
+            return Err("Cannot register party: channel is settled");
+        }
+        if self.parties.contains_key(&party_id) {
+            return Err("Party is already registered");
+        }
+        key.validate()?;
+        self.parties.insert(party_id, key);
+        Ok(())
+    }
+
+    pub fn set_threshold(&mut self, threshold: usize) -> Result<(), &'static str> {
+        if self.settled {
+            return Err("Cannot change threshold: channel is settled");
+        }
+        if threshold == 0 || threshold > self.parties.len() {
+            return Err("Threshold must be between 1 and the number of parties");
+        }
+        self.threshold = threshold;
+        Ok(())
+    }
+
+    pub fn parties(&self) -> &HashMap<String, PartyKey> {
+        &self.parties
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Verified signatures over the current settlement message, by party.
+    pub fn signatures(&self) -> &HashMap<String, Vec<u8>> {
+        &self.signatures
+    }
+
+    pub fn settlement_nonce(&self) -> u64 {
+        self.settlement_nonce
+    }
+
+    /// Bytes every party signs to approve settling at the current balance
+    /// and settlement nonce.
+    pub fn settlement_message(&self) -> Vec<u8> {
+        let channel_id = self.channel_id.as_bytes();
+        let mut message = Vec::with_capacity(SETTLEMENT_DOMAIN.len() + channel_id.len() + 24);
+        message.extend_from_slice(SETTLEMENT_DOMAIN);
+        message.extend_from_slice(&(channel_id.len() as u64).to_le_bytes());
+        message.extend_from_slice(channel_id);
+        message.extend_from_slice(&self.balance.to_le_bytes());
+        message.extend_from_slice(&self.settlement_nonce.to_le_bytes());
+        message
+    }
+
     pub fn settle_channel(&mut self) -> Result<(), &'static str> {
         if self.settled {
             return Err("Channel already settled");
         }
+        if !self.threshold_met() {
+            return Err("Not enough party signatures to settle");
+        }
         self.settled = true;
         Ok(())
     }
 
+    /// Checks `signature` against the party's registered key and the
+    /// current settlement message.
     pub fn verify_signature(&self, party_id: &str, signature: &[u8]) -> bool {
-        match self.signatures.get(party_id) {
-            Some(stored_signature) => stored_signature == signature,
+        match self.parties.get(party_id) {
+            Some(key) => key.verify(&self.settlement_message(), signature),
             None => false,
         }
     }
 
-    pub fn add_signature(&mut self, party_id: String, signature: Vec<u8>) {
+    /// Records a party's approval once its signature verifies.
+    pub fn add_signature(
+        &mut self,
+        party_id: String,
+        signature: Vec<u8>,
+    ) -> Result<(), &'static str> {
+        if self.settled {
+            return Err("Channel already settled");
+        }
+        if !self.parties.contains_key(&party_id) {
+            return Err("Unknown party");
+        }
+        if !self.verify_signature(&party_id, &signature) {
+            return Err("Invalid signature");
+        }
         self.signatures.insert(party_id, signature);
+        Ok(())
+    }
+
+    pub fn signature_count(&self) -> usize {
+        self.signatures.len()
+    }
+
+    pub fn threshold_met(&self) -> bool {
+        !self.parties.is_empty() && self.signatures.len() >= self.threshold
+    }
+
+    /// Settles at `final_balance` without the signature threshold. Only
+    /// for closures whose dispute window passed without a valid
+    /// counter-claim.
+    pub(crate) fn settle_unilaterally(&mut self, final_balance: u64) -> Result<(), &'static str> {
+        if self.settled {
+            return Err("Channel already settled");
+        }
+        self.update_balance(final_balance)?;
+        self.settled = true;
+        Ok(())
     }
 
     pub fn get_balance(&self) -> u64 {
@@ -49,7 +228,148 @@ impl DestinationContract {
         if self.settled {
             return Err("Cannot update balance: channel is settled");
         }
+        if new_balance != self.balance {
+            self.settlement_nonce = self
+                .settlement_nonce
+                .checked_add(1)
+                .ok_or("Settlement nonce exhausted")?;
+            self.signatures.clear();
+        }
         self.balance = new_balance;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use secp256k1::SecretKey;
+
+    fn ed25519_party(seed: u8) -> (SigningKey, PartyKey) {
+        let key = SigningKey::from_bytes(&[seed; 32]);
+        let public = PartyKey::Ed25519(key.verifying_key().to_bytes());
+        (key, public)
+    }
+
+    fn secp256k1_party(seed: u8) -> (SecretKey, PartyKey) {
+        let secret = SecretKey::from_byte_array(&[seed; 32]).unwrap();
+        let public = PublicKey::from_secret_key(&Secp256k1::new(), &secret);
+        (secret, PartyKey::Secp256k1(public.serialize()))
+    }
+
+    fn sign_secp256k1(secret: &SecretKey, message: &[u8]) -> Vec<u8> {
+        let digest = Message::from_digest(Sha256::digest(message).into());
+        Secp256k1::new()
+            .sign_ecdsa(&digest, secret)
+            .serialize_compact()
+            .to_vec()
+    }
+
+    fn contract() -> (SigningKey, SigningKey, SecretKey, DestinationContract) {
+        let (alice, alice_pub) = ed25519_party(1);
+        let (bob, bob_pub) = ed25519_party(2);
+        let (carol, carol_pub) = secp256k1_party(3);
+        let parties = HashMap::from([
+            ("alice".to_string(), alice_pub),
+            ("bob".to_string(), bob_pub),
+            ("carol".to_string(), carol_pub),
+        ]);
+        let contract =
+            DestinationContract::with_parties("channel-1".to_string(), 100, parties, 2).unwrap();
+        (alice, bob, carol, contract)
+    }
+
+    #[test]
+    fn test_settlement_requires_threshold_of_valid_signatures() {
+        let (alice, bob, carol, mut contract) = contract();
+        let message = contract.settlement_message();
+
+        assert!(contract.settle_channel().is_err());
+
+        // Bob's key over Alice's slot and a signature over the wrong message.
+        let bob_sig = bob.sign(&message).to_bytes().to_vec();
+        assert!(contract
+            .add_signature("alice".to_string(), bob_sig.clone())
+            .is_err());
+        let wrong = alice.sign(b"something else").to_bytes().to_vec();
+        assert!(contract.add_signature("alice".to_string(), wrong).is_err());
+        assert!(contract
+            .add_signature("mallory".to_string(), bob_sig.clone())
+            .is_err());
+
+        contract
+            .add_signature("carol".to_string(), sign_secp256k1(&carol, &message))
+            .unwrap();
+        assert!(contract.settle_channel().is_err());
+
+        contract.add_signature("bob".to_string(), bob_sig).unwrap();
+        assert!(contract.threshold_met());
+        contract.settle_channel().unwrap();
+        assert!(contract.settle_channel().is_err());
+    }
+
+    #[test]
+    fn test_balance_change_discards_signatures() {
+        let (alice, bob, _, mut contract) = contract();
+        let message = contract.settlement_message();
+        for (party, key) in [("alice", &alice), ("bob", &bob)] {
+            contract
+                .add_signature(party.to_string(), key.sign(&message).to_bytes().to_vec())
+                .unwrap();
+        }
+
+        contract.update_balance(90).unwrap();
+        assert_eq!(contract.signature_count(), 0);
+        assert!(!contract.verify_signature("alice", &alice.sign(&message).to_bytes()));
+        assert!(contract.settle_channel().is_err());
+    }
+
+    #[test]
+    fn test_threshold_bounds() {
+        let (_, _, _, mut contract) = contract();
+        assert!(contract.set_threshold(0).is_err());
+        assert!(contract.set_threshold(4).is_err());
+        contract.set_threshold(3).unwrap();
+
+        let mut empty = DestinationContract::new("channel-2".to_string(), 0);
+        assert!(empty.settle_channel().is_err());
+        assert!(empty
+            .register_party("dave".to_string(), PartyKey::Secp256k1([0; 33]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_signatures_for_an_earlier_balance_cannot_be_replayed() {
+        let (alice, bob, _, mut contract) = contract();
+        let stale: Vec<_> = [("alice", &alice), ("bob", &bob)]
+            .iter()
+            .map(|(party, key)| {
+                let signature = key.sign(&contract.settlement_message());
+                (party.to_string(), signature.to_bytes().to_vec())
+            })
+            .collect();
+
+        // The balance moves away and back; the nonce does not.
+        contract.update_balance(90).unwrap();
+        contract.update_balance(100).unwrap();
+        assert_eq!(contract.settlement_nonce(), 2);
+        for (party, signature) in stale {
+            assert!(contract.add_signature(party, signature).is_err());
+        }
+        assert!(contract.signatures().is_empty());
+        assert!(contract.settle_channel().is_err());
+
+        // An unchanged balance keeps the nonce and the signatures.
+        let signature = alice
+            .sign(&contract.settlement_message())
+            .to_bytes()
+            .to_vec();
+        contract
+            .add_signature("alice".to_string(), signature)
+            .unwrap();
+        contract.update_balance(100).unwrap();
+        assert_eq!(contract.settlement_nonce(), 2);
+        assert_eq!(contract.signature_count(), 1);
+    }
+}
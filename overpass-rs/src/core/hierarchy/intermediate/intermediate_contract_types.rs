@@ -1,6 +1,6 @@
 // ./src/core/hierarchy/intermediate/intermediate_contract_types.rs
 
-use crate::core::hierarchy::client::wallet_extension::wallet_extension_types::ChannelClosureRequest;
+use crate::core::hierarchy::intermediate::closure_i::ChannelClosureRequest;
 
 use crate::core::hierarchy::intermediate::destination_contract::DestinationContract;
 use crate::core::hierarchy::intermediate::sparse_merkle_tree_i::SparseMerkleTreeI;
@@ -30,10 +30,7 @@ pub trait ByteArray<const N: usize> {
 // Remove the recursive type alias
 // type IntermediateContract = IntermediateContract<RebalanceRequest>;
 
-// Define RebalanceRequest as a struct instead of a recursive type alias
-pub struct RebalanceRequest {
-    // Add fields as needed
-}
+pub use crate::core::hierarchy::intermediate::rebalance_i::RebalanceRequest;
 
 type RebalanceRequestMap = HashMap<ChannelId, RebalanceRequest>;
 
@@ -2,10 +2,16 @@
 
 // src/core/hierarchy/intermediate/mod.rs
 
+pub mod closure_i;
 pub mod destination_contract;
 //pub mod intermediate_contract;
 
-//pub mod settlement_i;
+pub mod settlement_i;
+pub mod settlement_batch_i;
 pub mod intermediate_contract_types;
+pub mod rebalance_i;
+pub mod root_submitter_i;
 pub mod sparse_merkle_tree_i;
 pub mod state_tracking_i;
+pub mod storage_assignment_i;
+pub mod wallet_update_i;
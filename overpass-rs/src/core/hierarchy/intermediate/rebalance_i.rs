@@ -0,0 +1,589 @@
+// ./src/core/hierarchy/intermediate/rebalance_i.rs
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::clock::Clock;
+use crate::core::hierarchy::root::global_tree_manager::IntermediateProofVerifier;
+use crate::core::types::rebalance::RebalanceConfig;
+use crate::core::zkps::proof::ZkProof;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, VecDeque};
+
+type ChannelId = [u8; 32];
+type WalletId = [u8; 32];
+
+/// Latest known balance of one channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelBalance {
+    pub channel_id: ChannelId,
+    pub wallet_id: WalletId,
+    pub balance: u64,
+    pub nonce: u64,
+}
+
+/// A transfer between two channels of the same wallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebalanceRequest {
+    pub wallet_id: WalletId,
+    pub from_channel: ChannelId,
+    pub to_channel: ChannelId,
+    pub amount: u64,
+    /// The receiving channel was below the emergency threshold when planned.
+    pub emergency: bool,
+    pub requested_at: u64,
+}
+
+/// One channel's side of an executed rebalance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelTransition {
+    pub channel_id: ChannelId,
+    pub old_balance: u64,
+    pub old_nonce: u64,
+    pub new_balance: u64,
+    pub new_nonce: u64,
+}
+
+/// Hash a rebalance proof must commit to as its `merkle_root`.
+pub fn rebalance_commitment(from: &ChannelTransition, to: &ChannelTransition) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for transition in [from, to] {
+        hasher.update(transition.channel_id);
+        hasher.update(transition.old_balance.to_le_bytes());
+        hasher.update(transition.old_nonce.to_le_bytes());
+        hasher.update(transition.new_balance.to_le_bytes());
+        hasher.update(transition.new_nonce.to_le_bytes());
+    }
+    hasher.finalize().into()
+}
+
+/// Proves that a debit of `from` and a credit of `to` by the same amount
+/// are valid state transitions.
+pub trait RebalanceProver {
+    fn prove(
+        &self,
+        from: &ChannelTransition,
+        to: &ChannelTransition,
+        amount: u64,
+    ) -> Result<ZkProof, SystemError>;
+}
+
+#[derive(Debug, Clone)]
+pub struct ExecutedRebalance {
+    pub request: RebalanceRequest,
+    pub from: ChannelTransition,
+    pub to: ChannelTransition,
+    pub proof: ZkProof,
+    pub executed_at: u64,
+}
+
+#[derive(Debug, Clone)]
+pub enum RebalanceOutcome {
+    Executed(ExecutedRebalance),
+    /// Removed from the queue because it no longer validates or could not
+    /// be proven.
+    Dropped {
+        request: RebalanceRequest,
+        error: SystemError,
+    },
+}
+
+/// Keeps a wallet's channels near `target_balance` by moving funds from
+/// channels holding too much to channels holding too little.
+///
+/// Balances are observed from outside; the engine plans transfers, queues
+/// them, and executes them one proven transition at a time against its own
+/// copy of the balances. A transition only counts as proven once `verifier`
+/// accepts the proof and the proof commits to that transition. Emergency
+/// transfers jump the queue and are not rate limited.
+pub struct RebalanceEngine<C: Clock, P: RebalanceProver, V: IntermediateProofVerifier> {
+    clock: C,
+    config: RebalanceConfig,
+    prover: P,
+    verifier: V,
+    channels: BTreeMap<ChannelId, ChannelBalance>,
+    queue: VecDeque<RebalanceRequest>,
+    executions: BTreeMap<WalletId, VecDeque<u64>>,
+}
+
+impl<C: Clock, P: RebalanceProver, V: IntermediateProofVerifier> RebalanceEngine<C, P, V> {
+    pub fn new(clock: C, config: RebalanceConfig, prover: P, verifier: V) -> Self {
+        Self {
+            clock,
+            config,
+            prover,
+            verifier,
+            channels: BTreeMap::new(),
+            queue: VecDeque::new(),
+            executions: BTreeMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &RebalanceConfig {
+        &self.config
+    }
+
+    /// Records the latest balance of a channel.
+    pub fn observe_channel(&mut self, channel: ChannelBalance) {
+        self.channels.insert(channel.channel_id, channel);
+    }
+
+    pub fn remove_channel(&mut self, channel_id: &ChannelId) -> Option<ChannelBalance> {
+        self.queue.retain(|request| {
+            request.from_channel != *channel_id && request.to_channel != *channel_id
+        });
+        self.channels.remove(channel_id)
+    }
+
+    pub fn channel(&self, channel_id: &ChannelId) -> Option<&ChannelBalance> {
+        self.channels.get(channel_id)
+    }
+
+    /// Queued transfers in execution order.
+    pub fn queue(&self) -> impl Iterator<Item = &RebalanceRequest> {
+        self.queue.iter()
+    }
+
+    /// Plans transfers for every wallet whose channels are outside the
+    /// allowed deviation. Planned transfers are queued when
+    /// `auto_rebalance` is set, skipping any channel pair already queued.
+    pub fn check_channel_balances(&mut self) -> Vec<RebalanceRequest> {
+        let now = self.clock.now();
+        let mut by_wallet: BTreeMap<WalletId, Vec<&ChannelBalance>> = BTreeMap::new();
+        for channel in self.channels.values() {
+            by_wallet
+                .entry(channel.wallet_id)
+                .or_default()
+                .push(channel);
+        }
+
+        let planned: Vec<RebalanceRequest> = by_wallet
+            .values()
+            .flat_map(|channels| plan_transfers(&self.config, channels, now))
+            .collect();
+
+        if self.config.auto_rebalance {
+            for request in &planned {
+                let queued = self.queue.iter().any(|queued| {
+                    queued.from_channel == request.from_channel
+                        && queued.to_channel == request.to_channel
+                });
+                if !queued {
+                    self.enqueue(request.clone());
+                }
+            }
+        }
+        planned
+    }
+
+    /// Queues a transfer requested by the wallet owner.
+    pub fn request_manual_rebalance(
+        &mut self,
+        from_channel: ChannelId,
+        to_channel: ChannelId,
+        amount: u64,
+    ) -> Result<(), SystemError> {
+        let wallet_id = self.require(&from_channel)?.wallet_id;
+        let request = RebalanceRequest {
+            wallet_id,
+            from_channel,
+            to_channel,
+            amount,
+            emergency: false,
+            requested_at: self.clock.now(),
+        };
+        self.validate_rebalance(&request)?;
+        self.enqueue(request);
+        Ok(())
+    }
+
+    /// Checks `request` against the current balances.
+    pub fn validate_rebalance(&self, request: &RebalanceRequest) -> Result<(), SystemError> {
+        if request.from_channel == request.to_channel {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Cannot rebalance a channel into itself".to_string(),
+            ));
+        }
+        let from = self.require(&request.from_channel)?;
+        let to = self.require(&request.to_channel)?;
+        if from.wallet_id != request.wallet_id || to.wallet_id != request.wallet_id {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Rebalance must stay within one wallet's channels".to_string(),
+            ));
+        }
+        if request.amount == 0 || request.amount > self.config.max_rebalance_amount {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidAmount,
+                format!(
+                    "Rebalance amount must be between 1 and {}",
+                    self.config.max_rebalance_amount
+                ),
+            ));
+        }
+        if from.balance < request.amount {
+            return Err(SystemError::new(
+                SystemErrorType::InsufficientBalance,
+                "Source channel cannot cover the rebalance".to_string(),
+            ));
+        }
+        if to.balance.checked_add(request.amount).is_none() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidAmount,
+                "Rebalance would overflow the receiving channel".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Executes queued transfers in order. Transfers held back by a wallet's
+    /// rate limit stay queued; transfers that no longer validate or cannot
+    /// be proven are dropped.
+    pub fn execute_rebalances(&mut self) -> Vec<RebalanceOutcome> {
+        let now = self.clock.now();
+        let mut outcomes = Vec::new();
+        let mut deferred = VecDeque::new();
+
+        while let Some(request) = self.queue.pop_front() {
+            if !request.emergency && self.rate_limited(&request.wallet_id, now) {
+                deferred.push_back(request);
+                continue;
+            }
+            match self.execute(&request, now) {
+                Ok(executed) => outcomes.push(RebalanceOutcome::Executed(executed)),
+                Err(error) => outcomes.push(RebalanceOutcome::Dropped { request, error }),
+            }
+        }
+
+        self.queue = deferred;
+        outcomes
+    }
+
+    fn execute(
+        &mut self,
+        request: &RebalanceRequest,
+        now: u64,
+    ) -> Result<ExecutedRebalance, SystemError> {
+        self.validate_rebalance(request)?;
+        let from = self.channels[&request.from_channel];
+        let to = self.channels[&request.to_channel];
+
+        let from_transition = ChannelTransition {
+            channel_id: from.channel_id,
+            old_balance: from.balance,
+            old_nonce: from.nonce,
+            new_balance: from.balance - request.amount,
+            new_nonce: from.nonce + 1,
+        };
+        let to_transition = ChannelTransition {
+            channel_id: to.channel_id,
+            old_balance: to.balance,
+            old_nonce: to.nonce,
+            new_balance: to.balance + request.amount,
+            new_nonce: to.nonce + 1,
+        };
+
+        let proof = self
+            .prover
+            .prove(&from_transition, &to_transition, request.amount)?;
+        self.verifier.verify(
+            &proof,
+            &rebalance_commitment(&from_transition, &to_transition),
+        )?;
+
+        for transition in [&from_transition, &to_transition] {
+            let channel = self.channels.get_mut(&transition.channel_id).unwrap();
+            channel.balance = transition.new_balance;
+            channel.nonce = transition.new_nonce;
+        }
+        if !request.emergency {
+            self.executions
+                .entry(request.wallet_id)
+                .or_default()
+                .push_back(now);
+        }
+
+        Ok(ExecutedRebalance {
+            request: request.clone(),
+            from: from_transition,
+            to: to_transition,
+            proof,
+            executed_at: now,
+        })
+    }
+
+    fn rate_limited(&mut self, wallet_id: &WalletId, now: u64) -> bool {
+        let window = self.config.rate_limit_window;
+        let executions = self.executions.entry(*wallet_id).or_default();
+        while executions
+            .front()
+            .is_some_and(|executed_at| executed_at + window <= now)
+        {
+            executions.pop_front();
+        }
+        executions.len() >= self.config.max_transfers_per_window
+    }
+
+    fn enqueue(&mut self, request: RebalanceRequest) {
+        if request.emergency {
+            // Behind earlier emergencies, ahead of everything else.
+            let position = self
+                .queue
+                .iter()
+                .position(|queued| !queued.emergency)
+                .unwrap_or(self.queue.len());
+            self.queue.insert(position, request);
+        } else {
+            self.queue.push_back(request);
+        }
+    }
+
+    fn require(&self, channel_id: &ChannelId) -> Result<&ChannelBalance, SystemError> {
+        self.channels.get(channel_id).ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NotFound,
+                "Channel is not tracked for rebalancing".to_string(),
+            )
+        })
+    }
+}
+
+/// Pairs the channels of one wallet that are below the allowed band with
+/// those above it. Emergencies are served first, then larger deficits; no
+/// surplus channel is drained below the target.
+fn plan_transfers(
+    config: &RebalanceConfig,
+    channels: &[&ChannelBalance],
+    now: u64,
+) -> Vec<RebalanceRequest> {
+    let low = config
+        .target_balance
+        .saturating_sub(config.allowed_deviation);
+    let high = config
+        .target_balance
+        .saturating_add(config.allowed_deviation);
+
+    let mut deficits: Vec<(&ChannelBalance, u64, bool)> = channels
+        .iter()
+        .filter(|channel| channel.balance < low)
+        .map(|channel| {
+            let need = config.target_balance - channel.balance;
+            let emergency = channel.balance < config.emergency_threshold;
+            (*channel, need, emergency)
+        })
+        .collect();
+    deficits.sort_by(|a, b| b.2.cmp(&a.2).then(b.1.cmp(&a.1)));
+
+    let mut surpluses: Vec<(&ChannelBalance, u64)> = channels
+        .iter()
+        .filter(|channel| channel.balance > high)
+        .map(|channel| (*channel, channel.balance - config.target_balance))
+        .collect();
+    surpluses.sort_by_key(|&(_, spare)| std::cmp::Reverse(spare));
+
+    let mut transfers = Vec::new();
+    for (receiver, mut need, emergency) in deficits {
+        for (sender, spare) in surpluses.iter_mut() {
+            while need > 0 && *spare > 0 {
+                let amount = need.min(*spare).min(config.max_rebalance_amount);
+                if amount == 0 {
+                    return transfers;
+                }
+                transfers.push(RebalanceRequest {
+                    wallet_id: receiver.wallet_id,
+                    from_channel: sender.channel_id,
+                    to_channel: receiver.channel_id,
+                    amount,
+                    emergency,
+                    requested_at: now,
+                });
+                need -= amount;
+                *spare -= amount;
+            }
+        }
+    }
+    transfers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::hierarchy::clock::ManualClock;
+
+    struct CommittingProver;
+
+    /// Rejects proofs whose data is `[0xff]` or whose `merkle_root` is not
+    /// the expected commitment.
+    struct RejectMarked;
+
+    impl IntermediateProofVerifier for RejectMarked {
+        fn verify(&self, proof: &ZkProof, commitment: &[u8; 32]) -> Result<(), SystemError> {
+            if proof.proof_data == [0xff] || proof.merkle_root != *commitment {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidProof,
+                    "rejected".to_string(),
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    impl RebalanceProver for CommittingProver {
+        fn prove(
+            &self,
+            from: &ChannelTransition,
+            to: &ChannelTransition,
+            amount: u64,
+        ) -> Result<ZkProof, SystemError> {
+            // Transfers of 13 get a proof the verifier rejects.
+            let proof_data = if amount == 13 { vec![0xff] } else { vec![1] };
+            Ok(ZkProof::new(
+                proof_data,
+                vec![from.old_balance, from.new_balance, amount],
+                rebalance_commitment(from, to).to_vec(),
+                0,
+            ))
+        }
+    }
+
+    fn channel(id: u8, balance: u64) -> ChannelBalance {
+        ChannelBalance {
+            channel_id: [id; 32],
+            wallet_id: [0xaa; 32],
+            balance,
+            nonce: 0,
+        }
+    }
+
+    fn engine(
+        config: RebalanceConfig,
+    ) -> (
+        ManualClock,
+        RebalanceEngine<ManualClock, CommittingProver, RejectMarked>,
+    ) {
+        let clock = ManualClock::new(0);
+        let engine = RebalanceEngine::new(clock.clone(), config, CommittingProver, RejectMarked);
+        (clock, engine)
+    }
+
+    fn executed(outcomes: &[RebalanceOutcome]) -> Vec<(u8, u8, u64)> {
+        outcomes
+            .iter()
+            .filter_map(|outcome| match outcome {
+                RebalanceOutcome::Executed(executed) => Some((
+                    executed.request.from_channel[0],
+                    executed.request.to_channel[0],
+                    executed.request.amount,
+                )),
+                RebalanceOutcome::Dropped { .. } => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_moves_surplus_to_deficits_emergency_first() {
+        let (_, mut engine) = engine(RebalanceConfig::default());
+        engine.observe_channel(channel(1, 1_900));
+        engine.observe_channel(channel(2, 1_100));
+        engine.observe_channel(channel(3, 700));
+        engine.observe_channel(channel(4, 50));
+
+        let planned = engine.check_channel_balances();
+        let summary: Vec<_> = planned
+            .iter()
+            .map(|r| (r.from_channel[0], r.to_channel[0], r.amount, r.emergency))
+            .collect();
+        assert_eq!(summary, vec![(1, 4, 900, true)]);
+        assert_eq!(engine.queue().count(), 1);
+
+        let outcomes = engine.execute_rebalances();
+        assert_eq!(executed(&outcomes), vec![(1, 4, 900)]);
+        assert_eq!(engine.channel(&[1; 32]).unwrap().balance, 1_000);
+        assert_eq!(engine.channel(&[4; 32]).unwrap().balance, 950);
+        assert_eq!(engine.channel(&[4; 32]).unwrap().nonce, 1);
+
+        // Channel 4 is inside the band now; channel 3 is still short but
+        // nothing has spare balance left.
+        assert!(engine.check_channel_balances().is_empty());
+    }
+
+    #[test]
+    fn test_rate_limit_defers_non_emergency_transfers() {
+        let config = RebalanceConfig {
+            max_transfers_per_window: 1,
+            rate_limit_window: 60,
+            ..RebalanceConfig::default()
+        };
+        let (clock, mut engine) = engine(config);
+        engine.observe_channel(channel(1, 2_000));
+        engine.observe_channel(channel(2, 1_000));
+
+        engine
+            .request_manual_rebalance([1; 32], [2; 32], 10)
+            .unwrap();
+        engine
+            .request_manual_rebalance([1; 32], [2; 32], 20)
+            .unwrap();
+        assert_eq!(executed(&engine.execute_rebalances()), vec![(1, 2, 10)]);
+        assert_eq!(engine.queue().count(), 1);
+
+        // An emergency is not held back by the limit.
+        engine.observe_channel(channel(3, 10));
+        engine.check_channel_balances();
+        assert_eq!(executed(&engine.execute_rebalances()), vec![(1, 3, 990)]);
+        assert_eq!(engine.queue().count(), 1);
+
+        clock.advance(60);
+        assert_eq!(executed(&engine.execute_rebalances()), vec![(1, 2, 20)]);
+    }
+
+    #[test]
+    fn test_invalid_rebalances_are_rejected_or_dropped() {
+        let (_, mut engine) = engine(RebalanceConfig::default());
+        engine.observe_channel(channel(1, 500));
+        engine.observe_channel(channel(2, 500));
+        let mut other_wallet = channel(3, 500);
+        other_wallet.wallet_id = [0xbb; 32];
+        engine.observe_channel(other_wallet);
+
+        assert!(engine
+            .request_manual_rebalance([1; 32], [1; 32], 10)
+            .is_err());
+        assert!(engine
+            .request_manual_rebalance([1; 32], [3; 32], 10)
+            .is_err());
+        assert!(engine
+            .request_manual_rebalance([1; 32], [2; 32], 501)
+            .is_err());
+        assert!(engine
+            .request_manual_rebalance([1; 32], [2; 32], 0)
+            .is_err());
+
+        // Valid when queued, but the balance moved before execution.
+        engine
+            .request_manual_rebalance([1; 32], [2; 32], 400)
+            .unwrap();
+        engine.observe_channel(channel(1, 100));
+        let outcomes = engine.execute_rebalances();
+        assert!(matches!(outcomes[..], [RebalanceOutcome::Dropped { .. }]));
+        assert_eq!(engine.channel(&[2; 32]).unwrap().balance, 500);
+    }
+
+    #[test]
+    fn test_transfer_with_rejected_proof_is_dropped() {
+        let (_, mut engine) = engine(RebalanceConfig::default());
+        engine.observe_channel(channel(1, 1_000));
+        engine.observe_channel(channel(2, 1_000));
+
+        engine
+            .request_manual_rebalance([1; 32], [2; 32], 13)
+            .unwrap();
+        let outcomes = engine.execute_rebalances();
+        assert!(matches!(
+            &outcomes[..],
+            [RebalanceOutcome::Dropped { error, .. }]
+                if error.error_type == SystemErrorType::InvalidProof
+        ));
+        assert_eq!(engine.channel(&[1; 32]).unwrap().balance, 1_000);
+        assert_eq!(engine.channel(&[2; 32]).unwrap().nonce, 0);
+    }
+}
@@ -0,0 +1,805 @@
+// ./src/core/hierarchy/intermediate/root_submitter_i.rs
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::clock::Clock;
+use crate::core::hierarchy::intermediate::sparse_merkle_tree_i::SparseMerkleTreeI;
+use crate::core::hierarchy::intermediate::state_tracking_i::{ProofGeneratorI, ProofInputsI};
+use crate::core::hierarchy::root::global_tree_manager::RootTransition;
+use crate::core::hierarchy::root::intermediate_registry::root_submission_message;
+use crate::core::hierarchy::root::root_contract::RootContract;
+use crate::core::types::ovp_ops::{IntermediateOpCode, OpCode};
+use crate::core::zkps::proof::ZkProof;
+use ed25519_dalek::{Signer, SigningKey};
+use std::collections::{BTreeMap, VecDeque};
+
+type Hash = [u8; 32];
+type Address = [u8; 32];
+
+const SUBMIT_TO_ROOT: OpCode = OpCode::Intermediate(IntermediateOpCode::SubmitToRoot);
+
+/// Fixed-size prefix of an encoded submission: opcode, contract address,
+/// sequence, epoch, root, update commitment, wallet count and signature.
+const SUBMISSION_HEADER_LEN: usize = OpCode::ENCODED_LEN + 32 + 8 + 8 + 32 + 32 + 8 + 64;
+
+/// An intermediate root on its way to the root contract.
+///
+/// `sequence` numbers an intermediate's snapshots from zero upwards and is
+/// also the epoch input of `proof`, so a proof cannot be replayed under
+/// another sequence. `commitment` commits to the wallet updates the proof
+/// applies. `epoch` is the root epoch the signature is made for and
+/// `wallet_count` the number of wallets in the tree under `root`.
+#[derive(Debug, Clone)]
+pub struct IntermediateRootSubmission {
+    pub contract_addr: Address,
+    pub sequence: u64,
+    pub epoch: u64,
+    pub root: Hash,
+    pub commitment: Hash,
+    pub wallet_count: u64,
+    pub signature: [u8; 64],
+    pub proof: ZkProof,
+}
+
+impl IntermediateRootSubmission {
+    /// Encodes the submission as an `IntermediateOpCode::SubmitToRoot`
+    /// message for endpoints on the other side of a network.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SystemError> {
+        let proof = bincode::serialize(&self.proof)
+            .map_err(|e| SystemError::new(SystemErrorType::SerializationError, e.to_string()))?;
+        let mut bytes = Vec::with_capacity(SUBMISSION_HEADER_LEN + proof.len());
+        bytes.extend_from_slice(&SUBMIT_TO_ROOT.encode());
+        bytes.extend_from_slice(&self.contract_addr);
+        bytes.extend_from_slice(&self.sequence.to_le_bytes());
+        bytes.extend_from_slice(&self.epoch.to_le_bytes());
+        bytes.extend_from_slice(&self.root);
+        bytes.extend_from_slice(&self.commitment);
+        bytes.extend_from_slice(&self.wallet_count.to_le_bytes());
+        bytes.extend_from_slice(&self.signature);
+        bytes.extend_from_slice(&proof);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SystemError> {
+        let (op, consumed) = OpCode::decode(bytes)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidInput, e.to_string()))?;
+        if op != SUBMIT_TO_ROOT {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                format!("Unexpected opcode {:?} for root submission", op),
+            ));
+        }
+        if bytes.len() < consumed + SUBMISSION_HEADER_LEN - OpCode::ENCODED_LEN {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Truncated root submission".to_string(),
+            ));
+        }
+        let mut body = &bytes[consumed..];
+        let mut take = |len: usize| {
+            let (head, rest) = body.split_at(len);
+            body = rest;
+            head
+        };
+        let contract_addr = take(32).try_into().unwrap();
+        let sequence = u64::from_le_bytes(take(8).try_into().unwrap());
+        let epoch = u64::from_le_bytes(take(8).try_into().unwrap());
+        let root = take(32).try_into().unwrap();
+        let commitment = take(32).try_into().unwrap();
+        let wallet_count = u64::from_le_bytes(take(8).try_into().unwrap());
+        let signature = take(64).try_into().unwrap();
+        let proof = bincode::deserialize(body)
+            .map_err(|e| SystemError::new(SystemErrorType::SerializationError, e.to_string()))?;
+        Ok(Self {
+            contract_addr,
+            sequence,
+            epoch,
+            root,
+            commitment,
+            wallet_count,
+            signature,
+            proof,
+        })
+    }
+}
+
+/// Where intermediate roots are submitted: the in-process root contract or
+/// a client for a remote one.
+///
+/// Endpoints must treat a submission whose sequence they have already
+/// accepted as a success without applying it again, since an acknowledgement
+/// lost in transit makes the intermediate resend it.
+pub trait RootEndpoint {
+    /// Epoch the root is currently collecting intermediate roots for.
+    fn collecting_epoch(&mut self) -> Result<u64, SystemError>;
+
+    /// Highest sequence accepted from `contract_addr`, if any.
+    fn last_accepted_sequence(
+        &mut self,
+        contract_addr: &Address,
+    ) -> Result<Option<u64>, SystemError>;
+
+    /// Handles `IntermediateOpCode::SubmitToRoot`. An error schedules a
+    /// retry.
+    fn submit(&mut self, submission: &IntermediateRootSubmission) -> Result<(), SystemError>;
+}
+
+/// Feeds submissions straight into a [`RootContract`] in the same process.
+/// Accepted sequences are read back from the contract, so they survive its
+/// restarts along with the rest of its state.
+pub struct InProcessRootEndpoint {
+    root_contract: RootContract,
+}
+
+impl InProcessRootEndpoint {
+    pub fn new(root_contract: RootContract) -> Self {
+        Self { root_contract }
+    }
+
+    pub fn root_contract(&self) -> &RootContract {
+        &self.root_contract
+    }
+
+    pub fn root_contract_mut(&mut self) -> &mut RootContract {
+        &mut self.root_contract
+    }
+}
+
+impl RootEndpoint for InProcessRootEndpoint {
+    fn collecting_epoch(&mut self) -> Result<u64, SystemError> {
+        Ok(self.root_contract.epoch() + 1)
+    }
+
+    fn last_accepted_sequence(
+        &mut self,
+        contract_addr: &Address,
+    ) -> Result<Option<u64>, SystemError> {
+        Ok(self.root_contract.last_root_sequence(contract_addr))
+    }
+
+    /// Applies the root to the contract, which checks its proof against the
+    /// root last accepted from the same intermediate.
+    fn submit(&mut self, submission: &IntermediateRootSubmission) -> Result<(), SystemError> {
+        if self
+            .root_contract
+            .last_root_sequence(&submission.contract_addr)
+            .is_some_and(|accepted| accepted >= submission.sequence)
+        {
+            return Ok(());
+        }
+        self.root_contract.process_intermediate_root(
+            submission.contract_addr,
+            submission.root,
+            submission.wallet_count,
+            &submission.signature,
+            &RootTransition {
+                sequence: submission.sequence,
+                commitment: submission.commitment,
+                proof: submission.proof.clone(),
+            },
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RootSubmitterConfig {
+    /// Seconds between snapshots of the intermediate tree, i.e.
+    /// `IntermediateContract::state_update_interval`.
+    pub state_update_interval: u64,
+    /// Delay before the first retry; doubles on every further failure.
+    pub retry_backoff: u64,
+    /// Upper bound on the delay between retries.
+    pub max_backoff: u64,
+}
+
+impl Default for RootSubmitterConfig {
+    fn default() -> Self {
+        Self {
+            state_update_interval: 60,
+            retry_backoff: 2,
+            max_backoff: 300,
+        }
+    }
+}
+
+/// What happened during a call to [`RootSubmitterI::tick`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubmitterEvent {
+    /// Handles `IntermediateOpCode::PrepareRootSubmission`: staged wallet
+    /// roots were applied to the tree and proven.
+    SnapshotPrepared {
+        sequence: u64,
+        root: Hash,
+        updates: usize,
+    },
+    Submitted {
+        sequence: u64,
+        epoch: u64,
+    },
+    /// The endpoint already had this sequence, e.g. because the
+    /// acknowledgement of an earlier attempt was lost.
+    AlreadyAccepted {
+        sequence: u64,
+    },
+    SubmissionRetry {
+        sequence: u64,
+        attempt: u32,
+        retry_at: u64,
+    },
+}
+
+struct PreparedSnapshot {
+    sequence: u64,
+    root: Hash,
+    commitment: Hash,
+    wallet_count: u64,
+    proof: ZkProof,
+}
+
+/// Periodically commits staged wallet roots to the intermediate tree and
+/// submits the resulting roots to a [`RootEndpoint`].
+///
+/// Every `state_update_interval` the staged roots are applied to the tree in
+/// batches no larger than the prover's circuit, and each batch becomes a
+/// proof with its own sequence number. Snapshots are submitted strictly in
+/// sequence order, since each proof starts from the root of the one before
+/// it; a failed submission is retried with exponential backoff and is never
+/// skipped. Before submitting again after a failure, and before the first
+/// submission, the submitter asks the endpoint which sequence it last
+/// accepted, so a snapshot that already landed is not sent twice and a
+/// restarted submitter continues numbering after it.
+pub struct RootSubmitterI<C: Clock> {
+    clock: C,
+    config: RootSubmitterConfig,
+    contract_addr: Address,
+    operator_key: SigningKey,
+    prover: ProofGeneratorI,
+    tree: SparseMerkleTreeI,
+    staged: BTreeMap<[u8; 32], Hash>,
+    prepared: VecDeque<PreparedSnapshot>,
+    last_snapshot_at: u64,
+    next_sequence: u64,
+    acknowledged: Option<u64>,
+    reconciled: bool,
+    attempts: u32,
+    next_attempt_at: u64,
+}
+
+impl<C: Clock> RootSubmitterI<C> {
+    /// `tree` is the intermediate tree as of the last snapshot, e.g. one
+    /// restored with [`SparseMerkleTreeI::deserialize_state`].
+    pub fn new(
+        clock: C,
+        config: RootSubmitterConfig,
+        contract_addr: Address,
+        operator_key: SigningKey,
+        prover: ProofGeneratorI,
+        tree: SparseMerkleTreeI,
+    ) -> Self {
+        let last_snapshot_at = clock.now();
+        Self {
+            clock,
+            config,
+            contract_addr,
+            operator_key,
+            prover,
+            tree,
+            staged: BTreeMap::new(),
+            prepared: VecDeque::new(),
+            last_snapshot_at,
+            next_sequence: 0,
+            acknowledged: None,
+            reconciled: false,
+            attempts: 0,
+            next_attempt_at: 0,
+        }
+    }
+
+    /// The tree as of the most recent snapshot.
+    pub fn tree(&self) -> &SparseMerkleTreeI {
+        &self.tree
+    }
+
+    /// Queues a wallet root for the next snapshot. A zero root removes the
+    /// wallet; a later root for the same wallet replaces the earlier one.
+    pub fn stage_update(&mut self, wallet_id: [u8; 32], new_root: Hash) {
+        self.staged.insert(wallet_id, new_root);
+    }
+
+    /// The wallet's root including staged updates.
+    pub fn current_root(&self, wallet_id: &[u8; 32]) -> Option<Hash> {
+        match self.staged.get(wallet_id) {
+            Some(root) if *root == [0u8; 32] => None,
+            Some(root) => Some(*root),
+            None => self.tree.get(wallet_id),
+        }
+    }
+
+    pub fn staged_len(&self) -> usize {
+        self.staged.len()
+    }
+
+    /// Number of snapshots proven but not yet accepted by the endpoint.
+    pub fn pending_submissions(&self) -> usize {
+        self.prepared.len()
+    }
+
+    /// Highest sequence the endpoint is known to have accepted.
+    pub fn acknowledged_sequence(&self) -> Option<u64> {
+        self.acknowledged
+    }
+
+    /// Takes a snapshot if the interval has passed and submits as many
+    /// prepared snapshots as the endpoint accepts.
+    pub fn tick<E: RootEndpoint>(
+        &mut self,
+        endpoint: &mut E,
+    ) -> Result<Vec<SubmitterEvent>, SystemError> {
+        let now = self.clock.now();
+        let mut events = Vec::new();
+
+        let snapshot_due = !self.staged.is_empty()
+            && now >= self.last_snapshot_at + self.config.state_update_interval;
+        let has_work = snapshot_due || !self.prepared.is_empty();
+        if !self.reconciled && has_work && now >= self.next_attempt_at {
+            match endpoint.last_accepted_sequence(&self.contract_addr) {
+                Ok(accepted) => self.reconcile(accepted, &mut events),
+                Err(_) => self.back_off(now, &mut events),
+            }
+        }
+        if !self.reconciled {
+            return Ok(events);
+        }
+
+        if snapshot_due {
+            self.prepare_snapshots(now, &mut events)?;
+        }
+        self.submit_prepared(now, endpoint, &mut events);
+        Ok(events)
+    }
+
+    /// Adopts the endpoint's view of what has been accepted: snapshots it
+    /// already holds are dropped and new ones are numbered after them.
+    fn reconcile(&mut self, accepted: Option<u64>, events: &mut Vec<SubmitterEvent>) {
+        self.reconciled = true;
+        let Some(accepted) = accepted else {
+            return;
+        };
+        self.acknowledged = Some(self.acknowledged.map_or(accepted, |own| own.max(accepted)));
+        self.next_sequence = self.next_sequence.max(accepted + 1);
+        while let Some(snapshot) = self.prepared.front() {
+            if snapshot.sequence > accepted {
+                break;
+            }
+            events.push(SubmitterEvent::AlreadyAccepted {
+                sequence: snapshot.sequence,
+            });
+            self.prepared.pop_front();
+            self.attempts = 0;
+        }
+    }
+
+    fn prepare_snapshots(
+        &mut self,
+        now: u64,
+        events: &mut Vec<SubmitterEvent>,
+    ) -> Result<(), SystemError> {
+        let batch_size = self.prover.max_updates().max(1);
+        while !self.staged.is_empty() {
+            let batch: Vec<_> = std::iter::from_fn(|| self.staged.pop_first())
+                .take(batch_size)
+                .collect();
+            let old_root = self.tree.root();
+            let sequence = self.next_sequence;
+
+            let witnesses = self.tree.apply_with_witnesses(&batch)?;
+            let inputs = ProofInputsI::new(old_root, sequence, witnesses.clone());
+            let proof = self.prover.generate_proof(&inputs);
+            let proof = match proof {
+                Ok(proof) => proof,
+                Err(e) => {
+                    // Put the tree and the staged roots back so the batch is
+                    // proven again at the next snapshot.
+                    let restore: Vec<_> = witnesses
+                        .iter()
+                        .map(|witness| (witness.wallet_id, witness.old_root))
+                        .collect();
+                    self.tree.update_batch(&restore)?;
+                    for (wallet_id, new_root) in batch {
+                        self.staged.entry(wallet_id).or_insert(new_root);
+                    }
+                    return Err(e);
+                }
+            };
+
+            let root = self.tree.root();
+            events.push(SubmitterEvent::SnapshotPrepared {
+                sequence,
+                root,
+                updates: batch.len(),
+            });
+            self.prepared.push_back(PreparedSnapshot {
+                sequence,
+                root,
+                commitment: inputs.commitment(),
+                wallet_count: self.tree.len() as u64,
+                proof,
+            });
+            self.next_sequence += 1;
+        }
+        self.last_snapshot_at = now;
+        Ok(())
+    }
+
+    fn submit_prepared<E: RootEndpoint>(
+        &mut self,
+        now: u64,
+        endpoint: &mut E,
+        events: &mut Vec<SubmitterEvent>,
+    ) {
+        while let Some(snapshot) = self.prepared.front() {
+            if now < self.next_attempt_at {
+                break;
+            }
+            let sequence = snapshot.sequence;
+            let result = endpoint.collecting_epoch().and_then(|epoch| {
+                let signature = self
+                    .operator_key
+                    .sign(&root_submission_message(
+                        &self.contract_addr,
+                        epoch,
+                        &snapshot.root,
+                        snapshot.wallet_count,
+                    ))
+                    .to_bytes();
+                endpoint
+                    .submit(&IntermediateRootSubmission {
+                        contract_addr: self.contract_addr,
+                        sequence,
+                        epoch,
+                        root: snapshot.root,
+                        commitment: snapshot.commitment,
+                        wallet_count: snapshot.wallet_count,
+                        signature,
+                        proof: snapshot.proof.clone(),
+                    })
+                    .map(|()| epoch)
+            });
+
+            match result {
+                Ok(epoch) => {
+                    self.prepared.pop_front();
+                    self.acknowledged = Some(sequence);
+                    self.attempts = 0;
+                    events.push(SubmitterEvent::Submitted { sequence, epoch });
+                }
+                Err(_) => {
+                    // The submission may have landed even though it failed
+                    // here; ask the endpoint before sending it again.
+                    self.reconciled = false;
+                    self.back_off(now, events);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn back_off(&mut self, now: u64, events: &mut Vec<SubmitterEvent>) {
+        self.attempts += 1;
+        let backoff = self
+            .config
+            .retry_backoff
+            .saturating_mul(1u64 << (self.attempts - 1).min(32))
+            .min(self.config.max_backoff);
+        self.next_attempt_at = now.saturating_add(backoff);
+        events.push(SubmitterEvent::SubmissionRetry {
+            sequence: self
+                .prepared
+                .front()
+                .map_or(self.next_sequence, |snapshot| snapshot.sequence),
+            attempt: self.attempts,
+            retry_at: self.next_attempt_at,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::hierarchy::clock::ManualClock;
+    use crate::core::hierarchy::intermediate::state_tracking_i::ProofVerifierI;
+    use crate::core::hierarchy::root::global_tree_manager::IntermediateProofVerifier;
+    use crate::core::hierarchy::root::intermediate_registry::{
+        registration_message, IntermediateRegistration, IntermediateStatus,
+    };
+    use crate::core::zkps::wallet_root_circuit::WalletRootBatchCircuit;
+    use std::sync::Arc;
+
+    const INTERMEDIATE: Address = [1; 32];
+
+    /// Roots are submitted with validity proofs here, so nothing is ever
+    /// challenged with a fraud proof.
+    struct NoFraudProofs;
+
+    impl IntermediateProofVerifier for NoFraudProofs {
+        fn verify(&self, _proof: &ZkProof, _commitment: &[u8; 32]) -> Result<(), SystemError> {
+            Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "unexpected fraud proof".to_string(),
+            ))
+        }
+    }
+
+    fn operator() -> SigningKey {
+        SigningKey::from_bytes(&[1; 32])
+    }
+
+    fn wallet(n: u8) -> [u8; 32] {
+        [n; 32]
+    }
+
+    fn wallet_root(n: u8) -> Hash {
+        let mut root = [0u8; 32];
+        root[0] = n;
+        root
+    }
+
+    fn endpoint(circuit: &Arc<WalletRootBatchCircuit>) -> InProcessRootEndpoint {
+        let mut root_contract =
+            RootContract::new(10, ProofVerifierI::new(circuit.clone()), NoFraudProofs);
+        let registration = IntermediateRegistration {
+            contract_addr: INTERMEDIATE,
+            operator_key: operator().verifying_key().to_bytes(),
+            key_version: 0,
+            stake: 0,
+            capacity: 100,
+            registered_at: 0,
+            status: IntermediateStatus::Active,
+        };
+        let signature = operator()
+            .sign(&registration_message(&registration))
+            .to_bytes();
+        root_contract
+            .register_intermediate(registration, &signature)
+            .unwrap();
+        InProcessRootEndpoint::new(root_contract)
+    }
+
+    fn submitter(
+        clock: &ManualClock,
+        circuit: &Arc<WalletRootBatchCircuit>,
+        tree: SparseMerkleTreeI,
+    ) -> RootSubmitterI<ManualClock> {
+        let config = RootSubmitterConfig {
+            state_update_interval: 10,
+            retry_backoff: 2,
+            max_backoff: 5,
+        };
+        RootSubmitterI::new(
+            clock.clone(),
+            config,
+            INTERMEDIATE,
+            operator(),
+            ProofGeneratorI::new(circuit.clone()),
+            tree,
+        )
+    }
+
+    /// Forwards to an inner endpoint but can refuse submissions outright, or
+    /// apply them and then report a failure as a lost acknowledgement would.
+    struct FlakyEndpoint<E> {
+        inner: E,
+        refuse: u32,
+        lose_ack: u32,
+        submissions: Vec<u64>,
+    }
+
+    impl<E: RootEndpoint> RootEndpoint for FlakyEndpoint<E> {
+        fn collecting_epoch(&mut self) -> Result<u64, SystemError> {
+            self.inner.collecting_epoch()
+        }
+
+        fn last_accepted_sequence(
+            &mut self,
+            contract_addr: &Address,
+        ) -> Result<Option<u64>, SystemError> {
+            self.inner.last_accepted_sequence(contract_addr)
+        }
+
+        fn submit(&mut self, submission: &IntermediateRootSubmission) -> Result<(), SystemError> {
+            self.submissions.push(submission.sequence);
+            let unavailable =
+                || SystemError::new(SystemErrorType::NetworkError, "unavailable".to_string());
+            if self.refuse > 0 {
+                self.refuse -= 1;
+                return Err(unavailable());
+            }
+            self.inner.submit(submission)?;
+            if self.lose_ack > 0 {
+                self.lose_ack -= 1;
+                return Err(unavailable());
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_snapshots_at_interval_in_circuit_sized_batches() {
+        let circuit = Arc::new(WalletRootBatchCircuit::new(2));
+        let clock = ManualClock::new(0);
+        let mut endpoint = endpoint(&circuit);
+        let mut submitter = submitter(&clock, &circuit, SparseMerkleTreeI::new());
+
+        for n in 1..=3 {
+            submitter.stage_update(wallet(n), wallet_root(n));
+        }
+        assert_eq!(submitter.current_root(&wallet(2)), Some(wallet_root(2)));
+        assert!(submitter.tick(&mut endpoint).unwrap().is_empty());
+
+        clock.advance(10);
+        let events = submitter.tick(&mut endpoint).unwrap();
+        let root = submitter.tree().root();
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| matches!(event, SubmitterEvent::Submitted { .. }))
+                .count(),
+            2
+        );
+        assert!(events.contains(&SubmitterEvent::SnapshotPrepared {
+            sequence: 1,
+            root,
+            updates: 1,
+        }));
+        assert_eq!(submitter.acknowledged_sequence(), Some(1));
+        assert_eq!(submitter.staged_len(), 0);
+        assert_eq!(
+            endpoint.root_contract().intermediate_roots()[&INTERMEDIATE],
+            root
+        );
+    }
+
+    #[test]
+    fn test_retries_with_backoff_without_double_submission() {
+        let circuit = Arc::new(WalletRootBatchCircuit::new(2));
+        let clock = ManualClock::new(0);
+        let mut endpoint = FlakyEndpoint {
+            inner: endpoint(&circuit),
+            refuse: 2,
+            lose_ack: 1,
+            submissions: Vec::new(),
+        };
+        let mut submitter = submitter(&clock, &circuit, SparseMerkleTreeI::new());
+        submitter.stage_update(wallet(1), wallet_root(1));
+
+        clock.advance(10);
+        let events = submitter.tick(&mut endpoint).unwrap();
+        assert!(events.contains(&SubmitterEvent::SubmissionRetry {
+            sequence: 0,
+            attempt: 1,
+            retry_at: 12,
+        }));
+
+        // Nothing is sent before the retry is due.
+        clock.advance(1);
+        submitter.tick(&mut endpoint).unwrap();
+        assert_eq!(endpoint.submissions, vec![0]);
+
+        clock.advance(1);
+        let events = submitter.tick(&mut endpoint).unwrap();
+        assert!(events.contains(&SubmitterEvent::SubmissionRetry {
+            sequence: 0,
+            attempt: 2,
+            retry_at: 16,
+        }));
+
+        // This attempt lands but its acknowledgement is lost.
+        clock.set(16);
+        submitter.tick(&mut endpoint).unwrap();
+        assert_eq!(submitter.pending_submissions(), 1);
+
+        // The delay is capped at `max_backoff`.
+        clock.set(21);
+        let events = submitter.tick(&mut endpoint).unwrap();
+        assert_eq!(
+            events,
+            vec![SubmitterEvent::AlreadyAccepted { sequence: 0 }]
+        );
+        assert_eq!(endpoint.submissions, vec![0, 0, 0]);
+        assert_eq!(submitter.pending_submissions(), 0);
+    }
+
+    #[test]
+    fn test_restarted_submitter_continues_after_accepted_sequence() {
+        let circuit = Arc::new(WalletRootBatchCircuit::new(2));
+        let clock = ManualClock::new(0);
+        let mut endpoint = endpoint(&circuit);
+        let mut first = submitter(&clock, &circuit, SparseMerkleTreeI::new());
+        first.stage_update(wallet(1), wallet_root(1));
+        clock.advance(10);
+        first.tick(&mut endpoint).unwrap();
+
+        let snapshot = first.tree().serialize_state().unwrap();
+        let tree = SparseMerkleTreeI::deserialize_state(&snapshot).unwrap();
+        let mut restarted = submitter(&clock, &circuit, tree);
+        restarted.stage_update(wallet(1), [0u8; 32]);
+        assert_eq!(restarted.current_root(&wallet(1)), None);
+
+        clock.advance(10);
+        let events = restarted.tick(&mut endpoint).unwrap();
+        assert!(events.contains(&SubmitterEvent::Submitted {
+            sequence: 1,
+            epoch: 1
+        }));
+        assert_eq!(
+            endpoint.root_contract().intermediate_roots()[&INTERMEDIATE],
+            SparseMerkleTreeI::new().root()
+        );
+    }
+
+    #[test]
+    fn test_first_submission_starts_from_genesis_root() {
+        let circuit = Arc::new(WalletRootBatchCircuit::new(2));
+        let clock = ManualClock::new(0);
+        let mut endpoint = endpoint(&circuit);
+        let mut tree = SparseMerkleTreeI::new();
+        tree.update(&wallet(1), &wallet_root(1)).unwrap();
+        let genesis = tree.root();
+        let mut submitter = submitter(&clock, &circuit, tree);
+        submitter.stage_update(wallet(2), wallet_root(2));
+
+        // Without a registered genesis the first proof has to start from the
+        // empty tree.
+        clock.advance(10);
+        let events = submitter.tick(&mut endpoint).unwrap();
+        assert!(events.contains(&SubmitterEvent::SubmissionRetry {
+            sequence: 0,
+            attempt: 1,
+            retry_at: 12,
+        }));
+        assert!(endpoint
+            .root_contract()
+            .intermediate_roots()
+            .get(&INTERMEDIATE)
+            .is_none());
+
+        endpoint
+            .root_contract_mut()
+            .register_genesis_root(INTERMEDIATE, genesis)
+            .unwrap();
+        clock.advance(2);
+        let events = submitter.tick(&mut endpoint).unwrap();
+        assert!(events.contains(&SubmitterEvent::Submitted {
+            sequence: 0,
+            epoch: 1
+        }));
+        assert_eq!(
+            endpoint.root_contract().intermediate_roots()[&INTERMEDIATE],
+            submitter.tree().root()
+        );
+    }
+
+    #[test]
+    fn test_submission_wire_format_round_trips() {
+        let submission = IntermediateRootSubmission {
+            contract_addr: INTERMEDIATE,
+            sequence: 7,
+            epoch: 3,
+            root: [2; 32],
+            commitment: [6; 32],
+            wallet_count: 5,
+            signature: [3; 64],
+            proof: ZkProof::new(vec![1, 2], vec![4], vec![2; 32], 9),
+        };
+        let bytes = submission.to_bytes().unwrap();
+        assert_eq!(OpCode::decode(&bytes).unwrap().0, SUBMIT_TO_ROOT);
+
+        let decoded = IntermediateRootSubmission::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.sequence, 7);
+        assert_eq!(decoded.epoch, 3);
+        assert_eq!(decoded.commitment, [6; 32]);
+        assert_eq!(decoded.wallet_count, 5);
+        assert_eq!(decoded.signature, [3; 64]);
+        assert_eq!(decoded.proof.proof_data, vec![1, 2]);
+        assert!(IntermediateRootSubmission::from_bytes(&bytes[..100]).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+// ./src/core/hierarchy/intermediate/settlement_batch_i.rs
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+type ChannelId = [u8; 32];
+type Hash = [u8; 32];
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+const EMPTY_NODE: Hash = [0u8; 32];
+
+/// Leaf committing to one channel's final state and balances.
+pub fn settlement_leaf(
+    channel_id: &ChannelId,
+    state_root: &Hash,
+    balances: &BTreeMap<[u8; 32], u64>,
+) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(channel_id);
+    hasher.update(state_root);
+    hasher.update((balances.len() as u32).to_le_bytes());
+    for (participant, balance) in balances {
+        hasher.update(participant);
+        hasher.update(balance.to_le_bytes());
+    }
+    hasher.finalize().into()
+}
+
+fn hash_node(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Path from one channel's leaf to the root of the batch it was settled in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchInclusionProof {
+    pub index: usize,
+    pub siblings: Vec<Hash>,
+}
+
+impl BatchInclusionProof {
+    pub fn verify(&self, batch_root: &Hash, leaf: &Hash) -> bool {
+        let mut index = self.index;
+        let mut node = *leaf;
+        for sibling in &self.siblings {
+            node = if index & 1 == 0 {
+                hash_node(&node, sibling)
+            } else {
+                hash_node(sibling, &node)
+            };
+            index /= 2;
+        }
+        index == 0 && node == *batch_root
+    }
+}
+
+/// Channels settled together under a single proof.
+///
+/// The proof commits to a Merkle root over one [`settlement_leaf`] per
+/// channel, ordered by channel id, so each channel can later show its final
+/// balances were part of the batch without the other channels' data.
+#[derive(Debug, Clone)]
+pub struct SettlementBatch {
+    channels: Vec<ChannelId>,
+    /// Tree levels from the leaves up; the last level holds only the root.
+    levels: Vec<Vec<Hash>>,
+}
+
+impl SettlementBatch {
+    pub fn new(mut leaves: Vec<(ChannelId, Hash)>) -> Result<Self, SystemError> {
+        if leaves.is_empty() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Settlement batch must contain at least one channel".to_string(),
+            ));
+        }
+        leaves.sort_unstable_by_key(|(channel_id, _)| *channel_id);
+        if leaves.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Channel appears twice in a settlement batch".to_string(),
+            ));
+        }
+
+        let (channels, leaf_hashes): (Vec<_>, Vec<_>) = leaves.into_iter().unzip();
+        let mut levels = vec![leaf_hashes];
+        while levels.last().unwrap().len() > 1 {
+            let level = levels.last().unwrap();
+            let parents = level
+                .chunks(2)
+                .map(|pair| hash_node(&pair[0], pair.get(1).unwrap_or(&EMPTY_NODE)))
+                .collect();
+            levels.push(parents);
+        }
+
+        Ok(Self { channels, levels })
+    }
+
+    pub fn root(&self) -> Hash {
+        self.levels.last().unwrap()[0]
+    }
+
+    /// Channels in the batch, in leaf order.
+    pub fn channels(&self) -> &[ChannelId] {
+        &self.channels
+    }
+
+    pub fn leaves(&self) -> &[Hash] {
+        &self.levels[0]
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    pub fn inclusion_proof(&self, channel_id: &ChannelId) -> Option<BatchInclusionProof> {
+        let index = self.channels.binary_search(channel_id).ok()?;
+        let siblings = self.levels[..self.levels.len() - 1]
+            .iter()
+            .enumerate()
+            .map(|(depth, level)| {
+                let position = (index >> depth) ^ 1;
+                level.get(position).copied().unwrap_or(EMPTY_NODE)
+            })
+            .collect();
+        Some(BatchInclusionProof { index, siblings })
+    }
+
+    /// Public inputs the batch proof is expected to expose: the root as four
+    /// little-endian u64 limbs followed by the channel count.
+    pub fn public_inputs(&self) -> Vec<u64> {
+        let root = self.root();
+        let mut inputs: Vec<u64> = root
+            .chunks_exact(8)
+            .map(|limb| u64::from_le_bytes(limb.try_into().unwrap()))
+            .collect();
+        inputs.push(self.channels.len() as u64);
+        inputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: u8) -> Vec<(ChannelId, Hash)> {
+        (0..count)
+            .map(|i| {
+                let balances = BTreeMap::from([([i; 32], u64::from(i) * 10)]);
+                ([i; 32], settlement_leaf(&[i; 32], &[i; 32], &balances))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_every_channel_proves_inclusion() {
+        for count in 1..=7u8 {
+            let batch = SettlementBatch::new(leaves(count)).unwrap();
+            for (channel_id, leaf) in leaves(count) {
+                let proof = batch.inclusion_proof(&channel_id).unwrap();
+                assert!(proof.verify(&batch.root(), &leaf));
+                assert!(!proof.verify(&batch.root(), &[0xaa; 32]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_batch_root_is_order_independent() {
+        let mut reversed = leaves(5);
+        reversed.reverse();
+        let batch = SettlementBatch::new(leaves(5)).unwrap();
+        assert_eq!(batch.root(), SettlementBatch::new(reversed).unwrap().root());
+        assert_eq!(batch.public_inputs()[4], 5);
+
+        let mut duplicated = leaves(2);
+        duplicated.push(duplicated[0]);
+        assert!(SettlementBatch::new(duplicated).is_err());
+        assert!(SettlementBatch::new(Vec::new()).is_err());
+        assert!(batch.inclusion_proof(&[9; 32]).is_none());
+    }
+}
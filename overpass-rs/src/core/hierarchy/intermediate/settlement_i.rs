@@ -1,208 +1,1104 @@
 // ./src/core/hierarchy/intermediate/settlement_i.rs
 /// This module provides an implementation of the settlement intermediate layer.
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::clock::Clock;
+use crate::core::hierarchy::intermediate::settlement_batch_i::{
+    settlement_leaf, BatchInclusionProof, SettlementBatch,
+};
+use crate::core::hierarchy::root::intermediate_registry::settlement_batch_message;
 use crate::core::hierarchy::root::root_contract::RootContract;
-use crate::core::storage_node::storage_node_contract::StorageNode;
+use crate::core::hierarchy::root::root_history::RootSettlementStatus;
 use crate::core::types::boc::BOC;
+use crate::core::zkps::plonky2::Plonky2System;
 use crate::core::zkps::proof::ZkProof;
-use std::collections::HashMap;
-use std::sync::Arc;
+use ed25519_dalek::{Signer, SigningKey};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub struct SettlementIntermediate<RootTree> {
-    root_contract: Arc<RootContract>,
-    storage_nodes: Vec<StorageNode<RootTree, IntermediateTreeManager>>,
-    pending_settlements: HashMap<[u8; 32], SettlementState>,
-    settlement_proofs: HashMap<[u8; 32], ZkProof>,
+type ChannelId = [u8; 32];
+type Address = [u8; 32];
+
+/// Final state of a closing channel. Encoded as the single cell of the
+/// channel's final BOC.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FinalChannelState {
+    pub channel_id: ChannelId,
+    pub nonce: u64,
+    pub balances: BTreeMap<[u8; 32], u64>,
 }
-#[derive(Clone)]
-pub struct SettlementState {
-    channel_id: [u8; 32],
-    final_balances: HashMap<[u8; 32], u64>,
-    state_root: [u8; 32],
-    settlement_boc: BOC,
-    status: SettlementStatus,
+
+impl FinalChannelState {
+    pub fn to_boc(&self) -> Result<BOC, SystemError> {
+        let encoded = bincode::serialize(self)
+            .map_err(|e| SystemError::new(SystemErrorType::SerializationError, e.to_string()))?;
+        let mut boc = BOC::new().with_cells(vec![encoded]);
+        boc.set_hash(boc.compute_hash());
+        Ok(boc)
+    }
+
+    pub fn from_boc(boc: &BOC) -> Result<Self, SystemError> {
+        let cell = match boc.cells().as_slice() {
+            [cell] => cell,
+            _ => {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidInput,
+                    "Final channel BOC must hold exactly one cell".to_string(),
+                ))
+            }
+        };
+        bincode::deserialize(cell)
+            .map_err(|e| SystemError::new(SystemErrorType::SerializationError, e.to_string()))
+    }
+}
+
+/// A storage node's copy of channel state.
+pub trait SettlementStorage {
+    /// Returns the final state the node holds for `channel_id`, if any.
+    fn final_state(&self, channel_id: &ChannelId) -> Result<Option<BOC>, SystemError>;
+}
+
+/// A storage node's replica directory, holding one serialized final BOC per
+/// channel in a file named after the hex channel id.
+pub struct DirectoryStorageNode {
+    dir: PathBuf,
+}
+
+impl DirectoryStorageNode {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Writes the final state for the channel `final_state` belongs to.
+    pub fn store_final_state(&self, final_state: &BOC) -> Result<(), SystemError> {
+        let channel_id = FinalChannelState::from_boc(final_state)?.channel_id;
+        fs::create_dir_all(&self.dir).map_err(storage_error)?;
+        fs::write(self.path(&channel_id), final_state.serialize()?).map_err(storage_error)
+    }
+
+    fn path(&self, channel_id: &ChannelId) -> PathBuf {
+        self.dir.join(format!("{}.boc", hex::encode(channel_id)))
+    }
+}
+
+impl SettlementStorage for DirectoryStorageNode {
+    fn final_state(&self, channel_id: &ChannelId) -> Result<Option<BOC>, SystemError> {
+        match fs::read(self.path(channel_id)) {
+            Ok(bytes) => BOC::deserialize(&bytes).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(storage_error(e)),
+        }
+    }
+}
+
+fn storage_error(e: std::io::Error) -> SystemError {
+    SystemError::new(SystemErrorType::StorageError, e.to_string())
+}
+
+/// Produces the proof submitted to the root for a batch of settlements.
+pub trait SettlementProver {
+    /// `settlements` are in the batch's leaf order. The proof must commit to
+    /// the batch root.
+    fn prove(
+        &self,
+        batch: &SettlementBatch,
+        settlements: &[&SettlementState],
+    ) -> Result<ZkProof, SystemError>;
+}
+
+/// Proves a batch with the Plonky2 state transition circuit: the total
+/// balance of the settled channels carries over unchanged, and the proof
+/// commits to the batch root.
+pub struct Plonky2SettlementProver {
+    system: Plonky2System,
+}
+
+impl Plonky2SettlementProver {
+    pub fn new(system: Plonky2System) -> Self {
+        Self { system }
+    }
+}
+
+impl SettlementProver for Plonky2SettlementProver {
+    fn prove(
+        &self,
+        batch: &SettlementBatch,
+        settlements: &[&SettlementState],
+    ) -> Result<ZkProof, SystemError> {
+        let total = settlements
+            .iter()
+            .flat_map(|settlement| settlement.final_balances.values())
+            .try_fold(0u64, |total, balance| total.checked_add(*balance))
+            .ok_or_else(|| {
+                SystemError::new(
+                    SystemErrorType::InvalidAmount,
+                    "Settled balances overflow".to_string(),
+                )
+            })?;
+        let proof_data = self
+            .system
+            .generate_committed_proof(total, 0, total, 1, 0, &batch.root())
+            .map_err(|e| SystemError::new(SystemErrorType::ProofGenerationError, e.to_string()))?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        Ok(ZkProof::new(
+            proof_data,
+            batch.public_inputs(),
+            batch.root().to_vec(),
+            timestamp,
+        ))
+    }
+}
+
+/// What the root currently says about a submitted batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootSettlementDecision {
+    Pending,
+    Confirmed,
+    Finalized,
+    Rejected,
+}
+
+/// Destination for settlement proofs.
+pub trait SettlementSubmitter {
+    /// Submits a batch. An error is retried on the next tick.
+    fn submit(&mut self, batch: &SettlementBatch, proof: &ZkProof) -> Result<(), SystemError>;
+
+    fn decision(&mut self, batch_root: &[u8; 32]) -> Result<RootSettlementDecision, SystemError>;
 }
 
-#[derive(Clone)]
+/// Submits settlement batches to a [`RootContract`] in the same process,
+/// signed with the intermediate's operator key. A batch follows the epoch it
+/// settles with: pending until the epoch is sealed, confirmed once its
+/// global root is submitted, then finalized or rejected with it.
+pub struct InProcessSettlementSubmitter<'a> {
+    root_contract: &'a mut RootContract,
+    contract_addr: Address,
+    operator_key: &'a SigningKey,
+}
+
+impl<'a> InProcessSettlementSubmitter<'a> {
+    pub fn new(
+        root_contract: &'a mut RootContract,
+        contract_addr: Address,
+        operator_key: &'a SigningKey,
+    ) -> Self {
+        Self {
+            root_contract,
+            contract_addr,
+            operator_key,
+        }
+    }
+}
+
+impl SettlementSubmitter for InProcessSettlementSubmitter<'_> {
+    fn submit(&mut self, batch: &SettlementBatch, proof: &ZkProof) -> Result<(), SystemError> {
+        let epoch = self.root_contract.epoch() + 1;
+        let channel_count = batch.len() as u64;
+        let signature = self
+            .operator_key
+            .sign(&settlement_batch_message(
+                &self.contract_addr,
+                epoch,
+                &batch.root(),
+                channel_count,
+            ))
+            .to_bytes();
+        self.root_contract.submit_settlement_batch(
+            self.contract_addr,
+            batch.root(),
+            channel_count,
+            &signature,
+            proof,
+        )
+    }
+
+    fn decision(&mut self, batch_root: &[u8; 32]) -> Result<RootSettlementDecision, SystemError> {
+        let batch = self
+            .root_contract
+            .settlement_batch(batch_root)
+            .ok_or_else(|| {
+                SystemError::new(
+                    SystemErrorType::NotFound,
+                    "Root has no record of the settlement batch".to_string(),
+                )
+            })?;
+        let status = self
+            .root_contract
+            .history()
+            .get(batch.epoch)
+            .map(|record| record.status);
+        Ok(match status {
+            None => RootSettlementDecision::Pending,
+            Some(RootSettlementStatus::Submitted) => RootSettlementDecision::Confirmed,
+            Some(RootSettlementStatus::Finalized) => RootSettlementDecision::Finalized,
+            Some(RootSettlementStatus::Rejected) => RootSettlementDecision::Rejected,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SettlementConfig {
+    /// Storage nodes that must hold the same final state before it is settled.
+    pub quorum: usize,
+    /// Seconds a settlement may wait for quorum and a successful submission.
+    pub verification_timeout: u64,
+    /// Seconds from submission until the root must have finalized it.
+    pub finalization_timeout: u64,
+    /// Most channels settled under a single proof.
+    pub max_batch_size: usize,
+}
+
+impl Default for SettlementConfig {
+    fn default() -> Self {
+        Self {
+            quorum: 2,
+            verification_timeout: 300,
+            finalization_timeout: 3_600,
+            max_batch_size: 1_024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SettlementStatus {
+    /// Collected; waiting for storage-node quorum.
     Pending,
+    /// Proof submitted; waiting for the root to accept it.
     Verifying,
     Confirmed,
     Rejected,
     Finalized,
-    ConfirmedAndFinalized,
 }
 
-impl<RootTree> SettlementIntermediate<RootTree> {
-    pub fn new(
-        root_contract: Arc<RootContract>,
-        storage_nodes: Vec<StorageNode<RootTree, IntermediateTreeManager>>,
-    ) -> SettlementIntermediate<RootTree> {
-        SettlementIntermediate {
-            root_contract,
+impl SettlementStatus {
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Self::Rejected | Self::Finalized)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettlementRejection {
+    /// Too many storage nodes hold a different final state for quorum to be reached.
+    ConflictingState,
+    ProofGenerationFailed,
+    RejectedByRoot,
+    /// The settlement spent too long in the given status.
+    TimedOut(SettlementStatus),
+}
+
+/// What happened during a call to [`SettlementIntermediate::tick`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SettlementEvent {
+    Submitted {
+        channel_id: ChannelId,
+        batch_root: [u8; 32],
+    },
+    Confirmed {
+        channel_id: ChannelId,
+    },
+    Finalized {
+        channel_id: ChannelId,
+    },
+    Rejected {
+        channel_id: ChannelId,
+        reason: SettlementRejection,
+    },
+    /// Submitting a batch or asking the root about it failed. Its channels
+    /// stay where they were and are retried on the next tick.
+    BatchFailed {
+        batch_root: [u8; 32],
+        error: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct SettlementState {
+    pub channel_id: ChannelId,
+    pub final_balances: BTreeMap<[u8; 32], u64>,
+    pub state_root: [u8; 32],
+    pub settlement_boc: BOC,
+    pub status: SettlementStatus,
+    pub rejection: Option<SettlementRejection>,
+    pub created_at: u64,
+    pub submitted_at: Option<u64>,
+    /// Root of the batch this settlement was submitted in.
+    pub batch_root: Option<[u8; 32]>,
+    pub inclusion_proof: Option<BatchInclusionProof>,
+}
+
+impl SettlementState {
+    /// This channel's leaf in a settlement batch.
+    pub fn leaf(&self) -> [u8; 32] {
+        settlement_leaf(&self.channel_id, &self.state_root, &self.final_balances)
+    }
+}
+
+/// Drives channel settlements through
+/// Pending -> Verifying -> Confirmed -> Finalized, or to Rejected.
+///
+/// A final BOC is only proven once `quorum` storage nodes hold an identical
+/// copy. Channels that reach quorum in the same tick are settled together
+/// under one proof and one root submission, and move through the remaining
+/// states as a batch. Settlements that stall in any state past their
+/// timeout are rejected rather than left pending forever.
+pub struct SettlementIntermediate<C: Clock, N: SettlementStorage, P: SettlementProver> {
+    clock: C,
+    config: SettlementConfig,
+    storage_nodes: Vec<N>,
+    prover: P,
+    settlements: BTreeMap<ChannelId, SettlementState>,
+}
+
+impl<C: Clock, N: SettlementStorage, P: SettlementProver> SettlementIntermediate<C, N, P> {
+    pub fn new(clock: C, config: SettlementConfig, storage_nodes: Vec<N>, prover: P) -> Self {
+        Self {
+            clock,
+            config,
             storage_nodes,
-            pending_settlements: HashMap::new(),
-            settlement_proofs: HashMap::new(),
+            prover,
+            settlements: BTreeMap::new(),
         }
     }
 
-    // submit settlement proof
-    pub async fn submit_settlement_proof(
+    /// Starts settling a channel from its final BOC. A channel whose earlier
+    /// settlement was rejected may be settled again.
+    pub fn process_settlement(
         &mut self,
-        channel_id: [u8; 32],
-        settlement_state: SettlementState,
-        proof: ZkProof,
+        channel_id: ChannelId,
+        final_state: BOC,
     ) -> Result<(), SystemError> {
-        // Verify the proof
-        if !verify_proof(&settlement_state, &proof) {
-            return Err(SystemError::InvalidProof);
+        if self.config.quorum == 0 || self.config.quorum > self.storage_nodes.len() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidState,
+                "Settlement quorum cannot be met by the configured storage nodes".to_string(),
+            ));
+        }
+        if let Some(existing) = self.settlements.get(&channel_id) {
+            if existing.status != SettlementStatus::Rejected {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidState,
+                    "Channel already has a settlement".to_string(),
+                ));
+            }
         }
 
-        // Add to pending settlements
-        self.pending_settlements
-            .insert(channel_id, settlement_state);
-
-        // Add to settlement proofs
-        self.settlement_proofs.insert(channel_id, proof);
+        let decoded = FinalChannelState::from_boc(&final_state)?;
+        if decoded.channel_id != channel_id {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Final state belongs to a different channel".to_string(),
+            ));
+        }
 
+        self.settlements.insert(
+            channel_id,
+            SettlementState {
+                channel_id,
+                final_balances: decoded.balances,
+                state_root: final_state.compute_hash(),
+                settlement_boc: final_state,
+                status: SettlementStatus::Pending,
+                rejection: None,
+                created_at: self.clock.now(),
+                submitted_at: None,
+                batch_root: None,
+                inclusion_proof: None,
+            },
+        );
         Ok(())
     }
-    pub async fn process_settlement(
+
+    /// Advances every open settlement as far as the current time allows.
+    ///
+    /// Batches already submitted are polled first; channels that reach
+    /// quorum during this tick are then proven and submitted in batches of
+    /// at most `max_batch_size`. A batch the submitter fails on is reported
+    /// as [`SettlementEvent::BatchFailed`] and does not hold up the others.
+    pub fn tick<S: SettlementSubmitter>(
         &mut self,
-        channel_id: [u8; 32],
-        final_state: BOC,
-    ) -> Result<(), SystemError> {
-        // Verify final state
-        self.verify_final_state(&channel_id, &final_state)?;
+        submitter: &mut S,
+    ) -> Result<Vec<SettlementEvent>, SystemError> {
+        let now = self.clock.now();
+        let mut events = Vec::new();
 
-        // Create settlement state
-        let settlement_state = self.create_settlement_state(channel_id, final_state)?;
+        let submitted_batches: BTreeSet<[u8; 32]> = self
+            .settlements
+            .values()
+            .filter(|settlement| !settlement.status.is_terminal())
+            .filter_map(|settlement| settlement.batch_root)
+            .collect();
+        for batch_root in submitted_batches {
+            self.advance_batch(batch_root, now, submitter, &mut events);
+        }
 
-        // Generate settlement proof
-        let proof = self.generate_settlement_proof(&settlement_state)?;
+        let ready = self.collect_ready(now, &mut events);
+        for chunk in ready.chunks(self.config.max_batch_size.max(1)) {
+            self.submit_batch(chunk, now, submitter, &mut events)?;
+        }
 
-        // Store settlement state and proof
-        self.pending_settlements
-            .insert(channel_id, settlement_state);
-        self.settlement_proofs.insert(channel_id, proof);
+        Ok(events)
+    }
 
-        // Submit to root contract
-        self.submit_settlement(channel_id).await?;
+    /// Rejects pending settlements that timed out or can no longer reach
+    /// quorum, and returns those that have reached it.
+    fn collect_ready(&mut self, now: u64, events: &mut Vec<SettlementEvent>) -> Vec<ChannelId> {
+        let pending: Vec<ChannelId> = self
+            .settlements
+            .values()
+            .filter(|settlement| settlement.status == SettlementStatus::Pending)
+            .map(|settlement| settlement.channel_id)
+            .collect();
 
-        Ok(())
+        let mut ready = Vec::new();
+        for channel_id in pending {
+            let settlement = &self.settlements[&channel_id];
+            if now >= settlement.created_at + self.config.verification_timeout {
+                let reason = SettlementRejection::TimedOut(SettlementStatus::Pending);
+                self.reject(&channel_id, reason, events);
+                continue;
+            }
+
+            let (matching, conflicting) = self.count_replicas(&channel_id, &settlement.state_root);
+            if self.storage_nodes.len() - conflicting < self.config.quorum {
+                self.reject(&channel_id, SettlementRejection::ConflictingState, events);
+            } else if matching >= self.config.quorum {
+                ready.push(channel_id);
+            }
+        }
+        ready
     }
 
-    fn verify_final_state(
-        &self,
-        channel_id: &[u8; 32],
-        final_state: &BOC,
+    fn submit_batch<S: SettlementSubmitter>(
+        &mut self,
+        channels: &[ChannelId],
+        now: u64,
+        submitter: &mut S,
+        events: &mut Vec<SettlementEvent>,
     ) -> Result<(), SystemError> {
-        // Verify state consistency across storage nodes
-        for node in &self.storage_nodes {
-            let stored_state = node.get_channel_state(channel_id)?;
-            if !self.verify_state_consistency(&stored_state, final_state) {
-                return Err(SystemError::InvalidFinalState);
+        let leaves = channels
+            .iter()
+            .map(|channel_id| (*channel_id, self.settlements[channel_id].leaf()))
+            .collect();
+        let batch = SettlementBatch::new(leaves)?;
+
+        let proof = match self.generate_batch_proof(&batch) {
+            Ok(proof) => proof,
+            Err(_) => {
+                for channel_id in channels {
+                    self.reject(
+                        channel_id,
+                        SettlementRejection::ProofGenerationFailed,
+                        events,
+                    );
+                }
+                return Ok(());
             }
+        };
+        // A failed submission leaves the channels pending; they are batched
+        // and proven again on the next tick.
+        let batch_root = batch.root();
+        if let Err(e) = submitter.submit(&batch, &proof) {
+            events.push(SettlementEvent::BatchFailed {
+                batch_root,
+                error: e.message,
+            });
+            return Ok(());
+        }
+
+        for channel_id in batch.channels() {
+            let settlement = self.settlements.get_mut(channel_id).unwrap();
+            settlement.status = SettlementStatus::Verifying;
+            settlement.submitted_at = Some(now);
+            settlement.batch_root = Some(batch_root);
+            settlement.inclusion_proof = batch.inclusion_proof(channel_id);
+            events.push(SettlementEvent::Submitted {
+                channel_id: *channel_id,
+                batch_root,
+            });
         }
         Ok(())
     }
 
-    fn create_settlement_state(
-        &self,
-        channel_id: [u8; 32],
-        final_state: BOC,
-    ) -> Result<SettlementState, SystemError> {
-        let final_balances = self.extract_final_balances(&final_state)?;
-        let state_root = self.calculate_state_root(&final_state)?;
+    fn advance_batch<S: SettlementSubmitter>(
+        &mut self,
+        batch_root: [u8; 32],
+        now: u64,
+        submitter: &mut S,
+        events: &mut Vec<SettlementEvent>,
+    ) {
+        let decision = match submitter.decision(&batch_root) {
+            Ok(decision) => decision,
+            Err(e) => {
+                events.push(SettlementEvent::BatchFailed {
+                    batch_root,
+                    error: e.message,
+                });
+                RootSettlementDecision::Pending
+            }
+        };
+        let members: Vec<ChannelId> = self
+            .settlements
+            .values()
+            .filter(|settlement| {
+                settlement.batch_root == Some(batch_root) && !settlement.status.is_terminal()
+            })
+            .map(|settlement| settlement.channel_id)
+            .collect();
 
-        Ok(SettlementState {
-            channel_id,
-            final_balances,
-            state_root,
-            settlement_boc: final_state,
-            status: SettlementStatus::Pending,
-        })
+        for channel_id in members {
+            let settlement = self.settlements.get_mut(&channel_id).unwrap();
+            match decision {
+                RootSettlementDecision::Rejected => {
+                    self.reject(&channel_id, SettlementRejection::RejectedByRoot, events);
+                    continue;
+                }
+                RootSettlementDecision::Finalized => {
+                    if settlement.status == SettlementStatus::Verifying {
+                        events.push(SettlementEvent::Confirmed { channel_id });
+                    }
+                    settlement.status = SettlementStatus::Finalized;
+                    events.push(SettlementEvent::Finalized { channel_id });
+                    continue;
+                }
+                RootSettlementDecision::Confirmed => {
+                    if settlement.status == SettlementStatus::Verifying {
+                        settlement.status = SettlementStatus::Confirmed;
+                        events.push(SettlementEvent::Confirmed { channel_id });
+                    }
+                }
+                RootSettlementDecision::Pending => {}
+            }
+
+            let submitted_at = settlement.submitted_at.unwrap_or(settlement.created_at);
+            if now >= submitted_at + self.config.finalization_timeout {
+                let reason = SettlementRejection::TimedOut(settlement.status);
+                self.reject(&channel_id, reason, events);
+            }
+        }
     }
 
-    fn generate_settlement_proof(
-        &self,
-        settlement_state: &SettlementState,
-    ) -> Result<ZkProof, SystemError> {
-        let proof_inputs = self.prepare_proof_inputs(settlement_state)?;
-        let proof = ZkProof::generate_settlement_proof(&proof_inputs)?;
+    /// Counts storage nodes whose copy matches `state_root` and those that
+    /// hold a different one. Unreachable nodes and nodes without a copy count
+    /// as neither.
+    fn count_replicas(&self, channel_id: &ChannelId, state_root: &[u8; 32]) -> (usize, usize) {
+        let mut matching = 0;
+        let mut conflicting = 0;
+        for node in &self.storage_nodes {
+            match node.final_state(channel_id) {
+                Ok(Some(stored)) if stored.compute_hash() == *state_root => matching += 1,
+                Ok(Some(_)) => conflicting += 1,
+                Ok(None) | Err(_) => {}
+            }
+        }
+        (matching, conflicting)
+    }
+
+    fn generate_batch_proof(&self, batch: &SettlementBatch) -> Result<ZkProof, SystemError> {
+        let members: Vec<&SettlementState> = batch
+            .channels()
+            .iter()
+            .map(|channel_id| &self.settlements[channel_id])
+            .collect();
+        let proof = self.prover.prove(batch, &members)?;
+        if proof.public_inputs != batch.public_inputs() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Settlement proof does not expose the batch's public inputs".to_string(),
+            ));
+        }
+        if proof.merkle_root != batch.root() || proof.proof_data.is_empty() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Settlement proof does not commit to the batch root".to_string(),
+            ));
+        }
         Ok(proof)
     }
 
-    async fn submit_settlement(&mut self, channel_id: [u8; 32]) -> Result<(), SystemError> {
-        let settlement_state = self
-            .pending_settlements
-            .get(&channel_id)
-            .ok_or(SystemError::SettlementNotFound)?;
-        let proof = self
-            .settlement_proofs
-            .get(&channel_id)
-            .ok_or(SystemError::ProofNotFound)?;
-
-        // Submit settlement
-        self.root_contract
-            .submit_settlement_proof(channel_id, settlement_state.clone(), proof.clone())
-            .await?;
-        self.settlement_proofs.remove(&channel_id);
-        self.pending_settlements.remove(&channel_id);
-        Ok(())
+    fn reject(
+        &mut self,
+        channel_id: &ChannelId,
+        reason: SettlementRejection,
+        events: &mut Vec<SettlementEvent>,
+    ) {
+        if let Some(settlement) = self.settlements.get_mut(channel_id) {
+            settlement.status = SettlementStatus::Rejected;
+            settlement.rejection = Some(reason);
+        }
+        events.push(SettlementEvent::Rejected {
+            channel_id: *channel_id,
+            reason,
+        });
     }
-    fn verify_state_consistency(&self, stored_state: &BOC, final_state: &BOC) -> bool {
-        stored_state.merkle_root == final_state.merkle_root
+
+    /// The batch root a submitted channel was settled under, with the proof
+    /// that its final balances are part of that batch.
+    pub fn get_inclusion_proof(
+        &self,
+        channel_id: &ChannelId,
+    ) -> Option<([u8; 32], &BatchInclusionProof)> {
+        let settlement = self.settlements.get(channel_id)?;
+        Some((settlement.batch_root?, settlement.inclusion_proof.as_ref()?))
+    }
+
+    pub fn get_settlement(&self, channel_id: &ChannelId) -> Option<&SettlementState> {
+        self.settlements.get(channel_id)
     }
 
-    fn extract_final_balances(&self, state: &BOC) -> Result<HashMap<[u8; 32], u64>, SystemError> {
-        let mut balances = HashMap::new();
-        let state_data = state.deserialize_state()?;
+    pub fn get_settlement_status(&self, channel_id: &ChannelId) -> Option<SettlementStatus> {
+        self.settlements
+            .get(channel_id)
+            .map(|settlement| settlement.status)
+    }
+
+    pub fn get_final_balances(&self, channel_id: &ChannelId) -> Option<&BTreeMap<[u8; 32], u64>> {
+        self.settlements
+            .get(channel_id)
+            .map(|settlement| &settlement.final_balances)
+    }
+
+    /// Removes and returns every finalized or rejected settlement.
+    pub fn take_completed(&mut self) -> Vec<SettlementState> {
+        let completed: Vec<ChannelId> = self
+            .settlements
+            .iter()
+            .filter(|(_, settlement)| settlement.status.is_terminal())
+            .map(|(channel_id, _)| *channel_id)
+            .collect();
+        completed
+            .iter()
+            .filter_map(|channel_id| self.settlements.remove(channel_id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::hierarchy::clock::ManualClock;
+    use crate::core::hierarchy::root::global_tree_manager::RootTransitionVerifier;
+    use crate::core::hierarchy::root::intermediate_registry::{
+        registration_message, IntermediateRegistration, IntermediateStatus,
+    };
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Node(Rc<RefCell<HashMap<ChannelId, BOC>>>);
 
-        for (participant, balance) in state_data.balances {
-            balances.insert(participant, balance);
+    impl Node {
+        fn store(&self, boc: &BOC) {
+            let state = FinalChannelState::from_boc(boc).unwrap();
+            self.0.borrow_mut().insert(state.channel_id, boc.clone());
         }
+    }
 
-        Ok(balances)
+    impl SettlementStorage for Node {
+        fn final_state(&self, channel_id: &ChannelId) -> Result<Option<BOC>, SystemError> {
+            Ok(self.0.borrow().get(channel_id).cloned())
+        }
     }
 
-    fn calculate_state_root(&self, state: &BOC) -> Result<[u8; 32], SystemError> {
-        let mut hasher = sha2::Sha256::new();
-        hasher.update(&state.serialize()?);
-        let mut root = [0u8; 32];
-        root.copy_from_slice(&hasher.finalize());
-        Ok(root)
+    struct BoundProver;
+
+    impl SettlementProver for BoundProver {
+        fn prove(
+            &self,
+            batch: &SettlementBatch,
+            settlements: &[&SettlementState],
+        ) -> Result<ZkProof, SystemError> {
+            assert_eq!(settlements.len(), batch.len());
+            Ok(ZkProof::new(
+                vec![1],
+                batch.public_inputs(),
+                batch.root().to_vec(),
+                0,
+            ))
+        }
     }
 
-    fn prepare_proof_inputs(
-        &self,
-        settlement_state: &SettlementState,
-    ) -> Result<Vec<u8>, SystemError> {
-        let mut inputs = Vec::new();
+    /// Exposes the channel count one too high.
+    struct MiscountingProver;
+
+    impl SettlementProver for MiscountingProver {
+        fn prove(
+            &self,
+            batch: &SettlementBatch,
+            _settlements: &[&SettlementState],
+        ) -> Result<ZkProof, SystemError> {
+            let mut public_inputs = batch.public_inputs();
+            *public_inputs.last_mut().unwrap() += 1;
+            Ok(ZkProof::new(
+                vec![1],
+                public_inputs,
+                batch.root().to_vec(),
+                0,
+            ))
+        }
+    }
+
+    #[derive(Default)]
+    struct Root {
+        submitted: Vec<SettlementBatch>,
+        decisions: HashMap<[u8; 32], RootSettlementDecision>,
+        /// Batches whose decision cannot be fetched.
+        unreachable: BTreeSet<[u8; 32]>,
+    }
+
+    impl Root {
+        fn decide(&mut self, batch: usize, decision: RootSettlementDecision) {
+            let batch_root = self.submitted[batch].root();
+            self.decisions.insert(batch_root, decision);
+        }
+    }
+
+    impl SettlementSubmitter for Root {
+        fn submit(&mut self, batch: &SettlementBatch, _proof: &ZkProof) -> Result<(), SystemError> {
+            self.submitted.push(batch.clone());
+            Ok(())
+        }
+
+        fn decision(
+            &mut self,
+            batch_root: &[u8; 32],
+        ) -> Result<RootSettlementDecision, SystemError> {
+            if self.unreachable.contains(batch_root) {
+                return Err(SystemError::new(
+                    SystemErrorType::NetworkError,
+                    "unreachable".to_string(),
+                ));
+            }
+            Ok(self
+                .decisions
+                .get(batch_root)
+                .copied()
+                .unwrap_or(RootSettlementDecision::Pending))
+        }
+    }
 
-        // Add channel ID
-        inputs.extend_from_slice(&settlement_state.channel_id);
+    const INTERMEDIATE: Address = [9; 32];
 
-        // Add state root
-        inputs.extend_from_slice(&settlement_state.state_root);
+    /// Root proofs are not exercised by settlement.
+    struct NoRootProofs;
 
-        // Add balances
-        for (participant, balance) in &settlement_state.final_balances {
-            inputs.extend_from_slice(participant);
-            inputs.extend_from_slice(&balance.to_le_bytes());
+    impl RootTransitionVerifier for NoRootProofs {
+        fn verify_transition(
+            &self,
+            _proof: &ZkProof,
+            _old_root: &[u8; 32],
+            _sequence: u64,
+            _commitment: &[u8; 32],
+        ) -> Result<(), SystemError> {
+            Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "unexpected root proof".to_string(),
+            ))
         }
+    }
 
-        Ok(inputs)
+    fn root_contract(operator: &SigningKey) -> RootContract {
+        let mut root_contract = RootContract::new(10, NoRootProofs, Plonky2System::new().unwrap())
+            .with_settlement_verifier(Plonky2System::new().unwrap());
+        let registration = IntermediateRegistration {
+            contract_addr: INTERMEDIATE,
+            operator_key: operator.verifying_key().to_bytes(),
+            key_version: 0,
+            stake: 0,
+            capacity: 100,
+            registered_at: 0,
+            status: IntermediateStatus::Active,
+        };
+        let signature = operator
+            .sign(&registration_message(&registration))
+            .to_bytes();
+        root_contract
+            .register_intermediate(registration, &signature)
+            .unwrap();
+        root_contract
     }
 
-    pub fn get_settlement_status(&self, channel_id: &[u8; 32]) -> Option<SettlementStatus> {
-        self.pending_settlements
-            .get(channel_id)
-            .map(|s| s.status.clone())
+    fn final_state(channel: u8, balance: u64) -> BOC {
+        FinalChannelState {
+            channel_id: [channel; 32],
+            nonce: 1,
+            balances: BTreeMap::from([([1; 32], balance), ([2; 32], 10)]),
+        }
+        .to_boc()
+        .unwrap()
     }
 
-    pub fn get_final_balances(&self, channel_id: &[u8; 32]) -> Option<HashMap<[u8; 32], u64>> {
-        self.pending_settlements
-            .get(channel_id)
-            .map(|s| s.final_balances.clone())
+    fn setup(
+        nodes: usize,
+        max_batch_size: usize,
+    ) -> (
+        ManualClock,
+        Vec<Node>,
+        SettlementIntermediate<ManualClock, Node, BoundProver>,
+    ) {
+        setup_with(nodes, max_batch_size, BoundProver)
+    }
+
+    fn setup_with<P: SettlementProver>(
+        nodes: usize,
+        max_batch_size: usize,
+        prover: P,
+    ) -> (
+        ManualClock,
+        Vec<Node>,
+        SettlementIntermediate<ManualClock, Node, P>,
+    ) {
+        let clock = ManualClock::new(1_000);
+        let nodes: Vec<Node> = (0..nodes).map(|_| Node::default()).collect();
+        let config = SettlementConfig {
+            quorum: 2,
+            verification_timeout: 60,
+            finalization_timeout: 600,
+            max_batch_size,
+        };
+        let settlement = SettlementIntermediate::new(clock.clone(), config, nodes.clone(), prover);
+        (clock, nodes, settlement)
+    }
+
+    #[test]
+    fn test_settlement_moves_through_to_finalized() {
+        let (_, nodes, mut settlement) = setup(3, 16);
+        let boc = final_state(1, 90);
+        settlement.process_settlement([1; 32], boc.clone()).unwrap();
+        assert!(settlement.process_settlement([1; 32], boc.clone()).is_err());
+
+        let mut root = Root::default();
+        nodes[0].store(&boc);
+        assert!(settlement.tick(&mut root).unwrap().is_empty());
+        assert_eq!(
+            settlement.get_settlement_status(&[1; 32]),
+            Some(SettlementStatus::Pending)
+        );
+
+        nodes[2].store(&boc);
+        let events = settlement.tick(&mut root).unwrap();
+        assert_eq!(root.submitted.len(), 1);
+        assert_eq!(
+            events,
+            vec![SettlementEvent::Submitted {
+                channel_id: [1; 32],
+                batch_root: root.submitted[0].root(),
+            }]
+        );
+
+        root.decide(0, RootSettlementDecision::Confirmed);
+        settlement.tick(&mut root).unwrap();
+        assert_eq!(
+            settlement.get_settlement_status(&[1; 32]),
+            Some(SettlementStatus::Confirmed)
+        );
+
+        root.decide(0, RootSettlementDecision::Finalized);
+        assert_eq!(
+            settlement.tick(&mut root).unwrap(),
+            vec![SettlementEvent::Finalized {
+                channel_id: [1; 32]
+            }]
+        );
+        assert_eq!(
+            settlement.get_final_balances(&[1; 32]).unwrap()[&[1; 32]],
+            90
+        );
+        assert_eq!(settlement.take_completed().len(), 1);
+        assert!(settlement.get_settlement(&[1; 32]).is_none());
+    }
+
+    #[test]
+    fn test_channels_settle_in_batches_with_inclusion_proofs() {
+        let (_, nodes, mut settlement) = setup(2, 3);
+        let mut root = Root::default();
+        for channel in 1..=5u8 {
+            let boc = final_state(channel, u64::from(channel) * 10);
+            nodes.iter().for_each(|node| node.store(&boc));
+            settlement.process_settlement([channel; 32], boc).unwrap();
+        }
+
+        settlement.tick(&mut root).unwrap();
+        let sizes: Vec<usize> = root.submitted.iter().map(SettlementBatch::len).collect();
+        assert_eq!(sizes, vec![3, 2]);
+
+        for channel in 1..=5u8 {
+            let (batch_root, proof) = settlement.get_inclusion_proof(&[channel; 32]).unwrap();
+            let leaf = settlement.get_settlement(&[channel; 32]).unwrap().leaf();
+            assert!(proof.verify(&batch_root, &leaf));
+        }
+
+        // A decision on one batch moves only its own channels.
+        root.decide(1, RootSettlementDecision::Rejected);
+        let events = settlement.tick(&mut root).unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| matches!(
+            event,
+            SettlementEvent::Rejected {
+                reason: SettlementRejection::RejectedByRoot,
+                ..
+            }
+        )));
+        assert_eq!(
+            settlement.get_settlement_status(&[1; 32]),
+            Some(SettlementStatus::Verifying)
+        );
+    }
+
+    #[test]
+    fn test_conflicting_replicas_reject_settlement() {
+        let (_, nodes, mut settlement) = setup(3, 16);
+        settlement
+            .process_settlement([1; 32], final_state(1, 90))
+            .unwrap();
+        nodes[0].store(&final_state(1, 50));
+        nodes[1].store(&final_state(1, 50));
+
+        let events = settlement.tick(&mut Root::default()).unwrap();
+        assert_eq!(
+            events,
+            vec![SettlementEvent::Rejected {
+                channel_id: [1; 32],
+                reason: SettlementRejection::ConflictingState,
+            }]
+        );
+
+        // A rejected channel can be settled again with the agreed state.
+        settlement
+            .process_settlement([1; 32], final_state(1, 50))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_timeouts_surface_as_events() {
+        let (clock, nodes, mut settlement) = setup(2, 16);
+        let mut root = Root::default();
+
+        settlement
+            .process_settlement([1; 32], final_state(1, 90))
+            .unwrap();
+        clock.advance(60);
+        assert_eq!(
+            settlement.tick(&mut root).unwrap(),
+            vec![SettlementEvent::Rejected {
+                channel_id: [1; 32],
+                reason: SettlementRejection::TimedOut(SettlementStatus::Pending),
+            }]
+        );
+
+        let boc = final_state(2, 90);
+        nodes.iter().for_each(|node| node.store(&boc));
+        settlement.process_settlement([2; 32], boc).unwrap();
+        settlement.tick(&mut root).unwrap();
+
+        clock.advance(600);
+        assert_eq!(
+            settlement.tick(&mut root).unwrap(),
+            vec![SettlementEvent::Rejected {
+                channel_id: [2; 32],
+                reason: SettlementRejection::TimedOut(SettlementStatus::Verifying),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_proof_must_expose_batch_public_inputs() {
+        let (_, nodes, mut settlement) = setup_with(2, 16, MiscountingProver);
+        let boc = final_state(1, 90);
+        nodes.iter().for_each(|node| node.store(&boc));
+        settlement.process_settlement([1; 32], boc).unwrap();
+
+        let mut root = Root::default();
+        assert_eq!(
+            settlement.tick(&mut root).unwrap(),
+            vec![SettlementEvent::Rejected {
+                channel_id: [1; 32],
+                reason: SettlementRejection::ProofGenerationFailed,
+            }]
+        );
+        assert!(root.submitted.is_empty());
+    }
+
+    #[test]
+    fn test_unreachable_batch_does_not_block_others() {
+        let (_, nodes, mut settlement) = setup(2, 1);
+        let mut root = Root::default();
+        for channel in 1..=2u8 {
+            let boc = final_state(channel, 90);
+            nodes.iter().for_each(|node| node.store(&boc));
+            settlement.process_settlement([channel; 32], boc).unwrap();
+        }
+        settlement.tick(&mut root).unwrap();
+
+        let unreachable = root.submitted[0].root();
+        root.unreachable.insert(unreachable);
+        root.decide(1, RootSettlementDecision::Confirmed);
+        let events = settlement.tick(&mut root).unwrap();
+        assert!(events.contains(&SettlementEvent::BatchFailed {
+            batch_root: unreachable,
+            error: "unreachable".to_string(),
+        }));
+        assert!(events.contains(&SettlementEvent::Confirmed {
+            channel_id: [2; 32]
+        }));
+        assert_eq!(
+            settlement.get_settlement_status(&[1; 32]),
+            Some(SettlementStatus::Verifying)
+        );
+    }
+
+    #[test]
+    fn test_settles_through_storage_directories_and_root_contract() {
+        let dir = std::env::temp_dir().join(format!("settlement_{}", uuid::Uuid::new_v4()));
+        let nodes: Vec<_> = (0..2)
+            .map(|i| DirectoryStorageNode::new(dir.join(format!("node{}", i))))
+            .collect();
+        let boc = final_state(1, 90);
+        for node in &nodes {
+            assert!(node.final_state(&[1; 32]).unwrap().is_none());
+            node.store_final_state(&boc).unwrap();
+        }
+
+        let operator = SigningKey::from_bytes(&[1; 32]);
+        let mut root_contract = root_contract(&operator);
+        let clock = ManualClock::new(1_000);
+        let prover = Plonky2SettlementProver::new(Plonky2System::new().unwrap());
+        let mut settlement =
+            SettlementIntermediate::new(clock.clone(), SettlementConfig::default(), nodes, prover);
+        settlement.process_settlement([1; 32], boc).unwrap();
+
+        let mut submitter =
+            InProcessSettlementSubmitter::new(&mut root_contract, INTERMEDIATE, &operator);
+        let events = settlement.tick(&mut submitter).unwrap();
+        assert!(matches!(events[..], [SettlementEvent::Submitted { .. }]));
+        assert!(settlement.tick(&mut submitter).unwrap().is_empty());
+
+        root_contract.seal_epoch(10).unwrap();
+        let mut submitter =
+            InProcessSettlementSubmitter::new(&mut root_contract, INTERMEDIATE, &operator);
+        settlement.tick(&mut submitter).unwrap();
+        assert_eq!(
+            settlement.get_settlement_status(&[1; 32]),
+            Some(SettlementStatus::Confirmed)
+        );
+
+        root_contract
+            .history_mut()
+            .update_status(1, RootSettlementStatus::Finalized)
+            .unwrap();
+        let mut submitter =
+            InProcessSettlementSubmitter::new(&mut root_contract, INTERMEDIATE, &operator);
+        settlement.tick(&mut submitter).unwrap();
+        assert_eq!(
+            settlement.get_settlement_status(&[1; 32]),
+            Some(SettlementStatus::Finalized)
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -1,220 +1,548 @@
+// ./src/core/hierarchy/intermediate/sparse_merkle_tree_i.rs
+
 use crate::core::error::errors::{SystemError, SystemErrorType};
-use crate::core::hierarchy::client::channel::channel_contract::{Cell, CellType};
+use crate::core::hierarchy::merkle_bits::{flip_bit, get_bit, prefix, set_bit};
 use crate::core::types::boc::BOC;
-use plonky2::field::goldilocks_field::GoldilocksField;
-use plonky2::iop::target::Target;
-use plonky2::plonk::circuit_builder::CircuitBuilder;
-use plonky2::plonk::circuit_data::CircuitConfig;
-use plonky2_field::types::Field;
-use sha2::{Digest, Sha256};
-use std::collections::HashMap;
-
-/// Intermediate Tree Trait
-pub trait IntermediateTreeManagerTrait {
-    fn update_trees(
-        &mut self,
-        boc: &BOC,
-        intermediate_trees: &mut HashMap<[u8; 32], SparseMerkleTreeI>,
-        root_trees: &mut HashMap<[u8; 32], SparseMerkleTreeI>,
-    ) -> Result<(), SystemError>;
+use crate::core::zkps::wallet_root_circuit::{
+    empty_subtree_hashes, hash_from_bytes, hash_to_bytes, wallet_leaf_hash, wallet_node_hash,
+    WalletRootUpdate, WalletTreeHash, WALLET_TREE_DEPTH,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+const LEAF_CELL: u8 = 0x00;
+const NODE_CELL: u8 = 0x01;
+const LEAF_CELL_LEN: usize = 1 + 32 + 32;
+const NODE_CELL_LEN: usize = 1 + 2 + 32 + 32;
+
+/// Merkle path for a single wallet.
+///
+/// Uses the same layout as the root tree's proofs: bit `i` of `bitmap` is set
+/// when the sibling at depth `i + 1` is stored in `siblings`, which is
+/// ordered from the root downwards; every other sibling is an empty subtree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletMerkleProof {
+    pub bitmap: [u8; 32],
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl WalletMerkleProof {
+    /// Every sibling from the leaf level up, empty subtrees included, as
+    /// [`WalletRootUpdate::siblings`] expects them. Returns `None` if the
+    /// proof is malformed.
+    pub fn full_path(&self) -> Option<Vec<[u8; 32]>> {
+        let defaults = default_hashes();
+        let mut stored = self.siblings.iter().rev();
+        let mut path = Vec::with_capacity(WALLET_TREE_DEPTH);
+        for depth in (0..WALLET_TREE_DEPTH).rev() {
+            if get_bit(&self.bitmap, depth) {
+                path.push(*stored.next()?);
+            } else {
+                path.push(hash_to_bytes(&defaults[depth + 1]));
+            }
+        }
+        if stored.next().is_some() {
+            return None;
+        }
+        Some(path)
+    }
+
+    /// Recomputes the root from `wallet_id` and the hash of its leaf slot.
+    pub fn compute_root(&self, wallet_id: &[u8; 32], leaf: WalletTreeHash) -> Option<[u8; 32]> {
+        let mut current = leaf;
+        for (height, sibling) in self.full_path()?.iter().enumerate() {
+            let sibling = hash_from_bytes(sibling).ok()?;
+            current = if get_bit(wallet_id, WALLET_TREE_DEPTH - 1 - height) {
+                wallet_node_hash(&sibling, &current)
+            } else {
+                wallet_node_hash(&current, &sibling)
+            };
+        }
+        Some(hash_to_bytes(&current))
+    }
 }
 
-type VirtualCell = Target;
-
-/// Merkle Tree Node
-pub struct MerkleNode {
-    pub left: Option<[u8; 32]>,
-    pub right: Option<[u8; 32]>,
-    pub hash: Option<[u8; 32]>,
-    pub virtual_cell: Option<VirtualCell>,
-    pub value: Option<[u8; 32]>,
-    pub is_leaf: bool,
-    pub is_virtual: bool,
-    pub is_empty: bool,
-    pub(crate) data: Option<Vec<u8>>,
+/// Verifies a proof against an intermediate root.
+///
+/// With `Some(wallet_root)` this checks that the wallet maps to that root;
+/// with `None` it checks that the wallet is absent from the tree.
+pub fn verify(
+    root: &[u8; 32],
+    wallet_id: &[u8; 32],
+    wallet_root: Option<&[u8; 32]>,
+    proof: &WalletMerkleProof,
+) -> bool {
+    let leaf = wallet_leaf_hash(wallet_id, wallet_root.unwrap_or(&[0u8; 32]));
+    proof.compute_root(wallet_id, leaf).as_ref() == Some(root)
 }
 
-/// Sparse Merkle Tree Implementation
+/// Sparse Merkle Tree of wallet roots keyed by wallet id.
+///
+/// Hashes with the wallet root circuit's Poseidon leaf and node functions,
+/// so the root is the one [`WalletRootBatchCircuit`] proves transitions of.
+/// Only children of branch nodes (nodes whose two subtrees are both
+/// non-empty) are stored: those are exactly the non-empty siblings any path
+/// can need, and there are fewer than two per wallet. Anything else is
+/// rebuilt from the nodes and leaves beneath it when first needed.
+///
+/// [`WalletRootBatchCircuit`]: crate::core::zkps::wallet_root_circuit::WalletRootBatchCircuit
+#[derive(Debug, Clone)]
 pub struct SparseMerkleTreeI {
-    circuit_builder: CircuitBuilder<GoldilocksField, 2>,
-    root_hash: [u8; 32],
-    nodes: HashMap<[u8; 32], MerkleNode>,
-    height: usize,
-    virtual_cells: HashMap<VirtualCell, MerkleNode>,
-    virtual_cell_count: usize,
-    current_virtual_cell: VirtualCell,
-    current_virtual_cell_count: usize,
+    root_hash: WalletTreeHash,
+    nodes: HashMap<(usize, [u8; 32]), WalletTreeHash>,
+    leaves: BTreeMap<[u8; 32], [u8; 32]>,
 }
 
 impl SparseMerkleTreeI {
     /// Create a new Sparse Merkle Tree
     pub fn new() -> Self {
-        let config = CircuitConfig::standard_recursion_config();
-        let circuit_builder = CircuitBuilder::new(config);
-
         Self {
-            circuit_builder,
-            root_hash: [0u8; 32],
+            root_hash: default_hashes()[0],
             nodes: HashMap::new(),
-            height: 256,
-            virtual_cells: HashMap::new(),
-            virtual_cell_count: 0,
-            current_virtual_cell: Target::wire(0, 0),
-            current_virtual_cell_count: 0,
-        }
-    }
-
-    /// Update a leaf in the Merkle tree
-    pub fn update(&mut self, key: &[u8], value: &[u8]) -> Result<(), SystemError> {
-        let leaf_hash = self.hash_leaf(key, value);
-        let path = self.generate_merkle_path(key)?;
-        let _value_field = self.hash_to_field(&leaf_hash);
-        let _value_cell = self.circuit_builder.add_virtual_public_input();
-        let _key_field = self.hash_to_field(&self.hash_leaf(key, &[]));
-        let _key_cell = self.circuit_builder.add_virtual_public_input();
-        self.add_path_constraints(&path, _key_cell, _value_cell)?;
-        self.root_hash = self.calculate_new_root(&path, &leaf_hash)?;
-        Ok(())
-    }
-
-    pub fn add_virtual_public_input(&mut self) -> Target {
-        self.circuit_builder.add_virtual_public_input()
+            leaves: BTreeMap::new(),
+        }
     }
 
-    /// Add constraints to the path in the zk-SNARK circuit
-    fn add_path_constraints(
+    /// Set a wallet's root; an all-zero root removes the wallet
+    pub fn update(
         &mut self,
-        path: &[([u8; 32], bool)],
-        _key_cell: Target,
-        _value_cell: Target,
+        wallet_id: &[u8; 32],
+        wallet_root: &[u8; 32],
     ) -> Result<(), SystemError> {
-        let mut current = self.circuit_builder.add_virtual_target();
-
-        for (sibling, is_left) in path {
-            let _sibling_field = self.hash_to_field(sibling);
-            let sibling_cell = self.circuit_builder.add_virtual_public_input();
+        self.update_batch(&[(*wallet_id, *wallet_root)])
+    }
 
-            let _cells = if *is_left {
-                [current, sibling_cell]
+    /// Apply many updates, recomputing each node shared by their paths once.
+    /// A wallet that appears more than once takes its last root.
+    pub fn update_batch(&mut self, updates: &[([u8; 32], [u8; 32])]) -> Result<(), SystemError> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+        let mut level: BTreeMap<[u8; 32], WalletTreeHash> = BTreeMap::new();
+        for (wallet_id, wallet_root) in updates {
+            if *wallet_root == [0u8; 32] {
+                self.leaves.remove(wallet_id);
             } else {
-                [sibling_cell, current]
-            };
-            current = self.circuit_builder.add_virtual_target();
+                self.leaves.insert(*wallet_id, *wallet_root);
+            }
+            level.insert(*wallet_id, wallet_leaf_hash(wallet_id, wallet_root));
         }
 
-        let root_cell = self.circuit_builder.add_virtual_public_input();
-        let is_equal = self.circuit_builder.is_equal(current, root_cell);
-        let bool_target = self.circuit_builder.add_virtual_target();
-        self.circuit_builder.connect(is_equal.target, bool_target);
+        let defaults = default_hashes();
+        for depth in (0..WALLET_TREE_DEPTH).rev() {
+            let mut parents = BTreeMap::new();
+            for position in level.keys() {
+                let parent = prefix(position, depth);
+                if parents.contains_key(&parent) {
+                    continue;
+                }
+                let left = parent;
+                let mut right = parent;
+                set_bit(&mut right, depth);
+                let [left_hash, right_hash] = [left, right].map(|child| {
+                    level
+                        .get(&child)
+                        .copied()
+                        .unwrap_or_else(|| self.node_hash(depth + 1, &child))
+                });
 
-        Ok(())
-    }
-
-    /// Generate Merkle path for a given key
-    fn generate_merkle_path(&self, key: &[u8]) -> Result<Vec<([u8; 32], bool)>, SystemError> {
-        let mut path = Vec::new();
-        let mut current = self.root_hash;
-
-        for i in 0..self.height {
-            let bit = self.get_bit(key, i);
-            let node = self.nodes.get(&current).ok_or(SystemError {
-                error_type: SystemErrorType::NotFound,
-                message: "Node not found in path".to_string(),
-            })?;
-
-            if bit {
-                let right_hash = node.right.ok_or_else(|| SystemError {
-                    error_type: SystemErrorType::NotFound,
-                    message: "Invalid path".to_string(),
-                })?;
-                path.push((right_hash, true));
-                current = right_hash;
-            } else {
-                let left_hash = node.left.ok_or_else(|| SystemError {
-                    error_type: SystemErrorType::NotFound,
-                    message: "Invalid path".to_string(),
-                })?;
-                path.push((left_hash, false));
-                current = left_hash;
+                if left_hash != defaults[depth + 1] && right_hash != defaults[depth + 1] {
+                    self.nodes.insert((depth + 1, left), left_hash);
+                    self.nodes.insert((depth + 1, right), right_hash);
+                } else {
+                    self.nodes.remove(&(depth + 1, left));
+                    self.nodes.remove(&(depth + 1, right));
+                }
+                parents.insert(parent, wallet_node_hash(&left_hash, &right_hash));
             }
+            level = parents;
         }
 
-        Ok(path)
+        self.root_hash = level[&[0u8; 32]];
+        Ok(())
     }
 
-    /// Hash a leaf node
-    fn hash_leaf(&self, key: &[u8], value: &[u8]) -> [u8; 32] {
-        let mut hasher = Sha256::new();
-        hasher.update(key);
-        hasher.update(value);
-        hasher.finalize().into()
+    /// Apply updates one at a time, recording for each the path the wallet
+    /// root circuit needs to prove it against the tree left by the previous
+    /// one.
+    pub fn apply_with_witnesses(
+        &mut self,
+        updates: &[([u8; 32], [u8; 32])],
+    ) -> Result<Vec<WalletRootUpdate>, SystemError> {
+        let mut witnesses = Vec::with_capacity(updates.len());
+        for (wallet_id, new_root) in updates {
+            let siblings = self
+                .generate_merkle_path(wallet_id)
+                .full_path()
+                .expect("generated paths are well formed");
+            witnesses.push(WalletRootUpdate {
+                wallet_id: *wallet_id,
+                old_root: self.get(wallet_id).unwrap_or([0u8; 32]),
+                new_root: *new_root,
+                siblings,
+            });
+            self.update(wallet_id, new_root)?;
+        }
+        Ok(witnesses)
     }
 
-    /// Calculate the new root hash after updating a leaf
-    fn calculate_new_root(
-        &self,
-        path: &[([u8; 32], bool)],
-        leaf_hash: &[u8; 32],
-    ) -> Result<[u8; 32], SystemError> {
-        let mut current = *leaf_hash;
-
-        for (sibling, is_left) in path.iter().rev() {
-            let mut hasher = Sha256::new();
-            if *is_left {
-                hasher.update(current);
-                hasher.update(sibling);
-            } else {
-                hasher.update(sibling);
-                hasher.update(current);
-            }
-            current = hasher.finalize().into();
+    /// Remove a wallet, returning its previous root
+    pub fn remove(&mut self, wallet_id: &[u8; 32]) -> Result<Option<[u8; 32]>, SystemError> {
+        let previous = self.get(wallet_id);
+        if previous.is_some() {
+            self.update(wallet_id, &[0u8; 32])?;
         }
+        Ok(previous)
+    }
 
-        Ok(current)
+    pub fn get(&self, wallet_id: &[u8; 32]) -> Option<[u8; 32]> {
+        self.leaves.get(wallet_id).copied()
     }
 
-    /// Extract a bit from the key at a specific index
-    fn get_bit(&self, key: &[u8], index: usize) -> bool {
-        let byte_index = index / 8;
-        let bit_index = 7 - (index % 8);
-        if byte_index < key.len() {
-            (key[byte_index] >> bit_index) & 1 == 1
-        } else {
-            false
-        }
+    /// Number of wallets in the tree
+    pub fn len(&self) -> usize {
+        self.leaves.len()
     }
 
-    /// Convert hash to a field element
-    fn hash_to_field(&self, bytes: &[u8; 32]) -> GoldilocksField {
-        let mut array = [0u8; 8];
-        array.copy_from_slice(&bytes[0..8]);
-        let num = u64::from_le_bytes(array);
-        GoldilocksField::from_canonical_u64(num)
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
     }
 
     /// Return the current root hash of the tree
     pub fn root(&self) -> [u8; 32] {
-        self.root_hash
+        hash_to_bytes(&self.root_hash)
+    }
+
+    /// Proof that the wallet is present; fails if it is not
+    pub fn prove_inclusion(&self, wallet_id: &[u8; 32]) -> Result<WalletMerkleProof, SystemError> {
+        if !self.leaves.contains_key(wallet_id) {
+            return Err(SystemError::new(
+                SystemErrorType::NotFound,
+                "Wallet not present in intermediate tree".to_string(),
+            ));
+        }
+        Ok(self.generate_merkle_path(wallet_id))
+    }
+
+    /// Proof that the wallet is absent; fails if it is present
+    pub fn prove_non_inclusion(
+        &self,
+        wallet_id: &[u8; 32],
+    ) -> Result<WalletMerkleProof, SystemError> {
+        if self.leaves.contains_key(wallet_id) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Wallet is present in intermediate tree".to_string(),
+            ));
+        }
+        Ok(self.generate_merkle_path(wallet_id))
     }
 
     /// Serialize the tree state to a BOC format
+    ///
+    /// Leaf cells are `0x00 || wallet id || wallet root` and node cells are
+    /// `0x01 || depth (u16 LE) || prefix || hash`. The BOC root is the tree
+    /// root and its hash is [`BOC::compute_hash`], a checksum against
+    /// corruption. Reloading recomputes every node from the leaves, so the
+    /// stored node hashes are only compared, never trusted.
     pub fn serialize_state(&self) -> Result<BOC, SystemError> {
-        let mut boc = BOC::new();
-        let root_cell = Cell::new(vec![], vec![], CellType::Ordinary, self.root_hash, None);
-        let mut node_cells = Vec::new();
-
-        for (hash, node) in &self.nodes {
-            let mut node_data = Vec::new();
-            if let Some(left) = node.left {
-                node_data.extend_from_slice(&left);
+        let mut cells = Vec::with_capacity(self.leaves.len() + self.nodes.len());
+        for (wallet_id, wallet_root) in &self.leaves {
+            let mut cell = Vec::with_capacity(LEAF_CELL_LEN);
+            cell.push(LEAF_CELL);
+            cell.extend_from_slice(wallet_id);
+            cell.extend_from_slice(wallet_root);
+            cells.push(cell);
+        }
+
+        let mut nodes: Vec<_> = self.nodes.iter().collect();
+        nodes.sort_unstable_by_key(|(position, _)| **position);
+        for ((depth, position), hash) in nodes {
+            let mut cell = Vec::with_capacity(NODE_CELL_LEN);
+            cell.push(NODE_CELL);
+            cell.extend_from_slice(&(*depth as u16).to_le_bytes());
+            cell.extend_from_slice(position);
+            cell.extend_from_slice(&hash_to_bytes(hash));
+            cells.push(cell);
+        }
+
+        let mut boc = BOC::new()
+            .with_cells(cells)
+            .with_roots(vec![self.root().to_vec()]);
+        let checksum = boc.compute_hash();
+        boc.set_hash(checksum);
+        Ok(boc)
+    }
+
+    /// Rebuild a tree from a BOC written by [`serialize_state`](Self::serialize_state)
+    pub fn deserialize_state(boc: &BOC) -> Result<Self, SystemError> {
+        let malformed =
+            |message: &str| SystemError::new(SystemErrorType::InvalidInput, message.to_string());
+        if boc.hash.is_some_and(|hash| hash != boc.compute_hash()) {
+            return Err(SystemError::new(
+                SystemErrorType::StateDataMismatch,
+                "Intermediate tree snapshot checksum mismatch".to_string(),
+            ));
+        }
+
+        let mut leaves = BTreeMap::new();
+        let mut stored_nodes = HashMap::new();
+        for cell in boc.cells() {
+            match (cell.first(), cell.len()) {
+                (Some(&LEAF_CELL), LEAF_CELL_LEN) => {
+                    let wallet_id: [u8; 32] = cell[1..33].try_into().unwrap();
+                    let wallet_root: [u8; 32] = cell[33..65].try_into().unwrap();
+                    if wallet_root == [0u8; 32] {
+                        return Err(malformed("Leaf cell holds an empty wallet root"));
+                    }
+                    if leaves.insert(wallet_id, wallet_root).is_some() {
+                        return Err(malformed("Wallet appears in more than one leaf cell"));
+                    }
+                }
+                (Some(&NODE_CELL), NODE_CELL_LEN) => {
+                    let depth = u16::from_le_bytes([cell[1], cell[2]]) as usize;
+                    let position: [u8; 32] = cell[3..35].try_into().unwrap();
+                    if depth == 0
+                        || depth > WALLET_TREE_DEPTH
+                        || prefix(&position, depth) != position
+                    {
+                        return Err(malformed("Node cell has an invalid position"));
+                    }
+                    let hash = hash_from_bytes(&cell[35..67].try_into().unwrap())
+                        .map_err(|e| malformed(&e.to_string()))?;
+                    stored_nodes.insert((depth, position), hash);
+                }
+                _ => return Err(malformed("Unrecognised intermediate tree cell")),
             }
-            if let Some(right) = node.right {
-                node_data.extend_from_slice(&right);
+        }
+
+        let mut tree = Self::new();
+        let leaves: Vec<_> = leaves.into_iter().collect();
+        tree.update_batch(&leaves)?;
+        if tree.nodes != stored_nodes {
+            return Err(SystemError::new(
+                SystemErrorType::StateDataMismatch,
+                "Stored node hashes do not match the leaves".to_string(),
+            ));
+        }
+        let recorded = boc
+            .roots()
+            .first()
+            .ok_or_else(|| malformed("Intermediate tree snapshot has no root"))?;
+        if recorded.as_slice() != tree.root() {
+            return Err(SystemError::new(
+                SystemErrorType::StateDataMismatch,
+                "Rebuilt intermediate root does not match BOC root".to_string(),
+            ));
+        }
+        Ok(tree)
+    }
+
+    /// Collect the siblings along the wallet's path, root first
+    fn generate_merkle_path(&self, wallet_id: &[u8; 32]) -> WalletMerkleProof {
+        let defaults = default_hashes();
+        let mut bitmap = [0u8; 32];
+        let mut siblings = Vec::new();
+
+        for depth in 0..WALLET_TREE_DEPTH {
+            let sibling = self.node_hash(depth + 1, &flip_bit(wallet_id, depth));
+            if sibling != defaults[depth + 1] {
+                set_bit(&mut bitmap, depth);
+                siblings.push(hash_to_bytes(&sibling));
             }
-            let cell = Cell::new(node_data, vec![], CellType::Ordinary, *hash, None);
-            node_cells.push(cell);
         }
 
-        Ok(boc)
+        WalletMerkleProof { bitmap, siblings }
+    }
+
+    /// Hash of the node at `depth` on the path of `key`
+    fn node_hash(&self, depth: usize, key: &[u8; 32]) -> WalletTreeHash {
+        let position = prefix(key, depth);
+        if depth == WALLET_TREE_DEPTH {
+            return match self.leaves.get(&position) {
+                Some(wallet_root) => wallet_leaf_hash(&position, wallet_root),
+                None => default_hashes()[depth],
+            };
+        }
+        if let Some(hash) = self.nodes.get(&(depth, position)) {
+            return *hash;
+        }
+        if !self.has_leaves_under(depth, &position) {
+            return default_hashes()[depth];
+        }
+        // A node that is not a branch child: walk down to the stored nodes
+        // or the leaf beneath it.
+        let mut right = position;
+        set_bit(&mut right, depth);
+        wallet_node_hash(
+            &self.node_hash(depth + 1, &position),
+            &self.node_hash(depth + 1, &right),
+        )
+    }
+
+    fn has_leaves_under(&self, depth: usize, position: &[u8; 32]) -> bool {
+        let mut last = *position;
+        for index in depth..WALLET_TREE_DEPTH {
+            set_bit(&mut last, index);
+        }
+        self.leaves.range(*position..=last).next().is_some()
+    }
+}
+
+impl Default for SparseMerkleTreeI {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Default hash for every depth: index `WALLET_TREE_DEPTH` is the empty leaf
+/// and index 0 is the root of an empty tree.
+fn default_hashes() -> &'static [WalletTreeHash] {
+    static DEFAULTS: OnceLock<Vec<WalletTreeHash>> = OnceLock::new();
+    DEFAULTS.get_or_init(|| {
+        let mut defaults = empty_subtree_hashes();
+        defaults.reverse();
+        defaults
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::hierarchy::intermediate::state_tracking_i::ProofInputsI;
+
+    fn wallet(n: u32) -> [u8; 32] {
+        let mut id = [0u8; 32];
+        id[..4].copy_from_slice(&n.to_be_bytes());
+        id[31] = n as u8;
+        id
+    }
+
+    fn root(n: u32) -> [u8; 32] {
+        let mut root = [0xaa; 32];
+        root[..4].copy_from_slice(&n.to_le_bytes());
+        root
+    }
+
+    fn tree(wallets: impl IntoIterator<Item = u32>) -> SparseMerkleTreeI {
+        let mut tree = SparseMerkleTreeI::new();
+        let updates: Vec<_> = wallets.into_iter().map(|n| (wallet(n), root(n))).collect();
+        tree.update_batch(&updates).unwrap();
+        tree
+    }
+
+    #[test]
+    fn test_batch_matches_sequential_updates() {
+        assert_eq!(
+            SparseMerkleTreeI::new().root(),
+            hash_to_bytes(&empty_subtree_hashes()[WALLET_TREE_DEPTH])
+        );
+
+        let batched = tree([3, 1, 200, 7, 8]);
+        let mut sequential = SparseMerkleTreeI::new();
+        for n in [8, 7, 200, 1, 3] {
+            sequential.update(&wallet(n), &root(n)).unwrap();
+        }
+        assert_eq!(batched.root(), sequential.root());
+        assert_eq!(batched.len(), 5);
+
+        let mut removed = batched.clone();
+        assert_eq!(removed.remove(&wallet(200)).unwrap(), Some(root(200)));
+        assert_eq!(removed.root(), tree([1, 3, 7, 8]).root());
+        assert_eq!(removed.remove(&wallet(200)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_inclusion_and_non_inclusion_proofs() {
+        let tree = tree(0..20);
+        let proof = tree.prove_inclusion(&wallet(4)).unwrap();
+        assert!(verify(&tree.root(), &wallet(4), Some(&root(4)), &proof));
+        assert!(!verify(&tree.root(), &wallet(4), Some(&root(5)), &proof));
+        assert!(!verify(&tree.root(), &wallet(4), None, &proof));
+
+        let absent = tree.prove_non_inclusion(&wallet(99)).unwrap();
+        assert!(verify(&tree.root(), &wallet(99), None, &absent));
+        assert!(tree.prove_inclusion(&wallet(99)).is_err());
+        assert!(tree.prove_non_inclusion(&wallet(4)).is_err());
+    }
+
+    #[test]
+    fn test_witnesses_reproduce_tree_root() {
+        let mut tree = tree(0..10);
+        let old_root = tree.root();
+        let updates = [
+            (wallet(3), [0xee; 32]),
+            (wallet(50), root(50)),
+            (wallet(3), [0; 32]),
+        ];
+        let witnesses = tree.apply_with_witnesses(&updates).unwrap();
+
+        assert_eq!(witnesses[0].old_root, root(3));
+        assert_eq!(witnesses[2].old_root, [0xee; 32]);
+        let inputs = ProofInputsI::new(old_root, 1, witnesses);
+        assert_eq!(inputs.new_root().unwrap(), tree.root());
+    }
+
+    #[test]
+    fn test_boc_round_trip_preserves_nodes() {
+        let tree = tree(0..50);
+        let boc = tree.serialize_state().unwrap();
+        let restored = SparseMerkleTreeI::deserialize_state(&boc).unwrap();
+
+        assert_eq!(restored.root(), tree.root());
+        assert_eq!(restored.nodes, tree.nodes);
+        assert_eq!(restored.get(&wallet(7)), Some(root(7)));
+        assert_eq!(
+            restored.prove_inclusion(&wallet(7)).unwrap(),
+            tree.prove_inclusion(&wallet(7)).unwrap()
+        );
+
+        let mut corrupted = boc.clone();
+        corrupted.cells[0][40] ^= 1;
+        assert!(SparseMerkleTreeI::deserialize_state(&corrupted).is_err());
+        let mut wrong_root = boc.clone();
+        wrong_root.roots[0][0] ^= 1;
+        wrong_root.hash = None;
+        assert!(SparseMerkleTreeI::deserialize_state(&wrong_root).is_err());
+
+        // Without a checksum, tampered leaves or node hashes are still caught.
+        let mut forged_leaf = boc.clone();
+        forged_leaf.cells[0][40] ^= 1;
+        forged_leaf.hash = None;
+        assert!(SparseMerkleTreeI::deserialize_state(&forged_leaf).is_err());
+        let node_cell = boc
+            .cells
+            .iter()
+            .position(|cell| cell[0] == NODE_CELL)
+            .unwrap();
+        let mut forged_node = boc.clone();
+        forged_node.cells[node_cell][35] ^= 1;
+        forged_node.hash = None;
+        assert!(SparseMerkleTreeI::deserialize_state(&forged_node).is_err());
+    }
+
+    #[test]
+    fn test_large_tree_rebuilds_from_boc() {
+        let tree = tree(0..200);
+        let boc = tree.serialize_state().unwrap();
+        let restored = SparseMerkleTreeI::deserialize_state(&boc).unwrap();
+        assert_eq!(restored.root(), tree.root());
+        assert_eq!(restored.len(), 200);
+    }
+
+    #[test]
+    #[ignore = "builds a 100k-wallet tree; run with --ignored"]
+    fn test_100k_wallet_tree_rebuilds_from_boc() {
+        let tree = tree(0..100_000);
+        let bytes = tree.serialize_state().unwrap().serialize().unwrap();
+        let restored =
+            SparseMerkleTreeI::deserialize_state(&BOC::deserialize(&bytes).unwrap()).unwrap();
+        assert_eq!(restored.root(), tree.root());
+        assert_eq!(restored.len(), 100_000);
+        assert_eq!(
+            restored.prove_inclusion(&wallet(99_999)).unwrap(),
+            tree.prove_inclusion(&wallet(99_999)).unwrap()
+        );
     }
 }
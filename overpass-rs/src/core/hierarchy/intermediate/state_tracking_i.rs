@@ -1,59 +1,134 @@
 // ./src/core/hierarchy/intermediate/state_tracking_i.rs
 
-use std::collections::HashMap;
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::root::global_tree_manager::RootTransitionVerifier;
+use crate::core::zkps::plonky2::PlonkyError;
+use crate::core::zkps::proof::ZkProof;
+pub use crate::core::zkps::wallet_root_circuit::WalletRootPublicInputs;
+/// Witness for one wallet's change: the circuit's `WalletRootUpdate`, which is
+/// distinct from the root layer's [`fraud_proof::WalletRootUpdate`].
+///
+/// [`fraud_proof::WalletRootUpdate`]: crate::core::hierarchy::root::fraud_proof::WalletRootUpdate
+pub use crate::core::zkps::wallet_root_circuit::WalletRootUpdate as WalletRootWitness;
+use crate::core::zkps::wallet_root_circuit::{
+    hash_from_bytes, hash_to_bytes, path_root, update_commitment, wallet_leaf_hash,
+    WalletRootBatchCircuit, WalletTreeHash, WALLET_TREE_DEPTH,
+};
+use plonky2::hash::hash_types::HashOut;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+const EXPORT_FORMAT: &str = "ovp-intermediate-proof";
+const EXPORT_VERSION: u32 = 1;
+
+fn proof_error(e: PlonkyError) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidProof, e.to_string())
+}
+
+/// A batch of wallet root updates against one intermediate root.
 pub struct ProofInputsI {
-    pub old_root: Vec<u8>,
-    pub new_root: Vec<u8>,
+    pub old_root: [u8; 32],
     pub epoch: u64,
-    pub wallet_states: HashMap<String, Vec<u8>>,
+    pub updates: Vec<WalletRootWitness>,
 }
 
 impl ProofInputsI {
-    pub fn new(
-        old_root: Vec<u8>,
-        new_root: Vec<u8>,
-        epoch: u64,
-        wallet_states: HashMap<String, Vec<u8>>,
-    ) -> Self {
+    pub fn new(old_root: [u8; 32], epoch: u64, updates: Vec<WalletRootWitness>) -> Self {
         Self {
             old_root,
-            new_root,
             epoch,
-            wallet_states,
+            updates,
+        }
+    }
+
+    /// Applies the updates in order, checking each path against the root
+    /// left by the one before it, and returns the resulting root.
+    pub fn new_root(&self) -> Result<[u8; 32], SystemError> {
+        let mut root = hash_from_bytes(&self.old_root).map_err(proof_error)?;
+        for update in &self.updates {
+            if update.siblings.len() != WALLET_TREE_DEPTH {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidInput,
+                    format!("Wallet path must have {} siblings", WALLET_TREE_DEPTH),
+                ));
+            }
+            let siblings = update
+                .siblings
+                .iter()
+                .map(hash_from_bytes)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(proof_error)?;
+            let old_leaf = wallet_leaf_hash(&update.wallet_id, &update.old_root);
+            if path_root(&update.wallet_id, old_leaf, &siblings) != root {
+                return Err(SystemError::new(
+                    SystemErrorType::StateDataMismatch,
+                    "Wallet's old root is not in the current intermediate tree".to_string(),
+                ));
+            }
+            let new_leaf = wallet_leaf_hash(&update.wallet_id, &update.new_root);
+            root = path_root(&update.wallet_id, new_leaf, &siblings);
         }
+        Ok(hash_to_bytes(&root))
+    }
+
+    pub fn commitment(&self) -> [u8; 32] {
+        hash_to_bytes(&commit(
+            self.updates
+                .iter()
+                .map(|update| (update.wallet_id, update.old_root, update.new_root)),
+        ))
     }
 }
 
+fn commit(updates: impl Iterator<Item = ([u8; 32], [u8; 32], [u8; 32])>) -> WalletTreeHash {
+    updates.fold(
+        HashOut::ZERO,
+        |accumulator, (wallet_id, old_root, new_root)| {
+            update_commitment(&accumulator, &wallet_id, &old_root, &new_root)
+        },
+    )
+}
+
+/// Proves wallet root batches with a shared [`WalletRootBatchCircuit`].
 pub struct ProofGeneratorI {
-    pub old_root: Vec<u8>,
-    pub new_root: Vec<u8>,
-    pub epoch: u64,
-    pub wallet_states: HashMap<String, Vec<u8>>,
+    circuit: Arc<WalletRootBatchCircuit>,
 }
 
 impl ProofGeneratorI {
-    pub fn new(
-        old_root: Vec<u8>,
-        new_root: Vec<u8>,
-        epoch: u64,
-        wallet_states: HashMap<String, Vec<u8>>,
-    ) -> Self {
-        Self {
-            old_root,
-            new_root,
-            epoch,
-            wallet_states,
-        }
+    pub fn new(circuit: Arc<WalletRootBatchCircuit>) -> Self {
+        Self { circuit }
+    }
+
+    /// Largest batch a single proof can cover.
+    pub fn max_updates(&self) -> usize {
+        self.circuit.max_updates()
     }
 
-    pub fn generate_proof(&self) -> Result<Vec<u8>, &'static str> {
-        // Implementation for proof generation
-        Ok(Vec::new())
+    /// Returns a proof whose `merkle_root` is the intermediate root after
+    /// the batch and whose public inputs are laid out as
+    /// [`WalletRootPublicInputs`].
+    pub fn generate_proof(&self, inputs: &ProofInputsI) -> Result<ZkProof, SystemError> {
+        // Reject inconsistent batches before paying for a proof.
+        let new_root = inputs.new_root()?;
+        let (proof_data, public_inputs) = self
+            .circuit
+            .prove(&inputs.old_root, inputs.epoch, &inputs.updates)
+            .map_err(|e| SystemError::new(SystemErrorType::ProofGenerationError, e.to_string()))?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        Ok(ZkProof::new(
+            proof_data,
+            public_inputs,
+            new_root.to_vec(),
+            timestamp,
+        ))
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ProofMetadataI {
     pub timestamp: u64,
     pub nonce: u64,
@@ -72,7 +147,7 @@ impl ProofMetadataI {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ProofType {
     StateTransition,
     BalanceTransfer,
@@ -80,73 +155,286 @@ pub enum ProofType {
     Aggregate,
 }
 
+/// Verifies wallet root batch proofs. This is the verifier the root layer
+/// uses for intermediate submissions.
 pub struct ProofVerifierI {
-    pub old_root: Vec<u8>,
-    pub new_root: Vec<u8>,
-    pub epoch: u64,
-    pub wallet_states: HashMap<String, Vec<u8>>,
+    circuit: Arc<WalletRootBatchCircuit>,
 }
 
 impl ProofVerifierI {
-    pub fn new(
-        old_root: Vec<u8>,
-        new_root: Vec<u8>,
+    pub fn new(circuit: Arc<WalletRootBatchCircuit>) -> Self {
+        Self { circuit }
+    }
+
+    /// Verifies the proof and returns what it attests to, after checking
+    /// that the proof's declared public inputs and `merkle_root` agree with
+    /// the ones inside the proof.
+    pub fn verify_public_inputs(
+        &self,
+        proof: &ZkProof,
+    ) -> Result<WalletRootPublicInputs, SystemError> {
+        proof.verify_internally()?;
+        let attested = self
+            .circuit
+            .verify(&proof.proof_data)
+            .map_err(proof_error)?;
+        let declared =
+            WalletRootPublicInputs::from_slice(&proof.public_inputs).map_err(proof_error)?;
+        if declared != attested || proof.merkle_root != attested.new_root {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Proof metadata does not match its public inputs".to_string(),
+            ));
+        }
+        Ok(attested)
+    }
+
+    /// Checks that `proof` moves `old_root` to its `merkle_root` in `epoch`
+    /// by exactly `updates`, given as `(wallet id, old root, new root)`.
+    pub fn verify_proof(
+        &self,
+        proof: &ZkProof,
+        old_root: &[u8; 32],
         epoch: u64,
-        wallet_states: HashMap<String, Vec<u8>>,
-    ) -> Self {
-        Self {
+        updates: &[([u8; 32], [u8; 32], [u8; 32])],
+    ) -> Result<(), SystemError> {
+        let commitment = hash_to_bytes(&commit(updates.iter().copied()));
+        self.verify_transition(proof, old_root, epoch, &commitment)
+    }
+
+    /// Binds the verifier to the transition the caller expects: the
+    /// intermediate root the batch must start from and the commitment to
+    /// its updates.
+    pub fn bind(&self, old_root: [u8; 32], commitment: [u8; 32]) -> BoundProofVerifierI<'_> {
+        BoundProofVerifierI {
+            verifier: self,
             old_root,
-            new_root,
-            epoch,
-            wallet_states,
+            commitment,
+        }
+    }
+}
+
+/// A [`ProofVerifierI`] bound to an expected old root and update
+/// commitment. The [`RootTransitionVerifier`] impl always checks through the
+/// bound form, so a proof is never accepted just for being valid for some
+/// transition.
+pub struct BoundProofVerifierI<'a> {
+    verifier: &'a ProofVerifierI,
+    old_root: [u8; 32],
+    commitment: [u8; 32],
+}
+
+impl BoundProofVerifierI<'_> {
+    pub fn old_root(&self) -> &[u8; 32] {
+        &self.old_root
+    }
+
+    pub fn commitment(&self) -> &[u8; 32] {
+        &self.commitment
+    }
+
+    /// Verifies the proof, checks it against the bound old root and
+    /// commitment, and returns its public inputs.
+    pub fn verify_bound(&self, proof: &ZkProof) -> Result<WalletRootPublicInputs, SystemError> {
+        let attested = self.verifier.verify_public_inputs(proof)?;
+        if attested.old_root != self.old_root {
+            return Err(SystemError::new(
+                SystemErrorType::StateDataMismatch,
+                "Proof starts from a different intermediate root".to_string(),
+            ));
+        }
+        if attested.commitment != self.commitment {
+            return Err(SystemError::new(
+                SystemErrorType::StateDataMismatch,
+                "Proof covers a different set of wallet updates".to_string(),
+            ));
         }
+        Ok(attested)
     }
+}
 
-    pub fn verify_proof(&self, proof: &[u8]) -> Result<bool, &'static str> {
-        // Implementation for proof verification
-        Ok(true)
+/// Root submissions use the submission sequence as the proof's epoch.
+impl RootTransitionVerifier for ProofVerifierI {
+    fn verify_transition(
+        &self,
+        proof: &ZkProof,
+        old_root: &[u8; 32],
+        sequence: u64,
+        commitment: &[u8; 32],
+    ) -> Result<(), SystemError> {
+        let attested = self.bind(*old_root, *commitment).verify_bound(proof)?;
+        if attested.epoch != sequence {
+            return Err(SystemError::new(
+                SystemErrorType::StateDataMismatch,
+                "Proof is for a different epoch".to_string(),
+            ));
+        }
+        Ok(())
     }
 }
 
-pub struct ProofExporterI {
-    pub old_root: Vec<u8>,
-    pub new_root: Vec<u8>,
-    pub epoch: u64,
-    pub wallet_states: HashMap<String, Vec<u8>>,
+#[derive(Serialize, Deserialize)]
+struct ExportHeader {
+    format: String,
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportedProofV1 {
+    format: String,
+    version: u32,
+    metadata: ProofMetadataI,
+    /// Hex encoded.
+    proof_data: String,
+    public_inputs: Vec<u64>,
+    /// Hex encoded.
+    merkle_root: String,
+    timestamp: u64,
 }
 
+/// Encodes proofs as self-describing JSON tagged with a format name and
+/// version, so importers can reject encodings they do not understand.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProofExporterI;
+
 impl ProofExporterI {
-    pub fn new(
-        old_root: Vec<u8>,
-        new_root: Vec<u8>,
-        epoch: u64,
-        wallet_states: HashMap<String, Vec<u8>>,
-    ) -> Self {
-        Self {
-            old_root,
-            new_root,
-            epoch,
-            wallet_states,
-        }
+    pub fn new() -> Self {
+        Self
     }
 
     pub fn export_proof(
         &self,
-        proof: &[u8],
+        proof: &ZkProof,
         metadata: &ProofMetadataI,
-    ) -> Result<String, &'static str> {
-        // Implementation for proof export
-        Ok(String::new())
+    ) -> Result<String, SystemError> {
+        let exported = ExportedProofV1 {
+            format: EXPORT_FORMAT.to_string(),
+            version: EXPORT_VERSION,
+            metadata: metadata.clone(),
+            proof_data: hex::encode(&proof.proof_data),
+            public_inputs: proof.public_inputs.clone(),
+            merkle_root: hex::encode(&proof.merkle_root),
+            timestamp: proof.timestamp,
+        };
+        serde_json::to_string(&exported)
+            .map_err(|e| SystemError::new(SystemErrorType::SerializationError, e.to_string()))
     }
 
-    pub fn import_proof(
-        &self,
-        proof_data: &str,
-    ) -> Result<(Vec<u8>, ProofMetadataI), &'static str> {
-        // Implementation for proof import
-        Ok((
-            Vec::new(),
-            ProofMetadataI::new(0, 0, [0; 32], ProofType::StateTransition),
-        ))
+    pub fn import_proof(&self, proof_data: &str) -> Result<(ZkProof, ProofMetadataI), SystemError> {
+        let serialization_error =
+            |e: String| SystemError::new(SystemErrorType::SerializationError, e);
+        let header: ExportHeader =
+            serde_json::from_str(proof_data).map_err(|e| serialization_error(e.to_string()))?;
+        if header.format != EXPORT_FORMAT {
+            return Err(serialization_error(format!(
+                "Unknown proof format {:?}",
+                header.format
+            )));
+        }
+        match header.version {
+            1 => {
+                let exported: ExportedProofV1 = serde_json::from_str(proof_data)
+                    .map_err(|e| serialization_error(e.to_string()))?;
+                let proof = ZkProof::new(
+                    hex::decode(&exported.proof_data)
+                        .map_err(|e| serialization_error(e.to_string()))?,
+                    exported.public_inputs,
+                    hex::decode(&exported.merkle_root)
+                        .map_err(|e| serialization_error(e.to_string()))?,
+                    exported.timestamp,
+                );
+                Ok((proof, exported.metadata))
+            }
+            version => Err(serialization_error(format!(
+                "Unsupported proof export version {}",
+                version
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::zkps::wallet_root_circuit::empty_subtree_hashes;
+
+    fn single_insert() -> ProofInputsI {
+        let empty = empty_subtree_hashes();
+        let update = WalletRootWitness {
+            wallet_id: [0x42; 32],
+            old_root: [0; 32],
+            new_root: [5; 32],
+            siblings: empty[..WALLET_TREE_DEPTH]
+                .iter()
+                .map(hash_to_bytes)
+                .collect(),
+        };
+        ProofInputsI::new(hash_to_bytes(&empty[WALLET_TREE_DEPTH]), 3, vec![update])
+    }
+
+    #[test]
+    fn test_generated_proof_verifies_against_claimed_updates() {
+        let circuit = Arc::new(WalletRootBatchCircuit::new(2));
+        let generator = ProofGeneratorI::new(circuit.clone());
+        let verifier = ProofVerifierI::new(circuit);
+        let inputs = single_insert();
+        let proof = generator.generate_proof(&inputs).unwrap();
+
+        assert_eq!(proof.merkle_root, inputs.new_root().unwrap().to_vec());
+        let claimed = [([0x42; 32], [0; 32], [5; 32])];
+        verifier
+            .verify_proof(&proof, &inputs.old_root, 3, &claimed)
+            .unwrap();
+        let bound = verifier.bind(inputs.old_root, inputs.commitment());
+        bound.verify_bound(&proof).unwrap();
+        assert!(verifier
+            .bind([7; 32], inputs.commitment())
+            .verify_bound(&proof)
+            .is_err());
+        assert!(verifier
+            .bind(inputs.old_root, [7; 32])
+            .verify_bound(&proof)
+            .is_err());
+
+        assert!(verifier
+            .verify_proof(&proof, &inputs.old_root, 4, &claimed)
+            .is_err());
+        let other = [([0x42; 32], [0; 32], [6; 32])];
+        assert!(verifier
+            .verify_proof(&proof, &inputs.old_root, 3, &other)
+            .is_err());
+
+        let mut relabeled = proof.clone();
+        relabeled.merkle_root = vec![1; 32];
+        assert!(bound.verify_bound(&relabeled).is_err());
+    }
+
+    #[test]
+    fn test_inconsistent_batch_is_rejected_before_proving() {
+        let mut inputs = single_insert();
+        inputs.updates[0].old_root = [9; 32];
+        assert!(inputs.new_root().is_err());
+        let generator = ProofGeneratorI::new(Arc::new(WalletRootBatchCircuit::new(1)));
+        assert!(generator.generate_proof(&inputs).is_err());
+    }
+
+    #[test]
+    fn test_export_round_trip_and_version_check() {
+        let exporter = ProofExporterI::new();
+        let proof = ZkProof::new(vec![1, 2, 3], vec![4, 5], vec![6; 32], 7);
+        let metadata = ProofMetadataI::new(8, 9, [10; 32], ProofType::Aggregate);
+
+        let encoded = exporter.export_proof(&proof, &metadata).unwrap();
+        let (decoded, decoded_metadata) = exporter.import_proof(&encoded).unwrap();
+        assert_eq!(decoded.proof_data, proof.proof_data);
+        assert_eq!(decoded.public_inputs, proof.public_inputs);
+        assert_eq!(decoded.merkle_root, proof.merkle_root);
+        assert_eq!(decoded.timestamp, proof.timestamp);
+        assert_eq!(decoded_metadata, metadata);
+
+        let future = encoded.replace("\"version\":1", "\"version\":2");
+        assert!(exporter.import_proof(&future).is_err());
+        let foreign = encoded.replace(EXPORT_FORMAT, "something-else");
+        assert!(exporter.import_proof(&foreign).is_err());
     }
 }
@@ -0,0 +1,453 @@
+// ./src/core/hierarchy/intermediate/storage_assignment_i.rs
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::storage_node::battery::BatteryChargingSystem;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+type NodeId = [u8; 32];
+type WalletId = [u8; 32];
+
+const ASSIGNMENT_DOMAIN: &[u8] = b"ovp:storage-assignment";
+
+/// Whether a storage node can currently hold wallet states.
+pub trait NodeStatus {
+    fn is_suspended(&self) -> bool;
+}
+
+impl NodeStatus for BatteryChargingSystem {
+    fn is_suspended(&self) -> bool {
+        BatteryChargingSystem::is_suspended(self)
+    }
+}
+
+impl<T: NodeStatus + ?Sized> NodeStatus for Arc<T> {
+    fn is_suspended(&self) -> bool {
+        (**self).is_suspended()
+    }
+}
+
+/// Redundancy bounds, usually taken from the intermediate contract's
+/// `min_storage_nodes` / `max_storage_nodes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageAssignmentConfig {
+    pub min_replicas: usize,
+    pub max_replicas: usize,
+}
+
+impl StorageAssignmentConfig {
+    pub fn new(min_replicas: usize, max_replicas: usize) -> Result<Self, SystemError> {
+        if min_replicas == 0 || min_replicas > max_replicas {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Replica bounds must satisfy 1 <= min <= max".to_string(),
+            ));
+        }
+        Ok(Self {
+            min_replicas,
+            max_replicas,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssignmentEvent {
+    Assigned {
+        wallet_id: WalletId,
+        node_id: NodeId,
+    },
+    Released {
+        wallet_id: WalletId,
+        node_id: NodeId,
+    },
+    /// Fewer than `min_replicas` active nodes are left to hold the wallet.
+    UnderReplicated {
+        wallet_id: WalletId,
+        replicas: usize,
+    },
+    NodeSuspended(NodeId),
+    NodeResumed(NodeId),
+}
+
+struct StorageNodeEntry<S> {
+    stake: u64,
+    status: S,
+    suspended: bool,
+}
+
+/// Fractional bits of [`log2_fixed`].
+const LOG2_FRAC_BITS: u32 = 32;
+
+/// `log2(x)` for `x >= 1` as a fixed-point number with [`LOG2_FRAC_BITS`]
+/// fractional bits, rounded down.
+fn log2_fixed(x: u64) -> u64 {
+    debug_assert!(x > 0);
+    let int_part = 63 - x.leading_zeros() as u64;
+    // Mantissa in [1, 2) with 63 fractional bits; squaring it yields the
+    // next fractional bit of the logarithm.
+    let mut mantissa = (x as u128) << (63 - int_part);
+    let mut frac_part = 0u64;
+    for bit in (0..LOG2_FRAC_BITS).rev() {
+        mantissa = (mantissa * mantissa) >> 63;
+        if mantissa >= 1 << 64 {
+            frac_part |= 1 << bit;
+            mantissa >>= 1;
+        }
+    }
+    (int_part << LOG2_FRAC_BITS) | frac_part
+}
+
+/// Weighted rendezvous score of `node_id` for `wallet_id`.
+///
+/// The hash is mapped to `u` in (0, 1) and scored as `stake / -log2(u)`, so a
+/// node's chance of ranking first is proportional to its stake, and adding or
+/// removing one node only moves the wallets it wins or loses. The score is
+/// computed in integer fixed point so every intermediate ranks nodes
+/// identically.
+fn rendezvous_score(node_id: &NodeId, wallet_id: &WalletId, stake: u64) -> u128 {
+    let mut hasher = Sha256::new();
+    hasher.update(ASSIGNMENT_DOMAIN);
+    hasher.update(node_id);
+    hasher.update(wallet_id);
+    let digest = hasher.finalize();
+    let bits = u64::from_le_bytes(digest[..8].try_into().unwrap()) >> 11;
+    // u = (2 * bits + 1) / 2^54, so -log2(u) = 54 - log2(2 * bits + 1). It
+    // can round to zero for u just below 1; clamp to the smallest step.
+    let neg_log2 = (54 << LOG2_FRAC_BITS) - log2_fixed(2 * bits + 1);
+    ((stake as u128) << 64) / neg_log2.max(1) as u128
+}
+
+/// Assigns wallet states to storage nodes by stake-weighted rendezvous
+/// hashing.
+///
+/// Each wallet is held by the `max_replicas` highest-scoring active nodes.
+/// Assignments depend only on the node set, stakes and suspension state, so
+/// every intermediate computes the same placement. Suspended nodes are
+/// skipped and their wallets move to the next node in rank.
+pub struct StorageAssigner<S: NodeStatus> {
+    config: StorageAssignmentConfig,
+    nodes: BTreeMap<NodeId, StorageNodeEntry<S>>,
+    assignments: BTreeMap<WalletId, Vec<NodeId>>,
+}
+
+impl<S: NodeStatus> StorageAssigner<S> {
+    pub fn new(config: StorageAssignmentConfig) -> Self {
+        Self {
+            config,
+            nodes: BTreeMap::new(),
+            assignments: BTreeMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &StorageAssignmentConfig {
+        &self.config
+    }
+
+    pub fn register_node(
+        &mut self,
+        node_id: NodeId,
+        stake: u64,
+        status: S,
+    ) -> Result<Vec<AssignmentEvent>, SystemError> {
+        if self.nodes.contains_key(&node_id) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Storage node is already registered".to_string(),
+            ));
+        }
+        Self::check_stake(stake)?;
+        let suspended = status.is_suspended();
+        self.nodes.insert(
+            node_id,
+            StorageNodeEntry {
+                stake,
+                status,
+                suspended,
+            },
+        );
+        Ok(self.reassign())
+    }
+
+    pub fn update_stake(
+        &mut self,
+        node_id: &NodeId,
+        stake: u64,
+    ) -> Result<Vec<AssignmentEvent>, SystemError> {
+        Self::check_stake(stake)?;
+        self.node_mut(node_id)?.stake = stake;
+        Ok(self.reassign())
+    }
+
+    pub fn remove_node(&mut self, node_id: &NodeId) -> Result<Vec<AssignmentEvent>, SystemError> {
+        self.node_mut(node_id)?;
+        self.nodes.remove(node_id);
+        Ok(self.reassign())
+    }
+
+    /// Places a wallet's state. Assigning an already placed wallet returns
+    /// its current nodes.
+    pub fn assign_wallet(&mut self, wallet_id: WalletId) -> Result<Vec<NodeId>, SystemError> {
+        if let Some(nodes) = self.assignments.get(&wallet_id) {
+            return Ok(nodes.clone());
+        }
+        let nodes = self.select(&wallet_id);
+        if nodes.len() < self.config.min_replicas {
+            return Err(SystemError::new(
+                SystemErrorType::ResourceUnavailable,
+                format!(
+                    "{} active storage nodes, {} required",
+                    nodes.len(),
+                    self.config.min_replicas
+                ),
+            ));
+        }
+        self.assignments.insert(wallet_id, nodes.clone());
+        Ok(nodes)
+    }
+
+    pub fn remove_wallet(&mut self, wallet_id: &WalletId) -> Option<Vec<NodeId>> {
+        self.assignments.remove(wallet_id)
+    }
+
+    /// Nodes holding the wallet, highest rank first.
+    pub fn nodes_for(&self, wallet_id: &WalletId) -> Option<&[NodeId]> {
+        self.assignments.get(wallet_id).map(Vec::as_slice)
+    }
+
+    /// Whether `node_id` is currently responsible for storing the wallet.
+    pub fn validate_assignment(&self, wallet_id: &WalletId, node_id: &NodeId) -> bool {
+        self.assignments
+            .get(wallet_id)
+            .is_some_and(|nodes| nodes.contains(node_id))
+    }
+
+    pub fn wallets_on(&self, node_id: &NodeId) -> Vec<WalletId> {
+        self.assignments
+            .iter()
+            .filter(|(_, nodes)| nodes.contains(node_id))
+            .map(|(wallet_id, _)| *wallet_id)
+            .collect()
+    }
+
+    pub fn active_nodes(&self) -> usize {
+        self.nodes.values().filter(|node| !node.suspended).count()
+    }
+
+    /// Polls every node's battery and moves wallets off nodes that were
+    /// suspended since the last poll, and back onto nodes that resumed.
+    pub fn refresh_node_status(&mut self) -> Vec<AssignmentEvent> {
+        let mut events = Vec::new();
+        for (node_id, node) in self.nodes.iter_mut() {
+            let suspended = node.status.is_suspended();
+            if suspended != node.suspended {
+                node.suspended = suspended;
+                events.push(if suspended {
+                    AssignmentEvent::NodeSuspended(*node_id)
+                } else {
+                    AssignmentEvent::NodeResumed(*node_id)
+                });
+            }
+        }
+        if !events.is_empty() {
+            events.extend(self.reassign());
+        }
+        events
+    }
+
+    fn check_stake(stake: u64) -> Result<(), SystemError> {
+        if stake == 0 {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Storage node stake must be non-zero".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn node_mut(&mut self, node_id: &NodeId) -> Result<&mut StorageNodeEntry<S>, SystemError> {
+        self.nodes.get_mut(node_id).ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NotFound,
+                "Storage node is not registered".to_string(),
+            )
+        })
+    }
+
+    fn select(&self, wallet_id: &WalletId) -> Vec<NodeId> {
+        let mut ranked: Vec<(u128, NodeId)> = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| !node.suspended)
+            .map(|(node_id, node)| (rendezvous_score(node_id, wallet_id, node.stake), *node_id))
+            .collect();
+        ranked.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        ranked
+            .into_iter()
+            .take(self.config.max_replicas)
+            .map(|(_, node_id)| node_id)
+            .collect()
+    }
+
+    /// Recomputes every placed wallet and reports what moved.
+    fn reassign(&mut self) -> Vec<AssignmentEvent> {
+        let mut events = Vec::new();
+        let wallets: Vec<WalletId> = self.assignments.keys().copied().collect();
+        for wallet_id in wallets {
+            let next = self.select(&wallet_id);
+            let previous: BTreeSet<NodeId> = self.assignments[&wallet_id].iter().copied().collect();
+            let current: BTreeSet<NodeId> = next.iter().copied().collect();
+            for node_id in previous.difference(&current) {
+                events.push(AssignmentEvent::Released {
+                    wallet_id,
+                    node_id: *node_id,
+                });
+            }
+            for node_id in current.difference(&previous) {
+                events.push(AssignmentEvent::Assigned {
+                    wallet_id,
+                    node_id: *node_id,
+                });
+            }
+            if next.len() < self.config.min_replicas {
+                events.push(AssignmentEvent::UnderReplicated {
+                    wallet_id,
+                    replicas: next.len(),
+                });
+            }
+            self.assignments.insert(wallet_id, next);
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct FakeBattery(Rc<Cell<bool>>);
+
+    impl NodeStatus for FakeBattery {
+        fn is_suspended(&self) -> bool {
+            self.0.get()
+        }
+    }
+
+    fn wallet(i: u32) -> WalletId {
+        let mut id = [0u8; 32];
+        id[..4].copy_from_slice(&i.to_le_bytes());
+        id
+    }
+
+    fn assigner(
+        min: usize,
+        max: usize,
+        stakes: &[u64],
+    ) -> (Vec<FakeBattery>, StorageAssigner<FakeBattery>) {
+        let mut assigner = StorageAssigner::new(StorageAssignmentConfig::new(min, max).unwrap());
+        let batteries: Vec<FakeBattery> = stakes.iter().map(|_| FakeBattery::default()).collect();
+        for (i, (stake, battery)) in stakes.iter().zip(&batteries).enumerate() {
+            assigner
+                .register_node([i as u8; 32], *stake, battery.clone())
+                .unwrap();
+        }
+        (batteries, assigner)
+    }
+
+    #[test]
+    fn test_assignment_is_deterministic_and_stake_weighted() {
+        let (_, mut a) = assigner(1, 1, &[1, 3]);
+        let (_, mut b) = assigner(1, 1, &[1, 3]);
+        let mut heavy = 0;
+        for i in 0..2_000 {
+            let nodes = a.assign_wallet(wallet(i)).unwrap();
+            assert_eq!(nodes, b.assign_wallet(wallet(i)).unwrap());
+            if nodes == vec![[1; 32]] {
+                heavy += 1;
+            }
+        }
+        // Expected share is 3/4.
+        assert!((1_350..1_650).contains(&heavy), "heavy node won {heavy}");
+    }
+
+    #[test]
+    fn test_log2_fixed() {
+        let one = 1u64 << LOG2_FRAC_BITS;
+        assert_eq!(log2_fixed(1), 0);
+        assert_eq!(log2_fixed(2), one);
+        assert_eq!(log2_fixed(1 << 40), 40 * one);
+        // log2(3) = 1.584962500721156...
+        let expected = (1.584_962_500_721_156 * one as f64) as u64;
+        assert!(log2_fixed(3).abs_diff(expected) <= 1);
+        assert!(log2_fixed(u64::MAX) < 64 * one);
+    }
+
+    #[test]
+    fn test_suspended_node_is_replaced() {
+        let (batteries, mut assigner) = assigner(2, 3, &[10, 10, 10, 10, 10]);
+        let before: Vec<Vec<NodeId>> = (0..100)
+            .map(|i| assigner.assign_wallet(wallet(i)).unwrap())
+            .collect();
+        let affected = assigner.wallets_on(&[2; 32]);
+        assert!(!affected.is_empty());
+
+        batteries[2].0.set(true);
+        let events = assigner.refresh_node_status();
+        assert_eq!(events[0], AssignmentEvent::NodeSuspended([2; 32]));
+        assert_eq!(assigner.active_nodes(), 4);
+        for (i, previous) in before.iter().enumerate() {
+            let nodes = assigner.nodes_for(&wallet(i as u32)).unwrap();
+            assert_eq!(nodes.len(), 3);
+            assert!(!nodes.contains(&[2; 32]));
+            if !affected.contains(&wallet(i as u32)) {
+                assert_eq!(nodes, previous.as_slice());
+            }
+        }
+        let released = events
+            .iter()
+            .filter(|e| matches!(e, AssignmentEvent::Released { .. }))
+            .count();
+        assert_eq!(released, affected.len());
+
+        batteries[2].0.set(false);
+        assigner.refresh_node_status();
+        for (i, previous) in before.iter().enumerate() {
+            assert_eq!(
+                assigner.nodes_for(&wallet(i as u32)).unwrap(),
+                previous.as_slice()
+            );
+        }
+    }
+
+    #[test]
+    fn test_redundancy_bounds() {
+        assert!(StorageAssignmentConfig::new(0, 2).is_err());
+        assert!(StorageAssignmentConfig::new(3, 2).is_err());
+
+        let (batteries, mut assigner) = assigner(2, 2, &[5, 5]);
+        let nodes = assigner.assign_wallet(wallet(1)).unwrap();
+        assert!(assigner.validate_assignment(&wallet(1), &nodes[0]));
+        assert!(!assigner.validate_assignment(&wallet(2), &nodes[0]));
+
+        batteries[0].0.set(true);
+        let events = assigner.refresh_node_status();
+        assert!(events.contains(&AssignmentEvent::UnderReplicated {
+            wallet_id: wallet(1),
+            replicas: 1,
+        }));
+        assert!(assigner.assign_wallet(wallet(2)).is_err());
+        assert!(assigner
+            .register_node([1; 32], 5, FakeBattery::default())
+            .is_err());
+        assert!(assigner.update_stake(&[9; 32], 5).is_err());
+
+        assigner
+            .register_node([7; 32], 5, FakeBattery::default())
+            .unwrap();
+        assert_eq!(assigner.assign_wallet(wallet(2)).unwrap().len(), 2);
+        assert_eq!(assigner.nodes_for(&wallet(1)).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,581 @@
+// ./src/core/hierarchy/intermediate/wallet_update_i.rs
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::clock::Clock;
+use crate::core::hierarchy::intermediate::destination_contract::PartyKey;
+use crate::core::hierarchy::intermediate::root_submitter_i::RootSubmitterI;
+use crate::core::hierarchy::intermediate::sparse_merkle_tree_i::SparseMerkleTreeI;
+use crate::core::hierarchy::root::global_tree_manager::IntermediateProofVerifier;
+use crate::core::zkps::proof::ZkProof;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+
+type WalletId = [u8; 32];
+type Hash = [u8; 32];
+
+const WALLET_UPDATE_DOMAIN: &[u8] = b"ovp:wallet-state-update";
+const WALLET_ID_DOMAIN: &[u8] = b"ovp:wallet-id";
+const WALLET_REGISTRATION_DOMAIN: &[u8] = b"ovp:wallet-registration";
+
+/// The wallet id owned by `key`. Wallets can only be registered under the
+/// id derived from their own key.
+pub fn wallet_id_for_key(key: &PartyKey) -> WalletId {
+    let mut hasher = Sha256::new();
+    hasher.update(WALLET_ID_DOMAIN);
+    hasher.update(key_bytes(key));
+    hasher.finalize().into()
+}
+
+/// Bytes a wallet signs with `key` to register under `wallet_id`.
+pub fn wallet_registration_message(wallet_id: &WalletId, key: &PartyKey) -> Vec<u8> {
+    let key = key_bytes(key);
+    let mut message = Vec::with_capacity(WALLET_REGISTRATION_DOMAIN.len() + 32 + key.len());
+    message.extend_from_slice(WALLET_REGISTRATION_DOMAIN);
+    message.extend_from_slice(wallet_id);
+    message.extend_from_slice(key);
+    message
+}
+
+fn key_bytes(key: &PartyKey) -> &[u8] {
+    match key {
+        PartyKey::Ed25519(key) => key,
+        PartyKey::Secp256k1(key) => key,
+    }
+}
+
+/// A wallet's request to move its root in the intermediate tree from
+/// `previous_root` to `merkle_root`.
+#[derive(Debug, Clone)]
+pub struct WalletStateUpdate {
+    pub wallet_id: WalletId,
+    pub new_balance: u64,
+    /// The wallet root after the update. A zero root removes the wallet
+    /// from the tree.
+    pub merkle_root: Hash,
+    /// The epoch the update is made for; only updates for the
+    /// intermediate's current epoch are accepted.
+    pub epoch_id: u64,
+    /// Must exceed the nonce of the last update accepted for the wallet.
+    pub nonce: u64,
+    /// The wallet root the update builds on; zero for the first update.
+    pub previous_root: Hash,
+    /// Proof of the wallet state, committing to `merkle_root` as its own
+    /// `merkle_root`.
+    pub proof: ZkProof,
+    /// Signature over [`wallet_update_message`] by the wallet's registered
+    /// key.
+    pub signature: Vec<u8>,
+}
+
+/// Bytes a wallet signs to submit `update`.
+pub fn wallet_update_message(update: &WalletStateUpdate) -> Vec<u8> {
+    let mut message = Vec::with_capacity(WALLET_UPDATE_DOMAIN.len() + 120);
+    message.extend_from_slice(WALLET_UPDATE_DOMAIN);
+    message.extend_from_slice(&update.wallet_id);
+    message.extend_from_slice(&update.nonce.to_le_bytes());
+    message.extend_from_slice(&update.epoch_id.to_le_bytes());
+    message.extend_from_slice(&update.new_balance.to_le_bytes());
+    message.extend_from_slice(&update.previous_root);
+    message.extend_from_slice(&update.merkle_root);
+    message
+}
+
+/// Why an update was rejected. Each variant has a stable numeric
+/// [`code`](Self::code) and carries what the wallet needs to recover.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalletUpdateError {
+    /// The wallet has not been registered with this intermediate.
+    UnknownWallet,
+    /// The signature does not verify against the wallet's key.
+    InvalidSignature,
+    /// The exact update was the last one applied; nothing needs to be
+    /// resent.
+    Replay { nonce: u64 },
+    /// The update was made for another epoch than the current one.
+    WrongEpoch { current_epoch: u64 },
+    /// The nonce does not exceed the last accepted one.
+    StaleNonce { last_nonce: u64 },
+    /// The update builds on a root that is no longer the wallet's root.
+    StaleRoot { current_root: Option<Hash> },
+    /// The attached proof is malformed, does not commit to the new root or
+    /// fails verification.
+    InvalidProof(String),
+    /// The update was valid but the intermediate tree could not store it.
+    TreeUpdateFailed(String),
+}
+
+impl WalletUpdateError {
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::UnknownWallet => 1,
+            Self::InvalidSignature => 2,
+            Self::Replay { .. } => 3,
+            Self::StaleNonce { .. } => 4,
+            Self::StaleRoot { .. } => 5,
+            Self::InvalidProof(_) => 6,
+            Self::TreeUpdateFailed(_) => 7,
+            Self::WrongEpoch { .. } => 8,
+        }
+    }
+}
+
+impl fmt::Display for WalletUpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownWallet => write!(f, "Wallet is not registered"),
+            Self::InvalidSignature => write!(f, "Wallet update signature does not verify"),
+            Self::Replay { nonce } => write!(f, "Update with nonce {} was already applied", nonce),
+            Self::WrongEpoch { current_epoch } => {
+                write!(f, "Update is not for the current epoch {}", current_epoch)
+            }
+            Self::StaleNonce { last_nonce } => {
+                write!(f, "Nonce must exceed last accepted nonce {}", last_nonce)
+            }
+            Self::StaleRoot { .. } => write!(f, "Update builds on a stale wallet root"),
+            Self::InvalidProof(reason) => write!(f, "Invalid wallet proof: {}", reason),
+            Self::TreeUpdateFailed(reason) => {
+                write!(f, "Intermediate tree update failed: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WalletUpdateError {}
+
+impl From<WalletUpdateError> for SystemError {
+    fn from(err: WalletUpdateError) -> Self {
+        let error_type = match &err {
+            WalletUpdateError::UnknownWallet => SystemErrorType::NotFound,
+            WalletUpdateError::InvalidSignature => SystemErrorType::InvalidSignature,
+            WalletUpdateError::Replay { .. } | WalletUpdateError::StaleNonce { .. } => {
+                SystemErrorType::InvalidNonce
+            }
+            WalletUpdateError::WrongEpoch { .. } => SystemErrorType::InvalidSequence,
+            WalletUpdateError::StaleRoot { .. } => SystemErrorType::StateDataMismatch,
+            WalletUpdateError::InvalidProof(_) => SystemErrorType::InvalidProof,
+            WalletUpdateError::TreeUpdateFailed(_) => SystemErrorType::StateUpdateError,
+        };
+        SystemError::new(error_type, err.to_string())
+    }
+}
+
+/// The intermediate tree accepted wallet roots are written to: either the
+/// tree itself or a [`RootSubmitterI`] that stages them for its next
+/// snapshot.
+pub trait WalletRootStore {
+    fn wallet_root(&self, wallet_id: &WalletId) -> Option<Hash>;
+
+    fn set_wallet_root(&mut self, wallet_id: WalletId, root: Hash) -> Result<(), SystemError>;
+}
+
+impl WalletRootStore for SparseMerkleTreeI {
+    fn wallet_root(&self, wallet_id: &WalletId) -> Option<Hash> {
+        self.get(wallet_id)
+    }
+
+    fn set_wallet_root(&mut self, wallet_id: WalletId, root: Hash) -> Result<(), SystemError> {
+        self.update(&wallet_id, &root)
+    }
+}
+
+impl<C: Clock> WalletRootStore for RootSubmitterI<C> {
+    fn wallet_root(&self, wallet_id: &WalletId) -> Option<Hash> {
+        self.current_root(wallet_id)
+    }
+
+    fn set_wallet_root(&mut self, wallet_id: WalletId, root: Hash) -> Result<(), SystemError> {
+        self.stage_update(wallet_id, root);
+        Ok(())
+    }
+}
+
+struct WalletRecord {
+    key: PartyKey,
+    /// Nonce and digest of the last accepted update, to tell an exact resend
+    /// apart from a conflicting update that reuses its nonce.
+    last_applied: Option<(u64, Hash)>,
+}
+
+/// Accepts signed wallet state updates into the intermediate tree.
+///
+/// The signature is checked first, so an unsigned request learns nothing
+/// about the wallet's state, and the proof last, so it is only verified for
+/// updates whose epoch, nonce and previous root are current. A resend of the
+/// last applied update is reported as a replay, whichever epoch it was made
+/// for; older updates are rejected as stale.
+pub struct WalletUpdateIngestor<V: IntermediateProofVerifier> {
+    verifier: V,
+    current_epoch: u64,
+    wallets: HashMap<WalletId, WalletRecord>,
+}
+
+impl<V: IntermediateProofVerifier> WalletUpdateIngestor<V> {
+    pub fn new(verifier: V, current_epoch: u64) -> Self {
+        Self {
+            verifier,
+            current_epoch,
+            wallets: HashMap::new(),
+        }
+    }
+
+    pub fn current_epoch(&self) -> u64 {
+        self.current_epoch
+    }
+
+    /// Moves to `epoch`; updates made for earlier epochs are rejected from
+    /// then on.
+    pub fn advance_epoch(&mut self, epoch: u64) -> Result<(), SystemError> {
+        if epoch < self.current_epoch {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidSequence,
+                "Epoch cannot move backwards".to_string(),
+            ));
+        }
+        self.current_epoch = epoch;
+        Ok(())
+    }
+
+    /// Handles `IntermediateOpCode::RegisterWallet`: updates for
+    /// `wallet_id` must be signed with `key`. The wallet proves it owns the
+    /// id with `signature`, made with `key` over
+    /// [`wallet_registration_message`], and the id has to be the one
+    /// [`wallet_id_for_key`] derives from `key`.
+    pub fn register_wallet(
+        &mut self,
+        wallet_id: WalletId,
+        key: PartyKey,
+        signature: &[u8],
+    ) -> Result<(), SystemError> {
+        if wallet_id != wallet_id_for_key(&key) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Wallet id is not derived from the wallet key".to_string(),
+            ));
+        }
+        if !key.verify(&wallet_registration_message(&wallet_id, &key), signature) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidSignature,
+                "Wallet registration signature does not verify".to_string(),
+            ));
+        }
+        if self.wallets.contains_key(&wallet_id) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Wallet is already registered".to_string(),
+            ));
+        }
+        self.wallets.insert(
+            wallet_id,
+            WalletRecord {
+                key,
+                last_applied: None,
+            },
+        );
+        Ok(())
+    }
+
+    /// Nonce of the last update accepted for the wallet.
+    pub fn last_nonce(&self, wallet_id: &WalletId) -> Option<u64> {
+        self.wallets
+            .get(wallet_id)
+            .and_then(|wallet| wallet.last_applied)
+            .map(|(nonce, _)| nonce)
+    }
+
+    /// Handles `IntermediateOpCode::ValidateWalletRoot`: checks `update`
+    /// against the wallet's state in `store` without applying it.
+    pub fn validate_update<S: WalletRootStore>(
+        &self,
+        update: &WalletStateUpdate,
+        store: &S,
+    ) -> Result<(), WalletUpdateError> {
+        let wallet = self
+            .wallets
+            .get(&update.wallet_id)
+            .ok_or(WalletUpdateError::UnknownWallet)?;
+        if !wallet
+            .key
+            .verify(&wallet_update_message(update), &update.signature)
+        {
+            return Err(WalletUpdateError::InvalidSignature);
+        }
+
+        if wallet.last_applied == Some((update.nonce, update_digest(update))) {
+            return Err(WalletUpdateError::Replay {
+                nonce: update.nonce,
+            });
+        }
+        if update.epoch_id != self.current_epoch {
+            return Err(WalletUpdateError::WrongEpoch {
+                current_epoch: self.current_epoch,
+            });
+        }
+        if let Some((last_nonce, _)) = wallet.last_applied {
+            if update.nonce <= last_nonce {
+                return Err(WalletUpdateError::StaleNonce { last_nonce });
+            }
+        }
+
+        let current_root = store.wallet_root(&update.wallet_id);
+        if update.previous_root != current_root.unwrap_or([0u8; 32]) {
+            return Err(WalletUpdateError::StaleRoot { current_root });
+        }
+
+        update
+            .proof
+            .verify_internally()
+            .map_err(|e| WalletUpdateError::InvalidProof(e.message))?;
+        self.verifier
+            .verify(&update.proof, &update.merkle_root)
+            .map_err(|e| WalletUpdateError::InvalidProof(e.message))
+    }
+
+    /// Handles `IntermediateOpCode::UpdateWalletRoot`: validates `update`
+    /// and writes the new root to `store`.
+    pub fn apply_update<S: WalletRootStore>(
+        &mut self,
+        update: &WalletStateUpdate,
+        store: &mut S,
+    ) -> Result<(), WalletUpdateError> {
+        self.validate_update(update, store)?;
+        store
+            .set_wallet_root(update.wallet_id, update.merkle_root)
+            .map_err(|e| WalletUpdateError::TreeUpdateFailed(e.message))?;
+
+        let wallet = self.wallets.get_mut(&update.wallet_id).unwrap();
+        wallet.last_applied = Some((update.nonce, update_digest(update)));
+        Ok(())
+    }
+}
+
+/// Identifies an update by everything the wallet signed plus the signature
+/// itself.
+fn update_digest(update: &WalletStateUpdate) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(wallet_update_message(update));
+    hasher.update(&update.signature);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    /// Accepts any proof that commits to the expected value, reading the
+    /// commitment from `merkle_root`.
+    struct AcceptCommitted;
+
+    impl IntermediateProofVerifier for AcceptCommitted {
+        fn verify(&self, proof: &ZkProof, commitment: &[u8; 32]) -> Result<(), SystemError> {
+            if proof.merkle_root != *commitment {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidProof,
+                    "wrong commitment".to_string(),
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    struct RejectAll;
+
+    impl IntermediateProofVerifier for RejectAll {
+        fn verify(&self, _proof: &ZkProof, _commitment: &[u8; 32]) -> Result<(), SystemError> {
+            Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "rejected".to_string(),
+            ))
+        }
+    }
+
+    fn wallet_key() -> SigningKey {
+        SigningKey::from_bytes(&[3; 32])
+    }
+
+    fn wallet() -> (WalletId, PartyKey) {
+        let key = PartyKey::Ed25519(wallet_key().verifying_key().to_bytes());
+        (wallet_id_for_key(&key), key)
+    }
+
+    fn ingestor<V: IntermediateProofVerifier>(verifier: V) -> WalletUpdateIngestor<V> {
+        let mut ingestor = WalletUpdateIngestor::new(verifier, 1);
+        let (wallet_id, key) = wallet();
+        let signature = wallet_key().sign(&wallet_registration_message(&wallet_id, &key));
+        ingestor
+            .register_wallet(wallet_id, key, &signature.to_bytes())
+            .unwrap();
+        ingestor
+    }
+
+    fn update(nonce: u64, previous_root: Hash, merkle_root: Hash) -> WalletStateUpdate {
+        let mut update = WalletStateUpdate {
+            wallet_id: wallet().0,
+            new_balance: 100,
+            merkle_root,
+            epoch_id: 1,
+            nonce,
+            previous_root,
+            proof: ZkProof::new(vec![1], vec![1], merkle_root.to_vec(), 0),
+            signature: Vec::new(),
+        };
+        update.signature = wallet_key()
+            .sign(&wallet_update_message(&update))
+            .to_bytes()
+            .to_vec();
+        update
+    }
+
+    #[test]
+    fn test_updates_advance_root_and_nonce() {
+        let mut ingestor = ingestor(AcceptCommitted);
+        let mut tree = SparseMerkleTreeI::new();
+
+        ingestor
+            .apply_update(&update(1, [0; 32], [1; 32]), &mut tree)
+            .unwrap();
+        ingestor
+            .apply_update(&update(5, [1; 32], [2; 32]), &mut tree)
+            .unwrap();
+        assert_eq!(tree.get(&wallet().0), Some([2; 32]));
+        assert_eq!(ingestor.last_nonce(&wallet().0), Some(5));
+    }
+
+    #[test]
+    fn test_replays_and_stale_updates_get_distinct_codes() {
+        let mut ingestor = ingestor(AcceptCommitted);
+        let mut tree = SparseMerkleTreeI::new();
+        let first = update(1, [0; 32], [1; 32]);
+        ingestor.apply_update(&first, &mut tree).unwrap();
+
+        let replay = ingestor.apply_update(&first, &mut tree).unwrap_err();
+        assert_eq!(replay, WalletUpdateError::Replay { nonce: 1 });
+        assert_eq!(replay.code(), 3);
+
+        let reused_nonce = ingestor
+            .apply_update(&update(1, [1; 32], [2; 32]), &mut tree)
+            .unwrap_err();
+        assert_eq!(
+            reused_nonce,
+            WalletUpdateError::StaleNonce { last_nonce: 1 }
+        );
+
+        let stale_root = ingestor
+            .apply_update(&update(2, [0; 32], [2; 32]), &mut tree)
+            .unwrap_err();
+        assert_eq!(
+            stale_root,
+            WalletUpdateError::StaleRoot {
+                current_root: Some([1; 32])
+            }
+        );
+        assert_eq!(
+            SystemError::from(stale_root).error_type(),
+            SystemErrorType::StateDataMismatch
+        );
+        assert_eq!(tree.get(&wallet().0), Some([1; 32]));
+    }
+
+    #[test]
+    fn test_resend_of_last_update_is_a_replay() {
+        let mut ingestor = ingestor(AcceptCommitted);
+        let mut tree = SparseMerkleTreeI::new();
+        let first = update(1, [0; 32], [1; 32]);
+        let second = update(2, [1; 32], [2; 32]);
+        ingestor.apply_update(&first, &mut tree).unwrap();
+        ingestor.apply_update(&second, &mut tree).unwrap();
+        ingestor.advance_epoch(2).unwrap();
+
+        assert_eq!(
+            ingestor.apply_update(&second, &mut tree).unwrap_err(),
+            WalletUpdateError::Replay { nonce: 2 }
+        );
+        // Only the last update is remembered; earlier ones are just stale.
+        assert_eq!(
+            ingestor.apply_update(&first, &mut tree).unwrap_err(),
+            WalletUpdateError::WrongEpoch { current_epoch: 2 }
+        );
+        assert_eq!(tree.get(&wallet().0), Some([2; 32]));
+    }
+
+    #[test]
+    fn test_rejects_updates_for_other_epochs() {
+        let mut ingestor = ingestor(AcceptCommitted);
+        let mut tree = SparseMerkleTreeI::new();
+        ingestor.advance_epoch(2).unwrap();
+
+        let err = ingestor
+            .apply_update(&update(1, [0; 32], [1; 32]), &mut tree)
+            .unwrap_err();
+        assert_eq!(err, WalletUpdateError::WrongEpoch { current_epoch: 2 });
+        assert_eq!(err.code(), 8);
+        assert!(tree.is_empty());
+        assert!(ingestor.advance_epoch(1).is_err());
+    }
+
+    #[test]
+    fn test_rejects_bad_signature_and_proof_before_updating() {
+        let mut tree = SparseMerkleTreeI::new();
+        let mut forged = update(1, [0; 32], [1; 32]);
+        forged.new_balance += 1;
+        assert_eq!(
+            ingestor(AcceptCommitted)
+                .apply_update(&forged, &mut tree)
+                .unwrap_err(),
+            WalletUpdateError::InvalidSignature
+        );
+
+        let mut ingestor = ingestor(RejectAll);
+        let err = ingestor
+            .apply_update(&update(1, [0; 32], [1; 32]), &mut tree)
+            .unwrap_err();
+        assert_eq!(err.code(), 6);
+        assert!(tree.is_empty());
+        assert_eq!(ingestor.last_nonce(&wallet().0), None);
+
+        let mut unknown = update(1, [0; 32], [1; 32]);
+        unknown.wallet_id = [8; 32];
+        assert_eq!(
+            ingestor.validate_update(&unknown, &tree).unwrap_err(),
+            WalletUpdateError::UnknownWallet
+        );
+    }
+
+    #[test]
+    fn test_registration_requires_ownership_of_the_wallet_id() {
+        let mut ingestor = WalletUpdateIngestor::new(AcceptCommitted, 1);
+        let (wallet_id, key) = wallet();
+        let signature = wallet_key()
+            .sign(&wallet_registration_message(&wallet_id, &key))
+            .to_bytes();
+
+        let other_key = SigningKey::from_bytes(&[4; 32]);
+        let forged = other_key
+            .sign(&wallet_registration_message(&wallet_id, &key))
+            .to_bytes();
+        assert_eq!(
+            ingestor
+                .register_wallet(wallet_id, key, &forged)
+                .unwrap_err()
+                .error_type(),
+            SystemErrorType::InvalidSignature
+        );
+
+        // A key cannot claim an id derived from another key.
+        let squatter = PartyKey::Ed25519(other_key.verifying_key().to_bytes());
+        let squatted = other_key
+            .sign(&wallet_registration_message(&wallet_id, &squatter))
+            .to_bytes();
+        assert!(ingestor
+            .register_wallet(wallet_id, squatter, &squatted)
+            .is_err());
+
+        ingestor
+            .register_wallet(wallet_id, key, &signature)
+            .unwrap();
+        assert!(ingestor
+            .register_wallet(wallet_id, key, &signature)
+            .is_err());
+    }
+}
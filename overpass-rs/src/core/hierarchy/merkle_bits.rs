@@ -0,0 +1,31 @@
+// ./src/core/hierarchy/merkle_bits.rs
+
+//! Bit access on 256-bit keys, shared by the root and intermediate sparse
+//! Merkle trees. Bit 0 is the most significant bit of the first byte, which
+//! is also the branch taken first when walking down from the root.
+
+/// Extract a bit from the key at a specific index (MSB first)
+pub(crate) fn get_bit(key: &[u8; 32], index: usize) -> bool {
+    (key[index / 8] >> (7 - index % 8)) & 1 == 1
+}
+
+pub(crate) fn set_bit(key: &mut [u8; 32], index: usize) {
+    key[index / 8] |= 1 << (7 - index % 8);
+}
+
+pub(crate) fn flip_bit(key: &[u8; 32], index: usize) -> [u8; 32] {
+    let mut flipped = *key;
+    flipped[index / 8] ^= 1 << (7 - index % 8);
+    flipped
+}
+
+/// Keep the first `depth` bits of `key` and zero the rest
+pub(crate) fn prefix(key: &[u8; 32], depth: usize) -> [u8; 32] {
+    let mut masked = [0u8; 32];
+    let full_bytes = depth / 8;
+    masked[..full_bytes].copy_from_slice(&key[..full_bytes]);
+    if depth % 8 != 0 {
+        masked[full_bytes] = key[full_bytes] & (0xFF << (8 - depth % 8));
+    }
+    masked
+}
@@ -1,5 +1,7 @@
 // ./src/core/hierarchy/mod.rs
 
 mod client;
+pub mod clock;
 pub mod intermediate;
+pub(crate) mod merkle_bits;
 pub mod root;
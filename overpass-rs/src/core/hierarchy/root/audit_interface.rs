@@ -1,38 +1,59 @@
 // src/core/global/audit_interface.rs
 
-pub struct AuditInterface;
-
-impl AuditInterface {
-    /// Returns the current global root.
-    pub fn query_global_root() -> [u8; 32] {
-        let mut root = [0u8; 32];
-
-        // Read the current root from storage
-        if let Ok(stored_root) = std::fs::read("global_root.bin") {
-            if stored_root.len() == 32 {
-                root.copy_from_slice(&stored_root);
-            }
-        }
+use crate::core::error::errors::SystemError;
+use crate::core::hierarchy::root::root_history::{
+    EpochDiff, EpochRootRecord, HistoryPage, RootHistory,
+};
+use crate::core::hierarchy::root::sparse_merkle_tree_r::SparseMerkleProof;
+
+/// Read-only view over the root history for auditors.
+pub struct AuditInterface<'a> {
+    history: &'a RootHistory,
+}
+
+impl<'a> AuditInterface<'a> {
+    pub fn new(history: &'a RootHistory) -> Self {
+        Self { history }
+    }
+
+    /// Returns the most recently anchored global root.
+    pub fn query_global_root(&self) -> Option<&'a EpochRootRecord> {
+        self.history.latest()
+    }
 
-        root
+    /// Returns the record anchored for a specific epoch.
+    pub fn query_epoch(&self, epoch: u64) -> Option<&'a EpochRootRecord> {
+        self.history.get(epoch)
     }
 
-    /// Allows for querying the history of global roots or proofs.
-    pub fn query_root_history() -> Vec<[u8; 32]> {
-        let mut history = Vec::new();
-
-        // Read the history from storage
-        if let Ok(stored_history) = std::fs::read("root_history.bin") {
-            // Each root is 32 bytes
-            for chunk in stored_history.chunks(32) {
-                if chunk.len() == 32 {
-                    let mut root = [0u8; 32];
-                    root.copy_from_slice(chunk);
-                    history.push(root);
-                }
-            }
+    /// Returns every record between `from_epoch` and `to_epoch`, inclusive.
+    pub fn query_root_range(&self, from_epoch: u64, to_epoch: u64) -> Vec<&'a EpochRootRecord> {
+        if from_epoch > to_epoch {
+            return Vec::new();
         }
+        self.history.range(from_epoch..=to_epoch).collect()
+    }
+
+    /// Pages through the full history, oldest epoch first.
+    pub fn query_root_history(&self, after_epoch: Option<u64>, limit: usize) -> HistoryPage {
+        self.history.page(after_epoch, limit)
+    }
+
+    /// Shows which intermediate roots changed between two epochs.
+    pub fn query_epoch_diff(
+        &self,
+        from_epoch: u64,
+        to_epoch: u64,
+    ) -> Result<EpochDiff, SystemError> {
+        self.history.diff(from_epoch, to_epoch)
+    }
 
-        history
+    /// Proves that an intermediate root was part of the global root of `epoch`.
+    pub fn query_inclusion_proof(
+        &self,
+        epoch: u64,
+        contract_addr: &[u8; 32],
+    ) -> Result<([u8; 32], SparseMerkleProof), SystemError> {
+        self.history.prove_intermediate(epoch, contract_addr)
     }
 }
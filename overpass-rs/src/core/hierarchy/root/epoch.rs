@@ -5,6 +5,9 @@ use std::time::{SystemTime, UNIX_EPOCH};
 #[derive(Debug, Clone, PartialEq)]
 pub enum EpochStatus {
     Active,
+    Sealed,
+    Submitted,
+    Finalized,
     Completed,
 }
 
@@ -19,7 +22,11 @@ pub struct Epoch {
 impl Epoch {
     /// Starts a new epoch.
     pub fn start_new(epoch_number: u64) -> Self {
-        let start_time = current_timestamp();
+        Self::start_at(epoch_number, current_timestamp())
+    }
+
+    /// Starts a new epoch at the given timestamp.
+    pub fn start_at(epoch_number: u64, start_time: u64) -> Self {
         Self {
             epoch_number,
             start_time,
@@ -35,13 +42,13 @@ impl Epoch {
 
     /// Returns true if the epoch has been completed.
     pub fn is_completed(&self) -> bool {
-        matches!(self.state, EpochStatus::Completed)
+        matches!(self.state, EpochStatus::Completed | EpochStatus::Finalized)
     }
 
     /// Returns the duration of the epoch in seconds.
     /// Returns None if the epoch is still active.
     pub fn duration(&self) -> Option<u64> {
-        if self.is_completed() {
+        if !self.is_active() {
             Some(self.end_time.saturating_sub(self.start_time))
         } else {
             None
@@ -59,6 +66,35 @@ impl Epoch {
         Ok(())
     }
 
+    /// Stops collecting roots for this epoch.
+    /// Returns an error unless the epoch is active.
+    pub fn seal(&mut self, now: u64) -> Result<(), &'static str> {
+        if !self.is_active() {
+            return Err("Only an active epoch can be sealed");
+        }
+        self.end_time = now.max(self.start_time);
+        self.state = EpochStatus::Sealed;
+        Ok(())
+    }
+
+    /// Marks the sealed epoch's global root as submitted.
+    pub fn mark_submitted(&mut self) -> Result<(), &'static str> {
+        if !matches!(self.state, EpochStatus::Sealed) {
+            return Err("Only a sealed epoch can be submitted");
+        }
+        self.state = EpochStatus::Submitted;
+        Ok(())
+    }
+
+    /// Marks the submitted global root as final.
+    pub fn finalize(&mut self) -> Result<(), &'static str> {
+        if !matches!(self.state, EpochStatus::Submitted) {
+            return Err("Only a submitted epoch can be finalized");
+        }
+        self.state = EpochStatus::Finalized;
+        Ok(())
+    }
+
     /// Creates a new epoch from the given parameters.
     /// Returns an error if the parameters are invalid.
     pub fn new(
@@ -70,7 +106,7 @@ impl Epoch {
         if end_time != 0 && end_time < start_time {
             return Err("End time cannot be before start time");
         }
-        if end_time == 0 && matches!(state, EpochStatus::Completed) {
+        if end_time == 0 && !matches!(state, EpochStatus::Active) {
             return Err("Completed epoch must have an end time");
         }
         if end_time != 0 && matches!(state, EpochStatus::Active) {
@@ -129,5 +165,22 @@ mod tests {
         assert!(Epoch::new(1, 100, 150, EpochStatus::Active).is_err());
         assert!(Epoch::new(1, 100, 0, EpochStatus::Active).is_ok());
         assert!(Epoch::new(1, 100, 150, EpochStatus::Completed).is_ok());
+        assert!(Epoch::new(1, 100, 0, EpochStatus::Sealed).is_err());
+    }
+
+    #[test]
+    fn test_submission_lifecycle() {
+        let mut epoch = Epoch::start_at(1, 100);
+        assert!(epoch.mark_submitted().is_err());
+
+        epoch.seal(160).unwrap();
+        assert_eq!(epoch.state, EpochStatus::Sealed);
+        assert_eq!(epoch.duration(), Some(60));
+        assert!(epoch.seal(170).is_err());
+        assert!(epoch.finalize().is_err());
+
+        epoch.mark_submitted().unwrap();
+        epoch.finalize().unwrap();
+        assert!(epoch.is_completed());
     }
 }
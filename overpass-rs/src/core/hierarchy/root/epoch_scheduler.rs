@@ -0,0 +1,857 @@
+// src/core/hierarchy/root/epoch_scheduler.rs
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::clock::Clock;
+use crate::core::hierarchy::intermediate::sparse_merkle_tree_i::SparseMerkleTreeI;
+use crate::core::hierarchy::root::epoch::{Epoch, EpochStatus};
+use crate::core::hierarchy::root::global_tree_manager::RootTransition;
+use crate::core::hierarchy::root::root_contract::{GlobalRootSubmission, RootContract};
+use crate::core::hierarchy::root::root_history::RootSettlementStatus;
+use std::collections::{BTreeMap, VecDeque};
+
+type Hash = [u8; 32];
+type Address = [u8; 32];
+
+/// Destination for sealed global roots.
+pub trait RootSubmitter {
+    /// Submits a sealed global root. An error schedules a retry.
+    fn submit(&mut self, submission: &GlobalRootSubmission) -> Result<(), SystemError>;
+
+    /// Reports whether a previously submitted epoch has become final.
+    fn is_finalized(&mut self, epoch: u64) -> Result<bool, SystemError>;
+}
+
+/// Scheduling settings. Epochs last the root contract's epoch duration.
+#[derive(Debug, Clone)]
+pub struct EpochSchedulerConfig {
+    /// Seal early once this many distinct intermediates have submitted.
+    /// Zero disables the quorum trigger.
+    pub quorum: usize,
+    /// Submission attempts before an epoch is given up on.
+    pub max_submission_attempts: u32,
+    /// Delay before the first retry; doubles on every further failure.
+    pub retry_backoff: u64,
+}
+
+impl Default for EpochSchedulerConfig {
+    fn default() -> Self {
+        Self {
+            quorum: 0,
+            max_submission_attempts: 5,
+            retry_backoff: 2,
+        }
+    }
+}
+
+/// What happened during a call to [`EpochScheduler::tick`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchedulerEvent {
+    /// A buffered root no longer validated when its epoch sealed, e.g.
+    /// because the operator key was rotated or the root its proof starts
+    /// from was rolled back in between. Roots queued behind it go with it.
+    RootDropped {
+        contract_addr: Address,
+    },
+    /// A buffered root was left out of its epoch because the intermediate
+    /// still has optimistic roots in their challenge window, which later
+    /// roots must build on.
+    RootSkipped {
+        contract_addr: Address,
+    },
+    EpochSealed {
+        epoch: u64,
+        global_root: Hash,
+    },
+    EpochSubmitted {
+        epoch: u64,
+    },
+    SubmissionRetry {
+        epoch: u64,
+        attempt: u32,
+        retry_at: u64,
+    },
+    /// The epoch was given up on after the maximum number of attempts and
+    /// its roots were rolled back.
+    SubmissionFailed {
+        epoch: u64,
+    },
+    /// The epoch was sealed on top of one that failed, so it was rejected
+    /// with it.
+    EpochDiscarded {
+        epoch: u64,
+    },
+    EpochFinalized {
+        epoch: u64,
+    },
+}
+
+struct CollectedRoot {
+    root: Hash,
+    wallet_count: u64,
+    signature: [u8; 64],
+    transition: RootTransition,
+}
+
+struct PendingSubmission {
+    epoch: Epoch,
+    submission: GlobalRootSubmission,
+    attempts: u32,
+    next_attempt_at: u64,
+}
+
+/// Drives epochs through Active -> Sealed -> Submitted -> Finalized.
+///
+/// Intermediate roots are buffered for the active epoch and only applied to
+/// the root contract when the epoch seals, so every submission reflects
+/// exactly the roots collected during that epoch. A new epoch opens as soon
+/// as the previous one seals; submission and finality tracking of older
+/// epochs run alongside it.
+pub struct EpochScheduler<C: Clock> {
+    clock: C,
+    config: EpochSchedulerConfig,
+    root_contract: RootContract,
+    current: Epoch,
+    /// Per intermediate, in submission order. Each builds on the one before.
+    collected: BTreeMap<Address, Vec<CollectedRoot>>,
+    awaiting_submission: VecDeque<PendingSubmission>,
+    awaiting_finality: VecDeque<Epoch>,
+}
+
+impl<C: Clock> EpochScheduler<C> {
+    pub fn new(clock: C, config: EpochSchedulerConfig, root_contract: RootContract) -> Self {
+        let current = Epoch::start_at(root_contract.epoch() + 1, clock.now());
+        Self {
+            clock,
+            config,
+            root_contract,
+            current,
+            collected: BTreeMap::new(),
+            awaiting_submission: VecDeque::new(),
+            awaiting_finality: VecDeque::new(),
+        }
+    }
+
+    pub fn current_epoch(&self) -> &Epoch {
+        &self.current
+    }
+
+    pub fn root_contract(&self) -> &RootContract {
+        &self.root_contract
+    }
+
+    /// Registry changes (registration, key rotation, deregistration) go
+    /// through the root contract directly.
+    pub fn root_contract_mut(&mut self) -> &mut RootContract {
+        &mut self.root_contract
+    }
+
+    /// Epochs that are sealed or submitted but not yet final, oldest first.
+    pub fn in_flight(&self) -> Vec<&Epoch> {
+        self.awaiting_submission
+            .iter()
+            .map(|pending| &pending.epoch)
+            .chain(self.awaiting_finality.iter())
+            .collect()
+    }
+
+    /// Buffers a signed intermediate root for the active epoch once its
+    /// proof checks out. A later root from the same intermediate is queued
+    /// behind the earlier one, so its proof has to start from that root and
+    /// carry a higher sequence.
+    pub fn submit_intermediate_root(
+        &mut self,
+        contract_addr: Address,
+        root: Hash,
+        wallet_count: u64,
+        signature: [u8; 64],
+        transition: RootTransition,
+    ) -> Result<(), SystemError> {
+        if !self.current.is_active() {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidState,
+                "No active epoch is accepting roots".to_string(),
+            ));
+        }
+        self.root_contract.validate_intermediate_root(
+            &contract_addr,
+            &root,
+            wallet_count,
+            &signature,
+        )?;
+        let (old_root, after) = match self
+            .collected
+            .get(&contract_addr)
+            .and_then(|queued| queued.last())
+        {
+            Some(last) => (last.root, Some(last.transition.sequence)),
+            None => accepted_root(&self.root_contract, &contract_addr),
+        };
+        self.root_contract
+            .verify_root_transition(&old_root, &root, after, &transition)?;
+        self.collected
+            .entry(contract_addr)
+            .or_default()
+            .push(CollectedRoot {
+                root,
+                wallet_count,
+                signature,
+                transition,
+            });
+        Ok(())
+    }
+
+    /// Advances every epoch as far as the current time allows.
+    pub fn tick<S: RootSubmitter>(
+        &mut self,
+        submitter: &mut S,
+    ) -> Result<Vec<SchedulerEvent>, SystemError> {
+        let now = self.clock.now();
+        let mut events = Vec::new();
+
+        if self.should_seal(now) {
+            self.seal_current(now, &mut events)?;
+        }
+        self.submit_sealed(now, submitter, &mut events)?;
+        self.finalize_submitted(submitter, &mut events)?;
+
+        Ok(events)
+    }
+
+    fn should_seal(&self, now: u64) -> bool {
+        let deadline = self.current.start_time + self.root_contract.epoch_duration();
+        let quorum_met = self.config.quorum > 0 && self.collected.len() >= self.config.quorum;
+        now >= deadline || quorum_met
+    }
+
+    fn seal_current(
+        &mut self,
+        now: u64,
+        events: &mut Vec<SchedulerEvent>,
+    ) -> Result<(), SystemError> {
+        // Optimistic roots whose window has closed go in first, so only
+        // intermediates with roots still open to challenge are skipped.
+        self.root_contract.accept_unchallenged_roots(now)?;
+        let root_contract = &self.root_contract;
+        self.collected.retain(|contract_addr, queued| {
+            let (old_root, after) = accepted_root(root_contract, contract_addr);
+            let still_valid = root_contract
+                .verify_root_transition(&old_root, &queued[0].root, after, &queued[0].transition)
+                .is_ok()
+                && queued.iter().all(|collected| {
+                    root_contract
+                        .validate_intermediate_root(
+                            contract_addr,
+                            &collected.root,
+                            collected.wallet_count,
+                            &collected.signature,
+                        )
+                        .is_ok()
+                });
+            let event = if !root_contract.pending_roots(contract_addr).is_empty() {
+                SchedulerEvent::RootSkipped {
+                    contract_addr: *contract_addr,
+                }
+            } else if !still_valid {
+                SchedulerEvent::RootDropped {
+                    contract_addr: *contract_addr,
+                }
+            } else {
+                return true;
+            };
+            events.push(event);
+            false
+        });
+
+        // Roots are applied one at a time, before the epoch is touched. If
+        // one fails, those before it stay applied and leave the buffer; the
+        // epoch stays active and the rest are retried on the next tick.
+        while let Some((contract_addr, mut queued)) = self.collected.pop_first() {
+            while !queued.is_empty() {
+                let collected = &queued[0];
+                let applied = self.root_contract.process_intermediate_root(
+                    contract_addr,
+                    collected.root,
+                    collected.wallet_count,
+                    &collected.signature,
+                    &collected.transition,
+                );
+                if let Err(e) = applied {
+                    self.collected.insert(contract_addr, queued);
+                    return Err(e);
+                }
+                queued.remove(0);
+            }
+        }
+        let submission = self.root_contract.seal_epoch(now)?;
+
+        let mut sealed = std::mem::replace(
+            &mut self.current,
+            Epoch::start_at(submission.epoch + 1, now),
+        );
+        sealed.seal(now).map_err(invalid_state)?;
+
+        events.push(SchedulerEvent::EpochSealed {
+            epoch: submission.epoch,
+            global_root: submission.global_root,
+        });
+        self.awaiting_submission.push_back(PendingSubmission {
+            epoch: sealed,
+            submission,
+            attempts: 0,
+            next_attempt_at: now,
+        });
+        Ok(())
+    }
+
+    fn submit_sealed<S: RootSubmitter>(
+        &mut self,
+        now: u64,
+        submitter: &mut S,
+        events: &mut Vec<SchedulerEvent>,
+    ) -> Result<(), SystemError> {
+        // Submit strictly in epoch order: a later epoch never overtakes an
+        // earlier one that is still being retried.
+        while let Some(pending) = self.awaiting_submission.front_mut() {
+            if pending.next_attempt_at > now {
+                break;
+            }
+
+            let epoch = pending.submission.epoch;
+            match submitter.submit(&pending.submission) {
+                Ok(()) => {
+                    let mut pending = self.awaiting_submission.pop_front().unwrap();
+                    pending.epoch.mark_submitted().map_err(invalid_state)?;
+                    self.awaiting_finality.push_back(pending.epoch);
+                    events.push(SchedulerEvent::EpochSubmitted { epoch });
+                }
+                Err(_) => {
+                    pending.attempts += 1;
+                    if pending.attempts >= self.config.max_submission_attempts {
+                        // Every later sealed epoch builds on this one's roots,
+                        // so they are rolled back together.
+                        let rejected = self.root_contract.reject_epoch(epoch)?;
+                        self.awaiting_submission.clear();
+                        events.push(SchedulerEvent::SubmissionFailed { epoch });
+                        events.extend(
+                            rejected
+                                .into_iter()
+                                .skip(1)
+                                .map(|epoch| SchedulerEvent::EpochDiscarded { epoch }),
+                        );
+                        break;
+                    }
+
+                    let backoff = self
+                        .config
+                        .retry_backoff
+                        .saturating_mul(1u64 << (pending.attempts - 1).min(32));
+                    pending.next_attempt_at = now.saturating_add(backoff);
+                    events.push(SchedulerEvent::SubmissionRetry {
+                        epoch,
+                        attempt: pending.attempts,
+                        retry_at: pending.next_attempt_at,
+                    });
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finalize_submitted<S: RootSubmitter>(
+        &mut self,
+        submitter: &mut S,
+        events: &mut Vec<SchedulerEvent>,
+    ) -> Result<(), SystemError> {
+        while let Some(epoch) = self.awaiting_finality.front() {
+            debug_assert_eq!(epoch.state, EpochStatus::Submitted);
+            if !submitter.is_finalized(epoch.epoch_number)? {
+                break;
+            }
+
+            let mut epoch = self.awaiting_finality.pop_front().unwrap();
+            epoch.finalize().map_err(invalid_state)?;
+            self.root_contract
+                .history_mut()
+                .update_status(epoch.epoch_number, RootSettlementStatus::Finalized)?;
+            events.push(SchedulerEvent::EpochFinalized {
+                epoch: epoch.epoch_number,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The root and sequence the next proven root of `contract_addr` builds on
+/// in `root_contract`.
+fn accepted_root(root_contract: &RootContract, contract_addr: &Address) -> (Hash, Option<u64>) {
+    let root = root_contract
+        .intermediate_roots()
+        .get(contract_addr)
+        .copied()
+        .unwrap_or_else(|| SparseMerkleTreeI::new().root());
+    (root, root_contract.last_root_sequence(contract_addr))
+}
+
+fn invalid_state(message: &'static str) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidState, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::hierarchy::clock::ManualClock;
+    use crate::core::hierarchy::root::global_tree_manager::{
+        IntermediateProofVerifier, RootTransitionVerifier,
+    };
+    use crate::core::hierarchy::root::intermediate_registry::{
+        deregistration_message, key_rotation_message, registration_message,
+        root_submission_message, IntermediateRegistration, IntermediateStatus,
+    };
+    use crate::core::zkps::proof::ZkProof;
+    use ed25519_dalek::{Signer, SigningKey};
+    use std::collections::HashSet;
+
+    struct AcceptAll;
+
+    impl RootTransitionVerifier for AcceptAll {
+        fn verify_transition(
+            &self,
+            _proof: &ZkProof,
+            _old_root: &[u8; 32],
+            _sequence: u64,
+            _commitment: &[u8; 32],
+        ) -> Result<(), SystemError> {
+            Ok(())
+        }
+    }
+
+    impl IntermediateProofVerifier for AcceptAll {
+        fn verify(&self, _proof: &ZkProof, _commitment: &[u8; 32]) -> Result<(), SystemError> {
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct MockSubmitter {
+        failures_left: u32,
+        submitted: Vec<u64>,
+        finalized: HashSet<u64>,
+    }
+
+    impl RootSubmitter for MockSubmitter {
+        fn submit(&mut self, submission: &GlobalRootSubmission) -> Result<(), SystemError> {
+            if self.failures_left > 0 {
+                self.failures_left -= 1;
+                return Err(SystemError::new(
+                    SystemErrorType::NetworkError,
+                    "unavailable".to_string(),
+                ));
+            }
+            self.submitted.push(submission.epoch);
+            Ok(())
+        }
+
+        fn is_finalized(&mut self, epoch: u64) -> Result<bool, SystemError> {
+            Ok(self.finalized.contains(&epoch))
+        }
+    }
+
+    /// Test proofs carry the root they start from as their proof data.
+    struct ChainedProofs;
+
+    impl RootTransitionVerifier for ChainedProofs {
+        fn verify_transition(
+            &self,
+            proof: &ZkProof,
+            old_root: &[u8; 32],
+            _sequence: u64,
+            _commitment: &[u8; 32],
+        ) -> Result<(), SystemError> {
+            if proof.proof_data != old_root {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidProof,
+                    "rejected".to_string(),
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    fn scheduler(clock: &ManualClock, quorum: usize) -> EpochScheduler<ManualClock> {
+        scheduler_with(clock, quorum, AcceptAll)
+    }
+
+    fn scheduler_with(
+        clock: &ManualClock,
+        quorum: usize,
+        verifier: impl RootTransitionVerifier + 'static,
+    ) -> EpochScheduler<ManualClock> {
+        let config = EpochSchedulerConfig {
+            quorum,
+            max_submission_attempts: 3,
+            retry_backoff: 2,
+        };
+        let mut root_contract =
+            RootContract::new(10, verifier, AcceptAll).with_challenge_window(50);
+        for i in 1..=2u8 {
+            let registration = IntermediateRegistration {
+                contract_addr: [i; 32],
+                operator_key: operator(i).verifying_key().to_bytes(),
+                key_version: 0,
+                stake: 0,
+                capacity: 100,
+                registered_at: clock.now(),
+                status: IntermediateStatus::Active,
+            };
+            let signature = operator(i)
+                .sign(&registration_message(&registration))
+                .to_bytes();
+            root_contract
+                .register_intermediate(registration, &signature)
+                .unwrap();
+        }
+        EpochScheduler::new(clock.clone(), config, root_contract)
+    }
+
+    fn operator(i: u8) -> SigningKey {
+        SigningKey::from_bytes(&[i; 32])
+    }
+
+    fn transition(old_root: Hash, root: Hash, sequence: u64) -> RootTransition {
+        RootTransition {
+            sequence,
+            commitment: [0; 32],
+            proof: ZkProof::new(old_root.to_vec(), vec![1], root.to_vec(), 0),
+        }
+    }
+
+    fn submit_proven(
+        scheduler: &mut EpochScheduler<ManualClock>,
+        i: u8,
+        root: Hash,
+        transition: RootTransition,
+    ) -> Result<(), SystemError> {
+        let epoch = scheduler.current_epoch().epoch_number;
+        let signature = operator(i)
+            .sign(&root_submission_message(&[i; 32], epoch, &root, 1))
+            .to_bytes();
+        scheduler.submit_intermediate_root([i; 32], root, 1, signature, transition)
+    }
+
+    /// Submits with the epoch as the sequence, which only ever grows.
+    fn submit(scheduler: &mut EpochScheduler<ManualClock>, i: u8, root: Hash) {
+        let epoch = scheduler.current_epoch().epoch_number;
+        submit_proven(scheduler, i, root, transition([0; 32], root, epoch)).unwrap();
+    }
+
+    #[test]
+    fn test_epoch_seals_at_deadline_and_finalizes() {
+        let clock = ManualClock::new(100);
+        let mut scheduler = scheduler(&clock, 0);
+        let mut submitter = MockSubmitter::default();
+
+        submit(&mut scheduler, 1, [9; 32]);
+        assert!(scheduler.tick(&mut submitter).unwrap().is_empty());
+
+        clock.advance(10);
+        let events = scheduler.tick(&mut submitter).unwrap();
+        assert!(matches!(
+            events[0],
+            SchedulerEvent::EpochSealed { epoch: 1, .. }
+        ));
+        assert_eq!(events[1], SchedulerEvent::EpochSubmitted { epoch: 1 });
+        assert_eq!(scheduler.current_epoch().epoch_number, 2);
+
+        submitter.finalized.insert(1);
+        let events = scheduler.tick(&mut submitter).unwrap();
+        assert_eq!(events, vec![SchedulerEvent::EpochFinalized { epoch: 1 }]);
+        assert_eq!(
+            scheduler.root_contract().history().get(1).unwrap().status,
+            RootSettlementStatus::Finalized
+        );
+    }
+
+    #[test]
+    fn test_quorum_seals_early() {
+        let clock = ManualClock::new(100);
+        let mut scheduler = scheduler(&clock, 2);
+        let mut submitter = MockSubmitter::default();
+
+        submit(&mut scheduler, 1, [1; 32]);
+        assert!(scheduler.tick(&mut submitter).unwrap().is_empty());
+
+        submit(&mut scheduler, 2, [2; 32]);
+        let events = scheduler.tick(&mut submitter).unwrap();
+        assert!(matches!(
+            events[0],
+            SchedulerEvent::EpochSealed { epoch: 1, .. }
+        ));
+        assert_eq!(scheduler.root_contract().intermediate_roots().len(), 2);
+    }
+
+    #[test]
+    fn test_failed_submission_is_retried_with_backoff() {
+        let clock = ManualClock::new(100);
+        let mut scheduler = scheduler(&clock, 0);
+        let mut submitter = MockSubmitter {
+            failures_left: 2,
+            ..MockSubmitter::default()
+        };
+
+        clock.advance(10);
+        let events = scheduler.tick(&mut submitter).unwrap();
+        assert_eq!(
+            events[1],
+            SchedulerEvent::SubmissionRetry {
+                epoch: 1,
+                attempt: 1,
+                retry_at: 112
+            }
+        );
+
+        clock.advance(1);
+        assert!(scheduler.tick(&mut submitter).unwrap().is_empty());
+
+        clock.advance(1);
+        let events = scheduler.tick(&mut submitter).unwrap();
+        assert_eq!(
+            events,
+            vec![SchedulerEvent::SubmissionRetry {
+                epoch: 1,
+                attempt: 2,
+                retry_at: 116
+            }]
+        );
+
+        clock.set(116);
+        let events = scheduler.tick(&mut submitter).unwrap();
+        assert_eq!(events, vec![SchedulerEvent::EpochSubmitted { epoch: 1 }]);
+        assert_eq!(submitter.submitted, vec![1]);
+    }
+
+    #[test]
+    fn test_submission_gives_up_after_max_attempts() {
+        let clock = ManualClock::new(100);
+        let mut scheduler = scheduler(&clock, 0);
+        let mut submitter = MockSubmitter {
+            failures_left: u32::MAX,
+            ..MockSubmitter::default()
+        };
+
+        clock.advance(10);
+        scheduler.tick(&mut submitter).unwrap();
+        clock.advance(2);
+        scheduler.tick(&mut submitter).unwrap();
+        clock.advance(4);
+        let events = scheduler.tick(&mut submitter).unwrap();
+
+        assert!(events.contains(&SchedulerEvent::SubmissionFailed { epoch: 1 }));
+        assert!(scheduler.in_flight().is_empty());
+        assert_eq!(
+            scheduler.root_contract().history().get(1).unwrap().status,
+            RootSettlementStatus::Rejected
+        );
+    }
+
+    #[test]
+    fn test_rejected_epochs_roll_back_their_roots() {
+        let clock = ManualClock::new(100);
+        let mut scheduler = scheduler(&clock, 0);
+        let mut submitter = MockSubmitter {
+            failures_left: u32::MAX,
+            ..MockSubmitter::default()
+        };
+        let empty_root = scheduler.root_contract().global_root();
+
+        submit(&mut scheduler, 1, [1; 32]);
+        clock.advance(10);
+        scheduler.tick(&mut submitter).unwrap();
+        assert_eq!(scheduler.root_contract().intermediate_roots().len(), 1);
+
+        // Epoch 2 seals on top of epoch 1 while epoch 1 is still retried.
+        submit(&mut scheduler, 2, [2; 32]);
+        clock.set(120);
+        scheduler.tick(&mut submitter).unwrap();
+        clock.set(124);
+        let events = scheduler.tick(&mut submitter).unwrap();
+        assert_eq!(
+            events,
+            vec![
+                SchedulerEvent::SubmissionFailed { epoch: 1 },
+                SchedulerEvent::EpochDiscarded { epoch: 2 },
+            ]
+        );
+
+        let root_contract = scheduler.root_contract();
+        for epoch in 1..=2 {
+            assert_eq!(
+                root_contract.history().get(epoch).unwrap().status,
+                RootSettlementStatus::Rejected
+            );
+        }
+        assert!(root_contract.intermediate_roots().is_empty());
+        assert_eq!(root_contract.global_root(), empty_root);
+        assert!(scheduler.in_flight().is_empty());
+    }
+
+    #[test]
+    fn test_unregistered_or_unsigned_roots_are_refused() {
+        let clock = ManualClock::new(100);
+        let mut scheduler = scheduler(&clock, 0);
+        let root = [5; 32];
+
+        let forged = operator(2)
+            .sign(&root_submission_message(&[1; 32], 1, &root, 1))
+            .to_bytes();
+        assert!(scheduler
+            .submit_intermediate_root([1; 32], root, 1, forged, transition([0; 32], root, 1))
+            .is_err());
+
+        let unregistered = operator(3)
+            .sign(&root_submission_message(&[3; 32], 1, &root, 1))
+            .to_bytes();
+        assert!(scheduler
+            .submit_intermediate_root([3; 32], root, 1, unregistered, transition([0; 32], root, 1))
+            .is_err());
+    }
+
+    #[test]
+    fn test_rotated_key_drops_buffered_root() {
+        let clock = ManualClock::new(100);
+        let mut scheduler = scheduler(&clock, 0);
+        let mut submitter = MockSubmitter::default();
+        submit(&mut scheduler, 1, [1; 32]);
+
+        let new_key = SigningKey::from_bytes(&[7; 32]).verifying_key().to_bytes();
+        let rotation = operator(1)
+            .sign(&key_rotation_message(&[1; 32], &new_key, 0))
+            .to_bytes();
+        scheduler
+            .root_contract_mut()
+            .rotate_operator_key([1; 32], new_key, &rotation)
+            .unwrap();
+
+        clock.advance(10);
+        let events = scheduler.tick(&mut submitter).unwrap();
+        assert_eq!(
+            events[0],
+            SchedulerEvent::RootDropped {
+                contract_addr: [1; 32]
+            }
+        );
+        assert!(scheduler.root_contract().intermediate_roots().is_empty());
+    }
+
+    #[test]
+    fn test_drained_intermediate_is_retired_after_its_last_epoch() {
+        let clock = ManualClock::new(100);
+        let mut scheduler = scheduler(&clock, 0);
+        let mut submitter = MockSubmitter::default();
+        submit(&mut scheduler, 1, [1; 32]);
+        submit(&mut scheduler, 2, [2; 32]);
+        let signature = operator(1)
+            .sign(&deregistration_message(&[1; 32], 0))
+            .to_bytes();
+        scheduler
+            .root_contract_mut()
+            .begin_deregistration([1; 32], &signature, 105)
+            .unwrap();
+
+        clock.advance(10);
+        scheduler.tick(&mut submitter).unwrap();
+
+        // Its last root is part of the sealed epoch...
+        let sealed = scheduler.root_contract().history().get(1).unwrap();
+        assert!(sealed.intermediate_roots.contains_key(&[1; 32]));
+        // ...and it is gone from the next one.
+        assert!(scheduler.root_contract().registry().get(&[1; 32]).is_none());
+        assert!(!scheduler
+            .root_contract()
+            .intermediate_roots()
+            .contains_key(&[1; 32]));
+        assert!(scheduler
+            .root_contract()
+            .intermediate_roots()
+            .contains_key(&[2; 32]));
+    }
+
+    #[test]
+    fn test_root_skipped_while_optimistic_roots_are_pending() {
+        let clock = ManualClock::new(100);
+        let mut scheduler = scheduler(&clock, 0);
+        let mut submitter = MockSubmitter::default();
+
+        let proposed = [5; 32];
+        let signature = operator(1)
+            .sign(&root_submission_message(&[1; 32], 1, &proposed, 1))
+            .to_bytes();
+        scheduler
+            .root_contract_mut()
+            .propose_intermediate_root([1; 32], proposed, 1, &signature, [0; 32], 100)
+            .unwrap();
+        submit(&mut scheduler, 1, [1; 32]);
+        submit(&mut scheduler, 2, [2; 32]);
+
+        clock.advance(10);
+        let events = scheduler.tick(&mut submitter).unwrap();
+        assert_eq!(
+            events[0],
+            SchedulerEvent::RootSkipped {
+                contract_addr: [1; 32]
+            }
+        );
+        assert!(matches!(
+            events[1],
+            SchedulerEvent::EpochSealed { epoch: 1, .. }
+        ));
+        let roots = scheduler.root_contract().intermediate_roots();
+        assert!(!roots.contains_key(&[1; 32]));
+        assert_eq!(roots[&[2; 32]], [2; 32]);
+
+        // Once the window has closed the proposed root is accepted at the
+        // next seal and direct roots are taken again.
+        clock.advance(40);
+        submit(&mut scheduler, 1, [1; 32]);
+        let events = scheduler.tick(&mut submitter).unwrap();
+        assert!(matches!(
+            events[0],
+            SchedulerEvent::EpochSealed { epoch: 2, .. }
+        ));
+        assert_eq!(
+            scheduler.root_contract().intermediate_roots()[&[1; 32]],
+            [1; 32]
+        );
+    }
+
+    #[test]
+    fn test_buffered_roots_are_proven_in_a_chain() {
+        let clock = ManualClock::new(100);
+        let mut scheduler = scheduler_with(&clock, 0, ChainedProofs);
+        let mut submitter = MockSubmitter::default();
+        let empty = SparseMerkleTreeI::new().root();
+
+        assert!(
+            submit_proven(&mut scheduler, 1, [1; 32], transition([9; 32], [1; 32], 0)).is_err()
+        );
+        submit_proven(&mut scheduler, 1, [1; 32], transition(empty, [1; 32], 0)).unwrap();
+        // Later roots build on the buffered one with a higher sequence.
+        assert!(submit_proven(&mut scheduler, 1, [2; 32], transition(empty, [2; 32], 1)).is_err());
+        assert!(
+            submit_proven(&mut scheduler, 1, [2; 32], transition([1; 32], [2; 32], 0)).is_err()
+        );
+        submit_proven(&mut scheduler, 1, [2; 32], transition([1; 32], [2; 32], 1)).unwrap();
+
+        clock.advance(10);
+        scheduler.tick(&mut submitter).unwrap();
+        let root_contract = scheduler.root_contract();
+        assert_eq!(root_contract.intermediate_roots()[&[1; 32]], [2; 32]);
+        assert_eq!(root_contract.last_root_sequence(&[1; 32]), Some(1));
+
+        // The next epoch continues from the applied chain.
+        assert!(
+            submit_proven(&mut scheduler, 1, [3; 32], transition([1; 32], [3; 32], 2)).is_err()
+        );
+        submit_proven(&mut scheduler, 1, [3; 32], transition([2; 32], [3; 32], 2)).unwrap();
+    }
+}
@@ -0,0 +1,329 @@
+// src/core/hierarchy/root/fraud_proof.rs
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::intermediate::sparse_merkle_tree_i::{self, WalletMerkleProof};
+use crate::core::hierarchy::root::global_tree_manager::IntermediateProofVerifier;
+use crate::core::zkps::proof::ZkProof;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// One wallet-root change inside an optimistically submitted intermediate
+/// root. `proof` is only checked if someone challenges the submission.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletRootUpdate {
+    pub wallet_id: [u8; 32],
+    pub new_wallet_root: [u8; 32],
+    pub proof: ZkProof,
+}
+
+/// An intermediate root waiting out its challenge window.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingIntermediateRoot {
+    pub contract_addr: [u8; 32],
+    /// Root this submission builds on: the previous pending or accepted root.
+    pub previous_root: [u8; 32],
+    pub root: [u8; 32],
+    /// See [`transition_commitment`].
+    pub transition_commitment: [u8; 32],
+    pub submitted_at: u64,
+    pub challenge_deadline: u64,
+}
+
+/// Evidence that a pending root is not a valid transition from its
+/// predecessor. Every variant reveals the full update list, which must match
+/// the commitment made at submission.
+///
+/// Wallet values are proven against the intermediate roots with the
+/// intermediate tree's Poseidon paths; `None` proves the wallet is absent.
+#[derive(Debug, Clone)]
+pub enum FraudProof {
+    /// A listed update carries a proof that does not verify or does not
+    /// commit to the wallet root it claims.
+    InvalidTransition {
+        updates: Vec<WalletRootUpdate>,
+        index: usize,
+    },
+    /// The claimed root holds a different value for a listed wallet than
+    /// the update's `new_wallet_root`.
+    MismatchedLeaf {
+        updates: Vec<WalletRootUpdate>,
+        index: usize,
+        claimed_value: Option<[u8; 32]>,
+        claimed_proof: WalletMerkleProof,
+    },
+    /// A wallet root changed between the two roots without being listed.
+    UnlistedChange {
+        updates: Vec<WalletRootUpdate>,
+        wallet_id: [u8; 32],
+        previous_value: Option<[u8; 32]>,
+        previous_proof: WalletMerkleProof,
+        claimed_value: Option<[u8; 32]>,
+        claimed_proof: WalletMerkleProof,
+    },
+}
+
+/// Record of an intermediate punished for a fraudulent root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlashingEvent {
+    pub contract_addr: [u8; 32],
+    pub challenger: [u8; 32],
+    pub reverted_root: [u8; 32],
+    pub slashed_stake: u64,
+    pub timestamp: u64,
+}
+
+/// Commitment an intermediate publishes alongside an optimistic root.
+pub fn transition_commitment(updates: &[WalletRootUpdate]) -> Result<[u8; 32], SystemError> {
+    let encoded = bincode::serialize(updates).map_err(|e| {
+        SystemError::new(
+            SystemErrorType::SerializationError,
+            format!("Failed to encode wallet updates: {}", e),
+        )
+    })?;
+    Ok(Sha256::digest(&encoded).into())
+}
+
+/// Returns `Ok(())` if `fraud_proof` shows that `pending` is invalid.
+pub fn verify_fraud_proof(
+    pending: &PendingIntermediateRoot,
+    fraud_proof: &FraudProof,
+    verifier: &dyn IntermediateProofVerifier,
+) -> Result<(), SystemError> {
+    let updates = match fraud_proof {
+        FraudProof::InvalidTransition { updates, .. } => updates,
+        FraudProof::MismatchedLeaf { updates, .. } => updates,
+        FraudProof::UnlistedChange { updates, .. } => updates,
+    };
+    if transition_commitment(updates)? != pending.transition_commitment {
+        return Err(rejected("Revealed updates do not match the commitment"));
+    }
+
+    match fraud_proof {
+        FraudProof::InvalidTransition { updates, index } => {
+            let update = updates
+                .get(*index)
+                .ok_or_else(|| rejected("Update index out of range"))?;
+            let valid = update.proof.verify_internally().is_ok()
+                && verifier
+                    .verify(&update.proof, &update.new_wallet_root)
+                    .is_ok();
+            if valid {
+                return Err(rejected("Challenged update is valid"));
+            }
+            Ok(())
+        }
+        FraudProof::MismatchedLeaf {
+            updates,
+            index,
+            claimed_value,
+            claimed_proof,
+        } => {
+            let update = updates
+                .get(*index)
+                .ok_or_else(|| rejected("Update index out of range"))?;
+            // A zero wallet root lists the wallet's removal.
+            let listed = Some(update.new_wallet_root).filter(|root| *root != [0u8; 32]);
+            if *claimed_value == listed {
+                return Err(rejected("Claimed root holds the listed wallet root"));
+            }
+            if !sparse_merkle_tree_i::verify(
+                &pending.root,
+                &update.wallet_id,
+                claimed_value.as_ref(),
+                claimed_proof,
+            ) {
+                return Err(rejected("Inclusion proof does not match the claimed root"));
+            }
+            Ok(())
+        }
+        FraudProof::UnlistedChange {
+            updates,
+            wallet_id,
+            previous_value,
+            previous_proof,
+            claimed_value,
+            claimed_proof,
+        } => {
+            if updates.iter().any(|update| update.wallet_id == *wallet_id) {
+                return Err(rejected("Wallet change is listed"));
+            }
+            if previous_value == claimed_value {
+                return Err(rejected("Wallet root did not change"));
+            }
+            let proofs_hold = sparse_merkle_tree_i::verify(
+                &pending.previous_root,
+                wallet_id,
+                previous_value.as_ref(),
+                previous_proof,
+            ) && sparse_merkle_tree_i::verify(
+                &pending.root,
+                wallet_id,
+                claimed_value.as_ref(),
+                claimed_proof,
+            );
+            if !proofs_hold {
+                return Err(rejected("Inclusion proofs do not match the roots"));
+            }
+            Ok(())
+        }
+    }
+}
+
+fn rejected(message: &str) -> SystemError {
+    SystemError::new(SystemErrorType::InvalidProof, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::hierarchy::intermediate::sparse_merkle_tree_i::SparseMerkleTreeI;
+
+    /// Treats `merkle_root` as the root the proof commits to.
+    struct RejectEmpty;
+
+    impl IntermediateProofVerifier for RejectEmpty {
+        fn verify(&self, proof: &ZkProof, commitment: &[u8; 32]) -> Result<(), SystemError> {
+            if proof.public_inputs == [0] || proof.merkle_root != *commitment {
+                return Err(rejected("bad proof"));
+            }
+            Ok(())
+        }
+    }
+
+    fn update(wallet: u8, root: u8, public_input: u64) -> WalletRootUpdate {
+        WalletRootUpdate {
+            wallet_id: [wallet; 32],
+            new_wallet_root: [root; 32],
+            proof: ZkProof::new(vec![1], vec![public_input], vec![root; 32], 0),
+        }
+    }
+
+    fn pending(
+        previous: &SparseMerkleTreeI,
+        claimed: &SparseMerkleTreeI,
+        updates: &[WalletRootUpdate],
+    ) -> PendingIntermediateRoot {
+        PendingIntermediateRoot {
+            contract_addr: [9; 32],
+            previous_root: previous.root(),
+            root: claimed.root(),
+            transition_commitment: transition_commitment(updates).unwrap(),
+            submitted_at: 0,
+            challenge_deadline: 10,
+        }
+    }
+
+    #[test]
+    fn test_invalid_transition_proof() {
+        let updates = vec![update(1, 11, 1), update(2, 12, 0)];
+        let tree = SparseMerkleTreeI::new();
+        let pending = pending(&tree, &tree, &updates);
+
+        let valid_update = FraudProof::InvalidTransition {
+            updates: updates.clone(),
+            index: 0,
+        };
+        assert!(verify_fraud_proof(&pending, &valid_update, &RejectEmpty).is_err());
+
+        let invalid_update = FraudProof::InvalidTransition {
+            updates: updates.clone(),
+            index: 1,
+        };
+        assert!(verify_fraud_proof(&pending, &invalid_update, &RejectEmpty).is_ok());
+
+        // Revealing a different update list than was committed to fails.
+        let forged = FraudProof::InvalidTransition {
+            updates: vec![update(2, 12, 0)],
+            index: 0,
+        };
+        assert!(verify_fraud_proof(&pending, &forged, &RejectEmpty).is_err());
+    }
+
+    #[test]
+    fn test_proof_for_another_wallet_root_is_invalid_transition() {
+        let mut relabeled = update(3, 13, 1);
+        relabeled.proof.merkle_root = vec![14; 32];
+        let updates = vec![relabeled];
+        let tree = SparseMerkleTreeI::new();
+        let pending = pending(&tree, &tree, &updates);
+
+        let fraud_proof = FraudProof::InvalidTransition { updates, index: 0 };
+        assert!(verify_fraud_proof(&pending, &fraud_proof, &RejectEmpty).is_ok());
+    }
+
+    #[test]
+    fn test_unlisted_change_proof() {
+        let mut previous = SparseMerkleTreeI::new();
+        previous.update(&[1; 32], &[1; 32]).unwrap();
+        previous.update(&[2; 32], &[2; 32]).unwrap();
+
+        let mut claimed = previous.clone();
+        claimed.update(&[1; 32], &[11; 32]).unwrap();
+        claimed.update(&[2; 32], &[99; 32]).unwrap();
+
+        let updates = vec![update(1, 11, 1)];
+        let pending = pending(&previous, &claimed, &updates);
+
+        let proof_for = |wallet: u8| FraudProof::UnlistedChange {
+            updates: updates.clone(),
+            wallet_id: [wallet; 32],
+            previous_value: previous.get(&[wallet; 32]),
+            previous_proof: previous.prove_inclusion(&[wallet; 32]).unwrap(),
+            claimed_value: claimed.get(&[wallet; 32]),
+            claimed_proof: claimed.prove_inclusion(&[wallet; 32]).unwrap(),
+        };
+
+        assert!(verify_fraud_proof(&pending, &proof_for(2), &RejectEmpty).is_ok());
+        assert!(verify_fraud_proof(&pending, &proof_for(1), &RejectEmpty).is_err());
+    }
+
+    #[test]
+    fn test_mismatched_leaf_proof() {
+        let mut previous = SparseMerkleTreeI::new();
+        previous.update(&[1; 32], &[1; 32]).unwrap();
+
+        // Wallet 1 is listed as moving to 11 but the claimed root has 12,
+        // and wallet 2 is listed as added but is missing.
+        let updates = vec![update(1, 11, 1), update(2, 12, 1)];
+        let mut claimed = previous.clone();
+        claimed.update(&[1; 32], &[12; 32]).unwrap();
+        let challenged = pending(&previous, &claimed, &updates);
+
+        let proof_for = |index: usize| {
+            let wallet_id = updates[index].wallet_id;
+            FraudProof::MismatchedLeaf {
+                updates: updates.clone(),
+                index,
+                claimed_value: claimed.get(&wallet_id),
+                claimed_proof: match claimed.get(&wallet_id) {
+                    Some(_) => claimed.prove_inclusion(&wallet_id).unwrap(),
+                    None => claimed.prove_non_inclusion(&wallet_id).unwrap(),
+                },
+            }
+        };
+        assert!(verify_fraud_proof(&challenged, &proof_for(0), &RejectEmpty).is_ok());
+        assert!(verify_fraud_proof(&challenged, &proof_for(1), &RejectEmpty).is_ok());
+
+        // A root that holds the listed value cannot be challenged this way.
+        let mut honest = previous.clone();
+        honest.update(&[1; 32], &[11; 32]).unwrap();
+        honest.update(&[2; 32], &[12; 32]).unwrap();
+        let challenged = pending(&previous, &honest, &updates);
+        let proof = FraudProof::MismatchedLeaf {
+            updates: updates.clone(),
+            index: 0,
+            claimed_value: honest.get(&[1; 32]),
+            claimed_proof: honest.prove_inclusion(&[1; 32]).unwrap(),
+        };
+        assert!(verify_fraud_proof(&challenged, &proof, &RejectEmpty).is_err());
+
+        // Nor with a value the claimed root does not hold.
+        let proof = FraudProof::MismatchedLeaf {
+            updates: updates.clone(),
+            index: 0,
+            claimed_value: Some([12; 32]),
+            claimed_proof: honest.prove_inclusion(&[1; 32]).unwrap(),
+        };
+        assert!(verify_fraud_proof(&challenged, &proof, &RejectEmpty).is_err());
+    }
+}
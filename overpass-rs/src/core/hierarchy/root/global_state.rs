@@ -1,4 +1,6 @@
-use std::collections::HashMap;
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 #[derive(Clone, Debug)]
 pub struct GlobalState {
@@ -10,6 +12,7 @@ pub struct GlobalState {
     last_epoch_id: u64,
     intermediate_roots: HashMap<u64, [u8; 32]>, // SEQNO -> Merkle root mapping
     global_merkle_tree: Vec<[u8; 32]>,
+    balance_sheets: BTreeMap<u64, EpochBalanceSheet>,
 }
 
 #[derive(Clone, Debug)]
@@ -18,6 +21,50 @@ pub struct AccountState {
     pub nonce: u64,
 }
 
+/// Signed balance change for one account. `nonce` must be the account's
+/// next nonce.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountDelta {
+    pub account: String,
+    pub delta: i64,
+    pub nonce: u64,
+}
+
+/// A set of account deltas applied atomically. The deltas must sum to zero.
+#[derive(Clone, Debug)]
+pub struct BalanceTransition {
+    pub epoch_id: u64,
+    pub root_hash: [u8; 32],
+    pub deltas: Vec<AccountDelta>,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BalanceSheetEntry {
+    pub opening_balance: u64,
+    pub transfers_in: u64,
+    pub transfers_out: u64,
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub closing_balance: u64,
+}
+
+/// Per-account movements within one epoch.
+///
+/// Transfers always net to zero across all accounts; only deposits and
+/// withdrawals change `closing_total` relative to `opening_total`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EpochBalanceSheet {
+    pub epoch_id: u64,
+    pub opening_total: u64,
+    pub closing_total: u64,
+    pub entries: BTreeMap<String, BalanceSheetEntry>,
+    /// Wallet ids touched by transitions, as recorded in `StateTransitionRecord`.
+    pub affected_wallet_ids: BTreeSet<[u8; 32]>,
+    pub transition_count: usize,
+    pub closing_root: Option<[u8; 32]>,
+}
+
 #[derive(Clone, Debug)]
 pub struct StateTransitionRecord {
     pub epoch_id: u64,
@@ -38,6 +85,7 @@ impl GlobalState {
             last_epoch_id: 0,
             intermediate_roots: HashMap::new(),
             global_merkle_tree: Vec::new(),
+            balance_sheets: BTreeMap::new(),
         }
     }
 
@@ -47,10 +95,238 @@ impl GlobalState {
     }
 
     /// Updates the global state with a new root hash, balance, and epoch ID.
-    pub fn update(&mut self, new_root_hash: [u8; 32], balance_update: i64, epoch_id: u64) {
+    /// Fails without changing anything if the total would go negative or
+    /// overflow.
+    pub fn update(
+        &mut self,
+        new_root_hash: [u8; 32],
+        balance_update: i64,
+        epoch_id: u64,
+    ) -> Result<(), SystemError> {
+        self.total_balance = apply_delta(self.total_balance, balance_update).ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::InvalidAmount,
+                format!(
+                    "Balance update {} out of range for total balance {}",
+                    balance_update, self.total_balance
+                ),
+            )
+        })?;
         self.root_hash = new_root_hash;
-        self.total_balance = (self.total_balance as i64 + balance_update).max(0) as u64;
         self.last_epoch_id = epoch_id;
+        Ok(())
+    }
+
+    /// Credits value entering the system, e.g. an on-chain deposit. Like a
+    /// transition, it cannot be booked into an epoch older than the last one.
+    pub fn deposit(
+        &mut self,
+        epoch_id: u64,
+        account: &str,
+        amount: u64,
+    ) -> Result<(), SystemError> {
+        self.check_epoch(epoch_id, "Deposit")?;
+        let balance = self.accounts.get(account).map_or(0, |state| state.balance);
+        let (new_balance, new_total) = balance
+            .checked_add(amount)
+            .zip(self.total_balance.checked_add(amount))
+            .ok_or_else(|| {
+                SystemError::new(
+                    SystemErrorType::InvalidAmount,
+                    "Deposit overflows balance".to_string(),
+                )
+            })?;
+
+        let opening_total = self.total_balance;
+        let entry = self.sheet_entry(epoch_id, opening_total, account, balance);
+        entry.deposits += amount;
+        entry.closing_balance = new_balance;
+
+        self.accounts
+            .entry(account.to_string())
+            .or_insert(AccountState {
+                balance: 0,
+                nonce: 0,
+            })
+            .balance = new_balance;
+        self.total_balance = new_total;
+        self.sheet_mut(epoch_id).closing_total = new_total;
+        self.last_epoch_id = epoch_id;
+        Ok(())
+    }
+
+    /// Debits value leaving the system, e.g. an on-chain withdrawal. Like a
+    /// transition, it cannot be booked into an epoch older than the last one.
+    pub fn withdraw(
+        &mut self,
+        epoch_id: u64,
+        account: &str,
+        amount: u64,
+    ) -> Result<(), SystemError> {
+        self.check_epoch(epoch_id, "Withdrawal")?;
+        let balance = self
+            .accounts
+            .get(account)
+            .map(|state| state.balance)
+            .ok_or_else(|| unknown_account(account))?;
+        if amount > balance {
+            return Err(SystemError::new(
+                SystemErrorType::InsufficientBalance,
+                format!("Account {} cannot withdraw {}", account, amount),
+            ));
+        }
+
+        let opening_total = self.total_balance;
+        let entry = self.sheet_entry(epoch_id, opening_total, account, balance);
+        entry.withdrawals += amount;
+        entry.closing_balance = balance - amount;
+
+        self.accounts.get_mut(account).unwrap().balance = balance - amount;
+        self.total_balance -= amount;
+        self.sheet_mut(epoch_id).closing_total = self.total_balance;
+        self.last_epoch_id = epoch_id;
+        Ok(())
+    }
+
+    /// Applies every delta in `transition` or none of them.
+    ///
+    /// Rejects the transition if its deltas do not sum to zero, if any nonce
+    /// is not the account's next nonce, or if any balance would go negative
+    /// or overflow. Returns the record that was appended to
+    /// `state_transitions`.
+    pub fn apply_transition(
+        &mut self,
+        transition: BalanceTransition,
+    ) -> Result<StateTransitionRecord, SystemError> {
+        self.check_epoch(transition.epoch_id, "Transition")?;
+
+        let net: i128 = transition.deltas.iter().map(|d| d.delta as i128).sum();
+        if net != 0 {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidAmount,
+                format!("Transition does not conserve value: net change {}", net),
+            ));
+        }
+
+        // Validate everything before touching any account.
+        let mut new_balances = Vec::with_capacity(transition.deltas.len());
+        let mut seen = BTreeSet::new();
+        for delta in &transition.deltas {
+            if !seen.insert(delta.account.as_str()) {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidInput,
+                    format!("Account {} appears twice in one transition", delta.account),
+                ));
+            }
+            let state = self
+                .accounts
+                .get(&delta.account)
+                .ok_or_else(|| unknown_account(&delta.account))?;
+            if delta.nonce != state.nonce + 1 {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidNonce,
+                    format!(
+                        "Account {} expected nonce {}, got {}",
+                        delta.account,
+                        state.nonce + 1,
+                        delta.nonce
+                    ),
+                ));
+            }
+            let new_balance = apply_delta(state.balance, delta.delta).ok_or_else(|| {
+                SystemError::new(
+                    SystemErrorType::InsufficientBalance,
+                    format!(
+                        "Account {} cannot apply {} to balance {}",
+                        delta.account, delta.delta, state.balance
+                    ),
+                )
+            })?;
+            new_balances.push((state.balance, new_balance));
+        }
+
+        let opening_total = self.total_balance;
+        let epoch_id = transition.epoch_id;
+        let mut affected_wallet_ids = Vec::with_capacity(transition.deltas.len());
+        for (delta, (old_balance, new_balance)) in transition.deltas.iter().zip(new_balances) {
+            let entry = self.sheet_entry(epoch_id, opening_total, &delta.account, old_balance);
+            if delta.delta >= 0 {
+                entry.transfers_in += delta.delta as u64;
+            } else {
+                entry.transfers_out += delta.delta.unsigned_abs();
+            }
+            entry.closing_balance = new_balance;
+
+            let state = self.accounts.get_mut(&delta.account).unwrap();
+            state.balance = new_balance;
+            state.nonce = delta.nonce;
+            affected_wallet_ids.push(wallet_id(&delta.account));
+        }
+
+        let sheet = self.sheet_mut(epoch_id);
+        sheet.transition_count += 1;
+        sheet.closing_root = Some(transition.root_hash);
+        sheet
+            .affected_wallet_ids
+            .extend(affected_wallet_ids.iter().copied());
+
+        self.root_hash = transition.root_hash;
+        self.last_epoch_id = epoch_id;
+
+        let record = StateTransitionRecord::new(
+            epoch_id,
+            transition.root_hash,
+            affected_wallet_ids,
+            transition.timestamp,
+        );
+        self.record_state_transition(record.clone());
+        Ok(record)
+    }
+
+    /// Balance sheet for `epoch_id`, if anything happened in that epoch.
+    pub fn balance_sheet(&self, epoch_id: u64) -> Option<&EpochBalanceSheet> {
+        self.balance_sheets.get(&epoch_id)
+    }
+
+    /// Rejects changes for epochs before the latest one anything was booked
+    /// into, whose balance sheets are already closed.
+    fn check_epoch(&self, epoch_id: u64, what: &str) -> Result<(), SystemError> {
+        if epoch_id < self.last_epoch_id {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidState,
+                format!(
+                    "{} for epoch {} is older than epoch {}",
+                    what, epoch_id, self.last_epoch_id
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn sheet_mut(&mut self, epoch_id: u64) -> &mut EpochBalanceSheet {
+        let opening_total = self.total_balance;
+        self.balance_sheets
+            .entry(epoch_id)
+            .or_insert_with(|| EpochBalanceSheet::new(epoch_id, opening_total))
+    }
+
+    fn sheet_entry(
+        &mut self,
+        epoch_id: u64,
+        opening_total: u64,
+        account: &str,
+        opening_balance: u64,
+    ) -> &mut BalanceSheetEntry {
+        self.balance_sheets
+            .entry(epoch_id)
+            .or_insert_with(|| EpochBalanceSheet::new(epoch_id, opening_total))
+            .entries
+            .entry(account.to_string())
+            .or_insert_with(|| BalanceSheetEntry {
+                opening_balance,
+                closing_balance: opening_balance,
+                ..BalanceSheetEntry::default()
+            })
     }
 
     /// Updates the intermediate contract state and recalculates global Merkle root
@@ -104,7 +380,6 @@ impl GlobalState {
     }
 
     fn hash_pair(&self, left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
-        use sha2::{Digest, Sha256};
         let mut hasher = Sha256::new();
         hasher.update(left);
         hasher.update(right);
@@ -120,6 +395,100 @@ impl GlobalState {
     }
 }
 
+impl EpochBalanceSheet {
+    fn new(epoch_id: u64, opening_total: u64) -> Self {
+        Self {
+            epoch_id,
+            opening_total,
+            closing_total: opening_total,
+            entries: BTreeMap::new(),
+            affected_wallet_ids: BTreeSet::new(),
+            transition_count: 0,
+            closing_root: None,
+        }
+    }
+
+    /// Checks that the sheet balances and agrees with the transition records
+    /// kept for the same epoch.
+    pub fn reconcile(&self, records: &[StateTransitionRecord]) -> Result<(), SystemError> {
+        let mismatch =
+            |message: String| SystemError::new(SystemErrorType::StateDataMismatch, message);
+
+        let mut opening: u128 = 0;
+        let mut closing: u128 = 0;
+        for (account, entry) in &self.entries {
+            let expected = entry.opening_balance as i128 + entry.transfers_in as i128
+                - entry.transfers_out as i128
+                + entry.deposits as i128
+                - entry.withdrawals as i128;
+            if expected != entry.closing_balance as i128 {
+                return Err(mismatch(format!("Account {} does not balance", account)));
+            }
+            opening += entry.opening_balance as u128;
+            closing += entry.closing_balance as u128;
+        }
+        let transfers_in: u128 = self.entries.values().map(|e| e.transfers_in as u128).sum();
+        let transfers_out: u128 = self.entries.values().map(|e| e.transfers_out as u128).sum();
+        if transfers_in != transfers_out {
+            return Err(mismatch("Transfers do not net to zero".to_string()));
+        }
+        if closing as i128 - opening as i128
+            != self.closing_total as i128 - self.opening_total as i128
+        {
+            return Err(mismatch(
+                "Account totals do not match the epoch totals".to_string(),
+            ));
+        }
+
+        let records: Vec<_> = records
+            .iter()
+            .filter(|record| record.epoch_id == self.epoch_id)
+            .collect();
+        if records.len() != self.transition_count {
+            return Err(mismatch(format!(
+                "Sheet has {} transitions, records have {}",
+                self.transition_count,
+                records.len()
+            )));
+        }
+        let recorded_ids: BTreeSet<[u8; 32]> = records
+            .iter()
+            .flat_map(|record| record.affected_wallet_ids.iter().copied())
+            .collect();
+        if recorded_ids != self.affected_wallet_ids {
+            return Err(mismatch(
+                "Affected wallets differ from the transition records".to_string(),
+            ));
+        }
+        if records.last().map(|record| record.root_hash) != self.closing_root {
+            return Err(mismatch(
+                "Closing root differs from the last transition record".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Wallet id under which an account appears in `StateTransitionRecord`.
+pub fn wallet_id(account: &str) -> [u8; 32] {
+    Sha256::digest(account.as_bytes()).into()
+}
+
+fn apply_delta(balance: u64, delta: i64) -> Option<u64> {
+    if delta >= 0 {
+        balance.checked_add(delta as u64)
+    } else {
+        balance.checked_sub(delta.unsigned_abs())
+    }
+}
+
+fn unknown_account(account: &str) -> SystemError {
+    SystemError::new(
+        SystemErrorType::NotFound,
+        format!("Unknown account {}", account),
+    )
+}
+
 impl StateTransitionRecord {
     pub fn new(
         epoch_id: u64,
@@ -168,15 +537,135 @@ mod tests {
     #[test]
     fn test_balance_update() {
         let mut state = GlobalState::new([0u8; 32], 0);
-        state.update([1u8; 32], 100, 1);
+        state.update([1u8; 32], 100, 1).unwrap();
         assert_eq!(state.total_balance, 100);
 
         // Test negative balance update
-        state.update([2u8; 32], -50, 2);
+        state.update([2u8; 32], -50, 2).unwrap();
         assert_eq!(state.total_balance, 50);
 
-        // Test that balance cannot go negative
-        state.update([3u8; 32], -100, 3);
-        assert_eq!(state.total_balance, 0);
+        // Test that balance cannot go negative, and is not clamped
+        assert!(state.update([3u8; 32], -100, 3).is_err());
+        assert_eq!(state.total_balance, 50);
+        assert_eq!(state.root_hash, [2u8; 32]);
+    }
+
+    fn delta(account: &str, delta: i64, nonce: u64) -> AccountDelta {
+        AccountDelta {
+            account: account.to_string(),
+            delta,
+            nonce,
+        }
+    }
+
+    fn transfer(epoch_id: u64, root: u8, deltas: Vec<AccountDelta>) -> BalanceTransition {
+        BalanceTransition {
+            epoch_id,
+            root_hash: [root; 32],
+            deltas,
+            timestamp: 0,
+        }
+    }
+
+    fn funded_state() -> GlobalState {
+        let mut state = GlobalState::new([0u8; 32], 0);
+        state.deposit(1, "alice", 100).unwrap();
+        state.deposit(1, "bob", 50).unwrap();
+        state
+    }
+
+    #[test]
+    fn test_transition_must_conserve_value() {
+        let mut state = funded_state();
+
+        let minted = transfer(1, 1, vec![delta("alice", -10, 1), delta("bob", 20, 1)]);
+        assert!(state.apply_transition(minted).is_err());
+
+        let overdrawn = transfer(1, 1, vec![delta("alice", -200, 1), delta("bob", 200, 1)]);
+        assert!(state.apply_transition(overdrawn).is_err());
+
+        assert_eq!(state.accounts["alice"].balance, 100);
+        assert_eq!(state.accounts["alice"].nonce, 0);
+        assert!(state.state_transitions.is_empty());
+
+        let valid = transfer(1, 1, vec![delta("alice", -30, 1), delta("bob", 30, 1)]);
+        state.apply_transition(valid).unwrap();
+        assert_eq!(state.accounts["alice"].balance, 70);
+        assert_eq!(state.accounts["bob"].balance, 80);
+        assert_eq!(state.total_balance, 150);
+    }
+
+    #[test]
+    fn test_transition_requires_next_nonce() {
+        let mut state = funded_state();
+        state
+            .apply_transition(transfer(
+                1,
+                1,
+                vec![delta("alice", -1, 1), delta("bob", 1, 1)],
+            ))
+            .unwrap();
+
+        let replayed = transfer(1, 2, vec![delta("alice", -1, 1), delta("bob", 1, 2)]);
+        assert!(state.apply_transition(replayed).is_err());
+        let skipped = transfer(1, 2, vec![delta("alice", -1, 3), delta("bob", 1, 2)]);
+        assert!(state.apply_transition(skipped).is_err());
+        assert_eq!(state.accounts["bob"].nonce, 1);
+    }
+
+    #[test]
+    fn test_balance_sheet_reconciles_with_records() {
+        let mut state = funded_state();
+        state
+            .apply_transition(transfer(
+                1,
+                1,
+                vec![delta("alice", -30, 1), delta("bob", 30, 1)],
+            ))
+            .unwrap();
+        state.withdraw(1, "bob", 20).unwrap();
+        state
+            .apply_transition(transfer(
+                2,
+                2,
+                vec![delta("bob", -5, 2), delta("alice", 5, 2)],
+            ))
+            .unwrap();
+
+        let sheet = state.balance_sheet(1).unwrap();
+        assert_eq!(sheet.opening_total, 0);
+        assert_eq!(sheet.closing_total, 130);
+        assert_eq!(sheet.entries["bob"].closing_balance, 60);
+        assert_eq!(sheet.transition_count, 1);
+        sheet.reconcile(&state.state_transitions).unwrap();
+
+        let sheet = state.balance_sheet(2).unwrap();
+        assert_eq!(sheet.opening_total, 130);
+        assert_eq!(sheet.entries["alice"].opening_balance, 70);
+        sheet.reconcile(&state.state_transitions).unwrap();
+
+        // A missing record is caught.
+        assert!(sheet.reconcile(&[]).is_err());
+    }
+
+    #[test]
+    fn test_deposit_and_withdraw_reject_past_epochs() {
+        let mut state = funded_state();
+        state
+            .apply_transition(transfer(
+                2,
+                1,
+                vec![delta("alice", -10, 1), delta("bob", 10, 1)],
+            ))
+            .unwrap();
+
+        assert!(state.deposit(1, "alice", 5).is_err());
+        assert!(state.withdraw(1, "bob", 5).is_err());
+        assert_eq!(state.total_balance, 150);
+        assert_eq!(state.balance_sheet(1).unwrap().closing_total, 150);
+
+        state.withdraw(3, "bob", 5).unwrap();
+        assert!(state.deposit(2, "alice", 5).is_err());
+        assert_eq!(state.total_balance, 145);
     }
 }
@@ -1,57 +1,353 @@
 // global_tree_manager.rs
-use crate::core::error::errors::SystemError;
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::intermediate::sparse_merkle_tree_i::SparseMerkleTreeI;
+use crate::core::hierarchy::root::sparse_merkle_tree_r::{
+    self, SparseMerkleProof, SparseMerkleTreeR,
+};
+use crate::core::zkps::plonky2::Plonky2System;
 use crate::core::zkps::proof::ZkProof;
-use blake2::{Blake2b512, Digest};
 
-#[derive(Debug)]
-pub struct GlobalTreeManager {
-    intermediate_roots: Vec<[u8; 32]>,
+/// Checks a wallet-level proof and that it was made for `commitment`, the
+/// root or digest the caller expects it to prove. The commitment has to be
+/// checked as part of verification; a proof's own `merkle_root` field is not
+/// covered by the proof and proves nothing.
+pub trait IntermediateProofVerifier {
+    fn verify(&self, proof: &ZkProof, commitment: &[u8; 32]) -> Result<(), SystemError>;
 }
 
-impl GlobalTreeManager {
-    pub fn new() -> Self {
+impl IntermediateProofVerifier for Plonky2System {
+    fn verify(&self, proof: &ZkProof, commitment: &[u8; 32]) -> Result<(), SystemError> {
+        self.verify_committed_proof(&proof.proof_data, commitment)
+            .map_err(|e| SystemError::new(SystemErrorType::InvalidProof, e.to_string()))
+    }
+}
+
+impl<T: IntermediateProofVerifier + ?Sized> IntermediateProofVerifier for Box<T> {
+    fn verify(&self, proof: &ZkProof, commitment: &[u8; 32]) -> Result<(), SystemError> {
+        (**self).verify(proof, commitment)
+    }
+}
+
+/// The proof an intermediate submits with a new root, the submission
+/// `sequence` it was made for and the commitment to the wallet updates it
+/// covers.
+#[derive(Debug, Clone)]
+pub struct RootTransition {
+    pub sequence: u64,
+    pub commitment: [u8; 32],
+    pub proof: ZkProof,
+}
+
+/// Checks the proof an intermediate submits with a new root against the
+/// transition the root layer expects: it has to start from `old_root`, the
+/// intermediate's previous root, be made for submission `sequence` and cover
+/// exactly the wallet updates hashed into `commitment`.
+pub trait RootTransitionVerifier {
+    fn verify_transition(
+        &self,
+        proof: &ZkProof,
+        old_root: &[u8; 32],
+        sequence: u64,
+        commitment: &[u8; 32],
+    ) -> Result<(), SystemError>;
+}
+
+impl<T: RootTransitionVerifier + ?Sized> RootTransitionVerifier for Box<T> {
+    fn verify_transition(
+        &self,
+        proof: &ZkProof,
+        old_root: &[u8; 32],
+        sequence: u64,
+        commitment: &[u8; 32],
+    ) -> Result<(), SystemError> {
+        (**self).verify_transition(proof, old_root, sequence, commitment)
+    }
+}
+
+/// Intermediate roots keyed by contract address in a sparse Merkle tree.
+///
+/// Updating one intermediate touches only its path, so the global root is
+/// always current and every intermediate can be handed its own inclusion
+/// proof.
+pub struct GlobalTreeManager<V: RootTransitionVerifier> {
+    tree: SparseMerkleTreeR,
+    verifier: V,
+}
+
+/// Checks an inclusion proof issued by [`GlobalTreeManager::get_inclusion_proof`].
+pub fn verify_inclusion(
+    global_root: &[u8; 32],
+    contract_addr: &[u8; 32],
+    intermediate_root: &[u8; 32],
+    proof: &SparseMerkleProof,
+) -> bool {
+    sparse_merkle_tree_r::verify(global_root, contract_addr, Some(intermediate_root), proof)
+}
+
+impl<V: RootTransitionVerifier> GlobalTreeManager<V> {
+    pub fn new(verifier: V) -> Self {
         Self {
-            intermediate_roots: Vec::new(),
+            tree: SparseMerkleTreeR::new(),
+            verifier,
         }
     }
 
+    /// Checks that `proof` moves the contract's current root, or the empty
+    /// intermediate tree for its first root, to `intermediate_root` for
+    /// submission `sequence` over the updates hashed into `commitment`.
+    pub fn verify_intermediate_root(
+        &self,
+        contract_addr: &[u8; 32],
+        intermediate_root: &[u8; 32],
+        sequence: u64,
+        commitment: &[u8; 32],
+        proof: &ZkProof,
+    ) -> Result<(), SystemError> {
+        let old_root = self
+            .get_intermediate_root(contract_addr)
+            .unwrap_or_else(|| SparseMerkleTreeI::new().root());
+        self.verify_transition(&old_root, intermediate_root, sequence, commitment, proof)
+    }
+
+    /// Checks that `proof` moves `old_root` to `new_root` for submission
+    /// `sequence` over the updates hashed into `commitment`, for roots that
+    /// build on one not yet in the tree.
+    pub fn verify_transition(
+        &self,
+        old_root: &[u8; 32],
+        new_root: &[u8; 32],
+        sequence: u64,
+        commitment: &[u8; 32],
+        proof: &ZkProof,
+    ) -> Result<(), SystemError> {
+        if proof.merkle_root != new_root {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidProof,
+                "Proof does not commit to the submitted intermediate root".to_string(),
+            ));
+        }
+        self.verifier
+            .verify_transition(proof, old_root, sequence, commitment)
+    }
+
+    /// Accepts `intermediate_root` for `contract_addr` once
+    /// [`verify_intermediate_root`](Self::verify_intermediate_root) passes.
+    /// Replaces any earlier root from the same contract.
     pub fn add_intermediate_root(
         &mut self,
+        contract_addr: [u8; 32],
         intermediate_root: [u8; 32],
-        _proof: ZkProof,
+        sequence: u64,
+        commitment: [u8; 32],
+        proof: ZkProof,
     ) -> Result<(), SystemError> {
-        self.intermediate_roots.push(intermediate_root);
-        Ok(())
+        self.verify_intermediate_root(
+            &contract_addr,
+            &intermediate_root,
+            sequence,
+            &commitment,
+            &proof,
+        )?;
+        self.set_intermediate_root(&contract_addr, &intermediate_root)
+    }
+
+    /// Stores a root without checking a proof, for roots that were verified
+    /// before, e.g. when a log of accepted roots is replayed.
+    pub fn set_intermediate_root(
+        &mut self,
+        contract_addr: &[u8; 32],
+        intermediate_root: &[u8; 32],
+    ) -> Result<(), SystemError> {
+        self.tree
+            .update_global_tree(contract_addr, intermediate_root)
+    }
+
+    pub fn remove_intermediate_root(&mut self, contract_addr: &[u8; 32]) -> Option<[u8; 32]> {
+        self.tree
+            .remove(contract_addr)
+            .and_then(|value| value.try_into().ok())
+    }
+
+    pub fn get_intermediate_root(&self, contract_addr: &[u8; 32]) -> Option<[u8; 32]> {
+        self.tree
+            .get(contract_addr)
+            .and_then(|value| value.try_into().ok())
     }
 
     pub fn generate_global_root(&self) -> [u8; 32] {
-        if self.intermediate_roots.is_empty() {
-            return [0u8; 32];
+        self.tree.get_global_root_hash()
+    }
+
+    /// Returns the path proving that the contract's current root is part of
+    /// the global root.
+    pub fn get_inclusion_proof(
+        &self,
+        contract_addr: &[u8; 32],
+    ) -> Result<SparseMerkleProof, SystemError> {
+        self.tree.prove_inclusion(contract_addr)
+    }
+
+    /// Returns every stored `(contract address, root)` pair, ordered by address.
+    pub fn get_stored_roots(&self) -> Vec<([u8; 32], [u8; 32])> {
+        let mut roots: Vec<_> = self
+            .tree
+            .leaves()
+            .filter_map(|(addr, value)| Some((*addr, value.as_slice().try_into().ok()?)))
+            .collect();
+        roots.sort_unstable();
+        roots
+    }
+
+    /// Replaces the tree with previously stored roots. The roots were
+    /// verified when first accepted, so no proofs are checked here.
+    pub fn store_roots(&mut self, roots: Vec<([u8; 32], [u8; 32])>) -> Result<(), SystemError> {
+        let mut tree = SparseMerkleTreeR::new();
+        for (contract_addr, root) in roots {
+            tree.update_global_tree(&contract_addr, &root)?;
         }
+        self.tree = tree;
+        Ok(())
+    }
+}
 
-        let mut current_hash = self.intermediate_roots[0];
-        for root in self.intermediate_roots.iter().skip(1) {
-            current_hash = Self::hash_combine(&current_hash, root);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test proofs carry the root they start from as their proof data and
+    /// their sequence as their timestamp.
+    struct ExpectOldRoot;
+
+    impl RootTransitionVerifier for ExpectOldRoot {
+        fn verify_transition(
+            &self,
+            proof: &ZkProof,
+            old_root: &[u8; 32],
+            sequence: u64,
+            commitment: &[u8; 32],
+        ) -> Result<(), SystemError> {
+            if proof.proof_data != old_root || proof.timestamp != sequence || commitment != &[0; 32]
+            {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidProof,
+                    "rejected".to_string(),
+                ));
+            }
+            Ok(())
         }
+    }
 
-        current_hash
+    fn proof_for(old_root: [u8; 32], root: [u8; 32]) -> ZkProof {
+        ZkProof::new(old_root.to_vec(), vec![1], root.to_vec(), 0)
     }
 
-    pub fn get_stored_roots(&self) -> Vec<[u8; 32]> {
-        self.intermediate_roots.clone()
+    fn empty_root() -> [u8; 32] {
+        SparseMerkleTreeI::new().root()
     }
 
-    pub fn store_roots(&mut self, roots: Vec<[u8; 32]>) {
-        self.intermediate_roots = roots;
+    fn manager() -> GlobalTreeManager<ExpectOldRoot> {
+        GlobalTreeManager::new(ExpectOldRoot)
     }
 
-    fn hash_combine(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
-        let mut hasher = Blake2b512::new();
-        hasher.update(left);
-        hasher.update(right);
-        let result = hasher.finalize();
-        let mut output = [0u8; 32];
-        output.copy_from_slice(&result[..32]);
-        output
+    fn add(manager: &mut GlobalTreeManager<ExpectOldRoot>, addr: [u8; 32], root: [u8; 32]) {
+        manager
+            .add_intermediate_root(addr, root, 0, [0; 32], proof_for(empty_root(), root))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_inclusion_proof_per_intermediate() {
+        let mut manager = manager();
+        for i in 1..=3u8 {
+            add(&mut manager, [i; 32], [i + 10; 32]);
+        }
+
+        let global_root = manager.generate_global_root();
+        let proof = manager.get_inclusion_proof(&[2; 32]).unwrap();
+        assert!(verify_inclusion(&global_root, &[2; 32], &[12; 32], &proof));
+        assert!(!verify_inclusion(&global_root, &[2; 32], &[13; 32], &proof));
+    }
+
+    #[test]
+    fn test_root_is_independent_of_insertion_order() {
+        let mut forward = manager();
+        let mut backward = manager();
+        for i in 1..=4u8 {
+            add(&mut forward, [i; 32], [i; 32]);
+        }
+        for i in (1..=4u8).rev() {
+            add(&mut backward, [i; 32], [i; 32]);
+        }
+        assert_eq!(
+            forward.generate_global_root(),
+            backward.generate_global_root()
+        );
+
+        let mut restored = manager();
+        restored.store_roots(forward.get_stored_roots()).unwrap();
+        assert_eq!(
+            restored.generate_global_root(),
+            forward.generate_global_root()
+        );
+    }
+
+    #[test]
+    fn test_proofs_are_bound_to_the_previous_root() {
+        let mut manager = manager();
+        add(&mut manager, [1; 32], [5; 32]);
+
+        // The next root has to build on [5; 32], not on the empty tree.
+        assert!(manager
+            .add_intermediate_root([1; 32], [6; 32], 1, [0; 32], {
+                let mut proof = proof_for(empty_root(), [6; 32]);
+                proof.timestamp = 1;
+                proof
+            })
+            .is_err());
+        let mut proof = proof_for([5; 32], [6; 32]);
+        proof.timestamp = 1;
+        manager
+            .add_intermediate_root([1; 32], [6; 32], 1, [0; 32], proof)
+            .unwrap();
+        assert_eq!(manager.get_intermediate_root(&[1; 32]), Some([6; 32]));
+    }
+
+    #[test]
+    fn test_unverified_roots_are_rejected() {
+        let mut manager = manager();
+        let empty_global_root = manager.generate_global_root();
+
+        // Proof bound to a different root.
+        assert!(manager
+            .add_intermediate_root(
+                [1; 32],
+                [1; 32],
+                0,
+                [0; 32],
+                proof_for(empty_root(), [2; 32])
+            )
+            .is_err());
+        // Proof for another sequence or another set of updates.
+        assert!(manager
+            .add_intermediate_root(
+                [1; 32],
+                [1; 32],
+                1,
+                [0; 32],
+                proof_for(empty_root(), [1; 32])
+            )
+            .is_err());
+        assert!(manager
+            .add_intermediate_root(
+                [1; 32],
+                [1; 32],
+                0,
+                [3; 32],
+                proof_for(empty_root(), [1; 32])
+            )
+            .is_err());
+
+        assert_eq!(manager.generate_global_root(), empty_global_root);
+        assert_eq!(manager.get_intermediate_root(&[1; 32]), None);
     }
 }
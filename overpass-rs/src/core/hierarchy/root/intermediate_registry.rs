@@ -0,0 +1,621 @@
+// src/core/hierarchy/root/intermediate_registry.rs
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const ROOT_SUBMISSION_DOMAIN: &[u8] = b"ovp:intermediate-root";
+const KEY_ROTATION_DOMAIN: &[u8] = b"ovp:rotate-operator-key";
+const REGISTRATION_DOMAIN: &[u8] = b"ovp:register-intermediate";
+const DEREGISTRATION_DOMAIN: &[u8] = b"ovp:deregister-intermediate";
+const SETTLEMENT_BATCH_DOMAIN: &[u8] = b"ovp:settlement-batch";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IntermediateStatus {
+    Active,
+    /// Still accepted until the first epoch sealed at or after `until`.
+    Draining {
+        until: u64,
+    },
+}
+
+/// Registration metadata for one intermediate contract.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntermediateRegistration {
+    pub contract_addr: [u8; 32],
+    /// Ed25519 key that signs the intermediate's roots.
+    pub operator_key: [u8; 32],
+    /// Bumped on every key rotation so old rotation signatures cannot be replayed.
+    pub key_version: u64,
+    pub stake: u64,
+    /// Maximum number of wallets the intermediate will serve.
+    pub capacity: u64,
+    pub registered_at: u64,
+    pub status: IntermediateStatus,
+}
+
+/// Holds the stake intermediates lock to register, e.g. a staking contract on
+/// the settlement chain. A registration only counts stake the escrow reports
+/// as locked for its contract.
+pub trait StakeEscrow {
+    /// Stake locked for `contract_addr` and not yet released.
+    fn locked_stake(&self, contract_addr: &[u8; 32]) -> Result<u64, SystemError>;
+}
+
+/// Escrow for deployments that do not take stake: nothing is ever locked, so
+/// only registrations declaring no stake are accepted.
+pub struct NoStakeEscrow;
+
+impl StakeEscrow for NoStakeEscrow {
+    fn locked_stake(&self, _contract_addr: &[u8; 32]) -> Result<u64, SystemError> {
+        Ok(0)
+    }
+}
+
+/// Intermediates allowed to submit roots to the root contract.
+///
+/// Validation and mutation are separate so the root contract can log a change
+/// after it has been validated and before it is applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntermediateRegistry {
+    intermediates: BTreeMap<[u8; 32], IntermediateRegistration>,
+    min_stake: u64,
+    drain_period: u64,
+}
+
+impl Default for IntermediateRegistry {
+    fn default() -> Self {
+        Self::new(0, 0)
+    }
+}
+
+impl IntermediateRegistry {
+    pub fn new(min_stake: u64, drain_period: u64) -> Self {
+        Self {
+            intermediates: BTreeMap::new(),
+            min_stake,
+            drain_period,
+        }
+    }
+
+    pub fn min_stake(&self) -> u64 {
+        self.min_stake
+    }
+
+    pub fn drain_period(&self) -> u64 {
+        self.drain_period
+    }
+
+    pub fn get(&self, contract_addr: &[u8; 32]) -> Option<&IntermediateRegistration> {
+        self.intermediates.get(contract_addr)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &IntermediateRegistration> {
+        self.intermediates.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.intermediates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intermediates.is_empty()
+    }
+
+    /// Checks a new registration. `signature` is made with the registration's
+    /// own operator key over [`registration_message`], so nobody can register
+    /// a key they do not hold, and the declared stake has to be locked in
+    /// `escrow`.
+    pub fn validate_registration(
+        &self,
+        registration: &IntermediateRegistration,
+        signature: &[u8; 64],
+        escrow: &dyn StakeEscrow,
+    ) -> Result<(), SystemError> {
+        if self.intermediates.contains_key(&registration.contract_addr) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Intermediate is already registered".to_string(),
+            ));
+        }
+        if registration.stake < self.min_stake {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidAmount,
+                format!(
+                    "Stake {} is below the minimum of {}",
+                    registration.stake, self.min_stake
+                ),
+            ));
+        }
+        if registration.capacity == 0 {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Intermediate capacity must be non-zero".to_string(),
+            ));
+        }
+        if registration.status != IntermediateStatus::Active || registration.key_version != 0 {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "New registrations start active at key version 0".to_string(),
+            ));
+        }
+        verify_signature(
+            &registration.operator_key,
+            &registration_message(registration),
+            signature,
+        )?;
+        let locked = escrow.locked_stake(&registration.contract_addr)?;
+        if registration.stake > locked {
+            return Err(SystemError::new(
+                SystemErrorType::InsufficientBalance,
+                format!(
+                    "Declared stake {} exceeds the {} locked in escrow",
+                    registration.stake, locked
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that the current operator key signed the switch to `new_key`.
+    pub fn validate_key_rotation(
+        &self,
+        contract_addr: &[u8; 32],
+        new_key: &[u8; 32],
+        signature: &[u8; 64],
+    ) -> Result<(), SystemError> {
+        let registration = self.require(contract_addr)?;
+        parse_key(new_key)?;
+        let message = key_rotation_message(contract_addr, new_key, registration.key_version);
+        verify_signature(&registration.operator_key, &message, signature)
+    }
+
+    /// Checks that the current operator key signed the deregistration and
+    /// returns the time until which the intermediate keeps draining.
+    pub fn validate_deregistration(
+        &self,
+        contract_addr: &[u8; 32],
+        signature: &[u8; 64],
+        now: u64,
+    ) -> Result<u64, SystemError> {
+        let registration = self.require(contract_addr)?;
+        let message = deregistration_message(contract_addr, registration.key_version);
+        verify_signature(&registration.operator_key, &message, signature)?;
+        if registration.status != IntermediateStatus::Active {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidState,
+                "Intermediate is already draining".to_string(),
+            ));
+        }
+        Ok(now.saturating_add(self.drain_period))
+    }
+
+    /// Checks that `root` for `epoch` comes from a registered intermediate,
+    /// covers no more wallets than its registered capacity and is signed with
+    /// its current operator key. Draining intermediates are still accepted so
+    /// the epoch in progress is not disrupted.
+    pub fn validate_root_submission(
+        &self,
+        contract_addr: &[u8; 32],
+        epoch: u64,
+        root: &[u8; 32],
+        wallet_count: u64,
+        signature: &[u8; 64],
+    ) -> Result<(), SystemError> {
+        let registration = self.require(contract_addr)?;
+        let message = root_submission_message(contract_addr, epoch, root, wallet_count);
+        verify_signature(&registration.operator_key, &message, signature)?;
+        if wallet_count > registration.capacity {
+            return Err(SystemError::new(
+                SystemErrorType::ResourceLimitReached,
+                format!(
+                    "Root covers {} wallets, above the capacity of {}",
+                    wallet_count, registration.capacity
+                ),
+            ));
+        }
+        self.require_stake(registration)
+    }
+
+    /// Checks that a settlement batch of `channel_count` channels under
+    /// `batch_root` is signed for `epoch` with the current operator key of a
+    /// registered intermediate.
+    pub fn validate_settlement_batch(
+        &self,
+        contract_addr: &[u8; 32],
+        epoch: u64,
+        batch_root: &[u8; 32],
+        channel_count: u64,
+        signature: &[u8; 64],
+    ) -> Result<(), SystemError> {
+        let registration = self.require(contract_addr)?;
+        let message = settlement_batch_message(contract_addr, epoch, batch_root, channel_count);
+        verify_signature(&registration.operator_key, &message, signature)?;
+        self.require_stake(registration)
+    }
+
+    /// Draining intermediates whose drain period has ended by `now`.
+    pub fn drained(&self, now: u64) -> Vec<[u8; 32]> {
+        self.intermediates
+            .values()
+            .filter(|registration| {
+                matches!(registration.status, IntermediateStatus::Draining { until } if until <= now)
+            })
+            .map(|registration| registration.contract_addr)
+            .collect()
+    }
+
+    pub(crate) fn insert(&mut self, registration: IntermediateRegistration) {
+        self.intermediates
+            .insert(registration.contract_addr, registration);
+    }
+
+    pub(crate) fn set_operator_key(&mut self, contract_addr: &[u8; 32], operator_key: [u8; 32]) {
+        if let Some(registration) = self.intermediates.get_mut(contract_addr) {
+            registration.operator_key = operator_key;
+            registration.key_version += 1;
+        }
+    }
+
+    pub(crate) fn start_draining(&mut self, contract_addr: &[u8; 32], until: u64) {
+        if let Some(registration) = self.intermediates.get_mut(contract_addr) {
+            registration.status = IntermediateStatus::Draining { until };
+        }
+    }
+
+    pub(crate) fn slash(&mut self, contract_addr: &[u8; 32], amount: u64) {
+        if let Some(registration) = self.intermediates.get_mut(contract_addr) {
+            registration.stake = registration.stake.saturating_sub(amount);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, contract_addr: &[u8; 32]) -> Option<IntermediateRegistration> {
+        self.intermediates.remove(contract_addr)
+    }
+
+    /// A slashed intermediate stays registered but cannot submit again until
+    /// its stake is back above the minimum.
+    fn require_stake(&self, registration: &IntermediateRegistration) -> Result<(), SystemError> {
+        if registration.stake < self.min_stake {
+            return Err(SystemError::new(
+                SystemErrorType::InsufficientBalance,
+                format!(
+                    "Stake {} is below the minimum of {}",
+                    registration.stake, self.min_stake
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn require(&self, contract_addr: &[u8; 32]) -> Result<&IntermediateRegistration, SystemError> {
+        self.intermediates.get(contract_addr).ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NotFound,
+                "Intermediate is not registered".to_string(),
+            )
+        })
+    }
+}
+
+/// Bytes an operator signs to submit `root`, covering `wallet_count`
+/// wallets, for `epoch`.
+pub fn root_submission_message(
+    contract_addr: &[u8; 32],
+    epoch: u64,
+    root: &[u8; 32],
+    wallet_count: u64,
+) -> Vec<u8> {
+    let mut message = Vec::with_capacity(ROOT_SUBMISSION_DOMAIN.len() + 80);
+    message.extend_from_slice(ROOT_SUBMISSION_DOMAIN);
+    message.extend_from_slice(contract_addr);
+    message.extend_from_slice(&epoch.to_le_bytes());
+    message.extend_from_slice(root);
+    message.extend_from_slice(&wallet_count.to_le_bytes());
+    message
+}
+
+/// Bytes an operator signs to settle `channel_count` channels under
+/// `batch_root` in `epoch`.
+pub fn settlement_batch_message(
+    contract_addr: &[u8; 32],
+    epoch: u64,
+    batch_root: &[u8; 32],
+    channel_count: u64,
+) -> Vec<u8> {
+    let mut message = Vec::with_capacity(SETTLEMENT_BATCH_DOMAIN.len() + 80);
+    message.extend_from_slice(SETTLEMENT_BATCH_DOMAIN);
+    message.extend_from_slice(contract_addr);
+    message.extend_from_slice(&epoch.to_le_bytes());
+    message.extend_from_slice(batch_root);
+    message.extend_from_slice(&channel_count.to_le_bytes());
+    message
+}
+
+/// Bytes the operator key of a new registration signs.
+pub fn registration_message(registration: &IntermediateRegistration) -> Vec<u8> {
+    let mut message = Vec::with_capacity(REGISTRATION_DOMAIN.len() + 88);
+    message.extend_from_slice(REGISTRATION_DOMAIN);
+    message.extend_from_slice(&registration.contract_addr);
+    message.extend_from_slice(&registration.operator_key);
+    message.extend_from_slice(&registration.stake.to_le_bytes());
+    message.extend_from_slice(&registration.capacity.to_le_bytes());
+    message.extend_from_slice(&registration.registered_at.to_le_bytes());
+    message
+}
+
+/// Bytes the current operator key signs to start draining the intermediate.
+pub fn deregistration_message(contract_addr: &[u8; 32], key_version: u64) -> Vec<u8> {
+    let mut message = Vec::with_capacity(DEREGISTRATION_DOMAIN.len() + 40);
+    message.extend_from_slice(DEREGISTRATION_DOMAIN);
+    message.extend_from_slice(contract_addr);
+    message.extend_from_slice(&key_version.to_le_bytes());
+    message
+}
+
+/// Bytes the current operator key signs to hand over to `new_key`.
+pub fn key_rotation_message(
+    contract_addr: &[u8; 32],
+    new_key: &[u8; 32],
+    key_version: u64,
+) -> Vec<u8> {
+    let mut message = Vec::with_capacity(KEY_ROTATION_DOMAIN.len() + 72);
+    message.extend_from_slice(KEY_ROTATION_DOMAIN);
+    message.extend_from_slice(contract_addr);
+    message.extend_from_slice(new_key);
+    message.extend_from_slice(&key_version.to_le_bytes());
+    message
+}
+
+fn parse_key(key: &[u8; 32]) -> Result<VerifyingKey, SystemError> {
+    VerifyingKey::from_bytes(key).map_err(|e| {
+        SystemError::new(
+            SystemErrorType::InvalidPublicKey,
+            format!("Invalid operator key: {}", e),
+        )
+    })
+}
+
+fn verify_signature(
+    key: &[u8; 32],
+    message: &[u8],
+    signature: &[u8; 64],
+) -> Result<(), SystemError> {
+    parse_key(key)?
+        .verify(message, &Signature::from_bytes(signature))
+        .map_err(|_| {
+            SystemError::new(
+                SystemErrorType::InvalidSignature,
+                "Signature does not match the operator key".to_string(),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use std::collections::HashMap;
+
+    struct Escrow(HashMap<[u8; 32], u64>);
+
+    impl StakeEscrow for Escrow {
+        fn locked_stake(&self, contract_addr: &[u8; 32]) -> Result<u64, SystemError> {
+            Ok(self.0.get(contract_addr).copied().unwrap_or(0))
+        }
+    }
+
+    fn registration(addr: u8, key: &SigningKey, stake: u64) -> IntermediateRegistration {
+        IntermediateRegistration {
+            contract_addr: [addr; 32],
+            operator_key: key.verifying_key().to_bytes(),
+            key_version: 0,
+            stake,
+            capacity: 1_000,
+            registered_at: 0,
+            status: IntermediateStatus::Active,
+        }
+    }
+
+    fn sign_registration(key: &SigningKey, entry: &IntermediateRegistration) -> [u8; 64] {
+        key.sign(&registration_message(entry)).to_bytes()
+    }
+
+    #[test]
+    fn test_registration_requires_stake_and_unique_address() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let mut registry = IntermediateRegistry::new(100, 10);
+        let escrow = Escrow(HashMap::from([([1; 32], 100)]));
+
+        let entry = registration(1, &key, 99);
+        assert!(registry
+            .validate_registration(&entry, &sign_registration(&key, &entry), &escrow)
+            .is_err());
+
+        let entry = registration(1, &key, 100);
+        let signature = sign_registration(&key, &entry);
+        registry
+            .validate_registration(&entry, &signature, &escrow)
+            .unwrap();
+        registry.insert(entry.clone());
+        assert!(registry
+            .validate_registration(&entry, &signature, &escrow)
+            .is_err());
+    }
+
+    #[test]
+    fn test_registration_stake_must_be_locked_in_escrow() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let registry = IntermediateRegistry::new(100, 10);
+        let escrow = Escrow(HashMap::from([([1; 32], 150), ([2; 32], 500)]));
+
+        // Stake locked for another contract does not count.
+        let entry = registration(1, &key, 200);
+        let err = registry
+            .validate_registration(&entry, &sign_registration(&key, &entry), &escrow)
+            .unwrap_err();
+        assert_eq!(err.error_type, SystemErrorType::InsufficientBalance);
+
+        let entry = registration(1, &key, 150);
+        registry
+            .validate_registration(&entry, &sign_registration(&key, &entry), &escrow)
+            .unwrap();
+
+        // Without an escrow only stake-free registrations pass.
+        let free = IntermediateRegistry::default();
+        let entry = registration(1, &key, 1);
+        assert!(free
+            .validate_registration(&entry, &sign_registration(&key, &entry), &NoStakeEscrow)
+            .is_err());
+        let entry = registration(1, &key, 0);
+        free.validate_registration(&entry, &sign_registration(&key, &entry), &NoStakeEscrow)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_registration_and_deregistration_require_operator_signature() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let other = SigningKey::from_bytes(&[2; 32]);
+        let mut registry = IntermediateRegistry::default();
+
+        let entry = registration(1, &key, 0);
+        assert!(registry
+            .validate_registration(&entry, &[0; 64], &NoStakeEscrow)
+            .is_err());
+        assert!(registry
+            .validate_registration(&entry, &sign_registration(&other, &entry), &NoStakeEscrow)
+            .is_err());
+        registry
+            .validate_registration(&entry, &sign_registration(&key, &entry), &NoStakeEscrow)
+            .unwrap();
+        registry.insert(entry);
+
+        let message = deregistration_message(&[1; 32], 0);
+        assert!(registry
+            .validate_deregistration(&[1; 32], &[0; 64], 0)
+            .is_err());
+        assert!(registry
+            .validate_deregistration(&[1; 32], &other.sign(&message).to_bytes(), 0)
+            .is_err());
+        registry
+            .validate_deregistration(&[1; 32], &key.sign(&message).to_bytes(), 0)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_root_submission_limited_to_capacity() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let mut registry = IntermediateRegistry::default();
+        registry.insert(registration(1, &key, 0));
+
+        let sign = |wallet_count| {
+            key.sign(&root_submission_message(
+                &[1; 32],
+                1,
+                &[7; 32],
+                wallet_count,
+            ))
+            .to_bytes()
+        };
+        registry
+            .validate_root_submission(&[1; 32], 1, &[7; 32], 1_000, &sign(1_000))
+            .unwrap();
+        let err = registry
+            .validate_root_submission(&[1; 32], 1, &[7; 32], 1_001, &sign(1_001))
+            .unwrap_err();
+        assert_eq!(err.error_type, SystemErrorType::ResourceLimitReached);
+        // The signed count cannot be swapped for a smaller one.
+        assert!(registry
+            .validate_root_submission(&[1; 32], 1, &[7; 32], 10, &sign(1_001))
+            .is_err());
+    }
+
+    #[test]
+    fn test_slashed_intermediate_cannot_submit_roots() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let mut registry = IntermediateRegistry::new(100, 10);
+        registry.insert(registration(1, &key, 150));
+        let signature = key
+            .sign(&root_submission_message(&[1; 32], 1, &[7; 32], 1))
+            .to_bytes();
+        registry
+            .validate_root_submission(&[1; 32], 1, &[7; 32], 1, &signature)
+            .unwrap();
+
+        registry.slash(&[1; 32], 60);
+        let err = registry
+            .validate_root_submission(&[1; 32], 1, &[7; 32], 1, &signature)
+            .unwrap_err();
+        assert_eq!(err.error_type, SystemErrorType::InsufficientBalance);
+    }
+
+    #[test]
+    fn test_root_submission_requires_current_key() {
+        let old_key = SigningKey::from_bytes(&[1; 32]);
+        let new_key = SigningKey::from_bytes(&[2; 32]);
+        let mut registry = IntermediateRegistry::default();
+        registry.insert(registration(1, &old_key, 0));
+
+        let message = root_submission_message(&[1; 32], 3, &[7; 32], 0);
+        let old_sig = old_key.sign(&message).to_bytes();
+        registry
+            .validate_root_submission(&[1; 32], 3, &[7; 32], 0, &old_sig)
+            .unwrap();
+        assert!(registry
+            .validate_root_submission(&[1; 32], 4, &[7; 32], 0, &old_sig)
+            .is_err());
+        assert!(registry
+            .validate_root_submission(&[2; 32], 3, &[7; 32], 0, &old_sig)
+            .is_err());
+
+        let new_pub = new_key.verifying_key().to_bytes();
+        let rotation = old_key
+            .sign(&key_rotation_message(&[1; 32], &new_pub, 0))
+            .to_bytes();
+        assert!(registry
+            .validate_key_rotation(&[1; 32], &new_pub, &new_key.sign(b"x").to_bytes())
+            .is_err());
+        registry
+            .validate_key_rotation(&[1; 32], &new_pub, &rotation)
+            .unwrap();
+        registry.set_operator_key(&[1; 32], new_pub);
+
+        // The old rotation signature cannot be replayed at the new version.
+        assert!(registry
+            .validate_key_rotation(&[1; 32], &new_pub, &rotation)
+            .is_err());
+        assert!(registry
+            .validate_root_submission(&[1; 32], 3, &[7; 32], 0, &old_sig)
+            .is_err());
+        let new_sig = new_key.sign(&message).to_bytes();
+        registry
+            .validate_root_submission(&[1; 32], 3, &[7; 32], 0, &new_sig)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_draining_intermediate_is_accepted_until_drained() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let mut registry = IntermediateRegistry::new(0, 30);
+        registry.insert(registration(1, &key, 0));
+
+        let signature = key.sign(&deregistration_message(&[1; 32], 0)).to_bytes();
+        let until = registry
+            .validate_deregistration(&[1; 32], &signature, 100)
+            .unwrap();
+        assert_eq!(until, 130);
+        registry.start_draining(&[1; 32], until);
+        assert!(registry
+            .validate_deregistration(&[1; 32], &signature, 100)
+            .is_err());
+
+        let message = root_submission_message(&[1; 32], 1, &[7; 32], 0);
+        registry
+            .validate_root_submission(&[1; 32], 1, &[7; 32], 0, &key.sign(&message).to_bytes())
+            .unwrap();
+
+        assert!(registry.drained(129).is_empty());
+        assert_eq!(registry.drained(130), vec![[1; 32]]);
+    }
+}
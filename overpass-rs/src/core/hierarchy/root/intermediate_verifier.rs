@@ -1,29 +1,61 @@
 // src/core/global/intermediate_verifier.rs
 
-use crate::core::hierarchy::client::wallet_extension::balance::ZkProof;
+use crate::core::zkps::wallet_root_circuit::{hash_from_bytes, hash_to_bytes};
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::hash::hash_types::HashOut;
+use plonky2::hash::merkle_proofs::{verify_merkle_proof, MerkleProof};
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::config::Hasher;
+
+type F = GoldilocksField;
 
 #[derive(Debug)]
 pub enum VerificationError {
     InvalidProof,
     InvalidMerkleRoot,
+    InvalidHash,
     BalanceOverflow,
     InconsistentGlobalState,
 }
 
+/// Roots and leaves are canonical Poseidon digests, encoded the same way as
+/// wallet tree hashes; bytes that are not a canonical digest are rejected.
+fn root_to_hash_out(root: &[u8; 32]) -> Result<HashOut<F>, VerificationError> {
+    hash_from_bytes(root).map_err(|_| VerificationError::InvalidHash)
+}
+
 pub struct IntermediateVerifier {
     global_merkle_root: [u8; 32],
+    last_update_timestamp: u64,
 }
 
 impl IntermediateVerifier {
+    pub fn new(global_merkle_root: [u8; 32]) -> Self {
+        Self {
+            global_merkle_root,
+            last_update_timestamp: 0,
+        }
+    }
+
+    pub fn global_merkle_root(&self) -> [u8; 32] {
+        self.global_merkle_root
+    }
+
+    pub fn last_update_timestamp(&self) -> u64 {
+        self.last_update_timestamp
+    }
+
     /// Verifies proofs submitted by the intermediate layers and updates global state
     pub fn verify_intermediate_proof(
         &mut self,
         intermediate_root: [u8; 32],
-        proof: ZkProof,
+        leaf: [u8; 32],
+        leaf_index: usize,
+        proof: &MerkleProof<F, PoseidonHash>,
         shard_balance: u64,
     ) -> Result<bool, VerificationError> {
         // Verify the Merkle proof for the intermediate contract state
-        if !self.verify_merkle_proof(&intermediate_root, &proof) {
+        if !self.verify_merkle_proof(&intermediate_root, &leaf, leaf_index, proof)? {
             return Err(VerificationError::InvalidProof);
         }
 
@@ -32,95 +64,44 @@ impl IntermediateVerifier {
             return Err(VerificationError::BalanceOverflow);
         }
 
-        // Verify the intermediate root hash
-        let computed_hash = self.compute_poseidon_hash(&intermediate_root);
-        if !self.verify_root_hash(computed_hash) {
+        // Verify the intermediate root hash against the anchored one; a
+        // fresh verifier anchors the first root it is given.
+        let computed_hash = self.compute_poseidon_hash(&intermediate_root)?;
+        if self.global_merkle_root != [0u8; 32] && !self.verify_root_hash(&computed_hash) {
             return Err(VerificationError::InvalidMerkleRoot);
         }
 
-        // Update global Merkle root (O(log m) operation)
+        // Update global Merkle root
         self.update_global_root(intermediate_root)?;
 
         Ok(true)
     }
 
-    fn verify_merkle_proof(&self, root: &[u8; 32], proof: &ZkProof) -> bool {
-        let mut current_hash = proof.leaf_hash;
-
-        for (sibling, is_left) in proof.siblings.iter().zip(proof.path.iter()) {
-            let (left, right) = if *is_left {
-                (sibling, &current_hash)
-            } else {
-                (&current_hash, sibling)
-            };
-
-            let mut combined = [0u8; 64];
-            combined[..32].copy_from_slice(left);
-            combined[32..].copy_from_slice(right);
-
-            current_hash = self.compute_poseidon_hash(&combined[..32]);
-        }
-
-        &current_hash == root
+    /// Checks `proof` with Plonky2's own Merkle verification, so the result
+    /// is the same as verifying the path inside a circuit.
+    pub fn verify_merkle_proof(
+        &self,
+        root: &[u8; 32],
+        leaf: &[u8; 32],
+        leaf_index: usize,
+        proof: &MerkleProof<F, PoseidonHash>,
+    ) -> Result<bool, VerificationError> {
+        let merkle_root = root_to_hash_out(root)?;
+        let leaf_data = root_to_hash_out(leaf)?.elements.to_vec();
+        Ok(verify_merkle_proof(leaf_data, leaf_index, merkle_root, proof).is_ok())
     }
 
-    fn compute_poseidon_hash(&self, data: &[u8; 32]) -> [u8; 32] {
-        let mut state = [0u8; 32];
-        let mut t = [0u8; 32];
-
-        // Initialize state with input
-        state.copy_from_slice(data);
-
-        // Apply Poseidon round constants and S-boxes
-        for r in 0..8 {
-            // Add round constants
-            for i in 0..32 {
-                state[i] = state[i].wrapping_add(POSEIDON_ROUND_CONSTANTS[r][i]);
-            }
-
-            // Apply S-box (x^5 in GF(p))
-            for i in 0..32 {
-                let x = state[i] as u32;
-                let x2 = (x * x) % 255;
-                let x4 = (x2 * x2) % 255;
-                state[i] = ((x4 * x) % 255) as u8;
-            }
-
-            // Mix layer using MDS matrix
-            t.copy_from_slice(&state);
-            for i in 0..32 {
-                state[i] = 0;
-                for j in 0..32 {
-                    state[i] = state[i]
-                        .wrapping_add((t[j] as u32 * POSEIDON_MDS_MATRIX[i][j] as u32 % 255) as u8);
-                }
-            }
-        }
-
-        state
+    /// Poseidon over Goldilocks of a root given as a canonical digest.
+    pub fn compute_poseidon_hash(&self, data: &[u8; 32]) -> Result<[u8; 32], VerificationError> {
+        Ok(hash_to_bytes(&PoseidonHash::hash_no_pad(
+            &root_to_hash_out(data)?.elements,
+        )))
     }
 
-    fn verify_root_hash(&self, hash: [u8; 32]) -> bool {
-        // Verify that the provided hash matches the stored global Merkle root
-        // This ensures the integrity of the intermediate contract hierarchy
-
-        // First check if hash is valid (non-zero)
-        let is_valid = hash.iter().any(|&x| x != 0);
-        if !is_valid {
-            return false;
-        }
-
-        // Compare the provided hash with stored global root
-        // Both must be exactly equal for verification to pass
-        let mut result = true;
-        for i in 0..32 {
-            if hash[i] != self.global_merkle_root[i] {
-                result = false;
-                break;
-            }
-        }
-
-        result
+    /// Returns true if `hash` is non-zero and matches the stored global
+    /// Merkle root.
+    pub fn verify_root_hash(&self, hash: &[u8; 32]) -> bool {
+        hash.iter().any(|&x| x != 0) && *hash == self.global_merkle_root
     }
 
     fn update_global_root(
@@ -133,13 +114,8 @@ impl IntermediateVerifier {
             return Err(VerificationError::InvalidHash);
         }
 
-        // Hash the new root to ensure it follows proper format
-        let hashed_root = self.hash_state(&new_intermediate_root);
-
-        // Update the stored global Merkle root with proper synchronization
-        self.global_merkle_root.copy_from_slice(&hashed_root);
+        self.global_merkle_root = self.compute_poseidon_hash(&new_intermediate_root)?;
 
-        // Emit event or update metadata if needed
         self.last_update_timestamp = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
@@ -148,3 +124,104 @@ impl IntermediateVerifier {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plonky2::hash::merkle_tree::MerkleTree;
+
+    fn leaves() -> Vec<[u8; 32]> {
+        (0..4u8).map(|i| [i + 1; 32]).collect()
+    }
+
+    fn tree() -> MerkleTree<F, PoseidonHash> {
+        let leaves = leaves()
+            .iter()
+            .map(|leaf| root_to_hash_out(leaf).unwrap().elements.to_vec())
+            .collect();
+        MerkleTree::new(leaves, 0)
+    }
+
+    #[test]
+    fn test_root_encoding_round_trips() {
+        let root: [u8; 32] = core::array::from_fn(|i| 0xf0 - i as u8);
+        let digest = root_to_hash_out(&root).unwrap();
+        assert_eq!(hash_to_bytes(&digest), root);
+
+        let digest = PoseidonHash::hash_no_pad(&digest.elements);
+        let bytes = hash_to_bytes(&digest);
+        assert_eq!(root_to_hash_out(&bytes).unwrap(), digest);
+
+        // u64::MAX is above the Goldilocks modulus.
+        assert!(root_to_hash_out(&[0xff; 32]).is_err());
+    }
+
+    #[test]
+    fn test_merkle_proof_matches_plonky2_tree() {
+        let tree = tree();
+        let root = hash_to_bytes(&tree.cap.0[0]);
+        let verifier = IntermediateVerifier::new([0; 32]);
+
+        for (index, leaf) in leaves().iter().enumerate() {
+            let proof = tree.prove(index);
+            assert!(verifier
+                .verify_merkle_proof(&root, leaf, index, &proof)
+                .unwrap());
+        }
+
+        let proof = tree.prove(0);
+        assert!(!verifier
+            .verify_merkle_proof(&root, &[9; 32], 0, &proof)
+            .unwrap());
+        assert!(!verifier
+            .verify_merkle_proof(&root, &leaves()[0], 1, &proof)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_verify_intermediate_proof_updates_global_root() {
+        let tree = tree();
+        let root = hash_to_bytes(&tree.cap.0[0]);
+        let mut verifier = IntermediateVerifier::new([0; 32]);
+
+        assert!(verifier
+            .verify_intermediate_proof(root, leaves()[2], 2, &tree.prove(2), 100)
+            .unwrap());
+        let anchored = verifier.compute_poseidon_hash(&root).unwrap();
+        assert!(verifier.verify_root_hash(&anchored));
+        assert!(verifier
+            .verify_intermediate_proof(root, leaves()[2], 3, &tree.prove(2), 100)
+            .is_err());
+    }
+
+    #[test]
+    fn test_proof_against_other_root_rejected_once_anchored() {
+        let tree = tree();
+        let root = hash_to_bytes(&tree.cap.0[0]);
+        let mut verifier = IntermediateVerifier::new([0; 32]);
+        verifier
+            .verify_intermediate_proof(root, leaves()[0], 0, &tree.prove(0), 100)
+            .unwrap();
+
+        let other: Vec<_> = leaves()
+            .iter()
+            .map(|leaf| {
+                root_to_hash_out(&[leaf[0] + 4; 32])
+                    .unwrap()
+                    .elements
+                    .to_vec()
+            })
+            .collect();
+        let other = MerkleTree::<F, PoseidonHash>::new(other, 0);
+        assert!(matches!(
+            verifier.verify_intermediate_proof(
+                hash_to_bytes(&other.cap.0[0]),
+                [5; 32],
+                0,
+                &other.prove(0),
+                100
+            ),
+            Err(VerificationError::InvalidMerkleRoot)
+        ));
+    }
+}
@@ -2,8 +2,13 @@
 
 pub mod audit_interface;
 pub mod epoch;
+pub mod epoch_scheduler;
+pub mod fraud_proof;
 pub mod global_state;
 pub mod global_tree_manager;
-//pub mod intermediate_verifier;
+pub mod intermediate_registry;
+pub mod intermediate_verifier;
 pub mod root_contract;
+pub mod root_history;
+pub mod root_state_store;
 pub mod sparse_merkle_tree_r;
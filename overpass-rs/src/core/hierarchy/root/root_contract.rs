@@ -2,15 +2,69 @@
 use crate::core::error::errors::{SystemError, SystemErrorType};
 use crate::core::hierarchy::client::channel::channel_contract::{Cell, CellType};
 use crate::core::hierarchy::client::wallet_extension::wallet_extension_types::Transaction;
-use crate::core::hierarchy::root::sparse_merkle_tree_r::SparseMerkleTreeR;
+use crate::core::hierarchy::intermediate::sparse_merkle_tree_i::SparseMerkleTreeI;
+use crate::core::hierarchy::root::fraud_proof::{
+    self, FraudProof, PendingIntermediateRoot, SlashingEvent,
+};
+use crate::core::hierarchy::root::global_tree_manager::{
+    GlobalTreeManager, IntermediateProofVerifier, RootTransition, RootTransitionVerifier,
+};
+use crate::core::hierarchy::root::intermediate_registry::{
+    IntermediateRegistration, IntermediateRegistry, IntermediateStatus, NoStakeEscrow, StakeEscrow,
+};
+use crate::core::hierarchy::root::root_history::{
+    RootHistory, RootSettlementStatus, SettlementBatchRecord,
+};
+use crate::core::hierarchy::root::root_state_store::{RootStateRecord, RootStateStore};
+use crate::core::hierarchy::root::sparse_merkle_tree_r::SparseMerkleProof;
+use crate::core::state::boc::{TonBoc, TonBuilder};
 use crate::core::types::boc::BOC;
+use crate::core::zkps::proof::ZkProof;
+use ovp_cell::{CellBuilder, CellError, CellSlice, MAX_CELL_REFS};
 use plonky2::field::goldilocks_field::GoldilocksField;
 use plonky2::hash::merkle_proofs::MerkleProof;
 use plonky2::hash::poseidon::PoseidonHash;
-use std::collections::HashMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Opens root state written with typed cells. State written before it
+/// starts directly with the epoch and is read as version 1.
+const STATE_MAGIC: u32 = 0x6f76_7072;
+/// Layout version stored after [`STATE_MAGIC`].
+const STATE_VERSION: u8 = 2;
+
+/// A contract address, its intermediate root and the lowest sequence its
+/// next proven root may carry, as packed into version 1 state.
+const V1_INTERMEDIATE_ENTRY_LEN: usize = 72;
+
+/// Global root sealed for one epoch, ready to be submitted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobalRootSubmission {
+    pub epoch: u64,
+    pub global_root: Hash,
+    pub intermediate_roots: BTreeMap<Address, Hash>,
+    pub proofs: HashMap<Address, SparseMerkleProof>,
+    pub submitted_at: u64,
+}
+
+/// Roots submitted in optimistic mode that are still open to challenge,
+/// plus every slashing that has happened.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct OptimisticState {
+    /// Per intermediate, oldest first. Each builds on the one before it.
+    pending: BTreeMap<Address, Vec<PendingIntermediateRoot>>,
+    slashing_events: Vec<SlashingEvent>,
+}
+
 pub struct RootContract {
-    global_tree: SparseMerkleTreeR,
+    /// Holds the verifier for intermediate root proofs alongside the tree.
+    global_tree: GlobalTreeManager<Box<dyn RootTransitionVerifier>>,
+    /// Checks the wallet-root proofs revealed by fraud proofs.
+    wallet_verifier: Box<dyn IntermediateProofVerifier>,
     intermediate_roots: HashMap<Address, Hash>,
+    /// Per intermediate, one past the sequence of its last proven root.
+    next_sequences: HashMap<Address, u64>,
     epoch: u64,
     epoch_duration: u64,
     last_submission: u64,
@@ -22,13 +76,30 @@ pub struct RootContract {
     verify_global_state: bool,
     verify_root_state: bool,
     submit_settlement: bool,
+    store: Option<RootStateStore>,
+    history: RootHistory,
+    registry: IntermediateRegistry,
+    stake_escrow: Box<dyn StakeEscrow>,
+    challenge_window: u64,
+    optimistic: OptimisticState,
+    /// Checks settlement batch proofs; without one no batch is accepted.
+    settlement_verifier: Option<Box<dyn IntermediateProofVerifier>>,
+    settlement_batches: BTreeMap<Hash, SettlementBatchRecord>,
 }
 
 impl RootContract {
-    pub fn new(epoch_duration: u64) -> Self {
+    /// Creates an empty contract whose intermediate root proofs are checked
+    /// by `verifier` and whose fraud proofs are checked by `wallet_verifier`.
+    pub fn new(
+        epoch_duration: u64,
+        verifier: impl RootTransitionVerifier + 'static,
+        wallet_verifier: impl IntermediateProofVerifier + 'static,
+    ) -> Self {
         Self {
-            global_tree: SparseMerkleTreeR::new(),
+            global_tree: GlobalTreeManager::new(Box::new(verifier)),
+            wallet_verifier: Box::new(wallet_verifier),
             intermediate_roots: HashMap::new(),
+            next_sequences: HashMap::new(),
             epoch: 0,
             epoch_duration,
             last_submission: 0,
@@ -40,40 +111,586 @@ impl RootContract {
             verify_global_state: false,
             verify_root_state: false,
             submit_settlement: true,
+            store: None,
+            history: RootHistory::in_memory(),
+            registry: IntermediateRegistry::default(),
+            stake_escrow: Box::new(NoStakeEscrow),
+            challenge_window: 0,
+            optimistic: OptimisticState::default(),
+            settlement_verifier: None,
+            settlement_batches: BTreeMap::new(),
         }
     }
 
+    /// Attaches a durable store. Every subsequent state change is written to
+    /// the store before it is applied in memory.
+    pub fn with_store(mut self, store: RootStateStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Records every submitted global root in `history` instead of an
+    /// in-memory history.
+    pub fn with_history(mut self, history: RootHistory) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Uses `registry` (and its stake and drain settings) to decide which
+    /// intermediates may submit roots.
+    pub fn with_registry(mut self, registry: IntermediateRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Checks the stake of new registrations against `stake_escrow`. Without
+    /// one, only registrations declaring no stake are accepted.
+    pub fn with_stake_escrow(mut self, stake_escrow: impl StakeEscrow + 'static) -> Self {
+        self.stake_escrow = Box::new(stake_escrow);
+        self
+    }
+
+    /// Accepts settlement batches whose proofs `settlement_verifier` checks
+    /// against the batch root.
+    pub fn with_settlement_verifier(
+        mut self,
+        settlement_verifier: impl IntermediateProofVerifier + 'static,
+    ) -> Self {
+        self.settlement_verifier = Some(Box::new(settlement_verifier));
+        self
+    }
+
+    /// Sets how long, in seconds, roots submitted with
+    /// [`propose_intermediate_root`](Self::propose_intermediate_root) stay
+    /// open to fraud proofs.
+    pub fn with_challenge_window(mut self, challenge_window: u64) -> Self {
+        self.challenge_window = challenge_window;
+        self
+    }
+
+    /// Rebuilds the contract from the latest snapshot in `store` plus every
+    /// record logged after it, then keeps using `store` for new changes.
+    ///
+    /// Snapshots only hold a cursor into the root history, so `history` must
+    /// be the durable history the contract was recording into; it is checked
+    /// to reach the snapshot's cursor. `epoch_duration` is configuration
+    /// rather than state, so it replaces whatever duration the snapshot was
+    /// taken with.
+    pub fn recover(
+        epoch_duration: u64,
+        verifier: impl RootTransitionVerifier + 'static,
+        wallet_verifier: impl IntermediateProofVerifier + 'static,
+        mut store: RootStateStore,
+        history: RootHistory,
+    ) -> Result<Self, SystemError> {
+        let recovered = store.load()?;
+
+        let mut migrate = false;
+        let mut contract = match recovered.snapshot {
+            Some(state_data) => {
+                let decoded = Self::decode_state(&state_data, verifier, wallet_verifier)?;
+                if decoded.history_cursor > 0 && history.get(decoded.history_cursor).is_none() {
+                    return Err(SystemError::new(
+                        SystemErrorType::StateDataMismatch,
+                        format!(
+                            "Root history does not reach the snapshot's epoch {}",
+                            decoded.history_cursor
+                        ),
+                    ));
+                }
+                migrate = decoded.version < STATE_VERSION;
+                decoded.contract
+            }
+            None => Self::new(epoch_duration, verifier, wallet_verifier),
+        };
+        contract.epoch_duration = epoch_duration;
+        contract.history = history;
+
+        for record in &recovered.records {
+            contract.apply_record(record)?;
+        }
+
+        contract.store = Some(store);
+        if migrate {
+            // Rewrite the snapshot in the current layout; the old one stays
+            // readable if this fails.
+            contract.write_snapshot();
+        }
+        Ok(contract)
+    }
+
+    /// Handles `RootOpCode::RegisterIntermediate`. `signature` is made with
+    /// the registration's operator key over
+    /// [`registration_message`](crate::core::hierarchy::root::intermediate_registry::registration_message).
+    pub fn register_intermediate(
+        &mut self,
+        registration: IntermediateRegistration,
+        signature: &[u8; 64],
+    ) -> Result<(), SystemError> {
+        self.registry.validate_registration(
+            &registration,
+            signature,
+            self.stake_escrow.as_ref(),
+        )?;
+        self.commit(RootStateRecord::IntermediateRegistered(registration))
+    }
+
+    /// Replaces an intermediate's operator key. `signature` is made with the
+    /// current key over [`key_rotation_message`](crate::core::hierarchy::root::intermediate_registry::key_rotation_message).
+    pub fn rotate_operator_key(
+        &mut self,
+        contract_addr: Address,
+        operator_key: [u8; 32],
+        signature: &[u8; 64],
+    ) -> Result<(), SystemError> {
+        self.registry
+            .validate_key_rotation(&contract_addr, &operator_key, signature)?;
+        self.commit(RootStateRecord::OperatorKeyRotated {
+            contract_addr,
+            operator_key,
+        })
+    }
+
+    /// Handles `RootOpCode::RemoveIntermediate`. The intermediate keeps
+    /// submitting roots for the drain period and is removed, together with
+    /// its root, when the first epoch after the drain period is sealed.
+    /// `signature` is made with the current operator key over
+    /// [`deregistration_message`](crate::core::hierarchy::root::intermediate_registry::deregistration_message).
+    pub fn begin_deregistration(
+        &mut self,
+        contract_addr: Address,
+        signature: &[u8; 64],
+        now: u64,
+    ) -> Result<(), SystemError> {
+        let until = self
+            .registry
+            .validate_deregistration(&contract_addr, signature, now)?;
+        self.commit(RootStateRecord::DeregistrationStarted {
+            contract_addr,
+            until,
+        })
+    }
+
+    /// Handles `RootOpCode::ValidateIntermediate`: checks that `root` comes
+    /// from a registered intermediate, covers no more than its capacity of
+    /// `wallet_count` wallets and is signed for the epoch currently
+    /// collecting roots.
+    pub fn validate_intermediate_root(
+        &self,
+        contract_addr: &Address,
+        root: &Hash,
+        wallet_count: u64,
+        signature: &[u8; 64],
+    ) -> Result<(), SystemError> {
+        self.registry.validate_root_submission(
+            contract_addr,
+            self.epoch + 1,
+            root,
+            wallet_count,
+            signature,
+        )
+    }
+
+    /// Checks that `transition` may follow a root accepted from the
+    /// intermediate with sequence `after`, and that its proof moves
+    /// `old_root` to `root`. Callers holding roots that are not applied yet
+    /// use this to check the ones that build on them.
+    pub fn verify_root_transition(
+        &self,
+        old_root: &Hash,
+        root: &Hash,
+        after: Option<u64>,
+        transition: &RootTransition,
+    ) -> Result<(), SystemError> {
+        if after.is_some_and(|after| transition.sequence <= after) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidSequence,
+                format!(
+                    "Root sequence {} does not follow {}",
+                    transition.sequence,
+                    after.unwrap()
+                ),
+            ));
+        }
+        self.global_tree.verify_transition(
+            old_root,
+            root,
+            transition.sequence,
+            &transition.commitment,
+            &transition.proof,
+        )
+    }
+
+    /// Applies a signed intermediate root. The contract checks the
+    /// operator's signature and capacity, that the submission sequence is
+    /// above the last one accepted from the intermediate, and that the proof
+    /// moves the intermediate's current root (its genesis root or the empty
+    /// tree at first) to `root`.
     pub fn process_intermediate_root(
         &mut self,
         contract_addr: Address,
         root: Hash,
-        _proof: MerkleProof<GoldilocksField, PoseidonHash>,
+        wallet_count: u64,
+        signature: &[u8; 64],
+        transition: &RootTransition,
+    ) -> Result<(), SystemError> {
+        self.validate_intermediate_root(&contract_addr, &root, wallet_count, signature)?;
+        if self.optimistic.pending.contains_key(&contract_addr) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidState,
+                "Intermediate has roots awaiting their challenge window".to_string(),
+            ));
+        }
+        let next = self
+            .next_sequences
+            .get(&contract_addr)
+            .copied()
+            .unwrap_or(0);
+        if transition.sequence < next {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidSequence,
+                format!("Root sequence must be at least {}", next),
+            ));
+        }
+        self.global_tree.verify_intermediate_root(
+            &contract_addr,
+            &root,
+            transition.sequence,
+            &transition.commitment,
+            &transition.proof,
+        )?;
+        self.commit(RootStateRecord::IntermediateRoot {
+            contract_addr,
+            root,
+            sequence: transition.sequence,
+        })
+    }
+
+    /// Sequence of the last proven root accepted from `contract_addr`, if it
+    /// still holds a root.
+    pub fn last_root_sequence(&self, contract_addr: &Address) -> Option<u64> {
+        self.next_sequences
+            .get(contract_addr)
+            .and_then(|next| next.checked_sub(1))
+    }
+
+    /// Sets the root a registered intermediate's first proof starts from,
+    /// for intermediates that begin with wallets already in their tree. Only
+    /// possible before the intermediate has a root.
+    pub fn register_genesis_root(
+        &mut self,
+        contract_addr: Address,
+        root: Hash,
+    ) -> Result<(), SystemError> {
+        if self.registry.get(&contract_addr).is_none() {
+            return Err(SystemError::new(
+                SystemErrorType::NotFound,
+                "Intermediate is not registered".to_string(),
+            ));
+        }
+        if self.intermediate_roots.contains_key(&contract_addr)
+            || self.optimistic.pending.contains_key(&contract_addr)
+        {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidState,
+                "Intermediate already has a root".to_string(),
+            ));
+        }
+        self.commit(RootStateRecord::GenesisRootRegistered {
+            contract_addr,
+            root,
+        })
+    }
+
+    /// Optimistic counterpart of [`process_intermediate_root`](Self::process_intermediate_root).
+    ///
+    /// The root is held back until the challenge window has passed and only
+    /// then enters the global tree. `transition_commitment` commits to the
+    /// wallet-root updates the root is built from (see
+    /// [`fraud_proof::transition_commitment`]); their proofs are only checked
+    /// if the root is challenged.
+    pub fn propose_intermediate_root(
+        &mut self,
+        contract_addr: Address,
+        root: Hash,
+        wallet_count: u64,
+        signature: &[u8; 64],
+        transition_commitment: Hash,
+        now: u64,
+    ) -> Result<(), SystemError> {
+        self.validate_intermediate_root(&contract_addr, &root, wallet_count, signature)?;
+        let previous_root = self
+            .pending_roots(&contract_addr)
+            .last()
+            .map(|pending| pending.root)
+            .or_else(|| self.intermediate_roots.get(&contract_addr).copied())
+            .unwrap_or_else(|| SparseMerkleTreeI::new().root());
+
+        self.commit(RootStateRecord::IntermediateRootProposed(
+            PendingIntermediateRoot {
+                contract_addr,
+                previous_root,
+                root,
+                transition_commitment,
+                submitted_at: now,
+                challenge_deadline: now.saturating_add(self.challenge_window),
+            },
+        ))
+    }
+
+    /// Accepts a batch of `channel_count` channel settlements under
+    /// `batch_root`, signed by the intermediate's operator over
+    /// [`settlement_batch_message`](crate::core::hierarchy::root::intermediate_registry::settlement_batch_message)
+    /// for the epoch currently collecting roots. The batch settles with that
+    /// epoch. Resubmitting an accepted batch changes nothing unless its epoch
+    /// was rejected.
+    pub fn submit_settlement_batch(
+        &mut self,
+        contract_addr: Address,
+        batch_root: Hash,
+        channel_count: u64,
+        signature: &[u8; 64],
+        proof: &ZkProof,
     ) -> Result<(), SystemError> {
-        self.intermediate_roots.insert(contract_addr, root);
-        self.global_tree.update_global_tree(&contract_addr, &root)?;
+        if let Some(existing) = self.settlement_batches.get(&batch_root) {
+            let status = self.history.get(existing.epoch).map(|record| record.status);
+            if status != Some(RootSettlementStatus::Rejected) {
+                return Ok(());
+            }
+        }
+        let epoch = self.epoch + 1;
+        self.registry.validate_settlement_batch(
+            &contract_addr,
+            epoch,
+            &batch_root,
+            channel_count,
+            signature,
+        )?;
+        let verifier = self.settlement_verifier.as_ref().ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::OperationDisabled,
+                "Root contract has no settlement verifier".to_string(),
+            )
+        })?;
+        verifier.verify(proof, &batch_root)?;
+        self.commit(RootStateRecord::SettlementBatchAccepted {
+            batch_root,
+            batch: SettlementBatchRecord {
+                contract_addr,
+                epoch,
+                channel_count,
+            },
+        })
+    }
+
+    /// The accepted settlement batch under `batch_root`, if any. Its status
+    /// is that of its epoch in [`history`](Self::history), once sealed.
+    pub fn settlement_batch(&self, batch_root: &Hash) -> Option<&SettlementBatchRecord> {
+        self.settlement_batches.get(batch_root)
+    }
+
+    /// Challenges a pending root with a fraud proof. On success the root and
+    /// every later pending root of the same intermediate are reverted, the
+    /// intermediate's stake is slashed and the slashing event is returned.
+    pub fn challenge_intermediate_root(
+        &mut self,
+        contract_addr: Address,
+        root: Hash,
+        fraud_proof: &FraudProof,
+        challenger: [u8; 32],
+        now: u64,
+    ) -> Result<SlashingEvent, SystemError> {
+        let pending = self
+            .pending_roots(&contract_addr)
+            .iter()
+            .find(|pending| pending.root == root)
+            .ok_or_else(|| {
+                SystemError::new(
+                    SystemErrorType::NotFound,
+                    "No pending root to challenge".to_string(),
+                )
+            })?;
+        if now >= pending.challenge_deadline {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidState,
+                "Challenge window has closed".to_string(),
+            ));
+        }
+        fraud_proof::verify_fraud_proof(pending, fraud_proof, self.wallet_verifier.as_ref())?;
+
+        let event = SlashingEvent {
+            contract_addr,
+            challenger,
+            reverted_root: root,
+            slashed_stake: self
+                .registry
+                .get(&contract_addr)
+                .map_or(0, |registration| registration.stake),
+            timestamp: now,
+        };
+        self.commit(RootStateRecord::IntermediateRootReverted(event.clone()))?;
+        Ok(event)
+    }
+
+    /// Moves every pending root whose challenge window has closed by `now`
+    /// into the global tree. Sealing an epoch does this first.
+    pub fn accept_unchallenged_roots(&mut self, now: u64) -> Result<(), SystemError> {
+        let matured: Vec<_> = self
+            .optimistic
+            .pending
+            .values()
+            .flat_map(|pending| {
+                pending
+                    .iter()
+                    .take_while(|pending| pending.challenge_deadline <= now)
+            })
+            .map(|pending| (pending.contract_addr, pending.root))
+            .collect();
+        for (contract_addr, root) in matured {
+            self.commit(RootStateRecord::ProposedRootAccepted {
+                contract_addr,
+                root,
+            })?;
+        }
         Ok(())
     }
 
+    pub fn pending_roots(&self, contract_addr: &Address) -> &[PendingIntermediateRoot] {
+        self.optimistic
+            .pending
+            .get(contract_addr)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    pub fn slashing_events(&self) -> &[SlashingEvent] {
+        &self.optimistic.slashing_events
+    }
+
+    /// Submits the global root once the epoch has elapsed, together with an
+    /// inclusion proof for every registered intermediate root.
     pub fn try_submit_global_root(
         &mut self,
         now: u64,
-    ) -> Option<(Hash, MerkleProof<GoldilocksField, PoseidonHash>)> {
+    ) -> Option<(Hash, HashMap<Address, SparseMerkleProof>)> {
         if now - self.last_submission < self.epoch_duration {
             return None;
         }
 
-        let root = self.global_tree.get_global_root_hash();
-        let proof = MerkleProof { siblings: vec![] };
+        let submission = self.seal_epoch(now).ok()?;
+        Some((submission.global_root, submission.proofs))
+    }
+
+    /// Closes the current epoch at `now` regardless of the epoch duration and
+    /// returns the global root with an inclusion proof per intermediate.
+    ///
+    /// Callers that pace epochs themselves (such as the epoch scheduler) use
+    /// this directly instead of [`try_submit_global_root`](Self::try_submit_global_root).
+    pub fn seal_epoch(&mut self, now: u64) -> Result<GlobalRootSubmission, SystemError> {
+        self.accept_unchallenged_roots(now)?;
+        let global_root = self.global_tree.generate_global_root();
+        let mut proofs = HashMap::with_capacity(self.intermediate_roots.len());
+        for contract_addr in self.intermediate_roots.keys() {
+            proofs.insert(
+                *contract_addr,
+                self.global_tree.get_inclusion_proof(contract_addr)?,
+            );
+        }
+        let intermediate_roots: BTreeMap<_, _> = self
+            .intermediate_roots
+            .iter()
+            .map(|(addr, root)| (*addr, *root))
+            .collect();
 
-        self.epoch += 1;
-        self.last_submission = now;
+        let epoch = self.epoch + 1;
+        let record = RootStateRecord::EpochAdvanced {
+            epoch,
+            last_submission: now,
+        };
+        // Written before the epoch advances; a retried submission for the
+        // same epoch simply replaces the entry.
+        self.history
+            .record_submission(epoch, global_root, intermediate_roots.clone(), now)?;
+        // Never advance the epoch in memory unless the change is durable;
+        // the submission is simply retried on the next call.
+        self.persist(&record)?;
+        self.apply_record(&record)?;
+        self.maybe_snapshot();
         self.verify_settlement_state = true;
         self.submit_settlement = true;
+        self.retire_drained(now);
 
-        if !self.verify_global_state {
-            return None;
+        Ok(GlobalRootSubmission {
+            epoch,
+            global_root,
+            intermediate_roots,
+            proofs,
+            submitted_at: now,
+        })
+    }
+
+    /// Gives up on `epoch` after its global root could not be submitted.
+    ///
+    /// Every later epoch was sealed on top of it, so those are rejected too,
+    /// and the intermediate roots go back to what the last epoch before them
+    /// anchored. Intermediates whose roots are reverted resubmit from the
+    /// restored root, and their roots still in a challenge window are dropped
+    /// with it. Returns the rejected epochs, oldest first.
+    pub fn reject_epoch(&mut self, epoch: u64) -> Result<Vec<u64>, SystemError> {
+        let rejected: Vec<_> = self
+            .history
+            .range(epoch..=u64::MAX)
+            .map(|record| (record.epoch, record.status))
+            .collect();
+        if rejected.first().map(|(first, _)| *first) != Some(epoch) {
+            return Err(SystemError::new(
+                SystemErrorType::NotFound,
+                format!("Unknown epoch {}", epoch),
+            ));
+        }
+        if rejected
+            .iter()
+            .any(|(_, status)| *status == RootSettlementStatus::Finalized)
+        {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidState,
+                format!("An epoch from {} on is already final", epoch),
+            ));
         }
-        Some((root, proof))
+
+        let registry = &self.registry;
+        let intermediate_roots = self
+            .history
+            .range(0..=epoch.saturating_sub(1))
+            .filter(|record| record.status != RootSettlementStatus::Rejected)
+            .last()
+            .map(|record| {
+                record
+                    .intermediate_roots
+                    .iter()
+                    .filter(|(contract_addr, _)| registry.get(contract_addr).is_some())
+                    .map(|(contract_addr, root)| (*contract_addr, *root))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        for (rejected_epoch, _) in &rejected {
+            self.history
+                .update_status(*rejected_epoch, RootSettlementStatus::Rejected)?;
+        }
+        self.commit(RootStateRecord::EpochRolledBack {
+            epoch,
+            intermediate_roots,
+        })?;
+        Ok(rejected.into_iter().map(|(epoch, _)| epoch).collect())
+    }
+
+    /// Proves that the intermediate at `contract_addr` is part of the
+    /// current global root.
+    pub fn prove_intermediate_root(
+        &self,
+        contract_addr: &Address,
+    ) -> Result<SparseMerkleProof, SystemError> {
+        self.global_tree.get_inclusion_proof(contract_addr)
     }
 
     pub fn verify_transaction(
@@ -86,65 +703,1421 @@ impl RootContract {
         }
         Ok(true)
     }
-    pub fn deserialize(boc: BOC) -> Result<Self, SystemError> {
-        let root_cell = boc.roots.first().ok_or(SystemError {
+    /// Restores the contract state from a BOC written by
+    /// [`serialize`](Self::serialize), checking intermediate root proofs with
+    /// `verifier` and fraud proofs with `wallet_verifier` from then on. The root history is kept in its own store and
+    /// comes back empty; attach it with [`with_history`](Self::with_history).
+    pub fn deserialize(
+        boc: BOC,
+        verifier: impl RootTransitionVerifier + 'static,
+        wallet_verifier: impl IntermediateProofVerifier + 'static,
+    ) -> Result<Self, SystemError> {
+        let state_data = boc.roots.first().ok_or(SystemError {
             error_type: SystemErrorType::NotFound,
             message: "Empty BOC".to_string(),
         })?;
+        Ok(Self::decode_state(state_data, verifier, wallet_verifier)?.contract)
+    }
+
+    /// Decodes state data written by [`to_state_data`](Self::to_state_data),
+    /// or in the version 1 layout earlier snapshots used.
+    fn decode_state(
+        state_data: &[u8],
+        verifier: impl RootTransitionVerifier + 'static,
+        wallet_verifier: impl IntermediateProofVerifier + 'static,
+    ) -> Result<DecodedState, SystemError> {
+        let bag = TonBoc::parse(state_data)?;
+        let root = *bag.roots.first().ok_or(SystemError {
+            error_type: SystemErrorType::NotFound,
+            message: "State data has no root cell".to_string(),
+        })?;
+        let state_cell = bag.tree(root)?;
+        let tagged = state_cell.parse().load_uint(32).ok() == Some(STATE_MAGIC as u128);
+        let snapshot = if tagged {
+            StateSnapshot::decode(&state_cell)?
+        } else {
+            StateSnapshot::decode_v1(&state_cell)?
+        };
 
-        let state_data = root_cell;
-        if state_data.len() < 26 {
+        let mut contract = Self::new(snapshot.epoch_duration, verifier, wallet_verifier);
+        contract.intermediate_roots = snapshot.intermediate_roots;
+        contract.next_sequences = snapshot.next_sequences;
+        contract.rebuild_global_tree()?;
+        if contract.global_root() != snapshot.global_root {
             return Err(SystemError {
-                error_type: SystemErrorType::NotFound,
-                message: "Invalid state data length".to_string(),
+                error_type: SystemErrorType::StateDataMismatch,
+                message: "Rebuilt global root does not match stored root".to_string(),
             });
         }
+        contract.epoch = snapshot.epoch;
+        contract.last_submission = snapshot.last_submission;
+        contract.verify_settlement_state = snapshot.verify_settlement_state;
+        contract.submit_settlement = snapshot.submit_settlement;
+        contract.registry = snapshot.registry;
+        contract.challenge_window = snapshot.challenge_window;
+        contract.optimistic = snapshot.optimistic;
+        contract.settlement_batches = snapshot.settlement_batches;
+        Ok(DecodedState {
+            contract,
+            history_cursor: snapshot.history_cursor,
+            version: snapshot.version,
+        })
+    }
 
-        let mut epoch_bytes = [0u8; 8];
-        epoch_bytes.copy_from_slice(&state_data[0..8]);
-        let epoch = u64::from_le_bytes(epoch_bytes);
+    /// Serializes the full contract state into a single-root BOC.
+    pub fn serialize(&self) -> Result<BOC, SystemError> {
+        Ok(BOC::new().with_roots(vec![self.to_state_data()?]))
+    }
 
-        let mut epoch_duration_bytes = [0u8; 8];
-        epoch_duration_bytes.copy_from_slice(&state_data[8..16]);
-        let epoch_duration = u64::from_le_bytes(epoch_duration_bytes);
+    /// Encodes the contract state as a single-root `serialized_boc`.
+    ///
+    /// The root cell holds [`STATE_MAGIC`] and [`STATE_VERSION`], then the
+    /// epoch, epoch duration, last submission, the two flags, the challenge
+    /// window, the global root and the latest epoch in the root history. Its
+    /// references hold the intermediate roots with their sequences, the
+    /// registry, the pending roots with the slashing events, and the
+    /// settlement batches, each list as a tree of one cell per entry. The
+    /// history itself stays in its own store.
+    pub fn to_state_data(&self) -> Result<Vec<u8>, SystemError> {
+        let state = self.state_cell()?;
 
-        let mut last_submission_bytes = [0u8; 8];
-        last_submission_bytes.copy_from_slice(&state_data[16..24]);
-        let last_submission = u64::from_le_bytes(last_submission_bytes);
+        // Snapshots are written locally, so only the layout bounds them.
+        let mut bag = TonBuilder::with_limits(usize::MAX, usize::MAX, usize::MAX);
+        let root = bag.add_tree(&state)?;
+        Ok(bag.serialize(vec![root])?)
+    }
 
-        let verify_settlement_state = state_data[24] != 0;
-        let submit_settlement = state_data[25] != 0;
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
 
-        let global_tree = SparseMerkleTreeR::new();
+    pub fn epoch_duration(&self) -> u64 {
+        self.epoch_duration
+    }
 
-        let intermediate_roots = HashMap::new();
+    pub fn last_submission(&self) -> u64 {
+        self.last_submission
+    }
+
+    pub fn intermediate_roots(&self) -> &HashMap<Address, Hash> {
+        &self.intermediate_roots
+    }
+
+    pub fn history(&self) -> &RootHistory {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut RootHistory {
+        &mut self.history
+    }
+
+    pub fn registry(&self) -> &IntermediateRegistry {
+        &self.registry
+    }
+
+    pub fn global_root(&self) -> Hash {
+        self.global_tree.generate_global_root()
+    }
+
+    fn apply_record(&mut self, record: &RootStateRecord) -> Result<(), SystemError> {
+        match record {
+            RootStateRecord::IntermediateRoot {
+                contract_addr,
+                root,
+                sequence,
+            } => {
+                self.intermediate_roots.insert(*contract_addr, *root);
+                self.next_sequences
+                    .insert(*contract_addr, sequence.saturating_add(1));
+                self.global_tree
+                    .set_intermediate_root(contract_addr, root)?;
+            }
+            RootStateRecord::GenesisRootRegistered {
+                contract_addr,
+                root,
+            } => {
+                self.intermediate_roots.insert(*contract_addr, *root);
+                self.global_tree
+                    .set_intermediate_root(contract_addr, root)?;
+            }
+            RootStateRecord::EpochAdvanced {
+                epoch,
+                last_submission,
+            } => {
+                // Sealing records the epoch in the history first; when the log
+                // is replayed the entry is rebuilt from the state it sealed.
+                if self.history.get(*epoch).is_none() {
+                    let intermediate_roots = self
+                        .intermediate_roots
+                        .iter()
+                        .map(|(addr, root)| (*addr, *root))
+                        .collect();
+                    self.history.record_submission(
+                        *epoch,
+                        self.global_tree.generate_global_root(),
+                        intermediate_roots,
+                        *last_submission,
+                    )?;
+                }
+                self.epoch = *epoch;
+                self.last_submission = *last_submission;
+            }
+            RootStateRecord::IntermediateRegistered(registration) => {
+                self.registry.insert(registration.clone());
+            }
+            RootStateRecord::OperatorKeyRotated {
+                contract_addr,
+                operator_key,
+            } => {
+                self.registry.set_operator_key(contract_addr, *operator_key);
+            }
+            RootStateRecord::DeregistrationStarted {
+                contract_addr,
+                until,
+            } => {
+                self.registry.start_draining(contract_addr, *until);
+            }
+            RootStateRecord::IntermediateRetired { contract_addr } => {
+                self.registry.remove(contract_addr);
+                self.intermediate_roots.remove(contract_addr);
+                self.next_sequences.remove(contract_addr);
+                self.global_tree.remove_intermediate_root(contract_addr);
+                self.optimistic.pending.remove(contract_addr);
+            }
+            RootStateRecord::IntermediateRootProposed(pending) => {
+                self.optimistic
+                    .pending
+                    .entry(pending.contract_addr)
+                    .or_default()
+                    .push(pending.clone());
+            }
+            RootStateRecord::ProposedRootAccepted {
+                contract_addr,
+                root,
+            } => {
+                let queue = self.optimistic.pending.get_mut(contract_addr);
+                if queue
+                    .as_ref()
+                    .and_then(|queue| queue.first())
+                    .map(|p| p.root)
+                    != Some(*root)
+                {
+                    return Err(SystemError::new(
+                        SystemErrorType::StateDataMismatch,
+                        "Accepted root is not the oldest pending root".to_string(),
+                    ));
+                }
+                let queue = queue.unwrap();
+                queue.remove(0);
+                if queue.is_empty() {
+                    self.optimistic.pending.remove(contract_addr);
+                }
+                self.intermediate_roots.insert(*contract_addr, *root);
+                self.global_tree
+                    .set_intermediate_root(contract_addr, root)?;
+            }
+            RootStateRecord::IntermediateRootReverted(event) => {
+                if let Some(queue) = self.optimistic.pending.get_mut(&event.contract_addr) {
+                    if let Some(index) = queue.iter().position(|p| p.root == event.reverted_root) {
+                        queue.truncate(index);
+                    }
+                    if queue.is_empty() {
+                        self.optimistic.pending.remove(&event.contract_addr);
+                    }
+                }
+                self.registry
+                    .slash(&event.contract_addr, event.slashed_stake);
+                self.optimistic.slashing_events.push(event.clone());
+            }
+            RootStateRecord::EpochRolledBack {
+                intermediate_roots, ..
+            } => {
+                let current = std::mem::take(&mut self.intermediate_roots);
+                for (contract_addr, root) in &current {
+                    if intermediate_roots.get(contract_addr) != Some(root) {
+                        self.optimistic.pending.remove(contract_addr);
+                    }
+                }
+                self.intermediate_roots = intermediate_roots
+                    .iter()
+                    .map(|(contract_addr, root)| (*contract_addr, *root))
+                    .collect();
+                // Sequences never go back; intermediates left without a root
+                // start over like new ones.
+                self.next_sequences
+                    .retain(|contract_addr, _| intermediate_roots.contains_key(contract_addr));
+                self.rebuild_global_tree()?;
+            }
+            RootStateRecord::SettlementBatchAccepted { batch_root, batch } => {
+                self.settlement_batches.insert(*batch_root, *batch);
+            }
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self, record: RootStateRecord) -> Result<(), SystemError> {
+        self.persist(&record)?;
+        self.apply_record(&record)?;
+        self.maybe_snapshot();
+        Ok(())
+    }
+
+    /// Removes intermediates whose drain period has ended. Runs after an
+    /// epoch is sealed, so their last roots are part of that epoch; anything
+    /// that cannot be logged now is retried at the next seal.
+    fn retire_drained(&mut self, now: u64) {
+        for contract_addr in self.registry.drained(now) {
+            if self
+                .commit(RootStateRecord::IntermediateRetired { contract_addr })
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+
+    fn persist(&mut self, record: &RootStateRecord) -> Result<(), SystemError> {
+        match self.store.as_mut() {
+            Some(store) => store.append(record),
+            None => Ok(()),
+        }
+    }
+
+    /// Compacts the log once enough records have accumulated.
+    fn maybe_snapshot(&mut self) {
+        let due = self
+            .store
+            .as_ref()
+            .is_some_and(|store| store.should_snapshot());
+        if due {
+            self.write_snapshot();
+        }
+    }
+
+    /// Replaces the store's snapshot with the current state. A failed
+    /// snapshot leaves the log intact, so it is logged and retried after the
+    /// next record instead of failing a change that is already durable.
+    fn write_snapshot(&mut self) {
+        let written = self
+            .to_state_data()
+            .and_then(|state_data| match self.store.as_mut() {
+                Some(store) => store.write_snapshot(&state_data),
+                None => Ok(()),
+            });
+        if let Err(e) = written {
+            log::warn!("Root state snapshot failed, keeping the log: {}", e);
+        }
+    }
+
+    fn state_cell(&self) -> Result<ovp_cell::Cell, CellError> {
+        // Sort so identical states always serialize to identical bytes.
+        let mut intermediate_roots: Vec<_> = self.intermediate_roots.iter().collect();
+        intermediate_roots.sort();
+        let intermediate_roots = intermediate_roots
+            .into_iter()
+            .map(|(contract_addr, root)| {
+                let next = self.next_sequences.get(contract_addr).copied().unwrap_or(0);
+                let mut entry = CellBuilder::new();
+                entry
+                    .store_bytes(contract_addr)?
+                    .store_bytes(root)?
+                    .store_uint(next as u128, 64)?;
+                Ok(entry.build())
+            })
+            .collect::<Result<Vec<_>, CellError>>()?;
+
+        let registrations = self
+            .registry
+            .iter()
+            .map(registration_cell)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut registry = CellBuilder::new();
+        registry
+            .store_uint(self.registry.min_stake() as u128, 64)?
+            .store_uint(self.registry.drain_period() as u128, 64)?
+            .store_ref(list_cell(&registrations)?)?;
+
+        let pending = self
+            .optimistic
+            .pending
+            .values()
+            .flatten()
+            .map(pending_root_cell)
+            .collect::<Result<Vec<_>, _>>()?;
+        let slashing_events = self
+            .optimistic
+            .slashing_events
+            .iter()
+            .map(slashing_event_cell)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut optimistic = CellBuilder::new();
+        optimistic
+            .store_ref(list_cell(&pending)?)?
+            .store_ref(list_cell(&slashing_events)?)?;
+
+        let settlement_batches = self
+            .settlement_batches
+            .iter()
+            .map(|(batch_root, record)| settlement_batch_cell(batch_root, record))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut state = CellBuilder::new();
+        state
+            .store_uint(STATE_MAGIC as u128, 32)?
+            .store_uint(STATE_VERSION as u128, 8)?
+            .store_uint(self.epoch as u128, 64)?
+            .store_uint(self.epoch_duration as u128, 64)?
+            .store_uint(self.last_submission as u128, 64)?
+            .store_bit(self.verify_settlement_state)?
+            .store_bit(self.submit_settlement)?
+            .store_uint(self.challenge_window as u128, 64)?
+            .store_bytes(&self.global_tree.generate_global_root())?
+            .store_uint(
+                self.history.latest().map_or(0, |record| record.epoch) as u128,
+                64,
+            )?
+            .store_ref(list_cell(&intermediate_roots)?)?
+            .store_ref(registry.build())?
+            .store_ref(optimistic.build())?
+            .store_ref(list_cell(&settlement_batches)?)?;
+        Ok(state.build())
+    }
+
+    /// Replaces the global tree with one holding exactly `intermediate_roots`.
+    fn rebuild_global_tree(&mut self) -> Result<(), SystemError> {
+        let mut entries: Vec<_> = self
+            .intermediate_roots
+            .iter()
+            .map(|(contract_addr, root)| (*contract_addr, *root))
+            .collect();
+        entries.sort();
+        self.global_tree.store_roots(entries)
+    }
+}
+
+/// A contract decoded from state data.
+struct DecodedState {
+    contract: RootContract,
+    /// Latest epoch the root history must reach.
+    history_cursor: u64,
+    /// Layout the state was written in.
+    version: u8,
+}
+
+/// The fields of a root state snapshot, whichever layout it was read from.
+struct StateSnapshot {
+    version: u8,
+    epoch: u64,
+    epoch_duration: u64,
+    last_submission: u64,
+    verify_settlement_state: bool,
+    submit_settlement: bool,
+    challenge_window: u64,
+    global_root: Hash,
+    history_cursor: u64,
+    intermediate_roots: HashMap<Address, Hash>,
+    next_sequences: HashMap<Address, u64>,
+    registry: IntermediateRegistry,
+    optimistic: OptimisticState,
+    settlement_batches: BTreeMap<Hash, SettlementBatchRecord>,
+}
+
+impl StateSnapshot {
+    /// Reads state written by [`RootContract::to_state_data`].
+    fn decode(state_cell: &ovp_cell::Cell) -> Result<Self, SystemError> {
+        let mut state = state_cell.parse();
+        state.load_uint(32)?;
+        let version = state.load_uint(8)? as u8;
+        if version != STATE_VERSION {
+            return Err(SystemError::new(
+                SystemErrorType::SerializationError,
+                format!("Unsupported root state version {}", version),
+            ));
+        }
+        let epoch = state.load_uint(64)? as u64;
+        let epoch_duration = state.load_uint(64)? as u64;
+        let last_submission = state.load_uint(64)? as u64;
+        let verify_settlement_state = state.load_bit()?;
+        let submit_settlement = state.load_bit()?;
+        let challenge_window = state.load_uint(64)? as u64;
+        let global_root = load_hash(&mut state)?;
+        let history_cursor = state.load_uint(64)? as u64;
+
+        let mut intermediate_roots = HashMap::new();
+        let mut next_sequences = HashMap::new();
+        for entry in load_list(state.load_ref()?)? {
+            let mut entry = entry.parse();
+            let contract_addr = load_hash(&mut entry)?;
+            intermediate_roots.insert(contract_addr, load_hash(&mut entry)?);
+            let next = entry.load_uint(64)? as u64;
+            entry.end_parse()?;
+            if next > 0 {
+                next_sequences.insert(contract_addr, next);
+            }
+        }
+
+        let mut registry_cell = state.load_ref()?.parse();
+        let mut registry = IntermediateRegistry::new(
+            registry_cell.load_uint(64)? as u64,
+            registry_cell.load_uint(64)? as u64,
+        );
+        for registration in load_list(registry_cell.load_ref()?)? {
+            registry.insert(load_registration(registration)?);
+        }
+        registry_cell.end_parse()?;
+
+        let mut optimistic_cell = state.load_ref()?.parse();
+        let mut optimistic = OptimisticState::default();
+        for pending in load_list(optimistic_cell.load_ref()?)? {
+            let pending = load_pending_root(pending)?;
+            optimistic
+                .pending
+                .entry(pending.contract_addr)
+                .or_default()
+                .push(pending);
+        }
+        for event in load_list(optimistic_cell.load_ref()?)? {
+            optimistic.slashing_events.push(load_slashing_event(event)?);
+        }
+        optimistic_cell.end_parse()?;
+
+        let mut settlement_batches = BTreeMap::new();
+        for batch in load_list(state.load_ref()?)? {
+            let (batch_root, record) = load_settlement_batch(batch)?;
+            settlement_batches.insert(batch_root, record);
+        }
+        state.end_parse()?;
 
         Ok(Self {
-            global_tree,
+            version,
+            epoch,
+            epoch_duration,
+            last_submission,
+            verify_settlement_state,
+            submit_settlement,
+            challenge_window,
+            global_root,
+            history_cursor,
             intermediate_roots,
+            next_sequences,
+            registry,
+            optimistic,
+            settlement_batches,
+        })
+    }
+
+    /// Reads the version 1 layout, which had no tag and kept the
+    /// intermediate roots as packed entries and the registry, the pending
+    /// roots and the settlement batches as bincode blobs.
+    fn decode_v1(state_cell: &ovp_cell::Cell) -> Result<Self, SystemError> {
+        let mut state = state_cell.parse();
+        let epoch = state.load_uint(64)? as u64;
+        let epoch_duration = state.load_uint(64)? as u64;
+        let last_submission = state.load_uint(64)? as u64;
+        let verify_settlement_state = state.load_bit()?;
+        let submit_settlement = state.load_bit()?;
+        let challenge_window = state.load_uint(64)? as u64;
+        let global_root = load_hash(&mut state)?;
+
+        let entries = state.load_blob()?;
+        if entries.len() % V1_INTERMEDIATE_ENTRY_LEN != 0 {
+            return Err(SystemError {
+                error_type: SystemErrorType::InvalidState,
+                message: "Intermediate roots do not match state data".to_string(),
+            });
+        }
+        let mut intermediate_roots = HashMap::new();
+        let mut next_sequences = HashMap::new();
+        for entry in entries.chunks(V1_INTERMEDIATE_ENTRY_LEN) {
+            let mut contract_addr = [0u8; 32];
+            let mut root = [0u8; 32];
+            let mut next = [0u8; 8];
+            contract_addr.copy_from_slice(&entry[0..32]);
+            root.copy_from_slice(&entry[32..64]);
+            next.copy_from_slice(&entry[64..72]);
+            intermediate_roots.insert(contract_addr, root);
+            let next = u64::from_le_bytes(next);
+            if next > 0 {
+                next_sequences.insert(contract_addr, next);
+            }
+        }
+
+        let registry = load_v1_section(&mut state)?;
+        let optimistic = load_v1_section(&mut state)?;
+        let settlement_batches = load_v1_section(&mut state)?;
+        let history_cursor = state.load_uint(64)? as u64;
+        state.end_parse()?;
+
+        Ok(Self {
+            version: 1,
             epoch,
             epoch_duration,
             last_submission,
             verify_settlement_state,
-            verify_intermediate_state: false,
-            verify_channel_state: false,
-            verify_transaction_state: false,
-            verify_storage_state: false,
-            verify_global_state: false,
-            verify_root_state: false,
             submit_settlement,
+            challenge_window,
+            global_root,
+            history_cursor,
+            intermediate_roots,
+            next_sequences,
+            registry,
+            optimistic,
+            settlement_batches,
         })
     }
-    fn to_state_data(&self) -> Vec<u8> {
-        let mut state_data = Vec::new();
-        state_data.extend_from_slice(&self.epoch.to_le_bytes());
-        state_data.extend_from_slice(&self.epoch_duration.to_le_bytes());
-        state_data.extend_from_slice(&self.last_submission.to_le_bytes());
-        state_data.push(self.verify_settlement_state as u8);
-        state_data.push(self.submit_settlement as u8);
-        state_data
+}
+
+/// Reads a bincode blob of version 1 state.
+fn load_v1_section<T: DeserializeOwned>(state: &mut CellSlice) -> Result<T, SystemError> {
+    bincode::deserialize(&state.load_blob()?).map_err(|e| SystemError {
+        error_type: SystemErrorType::SerializationError,
+        message: format!("Invalid root state section: {}", e),
+    })
+}
+
+/// Holds `items` in a tree of cells: each cell keeps one item in its first
+/// reference and the cells holding the rest, in order, split evenly between
+/// its other references, so long lists stay shallow.
+fn list_cell(items: &[ovp_cell::Cell]) -> Result<ovp_cell::Cell, CellError> {
+    let mut node = CellBuilder::new();
+    if let Some((first, rest)) = items.split_first() {
+        node.store_ref(first.clone())?;
+        if !rest.is_empty() {
+            for group in rest.chunks(rest.len().div_ceil(MAX_CELL_REFS - 1)) {
+                node.store_ref(list_cell(group)?)?;
+            }
+        }
     }
+    Ok(node.build())
+}
+
+/// The items of a list written by [`list_cell`], in order.
+fn load_list(list: &ovp_cell::Cell) -> Result<Vec<&ovp_cell::Cell>, CellError> {
+    let mut items = Vec::new();
+    collect_list(list, &mut items)?;
+    Ok(items)
+}
+
+fn collect_list<'a>(
+    node: &'a ovp_cell::Cell,
+    items: &mut Vec<&'a ovp_cell::Cell>,
+) -> Result<(), CellError> {
+    if node.bit_len() != 0 {
+        return Err(CellError::TrailingData {
+            bits: node.bit_len(),
+            refs: 0,
+        });
+    }
+    if let Some((item, rest)) = node.references().split_first() {
+        items.push(item);
+        for child in rest {
+            collect_list(child, items)?;
+        }
+    }
+    Ok(())
+}
+
+fn load_hash(slice: &mut CellSlice) -> Result<Hash, CellError> {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&slice.load_bytes(32)?);
+    Ok(hash)
+}
+
+fn registration_cell(registration: &IntermediateRegistration) -> Result<ovp_cell::Cell, CellError> {
+    let mut cell = CellBuilder::new();
+    cell.store_bytes(&registration.contract_addr)?
+        .store_bytes(&registration.operator_key)?
+        .store_uint(registration.key_version as u128, 64)?
+        .store_uint(registration.stake as u128, 64)?
+        .store_uint(registration.capacity as u128, 64)?
+        .store_uint(registration.registered_at as u128, 64)?;
+    match registration.status {
+        IntermediateStatus::Active => cell.store_bit(false)?,
+        IntermediateStatus::Draining { until } => {
+            cell.store_bit(true)?.store_uint(until as u128, 64)?
+        }
+    };
+    Ok(cell.build())
+}
+
+fn load_registration(cell: &ovp_cell::Cell) -> Result<IntermediateRegistration, CellError> {
+    let mut slice = cell.parse();
+    let registration = IntermediateRegistration {
+        contract_addr: load_hash(&mut slice)?,
+        operator_key: load_hash(&mut slice)?,
+        key_version: slice.load_uint(64)? as u64,
+        stake: slice.load_uint(64)? as u64,
+        capacity: slice.load_uint(64)? as u64,
+        registered_at: slice.load_uint(64)? as u64,
+        status: if slice.load_bit()? {
+            IntermediateStatus::Draining {
+                until: slice.load_uint(64)? as u64,
+            }
+        } else {
+            IntermediateStatus::Active
+        },
+    };
+    slice.end_parse()?;
+    Ok(registration)
+}
+
+/// The transition commitment does not fit next to the roots, so it goes in
+/// a reference.
+fn pending_root_cell(pending: &PendingIntermediateRoot) -> Result<ovp_cell::Cell, CellError> {
+    let mut commitment = CellBuilder::new();
+    commitment.store_bytes(&pending.transition_commitment)?;
+    let mut cell = CellBuilder::new();
+    cell.store_bytes(&pending.contract_addr)?
+        .store_bytes(&pending.previous_root)?
+        .store_bytes(&pending.root)?
+        .store_uint(pending.submitted_at as u128, 64)?
+        .store_uint(pending.challenge_deadline as u128, 64)?
+        .store_ref(commitment.build())?;
+    Ok(cell.build())
+}
+
+fn load_pending_root(cell: &ovp_cell::Cell) -> Result<PendingIntermediateRoot, CellError> {
+    let mut slice = cell.parse();
+    let contract_addr = load_hash(&mut slice)?;
+    let previous_root = load_hash(&mut slice)?;
+    let root = load_hash(&mut slice)?;
+    let submitted_at = slice.load_uint(64)? as u64;
+    let challenge_deadline = slice.load_uint(64)? as u64;
+    let mut commitment = slice.load_ref()?.parse();
+    let transition_commitment = load_hash(&mut commitment)?;
+    commitment.end_parse()?;
+    slice.end_parse()?;
+    Ok(PendingIntermediateRoot {
+        contract_addr,
+        previous_root,
+        root,
+        transition_commitment,
+        submitted_at,
+        challenge_deadline,
+    })
+}
+
+fn slashing_event_cell(event: &SlashingEvent) -> Result<ovp_cell::Cell, CellError> {
+    let mut cell = CellBuilder::new();
+    cell.store_bytes(&event.contract_addr)?
+        .store_bytes(&event.challenger)?
+        .store_bytes(&event.reverted_root)?
+        .store_uint(event.slashed_stake as u128, 64)?
+        .store_uint(event.timestamp as u128, 64)?;
+    Ok(cell.build())
+}
+
+fn load_slashing_event(cell: &ovp_cell::Cell) -> Result<SlashingEvent, CellError> {
+    let mut slice = cell.parse();
+    let event = SlashingEvent {
+        contract_addr: load_hash(&mut slice)?,
+        challenger: load_hash(&mut slice)?,
+        reverted_root: load_hash(&mut slice)?,
+        slashed_stake: slice.load_uint(64)? as u64,
+        timestamp: slice.load_uint(64)? as u64,
+    };
+    slice.end_parse()?;
+    Ok(event)
+}
+
+fn settlement_batch_cell(
+    batch_root: &Hash,
+    record: &SettlementBatchRecord,
+) -> Result<ovp_cell::Cell, CellError> {
+    let mut cell = CellBuilder::new();
+    cell.store_bytes(batch_root)?
+        .store_bytes(&record.contract_addr)?
+        .store_uint(record.epoch as u128, 64)?
+        .store_uint(record.channel_count as u128, 64)?;
+    Ok(cell.build())
+}
+
+fn load_settlement_batch(
+    cell: &ovp_cell::Cell,
+) -> Result<(Hash, SettlementBatchRecord), CellError> {
+    let mut slice = cell.parse();
+    let batch_root = load_hash(&mut slice)?;
+    let record = SettlementBatchRecord {
+        contract_addr: load_hash(&mut slice)?,
+        epoch: slice.load_uint(64)? as u64,
+        channel_count: slice.load_uint(64)? as u64,
+    };
+    slice.end_parse()?;
+    Ok((batch_root, record))
 }
 
 type Hash = [u8; 32];
 type Address = [u8; 32];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::hierarchy::root::fraud_proof::{transition_commitment, WalletRootUpdate};
+    use crate::core::hierarchy::root::intermediate_registry::{
+        deregistration_message, key_rotation_message, registration_message,
+        root_submission_message, settlement_batch_message, IntermediateStatus,
+    };
+    use crate::core::hierarchy::root::sparse_merkle_tree_r::SparseMerkleTreeR;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const INTERMEDIATE: Address = [1; 32];
+
+    /// Accepts wallet proofs that commit to the expected root unless their
+    /// data is `[0xff]`.
+    struct RejectMarked;
+
+    impl IntermediateProofVerifier for RejectMarked {
+        fn verify(&self, proof: &ZkProof, commitment: &Hash) -> Result<(), SystemError> {
+            if proof.proof_data == [0xff] || proof.merkle_root != *commitment {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidProof,
+                    "rejected".to_string(),
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    struct AcceptAll;
+
+    impl RootTransitionVerifier for AcceptAll {
+        fn verify_transition(
+            &self,
+            _proof: &ZkProof,
+            _old_root: &Hash,
+            _sequence: u64,
+            _commitment: &Hash,
+        ) -> Result<(), SystemError> {
+            Ok(())
+        }
+    }
+
+    struct Locked(u64);
+
+    impl StakeEscrow for Locked {
+        fn locked_stake(&self, _contract_addr: &Address) -> Result<u64, SystemError> {
+            Ok(self.0)
+        }
+    }
+
+    fn operator() -> SigningKey {
+        SigningKey::from_bytes(&[1; 32])
+    }
+
+    fn optimistic_contract() -> RootContract {
+        let mut contract = RootContract::new(10, AcceptAll, RejectMarked)
+            .with_registry(IntermediateRegistry::new(100, 0))
+            .with_challenge_window(100)
+            .with_stake_escrow(Locked(500));
+        register(&mut contract);
+        contract
+    }
+
+    fn registration() -> IntermediateRegistration {
+        IntermediateRegistration {
+            contract_addr: INTERMEDIATE,
+            operator_key: operator().verifying_key().to_bytes(),
+            key_version: 0,
+            stake: 500,
+            capacity: 10,
+            registered_at: 0,
+            status: IntermediateStatus::Active,
+        }
+    }
+
+    fn register(contract: &mut RootContract) {
+        let signature = operator()
+            .sign(&registration_message(&registration()))
+            .to_bytes();
+        contract
+            .register_intermediate(registration(), &signature)
+            .unwrap();
+    }
+
+    fn updates(root: Hash) -> Vec<WalletRootUpdate> {
+        vec![WalletRootUpdate {
+            wallet_id: [7; 32],
+            new_wallet_root: root,
+            proof: ZkProof::new(vec![1], vec![1], root.to_vec(), 0),
+        }]
+    }
+
+    /// Updates whose proof the contract's wallet verifier rejects.
+    fn forged_updates(root: Hash) -> Vec<WalletRootUpdate> {
+        let mut updates = updates(root);
+        updates[0].proof.proof_data = vec![0xff];
+        updates
+    }
+
+    fn propose(contract: &mut RootContract, root: Hash, now: u64) {
+        propose_updates(contract, root, &updates(root), now);
+    }
+
+    fn propose_updates(
+        contract: &mut RootContract,
+        root: Hash,
+        updates: &[WalletRootUpdate],
+        now: u64,
+    ) {
+        let signature = operator()
+            .sign(&root_submission_message(
+                &INTERMEDIATE,
+                contract.epoch() + 1,
+                &root,
+                1,
+            ))
+            .to_bytes();
+        let commitment = transition_commitment(updates).unwrap();
+        contract
+            .propose_intermediate_root(INTERMEDIATE, root, 1, &signature, commitment, now)
+            .unwrap();
+    }
+
+    fn fraud_proof(updates: Vec<WalletRootUpdate>) -> FraudProof {
+        FraudProof::InvalidTransition { updates, index: 0 }
+    }
+
+    #[test]
+    fn test_unchallenged_root_is_accepted_after_window() {
+        let mut contract = optimistic_contract();
+        propose(&mut contract, [2; 32], 0);
+
+        contract.accept_unchallenged_roots(99).unwrap();
+        assert!(contract.intermediate_roots().is_empty());
+
+        let submission = contract.seal_epoch(100).unwrap();
+        assert_eq!(submission.intermediate_roots[&INTERMEDIATE], [2; 32]);
+        assert!(contract.pending_roots(&INTERMEDIATE).is_empty());
+    }
+
+    #[test]
+    fn test_successful_challenge_reverts_and_slashes() {
+        let mut contract = optimistic_contract();
+        propose_updates(&mut contract, [2; 32], &forged_updates([2; 32]), 0);
+        propose(&mut contract, [3; 32], 5);
+        assert_eq!(
+            contract.pending_roots(&INTERMEDIATE)[1].previous_root,
+            [2; 32]
+        );
+
+        let event = contract
+            .challenge_intermediate_root(
+                INTERMEDIATE,
+                [2; 32],
+                &fraud_proof(forged_updates([2; 32])),
+                [9; 32],
+                50,
+            )
+            .unwrap();
+        assert_eq!(event.slashed_stake, 500);
+        assert_eq!(contract.slashing_events(), &[event]);
+        assert_eq!(contract.registry().get(&INTERMEDIATE).unwrap().stake, 0);
+
+        // The challenged root and everything built on it are gone.
+        assert!(contract.pending_roots(&INTERMEDIATE).is_empty());
+        contract.accept_unchallenged_roots(1_000).unwrap();
+        assert!(contract.intermediate_roots().is_empty());
+
+        // Slashed below the minimum stake, it cannot submit again.
+        let signature = operator()
+            .sign(&root_submission_message(&INTERMEDIATE, 1, &[4; 32], 1))
+            .to_bytes();
+        assert!(contract
+            .propose_intermediate_root(INTERMEDIATE, [4; 32], 1, &signature, [0; 32], 1_000)
+            .is_err());
+    }
+
+    #[test]
+    fn test_first_root_is_challenged_against_empty_tree() {
+        let mut contract = optimistic_contract();
+        let listed = updates([5; 32]);
+        let mut claimed = SparseMerkleTreeI::new();
+        claimed.update(&[7; 32], &[5; 32]).unwrap();
+        claimed.update(&[8; 32], &[6; 32]).unwrap();
+        propose_updates(&mut contract, claimed.root(), &listed, 0);
+        assert_eq!(
+            contract.pending_roots(&INTERMEDIATE)[0].previous_root,
+            SparseMerkleTreeI::new().root()
+        );
+
+        // Wallet 8 appears in the first root without being listed.
+        let fraud_proof = FraudProof::UnlistedChange {
+            updates: listed,
+            wallet_id: [8; 32],
+            previous_value: None,
+            previous_proof: SparseMerkleTreeI::new()
+                .prove_non_inclusion(&[8; 32])
+                .unwrap(),
+            claimed_value: Some([6; 32]),
+            claimed_proof: claimed.prove_inclusion(&[8; 32]).unwrap(),
+        };
+        contract
+            .challenge_intermediate_root(INTERMEDIATE, claimed.root(), &fraud_proof, [9; 32], 50)
+            .unwrap();
+        assert!(contract.pending_roots(&INTERMEDIATE).is_empty());
+    }
+
+    #[test]
+    fn test_challenge_rejected_outside_window_or_without_fraud() {
+        let mut contract = optimistic_contract();
+        propose_updates(&mut contract, [2; 32], &forged_updates([2; 32]), 0);
+        propose(&mut contract, [3; 32], 5);
+
+        assert!(contract
+            .challenge_intermediate_root(
+                INTERMEDIATE,
+                [3; 32],
+                &fraud_proof(updates([3; 32])),
+                [9; 32],
+                50
+            )
+            .is_err());
+        assert!(contract
+            .challenge_intermediate_root(
+                INTERMEDIATE,
+                [2; 32],
+                &fraud_proof(forged_updates([2; 32])),
+                [9; 32],
+                100
+            )
+            .is_err());
+        assert!(contract.slashing_events().is_empty());
+    }
+
+    #[test]
+    fn test_pending_roots_survive_serialization() {
+        let mut contract = optimistic_contract();
+        propose(&mut contract, [2; 32], 0);
+
+        let restored =
+            RootContract::deserialize(contract.serialize().unwrap(), AcceptAll, RejectMarked)
+                .unwrap();
+        assert_eq!(
+            restored.pending_roots(&INTERMEDIATE),
+            contract.pending_roots(&INTERMEDIATE)
+        );
+        assert!(restored.registry().get(&INTERMEDIATE).is_some());
+    }
+
+    #[test]
+    fn test_state_round_trips_through_cells() {
+        let mut contract = optimistic_contract();
+        propose(&mut contract, [2; 32], 0);
+        contract.seal_epoch(100).unwrap();
+        propose(&mut contract, [3; 32], 105);
+        contract.optimistic.slashing_events.push(SlashingEvent {
+            contract_addr: INTERMEDIATE,
+            challenger: [9; 32],
+            reverted_root: [8; 32],
+            slashed_stake: 50,
+            timestamp: 90,
+        });
+
+        let state_data = contract.to_state_data().unwrap();
+        let restored =
+            RootContract::deserialize(contract.serialize().unwrap(), AcceptAll, RejectMarked)
+                .unwrap();
+        assert_eq!(restored.epoch(), contract.epoch());
+        assert_eq!(restored.epoch_duration(), 10);
+        assert_eq!(restored.last_submission(), 100);
+        assert_eq!(restored.challenge_window, 100);
+        assert_eq!(restored.global_root(), contract.global_root());
+        assert_eq!(restored.intermediate_roots(), contract.intermediate_roots());
+        assert_eq!(restored.registry(), contract.registry());
+        assert_eq!(
+            restored.pending_roots(&INTERMEDIATE),
+            contract.pending_roots(&INTERMEDIATE)
+        );
+        assert_eq!(restored.slashing_events(), contract.slashing_events());
+        assert!(restored.history().is_empty());
+
+        // The history stays in its own store; the snapshot only points at it.
+        let decoded = RootContract::decode_state(&state_data, AcceptAll, RejectMarked).unwrap();
+        assert_eq!(decoded.history_cursor, 1);
+        assert_eq!(decoded.version, STATE_VERSION);
+
+        // The root cell is the tagged header followed by four references.
+        let bag = TonBoc::parse(&state_data).unwrap();
+        let state = bag.tree(bag.roots[0]).unwrap();
+        assert_eq!(state.bit_len(), 32 + 8 + 3 * 64 + 2 + 64 + 256 + 64);
+        assert_eq!(state.references().len(), 4);
+    }
+
+    /// State data in the version 1 layout, as snapshots were written before
+    /// the layout was tagged.
+    fn v1_state_data(contract: &RootContract) -> Vec<u8> {
+        let mut entries: Vec<_> = contract.intermediate_roots.iter().collect();
+        entries.sort();
+        let entries: Vec<u8> = entries
+            .into_iter()
+            .flat_map(|(contract_addr, root)| {
+                let next = contract.next_sequences.get(contract_addr).copied();
+                [
+                    &contract_addr[..],
+                    &root[..],
+                    &next.unwrap_or(0).to_le_bytes(),
+                ]
+                .concat()
+            })
+            .collect();
+        let mut state = CellBuilder::new();
+        state
+            .store_uint(contract.epoch as u128, 64)
+            .unwrap()
+            .store_uint(contract.epoch_duration as u128, 64)
+            .unwrap()
+            .store_uint(contract.last_submission as u128, 64)
+            .unwrap()
+            .store_bit(contract.verify_settlement_state)
+            .unwrap()
+            .store_bit(contract.submit_settlement)
+            .unwrap()
+            .store_uint(contract.challenge_window as u128, 64)
+            .unwrap()
+            .store_bytes(&contract.global_root())
+            .unwrap()
+            .store_blob(&entries)
+            .unwrap()
+            .store_blob(&bincode::serialize(&contract.registry).unwrap())
+            .unwrap()
+            .store_blob(&bincode::serialize(&contract.optimistic).unwrap())
+            .unwrap()
+            .store_blob(&bincode::serialize(&contract.settlement_batches).unwrap())
+            .unwrap()
+            .store_uint(
+                contract.history.latest().map_or(0, |record| record.epoch) as u128,
+                64,
+            )
+            .unwrap();
+        let mut bag = TonBuilder::new();
+        let root = bag.add_tree(&state.build()).unwrap();
+        bag.serialize(vec![root]).unwrap()
+    }
+
+    #[test]
+    fn test_version_1_snapshot_is_migrated_on_recovery() {
+        let dir = std::env::temp_dir().join(format!("root_contract_{}", uuid::Uuid::new_v4()));
+        let mut contract = optimistic_contract();
+        propose(&mut contract, [2; 32], 0);
+        contract.seal_epoch(100).unwrap();
+        propose(&mut contract, [3; 32], 105);
+
+        let decoded =
+            RootContract::decode_state(&v1_state_data(&contract), AcceptAll, RejectMarked).unwrap();
+        assert_eq!(decoded.version, 1);
+        assert_eq!(decoded.history_cursor, 1);
+        let migrated = decoded.contract;
+        assert_eq!(migrated.epoch(), contract.epoch());
+        assert_eq!(migrated.intermediate_roots(), contract.intermediate_roots());
+        assert_eq!(migrated.registry(), contract.registry());
+        assert_eq!(
+            migrated.pending_roots(&INTERMEDIATE),
+            contract.pending_roots(&INTERMEDIATE)
+        );
+
+        let mut store = RootStateStore::with_snapshot_interval(&dir, 100).unwrap();
+        store.write_snapshot(&v1_state_data(&contract)).unwrap();
+        drop(store);
+        let store = RootStateStore::with_snapshot_interval(&dir, 100).unwrap();
+        let history = std::mem::replace(contract.history_mut(), RootHistory::in_memory());
+        let recovered = RootContract::recover(10, AcceptAll, RejectMarked, store, history).unwrap();
+        assert_eq!(recovered.epoch(), 1);
+        assert_eq!(recovered.pending_roots(&INTERMEDIATE)[0].root, [3; 32]);
+        drop(recovered);
+
+        // Recovery rewrote the snapshot in the current layout.
+        let mut store = RootStateStore::with_snapshot_interval(&dir, 100).unwrap();
+        let snapshot = store.load().unwrap().snapshot.unwrap();
+        let decoded = RootContract::decode_state(&snapshot, AcceptAll, RejectMarked).unwrap();
+        assert_eq!(decoded.version, STATE_VERSION);
+        assert_eq!(
+            decoded.contract.intermediate_roots()[&INTERMEDIATE],
+            [2; 32]
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_malformed_state_rejected() {
+        let contract = optimistic_contract();
+        let state_data = contract.to_state_data().unwrap();
+        let truncated = BOC::new().with_roots(vec![state_data[..state_data.len() - 1].to_vec()]);
+        assert!(RootContract::deserialize(truncated, AcceptAll, RejectMarked).is_err());
+
+        // A header with a field missing no longer lines up.
+        let mut short = CellBuilder::new();
+        short.store_uint(1, 64).unwrap();
+        let mut bag = TonBuilder::new();
+        let root = bag.add_tree(&short.build()).unwrap();
+        let short = BOC::new().with_roots(vec![bag.serialize(vec![root]).unwrap()]);
+        assert!(matches!(
+            RootContract::deserialize(short, AcceptAll, RejectMarked),
+            Err(SystemError {
+                error_type: SystemErrorType::SerializationError,
+                ..
+            })
+        ));
+
+        // A tagged layout from a later version is not guessed at.
+        let mut future = CellBuilder::new();
+        future
+            .store_uint(STATE_MAGIC as u128, 32)
+            .unwrap()
+            .store_uint(STATE_VERSION as u128 + 1, 8)
+            .unwrap();
+        let mut bag = TonBuilder::new();
+        let root = bag.add_tree(&future.build()).unwrap();
+        let future = BOC::new().with_roots(vec![bag.serialize(vec![root]).unwrap()]);
+        assert!(RootContract::deserialize(future, AcceptAll, RejectMarked).is_err());
+    }
+
+    #[test]
+    fn test_recover_restores_snapshot_and_log() {
+        let dir = std::env::temp_dir().join(format!("root_contract_{}", uuid::Uuid::new_v4()));
+        let store = RootStateStore::with_snapshot_interval(&dir, 4).unwrap();
+        let history = RootHistory::open(dir.join("history")).unwrap();
+        let mut contract = RootContract::new(10, AcceptAll, RejectMarked)
+            .with_challenge_window(100)
+            .with_stake_escrow(Locked(500))
+            .with_store(store)
+            .with_history(history);
+        register(&mut contract);
+        propose(&mut contract, [2; 32], 0);
+        // Advancing the epoch is the fourth record and triggers a snapshot;
+        // the next proposal is only in the log.
+        contract.seal_epoch(100).unwrap();
+        propose(&mut contract, [3; 32], 105);
+        drop(contract);
+
+        // The snapshot points into the history, so a history that does not
+        // reach it is refused.
+        let store = RootStateStore::with_snapshot_interval(&dir, 4).unwrap();
+        assert!(RootContract::recover(
+            20,
+            AcceptAll,
+            RejectMarked,
+            store,
+            RootHistory::in_memory()
+        )
+        .is_err());
+
+        let store = RootStateStore::with_snapshot_interval(&dir, 4).unwrap();
+        let history = RootHistory::open(dir.join("history")).unwrap();
+        let mut recovered =
+            RootContract::recover(20, AcceptAll, RejectMarked, store, history).unwrap();
+        assert_eq!(recovered.epoch(), 1);
+        assert_eq!(recovered.epoch_duration(), 20);
+        assert_eq!(recovered.last_submission(), 100);
+        assert_eq!(recovered.intermediate_roots()[&INTERMEDIATE], [2; 32]);
+        assert_eq!(recovered.pending_roots(&INTERMEDIATE)[0].root, [3; 32]);
+        assert_eq!(recovered.history().get(1).unwrap().global_root, {
+            let mut tree = SparseMerkleTreeR::new();
+            tree.update_global_tree(&INTERMEDIATE, &[2; 32]).unwrap();
+            tree.get_global_root_hash()
+        });
+
+        // The challenge window came back with the snapshot.
+        propose(&mut recovered, [4; 32], 200);
+        assert_eq!(
+            recovered.pending_roots(&INTERMEDIATE)[1].challenge_deadline,
+            300
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_try_submit_global_root_returns_sealed_root() {
+        let mut contract = RootContract::new(10, AcceptAll, RejectMarked);
+        assert!(contract.try_submit_global_root(5).is_none());
+        assert_eq!(contract.epoch(), 0);
+
+        let (global_root, proofs) = contract.try_submit_global_root(10).unwrap();
+        assert_eq!(contract.epoch(), 1);
+        assert_eq!(global_root, contract.history().get(1).unwrap().global_root);
+        assert!(proofs.is_empty());
+    }
+
+    #[test]
+    fn test_registry_changes_require_operator_signature() {
+        let mut contract =
+            RootContract::new(10, AcceptAll, RejectMarked).with_stake_escrow(Locked(500));
+        let stranger = SigningKey::from_bytes(&[2; 32]);
+
+        let message = registration_message(&registration());
+        assert!(contract
+            .register_intermediate(registration(), &[0; 64])
+            .is_err());
+        assert!(contract
+            .register_intermediate(registration(), &stranger.sign(&message).to_bytes())
+            .is_err());
+        register(&mut contract);
+
+        let new_key = stranger.verifying_key().to_bytes();
+        let message = key_rotation_message(&INTERMEDIATE, &new_key, 0);
+        assert!(contract
+            .rotate_operator_key(INTERMEDIATE, new_key, &[0; 64])
+            .is_err());
+        assert!(contract
+            .rotate_operator_key(INTERMEDIATE, new_key, &stranger.sign(&message).to_bytes())
+            .is_err());
+
+        let message = deregistration_message(&INTERMEDIATE, 0);
+        assert!(contract
+            .begin_deregistration(INTERMEDIATE, &[0; 64], 0)
+            .is_err());
+        assert!(contract
+            .begin_deregistration(INTERMEDIATE, &stranger.sign(&message).to_bytes(), 0)
+            .is_err());
+        assert_eq!(
+            contract.registry().get(&INTERMEDIATE).unwrap().status,
+            IntermediateStatus::Active
+        );
+        contract
+            .begin_deregistration(INTERMEDIATE, &operator().sign(&message).to_bytes(), 0)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_root_above_capacity_rejected() {
+        let mut contract = optimistic_contract();
+        let root = [2; 32];
+        let signature = operator()
+            .sign(&root_submission_message(&INTERMEDIATE, 1, &root, 11))
+            .to_bytes();
+        let commitment = transition_commitment(&updates(root)).unwrap();
+        assert!(contract
+            .propose_intermediate_root(INTERMEDIATE, root, 11, &signature, commitment, 0)
+            .is_err());
+        assert!(contract.pending_roots(&INTERMEDIATE).is_empty());
+    }
+
+    #[test]
+    fn test_settlement_batch_requires_signature_and_proof() {
+        let mut contract = optimistic_contract().with_settlement_verifier(RejectMarked);
+        let batch_root = [4; 32];
+        let proof = ZkProof::new(vec![1], vec![1], batch_root.to_vec(), 0);
+        let sign = |epoch| {
+            operator()
+                .sign(&settlement_batch_message(
+                    &INTERMEDIATE,
+                    epoch,
+                    &batch_root,
+                    2,
+                ))
+                .to_bytes()
+        };
+
+        // Signed for an epoch that is not collecting roots.
+        assert!(contract
+            .submit_settlement_batch(INTERMEDIATE, batch_root, 2, &sign(2), &proof)
+            .is_err());
+        // A proof for another root.
+        let other = ZkProof::new(vec![1], vec![1], vec![5; 32], 0);
+        assert!(contract
+            .submit_settlement_batch(INTERMEDIATE, batch_root, 2, &sign(1), &other)
+            .is_err());
+        assert!(contract.settlement_batch(&batch_root).is_none());
+
+        contract
+            .submit_settlement_batch(INTERMEDIATE, batch_root, 2, &sign(1), &proof)
+            .unwrap();
+        let record = *contract.settlement_batch(&batch_root).unwrap();
+        assert_eq!(record.epoch, 1);
+        assert_eq!(record.channel_count, 2);
+
+        let restored =
+            RootContract::deserialize(contract.serialize().unwrap(), AcceptAll, RejectMarked)
+                .unwrap();
+        assert_eq!(restored.settlement_batch(&batch_root), Some(&record));
+    }
+
+    /// Test proofs carry the root they start from as their proof data and
+    /// their sequence as their timestamp.
+    struct ChainedProofs;
+
+    impl RootTransitionVerifier for ChainedProofs {
+        fn verify_transition(
+            &self,
+            proof: &ZkProof,
+            old_root: &Hash,
+            sequence: u64,
+            _commitment: &Hash,
+        ) -> Result<(), SystemError> {
+            if proof.proof_data != old_root || proof.timestamp != sequence {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidProof,
+                    "rejected".to_string(),
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    fn proven_contract() -> RootContract {
+        let mut contract =
+            RootContract::new(10, ChainedProofs, RejectMarked).with_stake_escrow(Locked(500));
+        register(&mut contract);
+        contract
+    }
+
+    fn submit_proven(
+        contract: &mut RootContract,
+        old_root: Hash,
+        root: Hash,
+        sequence: u64,
+    ) -> Result<(), SystemError> {
+        let signature = operator()
+            .sign(&root_submission_message(
+                &INTERMEDIATE,
+                contract.epoch() + 1,
+                &root,
+                1,
+            ))
+            .to_bytes();
+        let transition = RootTransition {
+            sequence,
+            commitment: [0; 32],
+            proof: ZkProof::new(old_root.to_vec(), vec![1], root.to_vec(), sequence),
+        };
+        contract.process_intermediate_root(INTERMEDIATE, root, 1, &signature, &transition)
+    }
+
+    #[test]
+    fn test_proven_roots_chain_from_previous_root_and_sequence() {
+        let mut contract = proven_contract();
+        let empty = SparseMerkleTreeI::new().root();
+        assert!(submit_proven(&mut contract, [9; 32], [2; 32], 0).is_err());
+        submit_proven(&mut contract, empty, [2; 32], 0).unwrap();
+        assert_eq!(contract.last_root_sequence(&INTERMEDIATE), Some(0));
+
+        // The same sequence again, or a proof from the replaced root.
+        assert!(submit_proven(&mut contract, [2; 32], [3; 32], 0).is_err());
+        assert!(submit_proven(&mut contract, empty, [3; 32], 1).is_err());
+        submit_proven(&mut contract, [2; 32], [3; 32], 4).unwrap();
+        assert_eq!(contract.intermediate_roots()[&INTERMEDIATE], [3; 32]);
+
+        let restored =
+            RootContract::deserialize(contract.serialize().unwrap(), ChainedProofs, RejectMarked)
+                .unwrap();
+        assert_eq!(restored.last_root_sequence(&INTERMEDIATE), Some(4));
+    }
+
+    #[test]
+    fn test_first_proof_starts_from_genesis_root() {
+        let mut contract = proven_contract();
+        assert!(contract.register_genesis_root([8; 32], [5; 32]).is_err());
+        contract
+            .register_genesis_root(INTERMEDIATE, [5; 32])
+            .unwrap();
+        assert!(contract
+            .register_genesis_root(INTERMEDIATE, [6; 32])
+            .is_err());
+        assert_eq!(contract.last_root_sequence(&INTERMEDIATE), None);
+
+        let empty = SparseMerkleTreeI::new().root();
+        assert!(submit_proven(&mut contract, empty, [2; 32], 0).is_err());
+        submit_proven(&mut contract, [5; 32], [2; 32], 0).unwrap();
+    }
+}
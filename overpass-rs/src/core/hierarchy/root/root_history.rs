@@ -0,0 +1,422 @@
+// src/core/hierarchy/root/root_history.rs
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::root::sparse_merkle_tree_r::{SparseMerkleProof, SparseMerkleTreeR};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+type Hash = [u8; 32];
+type Address = [u8; 32];
+
+const RECORD_EXTENSION: &str = "epoch";
+
+/// Settlement status of a global root anchored for an epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RootSettlementStatus {
+    Submitted,
+    Finalized,
+    Rejected,
+}
+
+/// Everything anchored for a single epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EpochRootRecord {
+    pub epoch: u64,
+    pub global_root: Hash,
+    pub intermediate_roots: BTreeMap<Address, Hash>,
+    pub submitted_at: u64,
+    pub status: RootSettlementStatus,
+}
+
+/// A settlement batch accepted by the root. It settles with `epoch` and
+/// takes on that epoch's [`RootSettlementStatus`] once the epoch is sealed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SettlementBatchRecord {
+    pub contract_addr: Address,
+    pub epoch: u64,
+    pub channel_count: u64,
+}
+
+/// One page of history, oldest epoch first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryPage {
+    pub records: Vec<EpochRootRecord>,
+    /// Pass as `after_epoch` to fetch the next page; `None` on the last page.
+    pub next_cursor: Option<u64>,
+}
+
+/// Intermediate-level changes between two anchored epochs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EpochDiff {
+    pub from_epoch: u64,
+    pub to_epoch: u64,
+    pub added: Vec<(Address, Hash)>,
+    pub removed: Vec<(Address, Hash)>,
+    /// `(address, root in from_epoch, root in to_epoch)`
+    pub changed: Vec<(Address, Hash, Hash)>,
+}
+
+/// Epoch-indexed history of submitted global roots.
+///
+/// When opened on a directory each epoch is kept in its own file, written to a
+/// temporary file and renamed into place, so a record is either fully present
+/// or absent after a crash. The in-memory index is rebuilt on open.
+#[derive(Debug, Default)]
+pub struct RootHistory {
+    records: BTreeMap<u64, EpochRootRecord>,
+    dir: Option<PathBuf>,
+}
+
+impl RootHistory {
+    /// Creates a history that lives only in memory.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Opens (or creates) a durable history in the given directory.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, SystemError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(storage_error)?;
+
+        let mut records = BTreeMap::new();
+        for entry in fs::read_dir(&dir).map_err(storage_error)? {
+            let path = entry.map_err(storage_error)?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(RECORD_EXTENSION) {
+                continue;
+            }
+            let bytes = fs::read(&path).map_err(storage_error)?;
+            let record: EpochRootRecord = bincode::deserialize(&bytes).map_err(|e| {
+                SystemError::new(SystemErrorType::SerializationError, e.to_string())
+            })?;
+            records.insert(record.epoch, record);
+        }
+
+        Ok(Self {
+            records,
+            dir: Some(dir),
+        })
+    }
+
+    /// Records the global root submitted for `epoch`.
+    ///
+    /// Re-recording an epoch that is still `Submitted` replaces it, so a
+    /// submission retried after a crash does not fail. Finalized or rejected
+    /// epochs are immutable.
+    pub fn record_submission(
+        &mut self,
+        epoch: u64,
+        global_root: Hash,
+        intermediate_roots: BTreeMap<Address, Hash>,
+        submitted_at: u64,
+    ) -> Result<(), SystemError> {
+        if let Some(existing) = self.records.get(&epoch) {
+            if existing.status != RootSettlementStatus::Submitted {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidState,
+                    format!("Epoch {} is already settled", epoch),
+                ));
+            }
+        }
+
+        self.write(EpochRootRecord {
+            epoch,
+            global_root,
+            intermediate_roots,
+            submitted_at,
+            status: RootSettlementStatus::Submitted,
+        })
+    }
+
+    /// Moves a submitted epoch to its final settlement status.
+    pub fn update_status(
+        &mut self,
+        epoch: u64,
+        status: RootSettlementStatus,
+    ) -> Result<(), SystemError> {
+        let mut record = self.records.get(&epoch).cloned().ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NotFound,
+                format!("Unknown epoch {}", epoch),
+            )
+        })?;
+
+        if record.status == status {
+            return Ok(());
+        }
+        if record.status != RootSettlementStatus::Submitted {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidState,
+                format!("Epoch {} is already settled", epoch),
+            ));
+        }
+
+        record.status = status;
+        self.write(record)
+    }
+
+    pub fn get(&self, epoch: u64) -> Option<&EpochRootRecord> {
+        self.records.get(&epoch)
+    }
+
+    pub fn latest(&self) -> Option<&EpochRootRecord> {
+        self.records.values().next_back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// All records whose epoch falls within `epochs`, oldest first.
+    pub fn range(&self, epochs: RangeInclusive<u64>) -> impl Iterator<Item = &EpochRootRecord> {
+        self.records.range(epochs).map(|(_, record)| record)
+    }
+
+    /// Returns up to `limit` records with epochs strictly after `after_epoch`.
+    pub fn page(&self, after_epoch: Option<u64>, limit: usize) -> HistoryPage {
+        let start = match after_epoch {
+            Some(epoch) => match epoch.checked_add(1) {
+                Some(start) => start,
+                None => {
+                    return HistoryPage {
+                        records: Vec::new(),
+                        next_cursor: None,
+                    }
+                }
+            },
+            None => 0,
+        };
+
+        let mut iter = self.records.range(start..).map(|(_, record)| record);
+        let records: Vec<_> = iter.by_ref().take(limit).cloned().collect();
+        let next_cursor = match (records.last(), iter.next()) {
+            (Some(last), Some(_)) => Some(last.epoch),
+            _ => None,
+        };
+
+        HistoryPage {
+            records,
+            next_cursor,
+        }
+    }
+
+    /// Lists the intermediates that appeared, disappeared or changed root
+    /// between two anchored epochs.
+    pub fn diff(&self, from_epoch: u64, to_epoch: u64) -> Result<EpochDiff, SystemError> {
+        let from = self.require(from_epoch)?;
+        let to = self.require(to_epoch)?;
+
+        let mut diff = EpochDiff {
+            from_epoch,
+            to_epoch,
+            ..EpochDiff::default()
+        };
+
+        for (addr, new_root) in &to.intermediate_roots {
+            match from.intermediate_roots.get(addr) {
+                None => diff.added.push((*addr, *new_root)),
+                Some(old_root) if old_root != new_root => {
+                    diff.changed.push((*addr, *old_root, *new_root))
+                }
+                Some(_) => {}
+            }
+        }
+        for (addr, old_root) in &from.intermediate_roots {
+            if !to.intermediate_roots.contains_key(addr) {
+                diff.removed.push((*addr, *old_root));
+            }
+        }
+
+        Ok(diff)
+    }
+
+    /// Rebuilds the global tree anchored for `epoch` and proves that the
+    /// intermediate at `contract_addr` was part of it.
+    pub fn prove_intermediate(
+        &self,
+        epoch: u64,
+        contract_addr: &Address,
+    ) -> Result<(Hash, SparseMerkleProof), SystemError> {
+        let record = self.require(epoch)?;
+
+        let mut tree = SparseMerkleTreeR::new();
+        for (addr, root) in &record.intermediate_roots {
+            tree.update_global_tree(addr, root)?;
+        }
+        if tree.get_global_root_hash() != record.global_root {
+            return Err(SystemError::new(
+                SystemErrorType::StateDataMismatch,
+                format!(
+                    "Stored intermediates do not rebuild the root of epoch {}",
+                    epoch
+                ),
+            ));
+        }
+
+        Ok((record.global_root, tree.prove_inclusion(contract_addr)?))
+    }
+
+    fn require(&self, epoch: u64) -> Result<&EpochRootRecord, SystemError> {
+        self.records.get(&epoch).ok_or_else(|| {
+            SystemError::new(
+                SystemErrorType::NotFound,
+                format!("Unknown epoch {}", epoch),
+            )
+        })
+    }
+
+    fn write(&mut self, record: EpochRootRecord) -> Result<(), SystemError> {
+        if let Some(dir) = &self.dir {
+            let bytes = bincode::serialize(&record).map_err(|e| {
+                SystemError::new(SystemErrorType::SerializationError, e.to_string())
+            })?;
+
+            let path = dir.join(format!("{:020}.{}", record.epoch, RECORD_EXTENSION));
+            let tmp_path = path.with_extension("tmp");
+            let mut tmp = File::create(&tmp_path).map_err(storage_error)?;
+            tmp.write_all(&bytes).map_err(storage_error)?;
+            tmp.sync_all().map_err(storage_error)?;
+            fs::rename(&tmp_path, &path).map_err(storage_error)?;
+            // The rename is only durable once the directory entry is.
+            #[cfg(unix)]
+            {
+                File::open(dir)
+                    .and_then(|dir| dir.sync_all())
+                    .map_err(storage_error)?;
+            }
+        }
+
+        self.records.insert(record.epoch, record);
+        Ok(())
+    }
+}
+
+fn storage_error(e: std::io::Error) -> SystemError {
+    SystemError::new(SystemErrorType::StorageError, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roots(entries: &[(u8, u8)]) -> BTreeMap<Address, Hash> {
+        entries.iter().map(|&(a, r)| ([a; 32], [r; 32])).collect()
+    }
+
+    fn history_with_epochs(count: u64) -> RootHistory {
+        let mut history = RootHistory::in_memory();
+        for epoch in 1..=count {
+            history
+                .record_submission(
+                    epoch,
+                    [epoch as u8; 32],
+                    roots(&[(1, epoch as u8)]),
+                    epoch * 10,
+                )
+                .unwrap();
+        }
+        history
+    }
+
+    #[test]
+    fn test_pagination() {
+        let history = history_with_epochs(5);
+
+        let first = history.page(None, 2);
+        assert_eq!(first.records.len(), 2);
+        assert_eq!(first.next_cursor, Some(2));
+
+        let second = history.page(first.next_cursor, 2);
+        assert_eq!(second.records[0].epoch, 3);
+
+        let last = history.page(Some(4), 2);
+        assert_eq!(last.records.len(), 1);
+        assert_eq!(last.next_cursor, None);
+    }
+
+    #[test]
+    fn test_range_and_status() {
+        let mut history = history_with_epochs(5);
+        assert_eq!(history.range(2..=4).count(), 3);
+
+        history
+            .update_status(2, RootSettlementStatus::Finalized)
+            .unwrap();
+        assert_eq!(
+            history.get(2).unwrap().status,
+            RootSettlementStatus::Finalized
+        );
+        assert!(history
+            .update_status(2, RootSettlementStatus::Rejected)
+            .is_err());
+        assert!(history
+            .record_submission(2, [0; 32], BTreeMap::new(), 0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut history = RootHistory::in_memory();
+        history
+            .record_submission(1, [1; 32], roots(&[(1, 1), (2, 2)]), 10)
+            .unwrap();
+        history
+            .record_submission(2, [2; 32], roots(&[(2, 3), (3, 3)]), 20)
+            .unwrap();
+
+        let diff = history.diff(1, 2).unwrap();
+        assert_eq!(diff.added, vec![([3; 32], [3; 32])]);
+        assert_eq!(diff.removed, vec![([1; 32], [1; 32])]);
+        assert_eq!(diff.changed, vec![([2; 32], [2; 32], [3; 32])]);
+    }
+
+    #[test]
+    fn test_prove_intermediate() {
+        let intermediates = roots(&[(1, 7), (2, 8)]);
+        let mut tree = SparseMerkleTreeR::new();
+        for (addr, root) in &intermediates {
+            tree.update_global_tree(addr, root).unwrap();
+        }
+
+        let mut history = RootHistory::in_memory();
+        history
+            .record_submission(1, tree.get_global_root_hash(), intermediates, 10)
+            .unwrap();
+
+        let (root, proof) = history.prove_intermediate(1, &[2; 32]).unwrap();
+        assert!(crate::core::hierarchy::root::sparse_merkle_tree_r::verify(
+            &root,
+            &[2; 32],
+            Some(&[8; 32]),
+            &proof
+        ));
+    }
+
+    #[test]
+    fn test_durable_history_reloads() {
+        let dir = std::env::temp_dir().join(format!("root_history_{}", uuid::Uuid::new_v4()));
+        {
+            let mut history = RootHistory::open(&dir).unwrap();
+            history
+                .record_submission(7, [7; 32], roots(&[(1, 1)]), 70)
+                .unwrap();
+            history
+                .update_status(7, RootSettlementStatus::Finalized)
+                .unwrap();
+        }
+
+        let history = RootHistory::open(&dir).unwrap();
+        let record = history.get(7).unwrap();
+        assert_eq!(record.status, RootSettlementStatus::Finalized);
+        assert_eq!(record.intermediate_roots, roots(&[(1, 1)]));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
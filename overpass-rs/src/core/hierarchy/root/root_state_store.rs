@@ -0,0 +1,434 @@
+// src/core/hierarchy/root/root_state_store.rs
+
+use crate::core::error::errors::{SystemError, SystemErrorType};
+use crate::core::hierarchy::root::fraud_proof::{PendingIntermediateRoot, SlashingEvent};
+use crate::core::hierarchy::root::intermediate_registry::IntermediateRegistration;
+use crate::core::hierarchy::root::root_history::SettlementBatchRecord;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const LOG_FILE: &str = "root_state.log";
+const SNAPSHOT_FILE: &str = "root_state.snapshot";
+const SNAPSHOT_TMP_FILE: &str = "root_state.snapshot.tmp";
+
+/// Length + checksum + sequence number.
+const LOG_HEADER_LEN: usize = 4 + 4 + 8;
+/// Checksum + sequence number.
+const SNAPSHOT_HEADER_LEN: usize = 4 + 8;
+
+const DEFAULT_SNAPSHOT_INTERVAL: u64 = 1024;
+
+const CRC32C: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+
+/// A single durable change to the root contract state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RootStateRecord {
+    /// An intermediate contract submitted a new root, proven as its
+    /// submission `sequence`.
+    IntermediateRoot {
+        contract_addr: [u8; 32],
+        root: [u8; 32],
+        sequence: u64,
+    },
+    /// The epoch counter advanced after a global root submission.
+    EpochAdvanced { epoch: u64, last_submission: u64 },
+    /// An intermediate joined the registry.
+    IntermediateRegistered(IntermediateRegistration),
+    /// An intermediate's operator key was replaced.
+    OperatorKeyRotated {
+        contract_addr: [u8; 32],
+        operator_key: [u8; 32],
+    },
+    /// An intermediate started draining ahead of deregistration.
+    DeregistrationStarted { contract_addr: [u8; 32], until: u64 },
+    /// A drained intermediate and its root were removed.
+    IntermediateRetired { contract_addr: [u8; 32] },
+    /// An intermediate root entered its challenge window.
+    IntermediateRootProposed(PendingIntermediateRoot),
+    /// A pending root outlived its challenge window and joined the tree.
+    ProposedRootAccepted {
+        contract_addr: [u8; 32],
+        root: [u8; 32],
+    },
+    /// A pending root was proven fraudulent and its intermediate slashed.
+    IntermediateRootReverted(SlashingEvent),
+    /// An epoch and every epoch sealed after it were given up on, and the
+    /// intermediate roots went back to `intermediate_roots`.
+    EpochRolledBack {
+        epoch: u64,
+        intermediate_roots: BTreeMap<[u8; 32], [u8; 32]>,
+    },
+    /// An intermediate settled a batch of channels under `batch_root`.
+    SettlementBatchAccepted {
+        batch_root: [u8; 32],
+        batch: SettlementBatchRecord,
+    },
+    /// The root an intermediate's first proof starts from instead of the
+    /// empty tree.
+    GenesisRootRegistered {
+        contract_addr: [u8; 32],
+        root: [u8; 32],
+    },
+}
+
+/// State recovered from disk: the latest snapshot (if any) plus every
+/// record appended after it, in order.
+#[derive(Debug, Default)]
+pub struct RecoveredRootState {
+    pub snapshot: Option<Vec<u8>>,
+    pub records: Vec<RootStateRecord>,
+}
+
+/// Append-only log with periodic snapshots for the root contract state.
+///
+/// Every record is framed as `len | crc32c | seq | payload` and fsynced before
+/// the caller applies it in memory. Snapshots are written to a temporary file
+/// and atomically renamed into place, so a crash at any point leaves either the
+/// old or the new snapshot plus a log that can be replayed on top of it. A torn
+/// record at the end of the log is discarded on recovery.
+pub struct RootStateStore {
+    dir: PathBuf,
+    log: File,
+    next_seq: u64,
+    snapshot_seq: u64,
+    records_since_snapshot: u64,
+    snapshot_interval: u64,
+}
+
+impl RootStateStore {
+    /// Opens (or creates) a store in the given directory.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, SystemError> {
+        Self::with_snapshot_interval(dir, DEFAULT_SNAPSHOT_INTERVAL)
+    }
+
+    /// Opens a store that requests a snapshot every `snapshot_interval` records.
+    pub fn with_snapshot_interval<P: AsRef<Path>>(
+        dir: P,
+        snapshot_interval: u64,
+    ) -> Result<Self, SystemError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(storage_error)?;
+
+        let log = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(dir.join(LOG_FILE))
+            .map_err(storage_error)?;
+
+        let mut store = Self {
+            dir,
+            log,
+            next_seq: 1,
+            snapshot_seq: 0,
+            records_since_snapshot: 0,
+            snapshot_interval: snapshot_interval.max(1),
+        };
+
+        // Position the sequence counter after whatever is already on disk.
+        store.load()?;
+        Ok(store)
+    }
+
+    /// Durably appends a record to the log.
+    pub fn append(&mut self, record: &RootStateRecord) -> Result<(), SystemError> {
+        let payload = bincode::serialize(record)
+            .map_err(|e| SystemError::new(SystemErrorType::SerializationError, e.to_string()))?;
+
+        let seq = self.next_seq;
+        let mut frame = Vec::with_capacity(LOG_HEADER_LEN + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&record_checksum(seq, &payload).to_le_bytes());
+        frame.extend_from_slice(&seq.to_le_bytes());
+        frame.extend_from_slice(&payload);
+
+        self.log.write_all(&frame).map_err(storage_error)?;
+        self.log.sync_data().map_err(storage_error)?;
+
+        self.next_seq += 1;
+        self.records_since_snapshot += 1;
+        Ok(())
+    }
+
+    /// Returns true once enough records have accumulated to warrant a snapshot.
+    pub fn should_snapshot(&self) -> bool {
+        self.records_since_snapshot >= self.snapshot_interval
+    }
+
+    /// Atomically replaces the snapshot with `state` and compacts the log.
+    ///
+    /// `state` must reflect every record appended so far.
+    pub fn write_snapshot(&mut self, state: &[u8]) -> Result<(), SystemError> {
+        let seq = self.next_seq - 1;
+
+        let mut contents = Vec::with_capacity(SNAPSHOT_HEADER_LEN + state.len());
+        contents.extend_from_slice(&record_checksum(seq, state).to_le_bytes());
+        contents.extend_from_slice(&seq.to_le_bytes());
+        contents.extend_from_slice(state);
+
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FILE);
+        {
+            let mut tmp = File::create(&tmp_path).map_err(storage_error)?;
+            tmp.write_all(&contents).map_err(storage_error)?;
+            tmp.sync_all().map_err(storage_error)?;
+        }
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE)).map_err(storage_error)?;
+        self.sync_dir()?;
+
+        // Records up to `seq` are now covered by the snapshot. If we crash
+        // before the truncation lands, recovery skips them by sequence number.
+        self.log.set_len(0).map_err(storage_error)?;
+        self.log.sync_all().map_err(storage_error)?;
+
+        self.snapshot_seq = seq;
+        self.records_since_snapshot = 0;
+        Ok(())
+    }
+
+    /// Reads the latest snapshot and every valid record appended after it.
+    pub fn load(&mut self) -> Result<RecoveredRootState, SystemError> {
+        let mut recovered = RecoveredRootState::default();
+
+        self.snapshot_seq = 0;
+        if let Some((seq, state)) = self.read_snapshot()? {
+            self.snapshot_seq = seq;
+            recovered.snapshot = Some(state);
+        }
+
+        let mut bytes = Vec::new();
+        let mut log = File::open(self.dir.join(LOG_FILE)).map_err(storage_error)?;
+        log.read_to_end(&mut bytes).map_err(storage_error)?;
+
+        let mut offset = 0;
+        let mut last_seq = self.snapshot_seq;
+        while let Some((seq, record, len)) = decode_log_frame(&bytes, offset)? {
+            if seq > self.snapshot_seq {
+                recovered.records.push(record);
+            }
+            last_seq = last_seq.max(seq);
+            offset += len;
+        }
+
+        if offset < bytes.len() {
+            // A crash mid-append left a torn last frame behind; drop it so
+            // new records are not appended after garbage.
+            self.log.set_len(offset as u64).map_err(storage_error)?;
+            self.log.sync_all().map_err(storage_error)?;
+        }
+
+        self.next_seq = last_seq + 1;
+        self.records_since_snapshot = recovered.records.len() as u64;
+        Ok(recovered)
+    }
+
+    fn read_snapshot(&self) -> Result<Option<(u64, Vec<u8>)>, SystemError> {
+        let bytes = match fs::read(self.dir.join(SNAPSHOT_FILE)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(storage_error(e)),
+        };
+
+        if bytes.len() < SNAPSHOT_HEADER_LEN {
+            return Err(SystemError::new(
+                SystemErrorType::StorageError,
+                "Root state snapshot is truncated".to_string(),
+            ));
+        }
+
+        let checksum = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+        let seq = u64::from_le_bytes(bytes[4..12].try_into().unwrap());
+        let state = bytes[SNAPSHOT_HEADER_LEN..].to_vec();
+
+        // Snapshots are renamed into place only after a full fsync, so a bad
+        // checksum means on-disk corruption rather than an interrupted write.
+        if record_checksum(seq, &state) != checksum {
+            return Err(SystemError::new(
+                SystemErrorType::StateDataMismatch,
+                "Root state snapshot checksum mismatch".to_string(),
+            ));
+        }
+
+        Ok(Some((seq, state)))
+    }
+
+    fn sync_dir(&self) -> Result<(), SystemError> {
+        #[cfg(unix)]
+        {
+            File::open(&self.dir)
+                .and_then(|dir| dir.sync_all())
+                .map_err(storage_error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the frame starting at `offset`.
+///
+/// Returns `None` when the frame is the torn last write of a crash: its
+/// header or payload runs past the end of the log, or it ends the log and
+/// fails its checksum. A bad frame followed by further frames cannot be torn
+/// and is reported as corruption, as is a frame whose payload does not decode.
+fn decode_log_frame(
+    bytes: &[u8],
+    offset: usize,
+) -> Result<Option<(u64, RootStateRecord, usize)>, SystemError> {
+    let bytes = &bytes[offset..];
+    if bytes.len() < LOG_HEADER_LEN {
+        return Ok(None);
+    }
+
+    let len = u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    let seq = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+
+    let end = match LOG_HEADER_LEN.checked_add(len) {
+        Some(end) if end <= bytes.len() => end,
+        _ => return Ok(None),
+    };
+    let payload = &bytes[LOG_HEADER_LEN..end];
+    if record_checksum(seq, payload) != checksum {
+        if end == bytes.len() {
+            return Ok(None);
+        }
+        return Err(corrupt_frame(offset, "checksum mismatch".to_string()));
+    }
+
+    let record = bincode::deserialize(payload).map_err(|e| corrupt_frame(offset, e.to_string()))?;
+    Ok(Some((seq, record, end)))
+}
+
+fn corrupt_frame(offset: usize, reason: String) -> SystemError {
+    SystemError::new(
+        SystemErrorType::StateDataMismatch,
+        format!("Root state log is corrupt at offset {}: {}", offset, reason),
+    )
+}
+
+fn record_checksum(seq: u64, payload: &[u8]) -> u32 {
+    let mut digest = CRC32C.digest();
+    digest.update(&seq.to_le_bytes());
+    digest.update(payload);
+    digest.finalize()
+}
+
+fn storage_error(e: std::io::Error) -> SystemError {
+    SystemError::new(SystemErrorType::StorageError, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("root_state_store_{}", uuid::Uuid::new_v4()))
+    }
+
+    fn intermediate(n: u8) -> RootStateRecord {
+        RootStateRecord::IntermediateRoot {
+            contract_addr: [n; 32],
+            root: [n.wrapping_add(1); 32],
+            sequence: n as u64,
+        }
+    }
+
+    #[test]
+    fn test_append_and_reload() {
+        let dir = temp_store_dir();
+        {
+            let mut store = RootStateStore::open(&dir).unwrap();
+            store.append(&intermediate(1)).unwrap();
+            store
+                .append(&RootStateRecord::EpochAdvanced {
+                    epoch: 1,
+                    last_submission: 100,
+                })
+                .unwrap();
+        }
+
+        let mut store = RootStateStore::open(&dir).unwrap();
+        let recovered = store.load().unwrap();
+        assert!(recovered.snapshot.is_none());
+        assert_eq!(recovered.records.len(), 2);
+        assert_eq!(recovered.records[0], intermediate(1));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_compacts_log() {
+        let dir = temp_store_dir();
+        {
+            let mut store = RootStateStore::with_snapshot_interval(&dir, 2).unwrap();
+            store.append(&intermediate(1)).unwrap();
+            assert!(!store.should_snapshot());
+            store.append(&intermediate(2)).unwrap();
+            assert!(store.should_snapshot());
+            store.write_snapshot(b"state").unwrap();
+            store.append(&intermediate(3)).unwrap();
+        }
+
+        let mut store = RootStateStore::open(&dir).unwrap();
+        let recovered = store.load().unwrap();
+        assert_eq!(recovered.snapshot.as_deref(), Some(&b"state"[..]));
+        assert_eq!(recovered.records, vec![intermediate(3)]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_tail_is_discarded() {
+        let dir = temp_store_dir();
+        {
+            let mut store = RootStateStore::open(&dir).unwrap();
+            store.append(&intermediate(1)).unwrap();
+        }
+
+        // Simulate a crash halfway through writing the next frame.
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.join(LOG_FILE))
+            .unwrap();
+        log.write_all(&[42, 0, 0, 0, 1, 2]).unwrap();
+        drop(log);
+
+        let mut store = RootStateStore::open(&dir).unwrap();
+        store.append(&intermediate(2)).unwrap();
+        let recovered = store.load().unwrap();
+        assert_eq!(recovered.records, vec![intermediate(1), intermediate(2)]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_frame_before_the_tail_is_an_error() {
+        let dir = temp_store_dir();
+        {
+            let mut store = RootStateStore::open(&dir).unwrap();
+            store.append(&intermediate(1)).unwrap();
+            store.append(&intermediate(2)).unwrap();
+        }
+        let path = dir.join(LOG_FILE);
+        let intact = fs::read(&path).unwrap();
+
+        // A complete last frame with a bad checksum is still a torn write.
+        let mut torn = intact.clone();
+        *torn.last_mut().unwrap() ^= 1;
+        fs::write(&path, &torn).unwrap();
+        let mut store = RootStateStore::open(&dir).unwrap();
+        assert_eq!(store.load().unwrap().records, vec![intermediate(1)]);
+        drop(store);
+
+        // The same damage to the first frame must not drop the second one.
+        let mut corrupt = intact.clone();
+        corrupt[LOG_HEADER_LEN] ^= 1;
+        fs::write(&path, &corrupt).unwrap();
+        let error = RootStateStore::open(&dir).err().unwrap();
+        assert_eq!(error.error_type, SystemErrorType::StateDataMismatch);
+        assert_eq!(fs::read(&path).unwrap(), corrupt);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,186 +1,216 @@
 // ./src/core/hierarchy/root/sparse_merkle_tree_r.rs
 use crate::core::error::errors::{SystemError, SystemErrorType};
-use crate::core::hierarchy::client::channel::channel_contract::{Cell, CellType};
-use crate::core::hierarchy::intermediate::sparse_merkle_tree_i::MerkleNode;
+use crate::core::hierarchy::merkle_bits::{flip_bit, get_bit, prefix, set_bit};
 use crate::core::types::boc::BOC;
-use plonky2::field::goldilocks_field::GoldilocksField;
-use plonky2::iop::target::Target;
-use plonky2::plonk::circuit_builder::CircuitBuilder;
-use plonky2::plonk::circuit_data::CircuitConfig;
-use plonky2_field::types::Field;
-use sha2::Digest;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Number of levels below the root. Every 256-bit key maps to its own leaf.
+pub const TREE_DEPTH: usize = 256;
+
+/// Hash of a leaf slot that holds no value.
+pub const EMPTY_LEAF: [u8; 32] = [0u8; 32];
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
 /// Root Tree Trait
 pub trait RootTreeManagerTrait {
-    /// Add a new cell to the tree  
-    fn add_cell(&mut self, cell: Cell) -> Result<(), SystemError>;
+    /// Insert or replace the value stored under `key`
+    fn insert(&mut self, key: &[u8; 32], value: &[u8]) -> Result<(), SystemError>;
     /// Get the root hash of the tree
     fn global_root(&self) -> [u8; 32];
     /// Serialize the tree state to a BOC format
     fn serialize_global_state(&self) -> Result<BOC, SystemError>;
 }
 
-type VirtualCell = Target;
+/// Merkle path for a single key.
+///
+/// Default (empty-subtree) siblings are omitted: bit `i` of `bitmap` is set
+/// when the sibling at depth `i + 1` is stored in `siblings`, which is ordered
+/// from the root downwards. Most keys in a sparse tree therefore carry only
+/// `O(log n)` hashes instead of 256.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SparseMerkleProof {
+    pub bitmap: [u8; 32],
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl SparseMerkleProof {
+    /// Recomputes the root from `key` and the hash of its leaf slot.
+    /// Returns `None` if the proof is malformed.
+    pub fn compute_root(&self, key: &[u8; 32], leaf_hash: &[u8; 32]) -> Option<[u8; 32]> {
+        let defaults = default_hashes();
+        let mut stored = self.siblings.iter().rev();
+        let mut current = *leaf_hash;
+
+        for depth in (0..TREE_DEPTH).rev() {
+            let sibling = if get_bit(&self.bitmap, depth) {
+                *stored.next()?
+            } else {
+                defaults[depth + 1]
+            };
+            current = if get_bit(key, depth) {
+                hash_node(&sibling, &current)
+            } else {
+                hash_node(&current, &sibling)
+            };
+        }
+
+        if stored.next().is_some() {
+            return None;
+        }
+        Some(current)
+    }
+}
+
+/// Verifies a proof against a global root.
+///
+/// With `Some(value)` this checks that `key` maps to `value`; with `None` it
+/// checks that `key` is absent from the tree.
+pub fn verify(
+    root: &[u8; 32],
+    key: &[u8; 32],
+    value: Option<&[u8]>,
+    proof: &SparseMerkleProof,
+) -> bool {
+    let leaf_hash = match value {
+        Some(value) => hash_leaf(key, value),
+        None => EMPTY_LEAF,
+    };
+    proof.compute_root(key, &leaf_hash).as_ref() == Some(root)
+}
 
 /// Sparse Merkle Tree Implementation
+///
+/// Nodes are stored by position (depth and key prefix) and only when they
+/// differ from the default hash for their depth, so memory grows with the
+/// number of populated leaves rather than with the 2^256 key space.
+#[derive(Debug, Clone)]
 pub struct SparseMerkleTreeR {
-    circuit_builder: CircuitBuilder<GoldilocksField, 2>,
     root_hash: [u8; 32],
-    nodes: HashMap<[u8; 32], MerkleNode>,
-    height: usize,
-    virtual_cells: HashMap<VirtualCell, MerkleNode>,
-    virtual_cell_count: usize,
-    current_virtual_cell: VirtualCell,
-    current_virtual_cell_count: usize,
+    nodes: HashMap<(usize, [u8; 32]), [u8; 32]>,
+    leaves: HashMap<[u8; 32], Vec<u8>>,
 }
 
 impl SparseMerkleTreeR {
     /// Create a new Global Sparse Merkle Tree
     pub fn new() -> Self {
-        let config = CircuitConfig::standard_recursion_config();
-        let circuit_builder = CircuitBuilder::new(config);
-
         Self {
-            circuit_builder,
-            root_hash: [0u8; 32],
+            root_hash: default_hashes()[0],
             nodes: HashMap::new(),
-            height: 256,
-            virtual_cells: HashMap::new(),
-            virtual_cell_count: 0,
-            current_virtual_cell: Target::wire(0, 0),
-            current_virtual_cell_count: 0,
+            leaves: HashMap::new(),
         }
     }
 
     /// Update a leaf in the Merkle tree
-    pub fn update_global_tree(&mut self, key: &[u8], value: &[u8]) -> Result<(), SystemError> {
-        let leaf_hash = self.hash_global_leaf(key, value);
-        let path = self.generate_global_merkle_path(key)?;
-        let _value_field = self.hash_to_field(&leaf_hash);
-        let _value_cell = self.circuit_builder.add_virtual_public_input();
-        let _key_field = self.hash_to_field(&self.hash_global_leaf(key, &[]));
-        let _key_cell = self.circuit_builder.add_virtual_public_input();
-        self.add_global_path_constraints(&path, _key_cell, _value_cell)?;
-        self.root_hash = self.calculate_new_global_root(&path, &leaf_hash)?;
+    pub fn update_global_tree(&mut self, key: &[u8; 32], value: &[u8]) -> Result<(), SystemError> {
+        let leaf_hash = hash_leaf(key, value);
+        self.leaves.insert(*key, value.to_vec());
+        self.root_hash = self.recompute_path(key, leaf_hash);
         Ok(())
     }
 
-    pub fn add_virtual_public_input(&mut self) -> Target {
-        self.circuit_builder.add_virtual_public_input()
+    /// Remove a leaf, returning its previous value
+    pub fn remove(&mut self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        let previous = self.leaves.remove(key)?;
+        self.root_hash = self.recompute_path(key, EMPTY_LEAF);
+        Some(previous)
     }
 
-    /// Add constraints to the path in the zk-SNARK circuit
-    fn add_global_path_constraints(
-        &mut self,
-        path: &[([u8; 32], bool)],
-        _key_cell: Target,
-        _value_cell: Target,
-    ) -> Result<(), SystemError> {
-        let mut current = self.circuit_builder.add_virtual_target();
+    /// Value stored under `key`, if any
+    pub fn get(&self, key: &[u8; 32]) -> Option<&[u8]> {
+        self.leaves.get(key).map(Vec::as_slice)
+    }
 
-        for (sibling, is_left) in path {
-            let _sibling_field = self.hash_to_field(sibling);
-            let sibling_cell = self.circuit_builder.add_virtual_public_input();
+    /// Number of populated leaves
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
 
-            let _cells = if *is_left {
-                [current, sibling_cell]
-            } else {
-                [sibling_cell, current]
-            };
-            current = self.circuit_builder.add_virtual_target();
-        }
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
 
-        let root_cell = self.circuit_builder.add_virtual_public_input();
-        let is_equal = self.circuit_builder.is_equal(current, root_cell);
-        let bool_target = self.circuit_builder.add_virtual_target();
-        self.circuit_builder.connect(is_equal.target, bool_target);
+    /// Populated leaves in no particular order
+    pub fn leaves(&self) -> impl Iterator<Item = (&[u8; 32], &Vec<u8>)> {
+        self.leaves.iter()
+    }
 
-        Ok(())
+    /// Proof that `key` is present; fails if it is not
+    pub fn prove_inclusion(&self, key: &[u8; 32]) -> Result<SparseMerkleProof, SystemError> {
+        if !self.leaves.contains_key(key) {
+            return Err(SystemError::new(
+                SystemErrorType::NotFound,
+                "Key not present in global tree".to_string(),
+            ));
+        }
+        Ok(self.generate_global_merkle_path(key))
     }
 
-    /// Generate Merkle path for a given key
-    fn generate_global_merkle_path(
-        &self,
-        key: &[u8],
-    ) -> Result<Vec<([u8; 32], bool)>, SystemError> {
-        let mut path = Vec::new();
-        let mut current = self.root_hash;
-
-        for i in 0..self.height {
-            let bit = self.get_bit(key, i);
-            let node = self.nodes.get(&current).ok_or_else(|| SystemError {
-                error_type: SystemErrorType::NotFound,
-                message: "Node not found in path".to_string(),
-            })?;
-
-            if bit {
-                let right_hash = node.right.ok_or_else(|| SystemError {
-                    error_type: SystemErrorType::NotFound,
-                    message: "Invalid path".to_string(),
-                })?;
-                path.push((right_hash, true));
-                current = right_hash;
-            } else {
-                let left_hash = node.left.ok_or_else(|| SystemError {
-                    error_type: SystemErrorType::NotFound,
-                    message: "Invalid path".to_string(),
-                })?;
-                path.push((left_hash, false));
-                current = left_hash;
+    /// Proof that `key` is absent; fails if it is present
+    pub fn prove_non_inclusion(&self, key: &[u8; 32]) -> Result<SparseMerkleProof, SystemError> {
+        if self.leaves.contains_key(key) {
+            return Err(SystemError::new(
+                SystemErrorType::InvalidInput,
+                "Key is present in global tree".to_string(),
+            ));
+        }
+        Ok(self.generate_global_merkle_path(key))
+    }
+
+    /// Collect the siblings along the path of `key`, root first
+    fn generate_global_merkle_path(&self, key: &[u8; 32]) -> SparseMerkleProof {
+        let defaults = default_hashes();
+        let mut bitmap = [0u8; 32];
+        let mut siblings = Vec::new();
+
+        for depth in 0..TREE_DEPTH {
+            let sibling_key = flip_bit(key, depth);
+            let sibling = self.node_hash(depth + 1, &sibling_key);
+            if sibling != defaults[depth + 1] {
+                set_bit(&mut bitmap, depth);
+                siblings.push(sibling);
             }
         }
 
-        Ok(path)
+        SparseMerkleProof { bitmap, siblings }
     }
 
-    /// Hash a leaf node
-    fn hash_global_leaf(&self, key: &[u8], value: &[u8]) -> [u8; 32] {
-        let mut hasher = sha2::Sha256::new();
-        hasher.update(key);
-        hasher.update(value);
-        hasher.finalize().into()
-    }
-    /// Calculate the new root hash after updating a leaf
-    fn calculate_new_global_root(
-        &self,
-        path: &[([u8; 32], bool)],
-        leaf_hash: &[u8; 32],
-    ) -> Result<[u8; 32], SystemError> {
-        use sha2::Digest;
-        let mut current = *leaf_hash;
+    /// Rewrite every node from the leaf of `key` up to the root
+    fn recompute_path(&mut self, key: &[u8; 32], leaf_hash: [u8; 32]) -> [u8; 32] {
+        let mut current = leaf_hash;
+        self.store_node(TREE_DEPTH, key, current);
 
-        for (sibling, is_left) in path.iter().rev() {
-            let mut hasher = sha2::Sha256::new();
-            if *is_left {
-                hasher.update(current);
-                hasher.update(sibling);
+        for depth in (0..TREE_DEPTH).rev() {
+            let sibling = self.node_hash(depth + 1, &flip_bit(key, depth));
+            current = if get_bit(key, depth) {
+                hash_node(&sibling, &current)
             } else {
-                hasher.update(sibling);
-                hasher.update(current);
-            }
-            current = hasher.finalize().into();
+                hash_node(&current, &sibling)
+            };
+            self.store_node(depth, key, current);
         }
 
-        Ok(current)
+        current
     }
 
-    /// Extract a bit from the key at a specific index
-    fn get_bit(&self, key: &[u8], index: usize) -> bool {
-        let byte_index = index / 8;
-        let bit_index = 7 - (index % 8);
-        if byte_index < key.len() {
-            (key[byte_index] >> bit_index) & 1 == 1
-        } else {
-            false
-        }
+    fn node_hash(&self, depth: usize, key: &[u8; 32]) -> [u8; 32] {
+        self.nodes
+            .get(&(depth, prefix(key, depth)))
+            .copied()
+            .unwrap_or(default_hashes()[depth])
     }
 
-    /// Convert hash to a field element
-    fn hash_to_field(&self, bytes: &[u8; 32]) -> GoldilocksField {
-        let mut array = [0u8; 8];
-        array.copy_from_slice(&bytes[0..8]);
-        let num = u64::from_le_bytes(array);
-        GoldilocksField::from_canonical_u64(num)
+    fn store_node(&mut self, depth: usize, key: &[u8; 32], hash: [u8; 32]) {
+        let position = (depth, prefix(key, depth));
+        if hash == default_hashes()[depth] {
+            self.nodes.remove(&position);
+        } else {
+            self.nodes.insert(position, hash);
+        }
     }
 
     /// Return the global root hash of the tree
@@ -189,36 +219,183 @@ impl SparseMerkleTreeR {
     }
 
     /// Serialize the tree state to a BOC format
+    ///
+    /// Each leaf becomes a cell of `key || value`, sorted by key; the root
+    /// hash is the single BOC root so the tree can be checked on reload.
     pub fn serialize_to_boc(&self) -> Result<BOC, SystemError> {
-        let root_cell = Cell::new(vec![], vec![], CellType::Ordinary, self.root_hash, None);
-        let mut node_cells = Vec::new();
+        let mut keys: Vec<_> = self.leaves.keys().collect();
+        keys.sort();
+
+        let cells = keys
+            .into_iter()
+            .map(|key| {
+                let mut cell = key.to_vec();
+                cell.extend_from_slice(&self.leaves[key]);
+                cell
+            })
+            .collect();
+
+        Ok(BOC::new()
+            .with_cells(cells)
+            .with_roots(vec![self.root_hash.to_vec()])
+            .with_hash(self.root_hash))
+    }
 
-        for (hash, node) in &self.nodes {
-            let mut node_refs = Vec::new();
-            if let Some(left) = node.left {
-                node_refs.push(self.hash_to_index(&left)?);
+    /// Rebuild a tree from a BOC written by [`serialize_to_boc`](Self::serialize_to_boc)
+    pub fn deserialize_from_boc(boc: &BOC) -> Result<Self, SystemError> {
+        let mut tree = Self::new();
+        for cell in boc.cells() {
+            if cell.len() < 32 {
+                return Err(SystemError::new(
+                    SystemErrorType::InvalidInput,
+                    "Leaf cell shorter than a key".to_string(),
+                ));
             }
-            if let Some(right) = node.right {
-                node_refs.push(self.hash_to_index(&right)?);
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&cell[..32]);
+            tree.update_global_tree(&key, &cell[32..])?;
+        }
+
+        if let Some(root) = boc.roots().first() {
+            if root.as_slice() != tree.root_hash {
+                return Err(SystemError::new(
+                    SystemErrorType::StateDataMismatch,
+                    "Rebuilt global root does not match BOC root".to_string(),
+                ));
             }
-            node_cells.push(Cell::new(
-                vec![],
-                node_refs,
-                CellType::Ordinary,
-                *hash,
-                None,
-            ));
         }
-        Ok(BOC::new())
+        Ok(tree)
     }
+}
 
-    fn hash_to_index(&self, hash: &[u8; 32]) -> Result<usize, SystemError> {
-        self.nodes
-            .iter()
-            .position(|(node_hash, _)| node_hash == hash)
-            .ok_or_else(|| SystemError {
-                error_type: SystemErrorType::NotFound,
-                message: "Node not found in tree".to_string(),
-            })
+impl Default for SparseMerkleTreeR {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RootTreeManagerTrait for SparseMerkleTreeR {
+    fn insert(&mut self, key: &[u8; 32], value: &[u8]) -> Result<(), SystemError> {
+        self.update_global_tree(key, value)
+    }
+
+    fn global_root(&self) -> [u8; 32] {
+        self.root_hash
+    }
+
+    fn serialize_global_state(&self) -> Result<BOC, SystemError> {
+        self.serialize_to_boc()
+    }
+}
+
+/// Default hash for every depth: index `TREE_DEPTH` is the empty leaf and
+/// index 0 is the root of an empty tree.
+pub fn default_hashes() -> &'static [[u8; 32]; TREE_DEPTH + 1] {
+    static DEFAULTS: OnceLock<[[u8; 32]; TREE_DEPTH + 1]> = OnceLock::new();
+    DEFAULTS.get_or_init(|| {
+        let mut defaults = [EMPTY_LEAF; TREE_DEPTH + 1];
+        for depth in (0..TREE_DEPTH).rev() {
+            defaults[depth] = hash_node(&defaults[depth + 1], &defaults[depth + 1]);
+        }
+        defaults
+    })
+}
+
+/// Hash a leaf node
+fn hash_leaf(key: &[u8; 32], value: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(key);
+    hasher.update(value);
+    hasher.finalize().into()
+}
+
+/// Hash an internal node from its children
+fn hash_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(n: u8) -> [u8; 32] {
+        let mut key = [0u8; 32];
+        key[0] = n;
+        key[31] = n;
+        key
+    }
+
+    #[test]
+    fn test_empty_tree_root_is_default() {
+        let tree = SparseMerkleTreeR::new();
+        assert_eq!(tree.get_global_root_hash(), default_hashes()[0]);
+    }
+
+    #[test]
+    fn test_inclusion_proof() {
+        let mut tree = SparseMerkleTreeR::new();
+        tree.update_global_tree(&key(1), b"one").unwrap();
+        tree.update_global_tree(&key(2), b"two").unwrap();
+        tree.update_global_tree(&key(200), b"big").unwrap();
+
+        let root = tree.get_global_root_hash();
+        let proof = tree.prove_inclusion(&key(2)).unwrap();
+        assert!(verify(&root, &key(2), Some(b"two"), &proof));
+        assert!(!verify(&root, &key(2), Some(b"wrong"), &proof));
+        assert!(!verify(&root, &key(1), Some(b"two"), &proof));
+        assert!(tree.prove_inclusion(&key(3)).is_err());
+    }
+
+    #[test]
+    fn test_non_inclusion_proof() {
+        let mut tree = SparseMerkleTreeR::new();
+        tree.update_global_tree(&key(1), b"one").unwrap();
+
+        let root = tree.get_global_root_hash();
+        let proof = tree.prove_non_inclusion(&key(3)).unwrap();
+        assert!(verify(&root, &key(3), None, &proof));
+        assert!(!verify(&root, &key(3), Some(b"one"), &proof));
+        assert!(tree.prove_non_inclusion(&key(1)).is_err());
+    }
+
+    #[test]
+    fn test_root_is_order_independent() {
+        let mut a = SparseMerkleTreeR::new();
+        let mut b = SparseMerkleTreeR::new();
+        for n in [5u8, 9, 1] {
+            a.update_global_tree(&key(n), &[n]).unwrap();
+        }
+        for n in [1u8, 5, 9] {
+            b.update_global_tree(&key(n), &[n]).unwrap();
+        }
+        assert_eq!(a.get_global_root_hash(), b.get_global_root_hash());
+    }
+
+    #[test]
+    fn test_remove_restores_previous_root() {
+        let mut tree = SparseMerkleTreeR::new();
+        tree.update_global_tree(&key(1), b"one").unwrap();
+        let root = tree.get_global_root_hash();
+
+        tree.update_global_tree(&key(2), b"two").unwrap();
+        assert_eq!(tree.remove(&key(2)), Some(b"two".to_vec()));
+        assert_eq!(tree.get_global_root_hash(), root);
+    }
+
+    #[test]
+    fn test_boc_round_trip() {
+        let mut tree = SparseMerkleTreeR::new();
+        tree.update_global_tree(&key(1), b"one").unwrap();
+        tree.update_global_tree(&key(2), b"two").unwrap();
+
+        let boc = tree.serialize_to_boc().unwrap();
+        let restored = SparseMerkleTreeR::deserialize_from_boc(&boc).unwrap();
+        assert_eq!(restored.get_global_root_hash(), tree.get_global_root_hash());
+        assert_eq!(restored.get(&key(2)), Some(&b"two"[..]));
     }
 }
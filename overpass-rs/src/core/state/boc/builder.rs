@@ -1,9 +1,8 @@
 // src/core/state/BOC/builder.rs
 use crate::core::error::errors::CellError;
-use crate::core::state::boc::cell::Cell;
-use crate::core::types::BOC;
-use sha2::Digest;
-use std::collections::HashMap;
+use crate::core::state::boc::cell::{Cell, CellReference, CellType};
+use crate::core::state::boc::cell_serialization::{Serializable, BOC};
+use std::collections::{HashMap, HashSet};
 /// A builder for constructing BOCs.
 pub struct Builder {
     cells: Vec<Cell>,
@@ -75,13 +74,13 @@ impl Builder {
         }
 
         // Create BOC
-        let mut boc = BOC {
+        let boc = BOC {
             cells: self.cells.clone(),
             roots,
         };
 
         // Validate references
-        for (i, cell) in boc.cells.iter().enumerate() {
+        for cell in &boc.cells {
             for reference in cell.references() {
                 if reference.cell_index >= boc.cells.len() {
                     return Err(CellError::InvalidData);
@@ -89,6 +88,12 @@ impl Builder {
             }
         }
 
+        // Validate depth
+        let depths = subtree_depths(&boc)?;
+        if boc.roots.iter().any(|&root| depths[root] > self.max_depth) {
+            return Err(CellError::InvalidData);
+        }
+
         Ok(boc)
     }
 
@@ -118,12 +123,168 @@ impl Builder {
 
         Ok(buffer)
     }
+
+    /// Builds a Merkle proof of the single root of `boc` that reveals only
+    /// the cells in `keep`.
+    ///
+    /// Kept cells are copied with their whole subtree, and so are the cells
+    /// on the way from the root down to them. Every other subtree is
+    /// replaced by a pruned branch carrying its Merkle hash and depth, and
+    /// the copied root is wrapped in a Merkle proof cell. The result checks
+    /// out with [`VerifyBOC::verify_merkle_proof`] against the Merkle hash
+    /// of the original root.
+    ///
+    /// [`VerifyBOC::verify_merkle_proof`]: super::verifiy_boc::VerifyBOC::verify_merkle_proof
+    pub fn prune(boc: &BOC, keep: &[usize]) -> Result<BOC, CellError> {
+        let root = match boc.roots.as_slice() {
+            [root] if *root < boc.cells.len() => *root,
+            _ => return Err(CellError::InvalidData),
+        };
+        if keep.iter().any(|&cell| cell >= boc.cells.len()) {
+            return Err(CellError::InvalidData);
+        }
+
+        // Pruned branches stand in for their subtree by hash alone, so the
+        // hashes of the source bag must be the real ones.
+        for cell in &boc.cells {
+            if cell.cell_type() != CellType::Ordinary || !cell.verify_merkle_hash() {
+                return Err(CellError::InvalidData);
+            }
+            for reference in cell.references() {
+                match boc.cells.get(reference.cell_index) {
+                    Some(child) if child.merkle_hash() == &reference.merkle_hash => {}
+                    _ => return Err(CellError::InvalidData),
+                }
+            }
+        }
+
+        let depths = subtree_depths(boc)?;
+        let keep: HashSet<usize> = keep.iter().copied().collect();
+
+        // A cell is on a path if it is kept or has a kept cell below it.
+        // Children are shallower than their parents, so visit by depth.
+        let mut order: Vec<usize> = (0..boc.cells.len()).collect();
+        order.sort_by_key(|&index| depths[index]);
+        let mut on_path = vec![false; boc.cells.len()];
+        for index in order {
+            on_path[index] = keep.contains(&index)
+                || boc.cells[index]
+                    .references()
+                    .iter()
+                    .any(|reference| on_path[reference.cell_index]);
+        }
+
+        let mut pruner = Pruner {
+            source: boc,
+            depths: &depths,
+            keep: &keep,
+            on_path: &on_path,
+            cells: Vec::new(),
+            copied: HashMap::new(),
+        };
+        let proven = pruner.copy(root, false)?;
+        let proof = Cell::merkle_proof(
+            CellReference {
+                cell_index: proven,
+                merkle_hash: *boc.cells[root].merkle_hash(),
+            },
+            depth_u16(depths[root])?,
+        )?;
+        pruner.cells.push(proof);
+        let proof_root = pruner.cells.len() - 1;
+        Ok(BOC::new(pruner.cells, vec![proof_root]))
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Depth of the subtree below each cell of `boc`, a leaf being at depth 0.
+/// Fails if the cells reference each other in a cycle.
+fn subtree_depths(boc: &BOC) -> Result<Vec<usize>, CellError> {
+    fn visit(
+        index: usize,
+        boc: &BOC,
+        depths: &mut [Option<usize>],
+        on_stack: &mut [bool],
+    ) -> Result<usize, CellError> {
+        if let Some(depth) = depths[index] {
+            return Ok(depth);
+        }
+        if on_stack[index] {
+            return Err(CellError::InvalidData);
+        }
+        on_stack[index] = true;
+        let mut depth = 0;
+        for reference in boc.cells[index].references() {
+            depth = depth.max(visit(reference.cell_index, boc, depths, on_stack)? + 1);
+        }
+        on_stack[index] = false;
+        depths[index] = Some(depth);
+        Ok(depth)
+    }
+
+    let mut depths = vec![None; boc.cells.len()];
+    let mut on_stack = vec![false; boc.cells.len()];
+    (0..boc.cells.len())
+        .map(|index| visit(index, boc, &mut depths, &mut on_stack))
+        .collect()
+}
+
+fn depth_u16(depth: usize) -> Result<u16, CellError> {
+    u16::try_from(depth).map_err(|_| CellError::InvalidData)
+}
+
+/// Copies the part of a bag that [`Builder::prune`] keeps, children first.
+struct Pruner<'a> {
+    source: &'a BOC,
+    depths: &'a [usize],
+    keep: &'a HashSet<usize>,
+    on_path: &'a [bool],
+    cells: Vec<Cell>,
+    /// New index of each source cell, keyed by whether it was copied as part
+    /// of a kept subtree.
+    copied: HashMap<(usize, bool), usize>,
+}
+
+impl Pruner<'_> {
+    fn copy(&mut self, index: usize, in_kept_subtree: bool) -> Result<usize, CellError> {
+        let whole = in_kept_subtree || self.keep.contains(&index);
+        if let Some(&copied) = self.copied.get(&(index, whole)) {
+            return Ok(copied);
+        }
+
+        let source = self.source;
+        let original = &source.cells[index];
+        let cell = if whole || self.on_path[index] {
+            let mut cell = Cell::new(original.data().to_vec(), original.cell_type())?;
+            for reference in original.references() {
+                cell.add_reference(CellReference {
+                    cell_index: self.copy(reference.cell_index, whole)?,
+                    merkle_hash: reference.merkle_hash,
+                })?;
+            }
+            cell.set_merkle_hash(*original.merkle_hash());
+            cell
+        } else {
+            Cell::pruned_branch(*original.merkle_hash(), depth_u16(self.depths[index])?)
+        };
+
+        self.cells.push(cell);
+        let copied = self.cells.len() - 1;
+        self.copied.insert((index, whole), copied);
+        Ok(copied)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::core::types::CellType;
+    use crate::core::error::errors::BocError;
+    use crate::core::state::boc::verifiy_boc::VerifyBOC;
 
     #[test]
     fn test_builder_basic() {
@@ -168,4 +329,82 @@ mod tests {
         assert!(builder.add_cell(cell1).is_ok());
         assert!(builder.add_cell(cell2).is_err());
     }
+
+    fn add(builder: &mut Builder, byte: u8, children: &[usize]) -> usize {
+        let mut cell = Cell::new(vec![byte], CellType::Ordinary).unwrap();
+        for &child in children {
+            cell.add_reference(CellReference {
+                cell_index: child,
+                merkle_hash: *builder.cells[child].merkle_hash(),
+            })
+            .unwrap();
+        }
+        builder.add_cell(cell).unwrap()
+    }
+
+    /// A wallet root over three channels, each holding a state cell.
+    fn wallet() -> (BOC, Vec<usize>) {
+        let mut builder = Builder::new();
+        let mut channels = Vec::new();
+        for i in 0..3 {
+            let state = add(&mut builder, 0x10 + i, &[]);
+            channels.push(add(&mut builder, 0x20 + i, &[state]));
+        }
+        let root = add(&mut builder, 0x01, &channels);
+        (builder.build(vec![root]).unwrap(), channels)
+    }
+
+    #[test]
+    fn test_prune_proves_one_channel() {
+        let (boc, channels) = wallet();
+        let root_hash = *boc.cells[boc.roots[0]].merkle_hash();
+        let verifier = VerifyBOC::new(1000, 1_000_000, 256);
+
+        let proof = Builder::prune(&boc, &[channels[1]]).unwrap();
+        let proven = verifier.verify_merkle_proof(&proof, &root_hash).unwrap();
+
+        // The root and the kept channel are readable; its siblings are not.
+        let root = &proof.cells[proven];
+        assert_eq!(root.data(), &[0x01]);
+        let kept = &proof.cells[root.references()[1].cell_index];
+        assert_eq!(kept.data(), &[0x21]);
+        assert_eq!(proof.cells[kept.references()[0].cell_index].data(), &[0x11]);
+        for sibling in [0, 2] {
+            let pruned = &proof.cells[root.references()[sibling].cell_index];
+            assert_eq!(pruned.cell_type(), CellType::PrunedBranch);
+            assert_eq!(pruned.committed_subtree().unwrap().1, 1);
+        }
+
+        assert!(matches!(
+            verifier.verify_merkle_proof(&proof, &[0u8; 32]),
+            Err(BocError::InvalidMerkleProof)
+        ));
+    }
+
+    #[test]
+    fn test_tampered_proof_rejected() {
+        let (boc, channels) = wallet();
+        let root_hash = *boc.cells[boc.roots[0]].merkle_hash();
+        let verifier = VerifyBOC::new(1000, 1_000_000, 256);
+
+        let mut proof = Builder::prune(&boc, &[channels[0]]).unwrap();
+        let proven = proof.cells[proof.roots[0]].references()[0].cell_index;
+        let kept = proof.cells[proven].references()[0].cell_index;
+        let state = proof.cells[kept].references()[0].cell_index;
+        let mut forged = Cell::new(vec![0x99], CellType::Ordinary).unwrap();
+        forged.calculate_merkle_hash().unwrap();
+        proof.cells[state] = forged;
+
+        assert!(matches!(
+            verifier.verify_merkle_proof(&proof, &root_hash),
+            Err(BocError::InvalidMerkleProof)
+        ));
+    }
+
+    #[test]
+    fn test_prune_rejects_inconsistent_hashes() {
+        let (mut boc, channels) = wallet();
+        boc.cells[channels[2]].set_merkle_hash([0u8; 32]);
+        assert!(Builder::prune(&boc, &[channels[0]]).is_err());
+    }
 }
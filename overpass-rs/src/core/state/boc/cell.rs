@@ -1,15 +1,52 @@
 // src/core/state/boc/cell.rs
 use crate::core::error::CellError;
-use crate::core::error::Error;
-use crate::core::types::CellReference;
-use crate::core::types::CellType;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::io::{self, Read, Write};
 
 pub const MAX_REFERENCES: usize = 4;
 pub(crate) const MAX_BYTES: usize = 128;
 
+/// The kind of a legacy [`Cell`], stored as one byte by
+/// [`Serializable`](super::cell_serialization::Serializable).
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum CellType {
+    Ordinary,
+    PrunedBranch,
+    LibraryReference,
+    MerkleProof,
+    MerkleUpdate,
+}
+
+impl CellType {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            CellType::Ordinary => 0,
+            CellType::PrunedBranch => 1,
+            CellType::LibraryReference => 2,
+            CellType::MerkleProof => 3,
+            CellType::MerkleUpdate => 4,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Result<Self, CellError> {
+        match value {
+            0 => Ok(CellType::Ordinary),
+            1 => Ok(CellType::PrunedBranch),
+            2 => Ok(CellType::LibraryReference),
+            3 => Ok(CellType::MerkleProof),
+            4 => Ok(CellType::MerkleUpdate),
+            _ => Err(CellError::InvalidData),
+        }
+    }
+}
+
+/// A reference from a legacy [`Cell`] to another cell of the same BOC.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct CellReference {
+    pub cell_index: usize,
+    pub merkle_hash: [u8; 32],
+}
+
 /// Represents a cell in the Bag of Cells (BOCContract) structure.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Cell {
@@ -21,9 +58,9 @@ pub struct Cell {
 
 impl Cell {
     /// Creates a new `Cell`.
-    pub fn new(data: Vec<u8>, cell_type: CellType) -> Result<Self, Error> {
+    pub fn new(data: Vec<u8>, cell_type: CellType) -> Result<Self, CellError> {
         if data.len() > MAX_BYTES {
-            return Err(Error::DataTooLarge);
+            return Err(CellError::DataTooLarge);
         }
 
         let cell = Cell {
@@ -48,43 +85,98 @@ impl Cell {
 
     /// Calculates the Merkle hash of the cell.
     pub fn calculate_merkle_hash(&mut self) -> Result<(), CellError> {
+        self.merkle_hash = self.compute_merkle_hash();
+        Ok(())
+    }
+
+    /// Checks that the stored Merkle hash matches the cell's type, data and
+    /// references.
+    pub fn verify_merkle_hash(&self) -> bool {
+        self.merkle_hash == self.compute_merkle_hash()
+    }
+
+    fn compute_merkle_hash(&self) -> [u8; 32] {
         let mut hasher = Sha256::new();
-        hasher.update(&[self.cell_type.to_u8()]);
+        hasher.update([self.cell_type.to_u8()]);
         hasher.update(&self.data);
 
         for reference in &self.references {
-            hasher.update(&reference.merkle_hash);
+            hasher.update(reference.merkle_hash);
         }
 
-        self.merkle_hash = hasher.finalize().into();
-        Ok(())
+        hasher.finalize().into()
     }
 
-    // Other methods like getters omitted for brevity
+    /// A pruned branch standing in for a subtree whose Merkle hash is
+    /// `merkle_hash` and whose depth is `depth`. The cell keeps that hash as
+    /// its own, so the hashes of its parents are unchanged.
+    pub fn pruned_branch(merkle_hash: [u8; 32], depth: u16) -> Self {
+        let mut data = merkle_hash.to_vec();
+        data.extend_from_slice(&depth.to_be_bytes());
+        Cell {
+            data,
+            references: Vec::new(),
+            cell_type: CellType::PrunedBranch,
+            merkle_hash,
+        }
+    }
 
-    pub fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
-        // Serialization logic here
-        Ok(())
+    /// A Merkle proof committing to the subtree at `proven`, whose depth is
+    /// `depth`.
+    pub fn merkle_proof(proven: CellReference, depth: u16) -> Result<Self, CellError> {
+        let mut data = proven.merkle_hash.to_vec();
+        data.extend_from_slice(&depth.to_be_bytes());
+        let mut cell = Cell::new(data, CellType::MerkleProof)?;
+        cell.add_reference(proven)?;
+        cell.calculate_merkle_hash()?;
+        Ok(cell)
     }
 
-    pub fn deserialize<R: Read>(reader: &mut R) -> io::Result<Self> {
-        // Deserialization logic here
-        Ok(Cell {
-            data: vec![],
-            references: vec![],
-            cell_type: CellType::Ordinary,
-            merkle_hash: [0u8; 32],
-        })
+    /// The subtree hash and depth a pruned branch or Merkle proof cell
+    /// commits to, or `None` for other cells and malformed data.
+    pub fn committed_subtree(&self) -> Option<([u8; 32], u16)> {
+        match self.cell_type {
+            CellType::PrunedBranch | CellType::MerkleProof => {}
+            _ => return None,
+        }
+        if self.data.len() != 34 {
+            return None;
+        }
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&self.data[..32]);
+        Some((hash, u16::from_be_bytes([self.data[32], self.data[33]])))
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn references(&self) -> &[CellReference] {
+        &self.references
+    }
+
+    pub fn cell_type(&self) -> CellType {
+        self.cell_type
+    }
+
+    pub fn merkle_hash(&self) -> &[u8; 32] {
+        &self.merkle_hash
+    }
+
+    pub fn set_merkle_hash(&mut self, merkle_hash: [u8; 32]) {
+        self.merkle_hash = merkle_hash;
+    }
+
+    pub fn bit_length(&self) -> usize {
+        self.data.len() * 8
     }
 
     /// Calculates the hash of the cell's data and references.
     pub fn calculate_hash(&self) -> [u8; 32] {
-        use sha2::{Digest, Sha256};
-
         let mut hasher = Sha256::new();
         hasher.update(&self.data);
         for reference in &self.references {
-            hasher.update(&reference.merkle_hash);
+            hasher.update(reference.merkle_hash);
         }
         hasher.finalize().into()
     }
@@ -0,0 +1,345 @@
+// src/core/state/boc/cell_hash.rs
+
+use super::ton_boc::{TonBoc, TonCell};
+use crate::core::error::errors::BocError;
+use sha2::{Digest, Sha256};
+
+/// Highest level a cell can have: each enclosing Merkle proof or update
+/// lowers the level of what it wraps by one.
+pub const MAX_LEVEL: usize = 3;
+
+/// Deepest cell tree the TON VM accepts.
+pub const MAX_CELL_DEPTH: u16 = 1024;
+
+/// What a cell is, judged by its exotic flag and, for exotic cells, the
+/// type byte at the start of its data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellKind {
+    Ordinary,
+    PrunedBranch,
+    LibraryReference,
+    MerkleProof,
+    MerkleUpdate,
+}
+
+impl CellKind {
+    pub fn of(cell: &TonCell) -> Result<Self, BocError> {
+        if !cell.is_exotic() {
+            return Ok(CellKind::Ordinary);
+        }
+        match cell.data().first() {
+            Some(1) => Ok(CellKind::PrunedBranch),
+            Some(2) => Ok(CellKind::LibraryReference),
+            Some(3) => Ok(CellKind::MerkleProof),
+            Some(4) => Ok(CellKind::MerkleUpdate),
+            other => Err(BocError::DeserializationError(format!(
+                "Unknown exotic cell type {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Representation hashes and depths of a cell at every level.
+///
+/// Level 0 is what the cell stands for once all pruned branches are put
+/// back; the highest level describes the cell exactly as stored. For cells
+/// without pruned branches below them every level is the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellHashes {
+    level_mask: u8,
+    hashes: [[u8; 32]; MAX_LEVEL + 1],
+    depths: [u16; MAX_LEVEL + 1],
+}
+
+impl CellHashes {
+    pub fn level_mask(&self) -> u8 {
+        self.level_mask
+    }
+
+    /// Hash at `level`, clamped to [`MAX_LEVEL`].
+    pub fn hash(&self, level: usize) -> [u8; 32] {
+        self.hashes[level.min(MAX_LEVEL)]
+    }
+
+    /// Depth at `level`, clamped to [`MAX_LEVEL`].
+    pub fn depth(&self, level: usize) -> u16 {
+        self.depths[level.min(MAX_LEVEL)]
+    }
+
+    /// The hash TON calls *the* cell hash: the one at the highest level.
+    pub fn representation_hash(&self) -> [u8; 32] {
+        self.hash(MAX_LEVEL)
+    }
+}
+
+/// Computes [`CellHashes`] for every cell of `boc`, indexed like
+/// `boc.cells`.
+///
+/// Exotic cells are checked on the way: pruned branches and library
+/// references must be well formed, and Merkle proofs and updates must
+/// commit to the hashes and depths their children actually have. Every
+/// cell's level mask must match the one derived from its children.
+pub fn compute_hashes(boc: &TonBoc) -> Result<Vec<CellHashes>, BocError> {
+    for (from, cell) in boc.cells.iter().enumerate() {
+        if let Some(&to) = cell.references().iter().find(|&&to| to >= boc.cells.len()) {
+            return Err(BocError::InvalidReference { from, to });
+        }
+    }
+
+    let mut hashes: Vec<Option<CellHashes>> = vec![None; boc.cells.len()];
+    for index in boc.topological_order()?.into_iter().rev() {
+        let cell = &boc.cells[index];
+        let children: Vec<CellHashes> = cell
+            .references()
+            .iter()
+            .map(|&child| hashes[child].expect("children are hashed before their parents"))
+            .collect();
+        hashes[index] = Some(hash_cell(cell, &children)?);
+    }
+    Ok(hashes.into_iter().flatten().collect())
+}
+
+/// Hashes one cell given the hashes of its children, following the TON
+/// whitepaper's "standard cell representation".
+pub fn hash_cell(cell: &TonCell, children: &[CellHashes]) -> Result<CellHashes, BocError> {
+    let kind = CellKind::of(cell)?;
+    let mut pruned = None;
+    let level_mask = match kind {
+        CellKind::Ordinary => children.iter().fold(0, |mask, c| mask | c.level_mask),
+        CellKind::PrunedBranch => {
+            let branch = PrunedBranch::parse(cell)?;
+            let mask = branch.level_mask;
+            pruned = Some(branch);
+            mask
+        }
+        CellKind::LibraryReference => {
+            if cell.bit_len() != 8 + 256 || !children.is_empty() {
+                return Err(BocError::DeserializationError(
+                    "Malformed library reference cell".to_string(),
+                ));
+            }
+            0
+        }
+        CellKind::MerkleProof => {
+            if cell.bit_len() != 8 + 256 + 16 || children.len() != 1 {
+                return Err(BocError::InvalidMerkleProof);
+            }
+            check_commitment(cell.data(), 1, &children[0])?;
+            children[0].level_mask >> 1
+        }
+        CellKind::MerkleUpdate => {
+            if cell.bit_len() != 8 + 2 * (256 + 16) || children.len() != 2 {
+                return Err(BocError::InvalidMerkleProof);
+            }
+            // Both hashes come first, then both depths.
+            let data = cell.data();
+            for (i, child) in children.iter().enumerate() {
+                let hash = &data[1 + 32 * i..33 + 32 * i];
+                let depth = u16::from_be_bytes([data[65 + 2 * i], data[66 + 2 * i]]);
+                if hash != child.hash(0) || depth != child.depth(0) {
+                    return Err(BocError::InvalidMerkleProof);
+                }
+            }
+            (children[0].level_mask | children[1].level_mask) >> 1
+        }
+    };
+    if cell.level_mask() != level_mask {
+        return Err(BocError::DeserializationError(format!(
+            "Cell declares level mask {} but its contents give {}",
+            cell.level_mask(),
+            level_mask
+        )));
+    }
+
+    // A pruned branch stores the hashes of every level below its own, so
+    // only its top-level hash is computed here.
+    let hash_count = level_mask.count_ones() as usize + 1;
+    let computed = if pruned.is_some() { 1 } else { hash_count };
+    let skipped = hash_count - computed;
+    let child_level_offset = match kind {
+        CellKind::MerkleProof | CellKind::MerkleUpdate => 1,
+        _ => 0,
+    };
+
+    let [_, d2] = cell.descriptors();
+    let mut own_hashes: Vec<[u8; 32]> = Vec::with_capacity(computed);
+    let mut own_depths: Vec<u16> = Vec::with_capacity(computed);
+    let significant = (0..=level_of(level_mask)).filter(|&level| is_significant(level_mask, level));
+    for level in significant.skip(skipped) {
+        let mut repr = Sha256::new();
+        let d1 = cell.references().len() as u8
+            + 8 * cell.is_exotic() as u8
+            + 32 * (level_mask & level_bits(level));
+        repr.update([d1, d2]);
+        // Higher levels chain from the level below instead of repeating
+        // the data.
+        match own_hashes.last() {
+            Some(previous) => repr.update(previous),
+            None => repr.update(cell.padded_data()),
+        }
+
+        let child_level = level + child_level_offset;
+        let mut depth = 0;
+        for child in children {
+            depth = depth.max(child.depth(child_level));
+            repr.update(child.depth(child_level).to_be_bytes());
+        }
+        for child in children {
+            repr.update(child.hash(child_level));
+        }
+        if !children.is_empty() {
+            depth += 1;
+        }
+        if depth > MAX_CELL_DEPTH {
+            return Err(BocError::MaxDepthExceeded);
+        }
+
+        own_hashes.push(repr.finalize().into());
+        own_depths.push(depth);
+    }
+
+    let mut hashes = [[0u8; 32]; MAX_LEVEL + 1];
+    let mut depths = [0u16; MAX_LEVEL + 1];
+    for level in 0..=MAX_LEVEL {
+        let hash_index = (level_mask & level_bits(level)).count_ones() as usize;
+        match &pruned {
+            Some(branch) if hash_index < skipped => {
+                hashes[level] = branch.hashes[hash_index];
+                depths[level] = branch.depths[hash_index];
+            }
+            _ => {
+                hashes[level] = own_hashes[hash_index - skipped];
+                depths[level] = own_depths[hash_index - skipped];
+            }
+        }
+    }
+
+    Ok(CellHashes {
+        level_mask,
+        hashes,
+        depths,
+    })
+}
+
+/// The contents of a pruned-branch cell: a level mask followed by the
+/// hashes and depths of the removed subtree below that level.
+struct PrunedBranch {
+    level_mask: u8,
+    hashes: Vec<[u8; 32]>,
+    depths: Vec<u16>,
+}
+
+impl PrunedBranch {
+    fn parse(cell: &TonCell) -> Result<Self, BocError> {
+        let data = cell.data();
+        if !cell.references().is_empty() || data.len() < 2 {
+            return Err(BocError::InvalidPrunedBranch);
+        }
+        let level_mask = data[1];
+        if level_mask == 0 || level_mask as usize > (1 << MAX_LEVEL) - 1 {
+            return Err(BocError::InvalidPrunedBranch);
+        }
+        let count = level_mask.count_ones() as usize;
+        if cell.bit_len() != 16 + count * (256 + 16) {
+            return Err(BocError::InvalidPrunedBranch);
+        }
+
+        let depths_start = 2 + 32 * count;
+        let hashes = data[2..depths_start]
+            .chunks_exact(32)
+            .map(|hash| hash.try_into().expect("chunks are 32 bytes"))
+            .collect();
+        let depths = data[depths_start..]
+            .chunks_exact(2)
+            .map(|depth| u16::from_be_bytes([depth[0], depth[1]]))
+            .collect();
+        Ok(Self {
+            level_mask,
+            hashes,
+            depths,
+        })
+    }
+}
+
+/// Checks a Merkle proof's stored level-0 hash and depth of the child it
+/// wraps, found at `offset` in its data.
+fn check_commitment(data: &[u8], offset: usize, child: &CellHashes) -> Result<(), BocError> {
+    let hash = &data[offset..offset + 32];
+    let depth = u16::from_be_bytes([data[offset + 32], data[offset + 33]]);
+    if hash != child.hash(0) || depth != child.depth(0) {
+        return Err(BocError::InvalidMerkleProof);
+    }
+    Ok(())
+}
+
+/// Level of a cell: one more than the position of the highest set bit.
+fn level_of(mask: u8) -> usize {
+    8 - mask.leading_zeros() as usize
+}
+
+/// Mask bits that are visible from `level`.
+fn level_bits(level: usize) -> u8 {
+    ((1u16 << level) - 1) as u8
+}
+
+fn is_significant(mask: u8, level: usize) -> bool {
+    level == 0 || (mask >> (level - 1)) & 1 == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes_of(cells: Vec<TonCell>, root: usize) -> Vec<CellHashes> {
+        compute_hashes(&TonBoc::new(cells, vec![root])).unwrap()
+    }
+
+    #[test]
+    fn test_empty_cell_hash() {
+        let hashes = hashes_of(vec![TonCell::new(vec![], 0, vec![]).unwrap()], 0);
+        // Well-known hash of the empty ordinary cell.
+        assert_eq!(
+            hex::encode(hashes[0].representation_hash()),
+            "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7"
+        );
+        assert_eq!(hashes[0].depth(0), 0);
+    }
+
+    #[test]
+    fn test_depth_and_child_hashes() {
+        let cells = vec![
+            TonCell::new(vec![0xaa], 8, vec![]).unwrap(),
+            TonCell::new(vec![0xbb], 8, vec![0]).unwrap(),
+            TonCell::new(vec![0xcc], 8, vec![1, 0]).unwrap(),
+        ];
+        let hashes = hashes_of(cells.clone(), 2);
+        assert_eq!(hashes[0].depth(0), 0);
+        assert_eq!(hashes[1].depth(0), 1);
+        assert_eq!(hashes[2].depth(0), 2);
+
+        // Changing a leaf changes every hash above it.
+        let mut changed = cells;
+        changed[0] = TonCell::new(vec![0xab], 8, vec![]).unwrap();
+        let changed = hashes_of(changed, 2);
+        assert_ne!(hashes[2].hash(0), changed[2].hash(0));
+    }
+
+    #[test]
+    fn test_malformed_exotic_cells_rejected() {
+        let pruned_without_mask = TonCell::from_parts(vec![1, 0], 16, vec![], true, 0).unwrap();
+        assert!(matches!(
+            compute_hashes(&TonBoc::new(vec![pruned_without_mask], vec![0])),
+            Err(BocError::InvalidPrunedBranch)
+        ));
+
+        let leaf = TonCell::new(vec![], 0, vec![]).unwrap();
+        let mut proof_data = vec![3];
+        proof_data.extend_from_slice(&[0u8; 34]);
+        let proof = TonCell::from_parts(proof_data, 280, vec![0], true, 0).unwrap();
+        assert!(matches!(
+            compute_hashes(&TonBoc::new(vec![leaf, proof], vec![1])),
+            Err(BocError::InvalidMerkleProof)
+        ));
+    }
+}
@@ -0,0 +1,247 @@
+// src/core/state/boc/cell_store.rs
+
+use super::cell_hash::compute_hashes;
+use super::ton_boc::{TonBoc, TonCell};
+use crate::core::error::errors::BocError;
+use std::collections::HashMap;
+
+/// A cell held by a [`CellStore`], with its references given as the
+/// representation hashes of the children.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredCell {
+    data: Vec<u8>,
+    bit_len: usize,
+    exotic: bool,
+    level_mask: u8,
+    children: Vec<[u8; 32]>,
+    ref_count: usize,
+}
+
+impl StoredCell {
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn bit_len(&self) -> usize {
+        self.bit_len
+    }
+
+    pub fn children(&self) -> &[[u8; 32]] {
+        &self.children
+    }
+
+    /// Parents in the store plus outstanding root handles.
+    pub fn ref_count(&self) -> usize {
+        self.ref_count
+    }
+}
+
+/// Content-addressed cell storage shared by many bags of cells.
+///
+/// Cells are keyed by representation hash, so a subtree that appears in
+/// several bags, or several times in one, is stored once. Channel states
+/// that differ in a single balance share everything but the path from that
+/// balance to the root.
+#[derive(Debug, Default)]
+pub struct CellStore {
+    cells: HashMap<[u8; 32], StoredCell>,
+}
+
+impl CellStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores every cell of `boc` that is not already present and returns
+    /// the representation hashes of its roots. Each root stays in the store
+    /// until a matching [`CellStore::release`].
+    pub fn insert_boc(&mut self, boc: &TonBoc) -> Result<Vec<[u8; 32]>, BocError> {
+        if let Some(&root) = boc.roots.iter().find(|&&root| root >= boc.cells.len()) {
+            return Err(BocError::InvalidRoot(root));
+        }
+        let hashes: Vec<[u8; 32]> = compute_hashes(boc)?
+            .iter()
+            .map(|hashes| hashes.representation_hash())
+            .collect();
+
+        for index in boc.topological_order()?.into_iter().rev() {
+            if self.cells.contains_key(&hashes[index]) {
+                continue;
+            }
+            let cell = &boc.cells[index];
+            let children: Vec<[u8; 32]> = cell
+                .references()
+                .iter()
+                .map(|&child| hashes[child])
+                .collect();
+            for child in &children {
+                self.cells
+                    .get_mut(child)
+                    .expect("children are stored before their parents")
+                    .ref_count += 1;
+            }
+            self.cells.insert(
+                hashes[index],
+                StoredCell {
+                    data: cell.data().to_vec(),
+                    bit_len: cell.bit_len(),
+                    exotic: cell.is_exotic(),
+                    level_mask: cell.level_mask(),
+                    children,
+                    ref_count: 0,
+                },
+            );
+        }
+
+        let roots: Vec<[u8; 32]> = boc.roots.iter().map(|&root| hashes[root]).collect();
+        for root in &roots {
+            self.cells
+                .get_mut(root)
+                .expect("roots were just stored")
+                .ref_count += 1;
+        }
+        Ok(roots)
+    }
+
+    pub fn get_cell(&self, hash: &[u8; 32]) -> Option<&StoredCell> {
+        self.cells.get(hash)
+    }
+
+    pub fn contains(&self, hash: &[u8; 32]) -> bool {
+        self.cells.contains_key(hash)
+    }
+
+    /// Number of distinct cells held.
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// Rebuilds a bag holding the trees under `roots`.
+    pub fn export(&self, roots: &[[u8; 32]]) -> Result<TonBoc, BocError> {
+        let mut cells = Vec::new();
+        let mut indices: HashMap<[u8; 32], usize> = HashMap::new();
+        let mut root_indices = Vec::with_capacity(roots.len());
+
+        for root in roots {
+            let mut stack = vec![(*root, false)];
+            while let Some((hash, expanded)) = stack.pop() {
+                if indices.contains_key(&hash) {
+                    continue;
+                }
+                let stored = self.cells.get(&hash).ok_or_else(|| {
+                    BocError::SerializationError(format!(
+                        "Cell {} is not in the store",
+                        hex::encode(hash)
+                    ))
+                })?;
+                if !expanded {
+                    stack.push((hash, true));
+                    stack.extend(stored.children.iter().map(|&child| (child, false)));
+                    continue;
+                }
+                let references = stored.children.iter().map(|child| indices[child]).collect();
+                cells.push(TonCell::from_parts(
+                    stored.data.clone(),
+                    stored.bit_len,
+                    references,
+                    stored.exotic,
+                    stored.level_mask,
+                )?);
+                indices.insert(hash, cells.len() - 1);
+            }
+            root_indices.push(indices[root]);
+        }
+
+        Ok(TonBoc::new(cells, root_indices))
+    }
+
+    /// Drops one handle on `root`, removing every cell nothing else refers
+    /// to any more.
+    pub fn release(&mut self, root: &[u8; 32]) -> Result<(), BocError> {
+        if !self.cells.contains_key(root) {
+            return Err(BocError::SerializationError(format!(
+                "Cell {} is not in the store",
+                hex::encode(root)
+            )));
+        }
+
+        let mut pending = vec![*root];
+        while let Some(hash) = pending.pop() {
+            let stored = self
+                .cells
+                .get_mut(&hash)
+                .expect("stored cells keep their children");
+            stored.ref_count = stored.ref_count.saturating_sub(1);
+            if stored.ref_count == 0 {
+                let removed = self.cells.remove(&hash).expect("cell was just read");
+                pending.extend(removed.children);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A channel state: a root over a balance cell and a shared history.
+    fn channel_state(balance: u8) -> TonBoc {
+        let cells = vec![
+            TonCell::new(vec![0xee; 32], 256, vec![]).unwrap(),
+            TonCell::new(vec![0xdd], 8, vec![0]).unwrap(),
+            TonCell::new(vec![balance], 8, vec![]).unwrap(),
+            TonCell::new(vec![0x01], 8, vec![2, 1]).unwrap(),
+        ];
+        TonBoc::new(cells, vec![3])
+    }
+
+    #[test]
+    fn test_identical_subtrees_stored_once() {
+        let mut store = CellStore::new();
+        let first = store.insert_boc(&channel_state(10)).unwrap();
+        let second = store.insert_boc(&channel_state(11)).unwrap();
+        assert_ne!(first, second);
+
+        // Two new cells for the second state: its balance and its root.
+        assert_eq!(store.len(), 6);
+        let history = store.get_cell(&first[0]).unwrap().children()[1];
+        assert_eq!(store.get_cell(&history).unwrap().ref_count(), 2);
+
+        // Storing the same state again only adds a handle.
+        assert_eq!(store.insert_boc(&channel_state(10)).unwrap(), first);
+        assert_eq!(store.len(), 6);
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let mut store = CellStore::new();
+        let boc = channel_state(10);
+        let roots = store.insert_boc(&boc).unwrap();
+
+        let exported = store.export(&roots).unwrap();
+        let hash = |boc: &TonBoc| compute_hashes(boc).unwrap()[boc.roots[0]];
+        assert_eq!(hash(&exported), hash(&boc));
+        assert_eq!(exported.cells.len(), boc.cells.len());
+    }
+
+    #[test]
+    fn test_release_collects_unshared_cells() {
+        let mut store = CellStore::new();
+        let first = store.insert_boc(&channel_state(10)).unwrap();
+        let second = store.insert_boc(&channel_state(11)).unwrap();
+
+        store.release(&first[0]).unwrap();
+        assert_eq!(store.len(), 4);
+        assert!(!store.contains(&first[0]));
+        assert!(store.export(&second).is_ok());
+
+        store.release(&second[0]).unwrap();
+        assert!(store.is_empty());
+        assert!(store.release(&second[0]).is_err());
+    }
+}
@@ -3,11 +3,22 @@
 // src/core/state/boc/mod.rs
 pub mod builder;
 pub mod cell;
+pub mod cell_hash;
 pub mod cell_serialization;
+pub mod cell_store;
 pub mod parser;
+pub mod stream_parser;
+pub mod ton_boc;
+pub mod ton_builder;
 pub mod verifiy_boc;
 
 pub use self::builder::Builder;
 pub use self::cell::Cell;
+pub use self::cell_hash::{CellHashes, CellKind};
 pub use self::cell_serialization::{Deserializable, Serializable};
+pub use self::cell_store::{CellStore, StoredCell};
 pub use self::parser::BOCParser;
+pub use self::stream_parser::{BocLimits, BocStreamParser};
+pub use self::ton_boc::{TonBoc, TonBocOptions, TonCell};
+pub use self::ton_builder::TonBuilder;
+pub use ovp_cell::{CellBuilder, CellSlice};
@@ -1,10 +1,9 @@
 // src/core/state/boc/parser.rs
 
-use crate::core::types::ovp_types::*;
-
-use std::io::{Cursor, Read};
-
 use crate::core::error::CellError;
+use crate::core::state::boc::cell::Cell;
+use crate::core::state::boc::cell_serialization::{Deserializable, BOC};
+use std::io::{Cursor, Read};
 
 /// Parses a BOC (Bag of Cells) from raw bytes.
 pub struct BOCParser;
@@ -41,11 +40,12 @@ impl BOCParser {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::core::state::boc::cell::Cell;
+    use crate::core::state::boc::cell::CellType;
+    use crate::core::state::boc::cell_serialization::Serializable;
 
     #[test]
     fn test_parse_boc() {
-        let cell = Cell::new(vec![1, 2, 3], Vec::new()).unwrap();
+        let cell = Cell::new(vec![1, 2, 3], CellType::Ordinary).unwrap();
         let boc = BOC {
             cells: vec![cell],
             roots: vec![0],
@@ -62,7 +62,7 @@ mod tests {
         bytes.extend_from_slice(&(0u32).to_le_bytes());
 
         let parsed_boc = BOCParser::parse(&bytes).unwrap();
-        assert_eq!(parsed_boc.cells.len(), 1);
-        assert_eq!(parsed_boc.roots.len(), 1);
+        assert_eq!(parsed_boc, boc);
+        assert!(BOCParser::parse(&bytes[..bytes.len() - 1]).is_err());
     }
 }
@@ -0,0 +1,403 @@
+// src/core/state/boc/stream_parser.rs
+
+use super::ton_boc::{
+    TonBoc, TonCell, BOC_MAGIC, CRC32C, HAS_CACHE_BITS, HAS_CRC32C, HAS_INDEX, MAX_CELL_BITS,
+    MAX_CELL_REFS, RESERVED_FLAGS,
+};
+use crate::core::error::errors::BocError;
+use std::io::{ErrorKind, Read};
+
+/// Resource limits enforced by [`BocStreamParser`] while it reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BocLimits {
+    pub max_cell_count: usize,
+    /// Sum of the data bits of all cells.
+    pub max_bits_size: usize,
+    /// Longest chain of references, counted in edges.
+    pub max_depth: usize,
+}
+
+impl Default for BocLimits {
+    fn default() -> Self {
+        Self {
+            max_cell_count: 1000,
+            max_bits_size: 1_000_000,
+            max_depth: 256,
+        }
+    }
+}
+
+/// Reads a `serialized_boc` incrementally from any [`Read`], such as a
+/// socket or a chain of received chunks.
+///
+/// Meant for untrusted input. Limits are checked as soon as the header
+/// declares a size and again as each cell arrives, and nothing is allocated
+/// from a declared size until that size has passed the limits. References
+/// must point to later cells, so a cycle is rejected at the first
+/// backwards reference. Failures carry the offset of the offending field,
+/// the cell being read and the reason.
+pub struct BocStreamParser<R> {
+    reader: R,
+    limits: BocLimits,
+    offset: u64,
+    field_offset: u64,
+    cell: Option<usize>,
+    crc: crc::Digest<'static, u32>,
+}
+
+impl<R: Read> BocStreamParser<R> {
+    pub fn new(reader: R, limits: BocLimits) -> Self {
+        Self {
+            reader,
+            limits,
+            offset: 0,
+            field_offset: 0,
+            cell: None,
+            crc: CRC32C.digest(),
+        }
+    }
+
+    /// Reads one bag and checks that the input ends right after it.
+    pub fn parse(mut self) -> Result<TonBoc, BocError> {
+        let mut magic = [0u8; 4];
+        self.read_exact(&mut magic)?;
+        if magic != BOC_MAGIC {
+            return Err(self.fail("missing serialized_boc magic"));
+        }
+
+        let flags = self.read_u8()?;
+        let has_index = flags & HAS_INDEX != 0;
+        let has_crc32c = flags & HAS_CRC32C != 0;
+        let has_cache_bits = flags & HAS_CACHE_BITS != 0;
+        if flags & RESERVED_FLAGS != 0 {
+            return Err(self.fail("reserved flags are set"));
+        }
+        if has_cache_bits && !has_index {
+            return Err(self.fail("cache bits require an index"));
+        }
+        let size = (flags & 0x07) as usize;
+        if !(1..=4).contains(&size) {
+            return Err(self.fail("reference size must be 1 to 4 bytes"));
+        }
+        let off_bytes = self.read_u8()? as usize;
+        if !(1..=8).contains(&off_bytes) {
+            return Err(self.fail("offset size must be 1 to 8 bytes"));
+        }
+
+        let cell_count = self.read_be(size)? as usize;
+        if cell_count > self.limits.max_cell_count {
+            return Err(self.fail(&format!(
+                "{} cells exceed the limit of {}",
+                cell_count, self.limits.max_cell_count
+            )));
+        }
+        let root_count = self.read_be(size)? as usize;
+        if root_count == 0 {
+            return Err(self.fail("no roots"));
+        }
+        if root_count > cell_count {
+            return Err(self.fail("more roots than cells"));
+        }
+        if self.read_be(size)? != 0 {
+            return Err(self.fail("absent cells are not supported"));
+        }
+        let tot_cells_size = self.read_be(off_bytes)?;
+        let max_cell_size = (2 + MAX_CELL_BITS.div_ceil(8) + MAX_CELL_REFS * size) as u64;
+        if tot_cells_size < cell_count as u64 * 2
+            || tot_cells_size > cell_count as u64 * max_cell_size
+        {
+            return Err(self.fail(&format!(
+                "{} bytes of cell data cannot hold {} cells",
+                tot_cells_size, cell_count
+            )));
+        }
+
+        let mut roots = Vec::with_capacity(root_count);
+        for _ in 0..root_count {
+            let root = self.read_be(size)? as usize;
+            if root >= cell_count {
+                return Err(self.fail(&format!("root {} is past the last cell", root)));
+            }
+            roots.push(root);
+        }
+        let mut index = Vec::with_capacity(if has_index { cell_count } else { 0 });
+        if has_index {
+            for _ in 0..cell_count {
+                let entry = self.read_be(off_bytes)?;
+                index.push(if has_cache_bits { entry >> 1 } else { entry });
+            }
+        }
+
+        let data_start = self.offset;
+        let mut cells = Vec::with_capacity(cell_count);
+        let mut depths = vec![0usize; cell_count];
+        let mut total_bits = 0usize;
+        for from in 0..cell_count {
+            self.cell = Some(from);
+            cells.push(self.read_cell(from, size, &mut depths)?);
+
+            total_bits += cells[from].bit_len();
+            if total_bits > self.limits.max_bits_size {
+                return Err(self.fail(&format!(
+                    "cells exceed the limit of {} bits",
+                    self.limits.max_bits_size
+                )));
+            }
+            let end = self.offset - data_start;
+            if end > tot_cells_size {
+                return Err(self.fail("cell data overruns the declared size"));
+            }
+            if index.get(from).is_some_and(|indexed| *indexed != end) {
+                return Err(self.fail("offset index does not match cell"));
+            }
+        }
+        self.cell = None;
+        if self.offset - data_start != tot_cells_size {
+            return Err(self.fail("cell data is shorter than declared"));
+        }
+
+        if has_crc32c {
+            let digest = std::mem::replace(&mut self.crc, CRC32C.digest());
+            let expected = digest.finalize().to_le_bytes();
+            let mut crc = [0u8; 4];
+            self.read_exact(&mut crc)?;
+            if crc != expected {
+                return Err(self.fail("CRC32C mismatch"));
+            }
+        }
+        self.expect_end()?;
+
+        Ok(TonBoc::new(cells, roots))
+    }
+
+    fn read_cell(
+        &mut self,
+        from: usize,
+        size: usize,
+        depths: &mut [usize],
+    ) -> Result<TonCell, BocError> {
+        let d1 = self.read_u8()?;
+        let reference_count = (d1 & 0x07) as usize;
+        if reference_count > MAX_CELL_REFS {
+            return Err(self.fail(&format!("{} references", reference_count)));
+        }
+        if d1 & 0x10 != 0 {
+            return Err(self.fail("stored cell hashes are not supported"));
+        }
+        let exotic = d1 & 0x08 != 0;
+        let level_mask = d1 >> 5;
+
+        let d2 = self.read_u8()?;
+        let byte_len = d2.div_ceil(2) as usize;
+        let mut data = vec![0u8; byte_len];
+        self.read_exact(&mut data)?;
+        let bit_len = if d2 & 1 == 0 {
+            byte_len * 8
+        } else {
+            let last = data.last().copied().unwrap_or(0);
+            if last == 0 {
+                return Err(self.fail("missing completion tag"));
+            }
+            let padding = last.trailing_zeros() as usize + 1;
+            data[byte_len - 1] &= !(1u8 << (padding - 1));
+            byte_len * 8 - padding
+        };
+        if bit_len > MAX_CELL_BITS {
+            return Err(self.fail(&format!("{} data bits", bit_len)));
+        }
+
+        let mut references = Vec::with_capacity(reference_count);
+        for _ in 0..reference_count {
+            let to = self.read_be(size)? as usize;
+            if to <= from {
+                return Err(self.fail(&format!("reference to cell {} does not point forward", to)));
+            }
+            if to >= depths.len() {
+                return Err(self.fail(&format!("reference to cell {} is past the last cell", to)));
+            }
+            // Every parent comes earlier, so this cell's depth is final and
+            // can be pushed down to its children.
+            depths[to] = depths[to].max(depths[from] + 1);
+            if depths[to] > self.limits.max_depth {
+                return Err(self.fail(&format!(
+                    "cell {} is deeper than the limit of {}",
+                    to, self.limits.max_depth
+                )));
+            }
+            references.push(to);
+        }
+
+        TonCell::from_parts(data, bit_len, references, exotic, level_mask)
+            .map_err(|err| self.fail(&err.to_string()))
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), BocError> {
+        self.field_offset = self.offset;
+        self.reader
+            .read_exact(buf)
+            .map_err(|err| match err.kind() {
+                ErrorKind::UnexpectedEof => self.fail("unexpected end of input"),
+                _ => self.fail(&format!("read failed: {}", err)),
+            })?;
+        self.crc.update(buf);
+        self.offset += buf.len() as u64;
+        Ok(())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, BocError> {
+        let mut byte = [0u8; 1];
+        self.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn read_be(&mut self, len: usize) -> Result<u64, BocError> {
+        let mut bytes = [0u8; 8];
+        self.read_exact(&mut bytes[8 - len..])?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    fn expect_end(&mut self) -> Result<(), BocError> {
+        self.field_offset = self.offset;
+        let mut byte = [0u8; 1];
+        loop {
+            return match self.reader.read(&mut byte) {
+                Ok(0) => Ok(()),
+                Ok(_) => Err(self.fail("trailing data after the bag")),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => Err(self.fail(&format!("read failed: {}", err))),
+            };
+        }
+    }
+
+    fn fail(&self, reason: &str) -> BocError {
+        BocError::Malformed {
+            offset: self.field_offset,
+            cell: self.cell,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::state::boc::ton_boc::TonBocOptions;
+
+    fn parse(bytes: &[u8], limits: BocLimits) -> Result<TonBoc, BocError> {
+        BocStreamParser::new(bytes, limits).parse()
+    }
+
+    /// Hands out its input three bytes at a time, like a slow socket.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(3).min(self.0.len());
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    /// A chain of `len` cells, each referencing the next.
+    fn chain(len: usize) -> TonBoc {
+        let cells = (0..len)
+            .map(|i| {
+                let references = if i + 1 < len { vec![i + 1] } else { vec![] };
+                TonCell::new(vec![i as u8], 8, references).unwrap()
+            })
+            .collect();
+        TonBoc::new(cells, vec![0])
+    }
+
+    #[test]
+    fn test_matches_slice_parser_across_chunks() {
+        let boc = chain(5);
+        for options in [
+            TonBocOptions::default(),
+            TonBocOptions {
+                has_index: true,
+                has_crc32c: true,
+            },
+        ] {
+            let bytes = boc.serialize_with(options).unwrap();
+            let parsed = BocStreamParser::new(Trickle(&bytes), BocLimits::default())
+                .parse()
+                .unwrap();
+            assert_eq!(parsed, TonBoc::parse(&bytes).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_limits_enforced_while_reading() {
+        let bytes = chain(5).serialize().unwrap();
+        let limits = BocLimits::default();
+
+        let few_cells = BocLimits {
+            max_cell_count: 4,
+            ..limits
+        };
+        assert!(matches!(
+            parse(&bytes, few_cells),
+            Err(BocError::Malformed { cell: None, .. })
+        ));
+
+        let shallow = BocLimits {
+            max_depth: 2,
+            ..limits
+        };
+        assert!(matches!(
+            parse(&bytes, shallow),
+            Err(BocError::Malformed { cell: Some(2), .. })
+        ));
+
+        let few_bits = BocLimits {
+            max_bits_size: 16,
+            ..limits
+        };
+        assert!(matches!(
+            parse(&bytes, few_bits),
+            Err(BocError::Malformed { cell: Some(2), .. })
+        ));
+    }
+
+    #[test]
+    fn test_backward_reference_rejected_at_its_offset() {
+        let mut bytes = chain(3)
+            .serialize_with(TonBocOptions {
+                has_index: false,
+                has_crc32c: false,
+            })
+            .unwrap();
+        // Header is 10 bytes and the root list one more; the second cell
+        // starts at 15 and its reference follows d1, d2 and one data byte.
+        bytes[18] = 0;
+        match parse(&bytes, BocLimits::default()) {
+            Err(BocError::Malformed { offset, cell, .. }) => {
+                assert_eq!(offset, 18);
+                assert_eq!(cell, Some(1));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_huge_declared_sizes_rejected_before_allocation() {
+        // Claims 2^32 - 1 cells in a ten-byte input.
+        let mut bytes = BOC_MAGIC.to_vec();
+        bytes.extend_from_slice(&[0x04, 0x08, 0xff, 0xff, 0xff, 0xff]);
+        assert!(matches!(
+            parse(&bytes, BocLimits::default()),
+            Err(BocError::Malformed { offset: 6, .. })
+        ));
+
+        // One cell with an exabyte of cell data.
+        let mut bytes = BOC_MAGIC.to_vec();
+        bytes.extend_from_slice(&[0x01, 0x08, 1, 1, 0]);
+        bytes.extend_from_slice(&[0x0f; 8]);
+        assert!(matches!(
+            parse(&bytes, BocLimits::default()),
+            Err(BocError::Malformed { offset: 9, .. })
+        ));
+    }
+}
@@ -0,0 +1,430 @@
+// src/core/state/boc/ton_boc.rs
+
+use super::stream_parser::{BocLimits, BocStreamParser};
+use crate::core::error::errors::BocError;
+use std::sync::Arc;
+
+/// Magic prefix of a TON `serialized_boc`.
+pub const BOC_MAGIC: [u8; 4] = [0xb5, 0xee, 0x9c, 0x72];
+pub const MAX_CELL_BITS: usize = 1023;
+pub const MAX_CELL_REFS: usize = 4;
+
+pub(crate) static CRC32C: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+
+pub(crate) const HAS_INDEX: u8 = 0x80;
+pub(crate) const HAS_CRC32C: u8 = 0x40;
+pub(crate) const HAS_CACHE_BITS: u8 = 0x20;
+pub(crate) const RESERVED_FLAGS: u8 = 0x18;
+
+/// A cell in the layout the TON VM uses: up to 1023 data bits and up to four
+/// references, given as indices into the owning [`TonBoc`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TonCell {
+    data: Vec<u8>,
+    bit_len: usize,
+    references: Vec<usize>,
+    exotic: bool,
+    level_mask: u8,
+}
+
+impl TonCell {
+    /// Creates an ordinary cell holding the first `bit_len` bits of `data`.
+    pub fn new(data: Vec<u8>, bit_len: usize, references: Vec<usize>) -> Result<Self, BocError> {
+        Self::from_parts(data, bit_len, references, false, 0)
+    }
+
+    /// Creates a cell with an explicit exotic flag and level mask. Exotic
+    /// cells carry their type in the first data byte.
+    pub fn from_parts(
+        mut data: Vec<u8>,
+        bit_len: usize,
+        references: Vec<usize>,
+        exotic: bool,
+        level_mask: u8,
+    ) -> Result<Self, BocError> {
+        if bit_len > MAX_CELL_BITS {
+            return Err(BocError::CellDataTooLarge);
+        }
+        if references.len() > MAX_CELL_REFS {
+            return Err(BocError::TooManyReferences);
+        }
+        if level_mask > 7 {
+            return Err(BocError::SerializationError(format!(
+                "Invalid level mask {}",
+                level_mask
+            )));
+        }
+        if data.len() != bit_len.div_ceil(8) {
+            return Err(BocError::SerializationError(format!(
+                "{} data bytes cannot hold exactly {} bits",
+                data.len(),
+                bit_len
+            )));
+        }
+        // Bits past `bit_len` are not part of the cell.
+        if bit_len % 8 != 0 {
+            let last = data.len() - 1;
+            data[last] &= 0xff << (8 - bit_len % 8);
+        }
+        Ok(Self {
+            data,
+            bit_len,
+            references,
+            exotic,
+            level_mask,
+        })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn bit_len(&self) -> usize {
+        self.bit_len
+    }
+
+    pub fn references(&self) -> &[usize] {
+        &self.references
+    }
+
+    pub fn is_exotic(&self) -> bool {
+        self.exotic
+    }
+
+    pub fn level_mask(&self) -> u8 {
+        self.level_mask
+    }
+
+    /// The `d1` (references, exotic flag, level mask) and `d2` (data
+    /// length) descriptor bytes.
+    pub fn descriptors(&self) -> [u8; 2] {
+        let d1 = self.references.len() as u8 + 8 * self.exotic as u8 + 32 * self.level_mask;
+        let d2 = (self.bit_len / 8 + self.bit_len.div_ceil(8)) as u8;
+        [d1, d2]
+    }
+
+    /// Data padded to whole bytes: if the length is not a multiple of eight,
+    /// a single 1 bit follows the data and zeros fill the rest of the byte.
+    pub fn padded_data(&self) -> Vec<u8> {
+        let mut data = self.data.clone();
+        if self.bit_len % 8 != 0 {
+            let last = data.len() - 1;
+            data[last] |= 0x80 >> (self.bit_len % 8);
+        }
+        data
+    }
+}
+
+/// Optional sections written by [`TonBoc::serialize_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TonBocOptions {
+    /// Write the offset index, so a reader can locate cells without parsing
+    /// the ones before them.
+    pub has_index: bool,
+    /// Append a CRC32C of everything before it.
+    pub has_crc32c: bool,
+}
+
+impl Default for TonBocOptions {
+    fn default() -> Self {
+        Self {
+            has_index: false,
+            has_crc32c: true,
+        }
+    }
+}
+
+/// A bag of cells in TON's standard `serialized_boc` encoding, as produced
+/// and accepted by `contracts_func/`.
+///
+/// This sits alongside the length-prefixed layout read by
+/// [`BOCParser`](super::parser::BOCParser); use it for anything exchanged
+/// with on-chain contracts.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TonBoc {
+    pub cells: Vec<TonCell>,
+    pub roots: Vec<usize>,
+}
+
+impl TonBoc {
+    pub fn new(cells: Vec<TonCell>, roots: Vec<usize>) -> Self {
+        Self { cells, roots }
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>, BocError> {
+        self.serialize_with(TonBocOptions::default())
+    }
+
+    /// Cells may reference each other in any acyclic order; they are written
+    /// so that every cell precedes the cells it references, as the format
+    /// requires, with the roots' subtrees first.
+    pub fn serialize_with(&self, options: TonBocOptions) -> Result<Vec<u8>, BocError> {
+        if self.roots.is_empty() {
+            return Err(BocError::NoRoots);
+        }
+        for (index, &root) in self.roots.iter().enumerate() {
+            if root >= self.cells.len() {
+                return Err(BocError::InvalidRoot(index));
+            }
+        }
+        for (from, cell) in self.cells.iter().enumerate() {
+            if let Some(&to) = cell.references.iter().find(|&&to| to >= self.cells.len()) {
+                return Err(BocError::InvalidReference { from, to });
+            }
+        }
+
+        let order = self.topological_order()?;
+        let mut position = vec![0usize; self.cells.len()];
+        for (new, &old) in order.iter().enumerate() {
+            position[old] = new;
+        }
+
+        let size = byte_len(self.cells.len() as u64);
+        let mut cell_data = Vec::new();
+        let mut ends = Vec::with_capacity(order.len());
+        for &old in &order {
+            let cell = &self.cells[old];
+            cell_data.extend_from_slice(&cell.descriptors());
+            cell_data.extend_from_slice(&cell.padded_data());
+            for &reference in &cell.references {
+                write_be(&mut cell_data, position[reference] as u64, size);
+            }
+            ends.push(cell_data.len() as u64);
+        }
+        let off_bytes = byte_len(cell_data.len() as u64);
+
+        let mut flags = size as u8;
+        if options.has_index {
+            flags |= HAS_INDEX;
+        }
+        if options.has_crc32c {
+            flags |= HAS_CRC32C;
+        }
+
+        let mut bytes = Vec::with_capacity(cell_data.len() + 32);
+        bytes.extend_from_slice(&BOC_MAGIC);
+        bytes.push(flags);
+        bytes.push(off_bytes as u8);
+        write_be(&mut bytes, self.cells.len() as u64, size);
+        write_be(&mut bytes, self.roots.len() as u64, size);
+        write_be(&mut bytes, 0, size);
+        write_be(&mut bytes, cell_data.len() as u64, off_bytes);
+        for &root in &self.roots {
+            write_be(&mut bytes, position[root] as u64, size);
+        }
+        if options.has_index {
+            for &end in &ends {
+                write_be(&mut bytes, end, off_bytes);
+            }
+        }
+        bytes.extend_from_slice(&cell_data);
+        if options.has_crc32c {
+            let crc = CRC32C.checksum(&bytes);
+            bytes.extend_from_slice(&crc.to_le_bytes());
+        }
+        Ok(bytes)
+    }
+
+    /// Parses a `serialized_boc`. Cell indices are kept as they appear in
+    /// the input, so every reference points to a later cell.
+    ///
+    /// Only the input length bounds what is read; use a [`BocStreamParser`]
+    /// with tighter [`BocLimits`] for untrusted input.
+    pub fn parse(bytes: &[u8]) -> Result<Self, BocError> {
+        // Every cell takes at least its two descriptor bytes.
+        let limits = BocLimits {
+            max_cell_count: bytes.len() / 2,
+            max_bits_size: bytes.len() * 8,
+            max_depth: bytes.len() / 2,
+        };
+        BocStreamParser::new(bytes, limits).parse()
+    }
+
+    /// The ordinary cell at `index` with its subtree, for reading with a
+    /// `CellSlice`.
+    pub fn tree(&self, index: usize) -> Result<ovp_cell::Cell, BocError> {
+        if index >= self.cells.len() {
+            return Err(BocError::InvalidRoot(index));
+        }
+        for (from, cell) in self.cells.iter().enumerate() {
+            if let Some(&to) = cell.references.iter().find(|&&to| to >= self.cells.len()) {
+                return Err(BocError::InvalidReference { from, to });
+            }
+        }
+
+        // Exotic cells, and every cell above one, stay unbuilt.
+        let mut trees: Vec<Option<Arc<ovp_cell::Cell>>> = vec![None; self.cells.len()];
+        for i in self.topological_order()?.into_iter().rev() {
+            let cell = &self.cells[i];
+            if cell.exotic {
+                continue;
+            }
+            let references: Option<Vec<_>> = cell
+                .references
+                .iter()
+                .map(|&child| trees[child].clone())
+                .collect();
+            if let Some(references) = references {
+                let tree = ovp_cell::Cell::new(cell.data.clone(), cell.bit_len, references)?;
+                trees[i] = Some(Arc::new(tree));
+            }
+        }
+        trees[index].as_deref().cloned().ok_or_else(|| {
+            BocError::SerializationError(format!(
+                "Cell {} has an exotic cell in its subtree",
+                index
+            ))
+        })
+    }
+
+    /// Cell indices ordered so that every cell comes before the cells it
+    /// references, starting with the roots.
+    pub(crate) fn topological_order(&self) -> Result<Vec<usize>, BocError> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            New,
+            Open,
+            Done,
+        }
+        let mut marks = vec![Mark::New; self.cells.len()];
+        let mut post_order = Vec::with_capacity(self.cells.len());
+
+        // Reverse post-order lists the tree explored last first, so explore
+        // the roots last and in reverse.
+        let starts = self.roots.iter().copied().chain(0..self.cells.len());
+        let starts: Vec<_> = starts.collect();
+        for &start in starts.iter().rev() {
+            if marks[start] != Mark::New {
+                continue;
+            }
+            marks[start] = Mark::Open;
+            let mut stack = vec![(start, 0usize)];
+            while let Some((cell, next)) = stack.last_mut() {
+                let cell = *cell;
+                if let Some(&child) = self.cells[cell].references.get(*next) {
+                    *next += 1;
+                    match marks[child] {
+                        Mark::New => {
+                            marks[child] = Mark::Open;
+                            stack.push((child, 0));
+                        }
+                        Mark::Open => return Err(BocError::CycleDetected),
+                        Mark::Done => {}
+                    }
+                } else {
+                    marks[cell] = Mark::Done;
+                    post_order.push(cell);
+                    stack.pop();
+                }
+            }
+        }
+
+        post_order.reverse();
+        Ok(post_order)
+    }
+}
+
+/// Bytes needed to hold `value`, at least one.
+fn byte_len(value: u64) -> usize {
+    ((64 - value.leading_zeros() as usize).div_ceil(8)).max(1)
+}
+
+fn write_be(buffer: &mut Vec<u8>, value: u64, len: usize) {
+    buffer.extend_from_slice(&value.to_be_bytes()[8 - len..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The cell's content with its subtree, independent of cell order.
+    #[derive(Debug, PartialEq)]
+    struct Tree(Vec<u8>, usize, Vec<Tree>);
+
+    fn tree(boc: &TonBoc, index: usize) -> Tree {
+        let cell = &boc.cells[index];
+        Tree(
+            cell.data().to_vec(),
+            cell.bit_len(),
+            cell.references().iter().map(|&r| tree(boc, r)).collect(),
+        )
+    }
+
+    /// Two roots sharing a leaf, laid out children first.
+    fn sample() -> TonBoc {
+        let leaf = TonCell::new(vec![0xab, 0xc0], 11, vec![]).unwrap();
+        let middle = TonCell::new(vec![0x01; 4], 32, vec![0]).unwrap();
+        let top = TonCell::new(vec![0xff], 3, vec![1, 0]).unwrap();
+        TonBoc::new(vec![leaf, middle, top], vec![2, 1])
+    }
+
+    #[test]
+    fn test_empty_cell_matches_reference_encoding() {
+        let empty = TonBoc::new(vec![TonCell::new(vec![], 0, vec![]).unwrap()], vec![0]);
+        let expected = hex::decode("b5ee9c724101010100020000004cacb9cd").unwrap();
+        assert_eq!(empty.serialize().unwrap(), expected);
+        assert_eq!(TonBoc::parse(&expected).unwrap(), empty);
+    }
+
+    #[test]
+    fn test_round_trip_reorders_parents_first() {
+        let boc = sample();
+        for options in [
+            TonBocOptions::default(),
+            TonBocOptions {
+                has_index: true,
+                has_crc32c: false,
+            },
+        ] {
+            let parsed = TonBoc::parse(&boc.serialize_with(options).unwrap()).unwrap();
+            assert_eq!(parsed.cells.len(), 3);
+            for (from, cell) in parsed.cells.iter().enumerate() {
+                assert!(cell.references().iter().all(|&to| to > from));
+            }
+            for (original, parsed_root) in boc.roots.iter().zip(&parsed.roots) {
+                assert_eq!(tree(&parsed, *parsed_root), tree(&boc, *original));
+            }
+        }
+        // Three padding bits: 0b111 followed by the completion tag.
+        let top = TonBoc::parse(&boc.serialize().unwrap()).unwrap().cells[0].padded_data();
+        assert_eq!(top, vec![0xf0]);
+    }
+
+    #[test]
+    fn test_rejects_corrupt_input() {
+        let bytes = sample().serialize().unwrap();
+
+        let mut flipped = bytes.clone();
+        flipped[12] ^= 0x01;
+        assert!(matches!(
+            TonBoc::parse(&flipped),
+            Err(BocError::Malformed { .. })
+        ));
+        assert!(TonBoc::parse(&bytes[..bytes.len() - 5]).is_err());
+
+        // Point the middle cell's reference back at the root. The cell data
+        // starts after a 10-byte header and two root indices; the root cell
+        // takes 5 bytes and the middle cell's reference is its 7th byte.
+        let unchecked = TonBocOptions {
+            has_index: false,
+            has_crc32c: false,
+        };
+        let mut backwards = sample().serialize_with(unchecked).unwrap();
+        backwards[12 + 5 + 6] = 0;
+        assert!(matches!(
+            TonBoc::parse(&backwards),
+            Err(BocError::Malformed { cell: Some(1), .. })
+        ));
+    }
+
+    #[test]
+    fn test_serialize_rejects_cycles() {
+        let a = TonCell::new(vec![], 0, vec![1]).unwrap();
+        let b = TonCell::new(vec![], 0, vec![0]).unwrap();
+        assert!(matches!(
+            TonBoc::new(vec![a, b], vec![0]).serialize(),
+            Err(BocError::CycleDetected)
+        ));
+        assert!(TonCell::new(vec![0; 128], 1024, vec![]).is_err());
+    }
+}
@@ -0,0 +1,252 @@
+// src/core/state/boc/ton_builder.rs
+use crate::core::error::errors::BocError;
+use crate::core::state::boc::cell_hash::{hash_cell, CellHashes};
+use crate::core::state::boc::ton_boc::{TonBoc, TonCell};
+use std::collections::HashMap;
+/// A builder for constructing [`TonBoc`]s, storing cells with the same
+/// representation hash once.
+pub struct TonBuilder {
+    cells: Vec<TonCell>,
+    hashes: Vec<CellHashes>,
+    cell_indices: HashMap<[u8; 32], usize>,
+    max_cell_count: usize,
+    max_bits_size: usize,
+    max_depth: usize,
+}
+
+impl TonBuilder {
+    /// Creates a new `TonBuilder` with default limits.
+    pub fn new() -> Self {
+        Self {
+            cells: Vec::new(),
+            hashes: Vec::new(),
+            cell_indices: HashMap::new(),
+            max_cell_count: 1000,
+            max_bits_size: 1_000_000,
+            max_depth: 256,
+        }
+    }
+
+    /// Creates a new `TonBuilder` with custom limits.
+    pub fn with_limits(max_cell_count: usize, max_bits_size: usize, max_depth: usize) -> Self {
+        Self {
+            cells: Vec::new(),
+            hashes: Vec::new(),
+            cell_indices: HashMap::new(),
+            max_cell_count,
+            max_bits_size,
+            max_depth,
+        }
+    }
+
+    /// Adds a cell to the builder and returns its index.
+    ///
+    /// Cells may only reference cells already added, so children go in
+    /// before their parents and no cycle can be built. Cells with the same
+    /// representation hash are stored once.
+    pub fn add_cell(&mut self, cell: TonCell) -> Result<usize, BocError> {
+        let idx = self.cells.len();
+        if let Some(&to) = cell.references().iter().find(|&&to| to >= idx) {
+            return Err(BocError::InvalidReference { from: idx, to });
+        }
+
+        // Calculate cell hash
+        let children: Vec<CellHashes> = cell
+            .references()
+            .iter()
+            .map(|&child| self.hashes[child])
+            .collect();
+        let hashes = hash_cell(&cell, &children)?;
+        let hash = hashes.representation_hash();
+
+        // Check if cell already exists
+        if let Some(&idx) = self.cell_indices.get(&hash) {
+            return Ok(idx);
+        }
+
+        if self.cells.len() >= self.max_cell_count {
+            return Err(BocError::TooManyCells);
+        }
+
+        // Add new cell
+        self.cells.push(cell);
+        self.hashes.push(hashes);
+        self.cell_indices.insert(hash, idx);
+        Ok(idx)
+    }
+
+    /// Adds `cell` and every cell below it, returning the index of `cell`.
+    pub fn add_tree(&mut self, cell: &ovp_cell::Cell) -> Result<usize, BocError> {
+        self.add_tree_once(cell, &mut HashMap::new())
+    }
+
+    /// Adds a subtree, visiting each shared child only once.
+    fn add_tree_once(
+        &mut self,
+        cell: &ovp_cell::Cell,
+        added: &mut HashMap<*const ovp_cell::Cell, usize>,
+    ) -> Result<usize, BocError> {
+        if let Some(&idx) = added.get(&(cell as *const _)) {
+            return Ok(idx);
+        }
+        let mut references = Vec::with_capacity(cell.references().len());
+        for child in cell.references() {
+            references.push(self.add_tree_once(child, added)?);
+        }
+        let idx = self.add_cell(TonCell::new(
+            cell.data().to_vec(),
+            cell.bit_len(),
+            references,
+        )?)?;
+        added.insert(cell as *const _, idx);
+        Ok(idx)
+    }
+
+    /// Builds the BOC with the specified root indices.
+    pub fn build(&self, roots: Vec<usize>) -> Result<TonBoc, BocError> {
+        if roots.is_empty() {
+            return Err(BocError::NoRoots);
+        }
+        // Validate roots
+        for &root in &roots {
+            if root >= self.cells.len() {
+                return Err(BocError::InvalidRoot(root));
+            }
+        }
+
+        // Calculate total bits size
+        let total_bits: usize = self.cells.iter().map(|cell| cell.bit_len()).sum();
+        if total_bits > self.max_bits_size {
+            return Err(BocError::TotalSizeTooLarge);
+        }
+
+        for &root in &roots {
+            if self.hashes[root].depth(0) as usize > self.max_depth {
+                return Err(BocError::MaxDepthExceeded);
+            }
+        }
+
+        Ok(TonBoc::new(self.cells.clone(), roots))
+    }
+
+    /// Serializes the built BOC into bytes.
+    pub fn serialize(&self, roots: Vec<usize>) -> Result<Vec<u8>, BocError> {
+        self.build(roots)?.serialize()
+    }
+}
+
+impl Default for TonBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ovp_cell::CellBuilder;
+
+    fn cell(byte: u8, references: Vec<usize>) -> TonCell {
+        TonCell::new(vec![byte], 8, references).unwrap()
+    }
+
+    #[test]
+    fn test_ton_builder_basic() {
+        let mut builder = TonBuilder::new();
+        let cell = TonCell::new(vec![1, 2, 3], 24, vec![]).unwrap();
+        let idx = builder.add_cell(cell.clone()).unwrap();
+        assert_eq!(idx, 0);
+
+        let boc = builder.build(vec![0]).unwrap();
+        assert_eq!(boc.cells.len(), 1);
+        assert_eq!(boc.roots, vec![0]);
+    }
+
+    #[test]
+    fn test_ton_builder_duplicate_cells() {
+        let mut builder = TonBuilder::new();
+        let cell = TonCell::new(vec![1, 2, 3], 24, vec![]).unwrap();
+
+        let idx1 = builder.add_cell(cell.clone()).unwrap();
+        let idx2 = builder.add_cell(cell.clone()).unwrap();
+
+        assert_eq!(idx1, idx2);
+        assert_eq!(builder.cells.len(), 1);
+
+        // Parents of equal children are equal too.
+        let parent = TonCell::new(vec![4], 8, vec![idx1]).unwrap();
+        let parent_again = TonCell::new(vec![4], 8, vec![idx2]).unwrap();
+        assert_eq!(
+            builder.add_cell(parent).unwrap(),
+            builder.add_cell(parent_again).unwrap()
+        );
+        assert_eq!(builder.cells.len(), 2);
+    }
+
+    #[test]
+    fn test_ton_builder_serialization() {
+        let mut builder = TonBuilder::new();
+        let cell = TonCell::new(vec![1, 2, 3], 24, vec![]).unwrap();
+        let idx = builder.add_cell(cell).unwrap();
+
+        let serialized = builder.serialize(vec![idx]).unwrap();
+        assert!(!serialized.is_empty());
+        assert_eq!(TonBoc::parse(&serialized).unwrap().cells.len(), 1);
+    }
+
+    #[test]
+    fn test_ton_builder_limits() {
+        let mut builder = TonBuilder::with_limits(1, 1000, 256);
+        let cell1 = TonCell::new(vec![1], 8, vec![]).unwrap();
+        let cell2 = TonCell::new(vec![2], 8, vec![]).unwrap();
+
+        assert!(builder.add_cell(cell1).is_ok());
+        assert!(builder.add_cell(cell2).is_err());
+
+        let mut shallow = TonBuilder::with_limits(10, 1000, 1);
+        let leaf = shallow.add_cell(cell(1, vec![])).unwrap();
+        let middle = shallow.add_cell(cell(2, vec![leaf])).unwrap();
+        let top = shallow.add_cell(cell(3, vec![middle])).unwrap();
+        assert!(matches!(
+            shallow.build(vec![top]),
+            Err(BocError::MaxDepthExceeded)
+        ));
+    }
+
+    #[test]
+    fn test_cell_tree_round_trip() {
+        let balance = CellBuilder::new().store_coins(5_000).unwrap().build();
+        let owner = ovp_cell::Address::new(0, [7; 32]);
+        let mut state = CellBuilder::new();
+        state
+            .store_uint(3, 32)
+            .unwrap()
+            .store_address(&owner)
+            .unwrap()
+            .store_ref(balance.clone())
+            .unwrap()
+            .store_ref(balance)
+            .unwrap();
+
+        let mut builder = TonBuilder::new();
+        let state = state.build();
+        let root = builder.add_tree(&state).unwrap();
+        assert_eq!(builder.cells.len(), 2);
+        // Both sides agree on the representation hash.
+        assert_eq!(builder.hashes[root].representation_hash(), state.hash());
+        let bytes = builder.serialize(vec![root]).unwrap();
+
+        let parsed = TonBoc::parse(&bytes).unwrap();
+        let tree = parsed.tree(parsed.roots[0]).unwrap();
+        let mut slice = tree.parse();
+        assert_eq!(slice.load_uint(32).unwrap(), 3);
+        assert_eq!(slice.load_address().unwrap(), owner);
+        for _ in 0..2 {
+            assert_eq!(
+                slice.load_ref().unwrap().parse().load_coins().unwrap(),
+                5_000
+            );
+        }
+        slice.end_parse().unwrap();
+    }
+}
@@ -1,12 +1,9 @@
 // src/core/state/boc/validation.rs
 
-use super::cell::*;
-use crate::core::state::boc::cell::MAX_REFERENCES;
-use crate::core::types::ovp_types::BOC;
-use crate::core::types::CellReference;
-use crate::core::types::CellType;
-use sha2::Digest;
-use std::collections::HashSet;
+use crate::core::error::errors::BocError;
+use crate::core::state::boc::cell::{Cell, CellType, MAX_BYTES, MAX_REFERENCES};
+use crate::core::state::boc::cell_serialization::BOC;
+use std::collections::{HashMap, HashSet};
 
 pub struct VerifyBOC {
     max_cell_count: usize,
@@ -51,6 +48,68 @@ impl VerifyBOC {
         Ok(())
     }
 
+    /// Checks that `boc` is a Merkle proof, as built by
+    /// [`Builder::prune`](super::builder::Builder::prune), of a tree whose
+    /// Merkle hash is `root_hash`, and returns the index of the proven tree's
+    /// root within `boc.cells`.
+    ///
+    /// Every cell is rehashed and every reference must carry the hash of the
+    /// cell it points to, so the hashes in pruned branches are bound to
+    /// `root_hash` through their parents rather than taken on trust.
+    pub fn verify_merkle_proof(&self, boc: &BOC, root_hash: &[u8; 32]) -> Result<usize, BocError> {
+        self.verify_boc(boc)?;
+
+        let proof = match boc.roots.as_slice() {
+            [root] => &boc.cells[*root],
+            _ => return Err(BocError::InvalidMerkleProof),
+        };
+        let (proven, depth) = match (proof.committed_subtree(), proof.references()) {
+            (Some((hash, depth)), [proven])
+                if proof.cell_type() == CellType::MerkleProof && hash == *root_hash =>
+            {
+                (proven.cell_index, depth)
+            }
+            _ => return Err(BocError::InvalidMerkleProof),
+        };
+
+        for cell in &boc.cells {
+            // Pruned branch hashes were checked against their data in verify_cell
+            if cell.cell_type() != CellType::PrunedBranch && !cell.verify_merkle_hash() {
+                return Err(BocError::InvalidMerkleProof);
+            }
+            for reference in cell.references() {
+                if boc.cells[reference.cell_index].merkle_hash() != &reference.merkle_hash {
+                    return Err(BocError::InvalidMerkleProof);
+                }
+            }
+        }
+
+        if Self::proven_depth(proven, boc, &mut HashMap::new()) != usize::from(depth) {
+            return Err(BocError::InvalidMerkleProof);
+        }
+        Ok(proven)
+    }
+
+    /// Depth of the subtree at `cell_idx`, counting each pruned branch as
+    /// deep as the subtree it replaces.
+    fn proven_depth(cell_idx: usize, boc: &BOC, depths: &mut HashMap<usize, usize>) -> usize {
+        if let Some(&depth) = depths.get(&cell_idx) {
+            return depth;
+        }
+        let cell = &boc.cells[cell_idx];
+        let depth = match cell.committed_subtree() {
+            Some((_, depth)) if cell.cell_type() == CellType::PrunedBranch => usize::from(depth),
+            _ => cell
+                .references()
+                .iter()
+                .map(|reference| Self::proven_depth(reference.cell_index, boc, depths) + 1)
+                .max()
+                .unwrap_or(0),
+        };
+        depths.insert(cell_idx, depth);
+        depth
+    }
+
     fn verify_structure(&self, boc: &BOC) -> Result<(), BocError> {
         // Check cell count
         if boc.cells.len() > self.max_cell_count {
@@ -94,17 +153,19 @@ impl VerifyBOC {
 
         // verify cell type specific rules
         match cell.cell_type() {
-            CellType::MerkleProof => {
-                // Merkle proof cells must have valid merkle hash
-                if !cell.verify_merkle_hash()? {
-                    return Err(BocError::InvalidMerkleProof);
-                }
+            // Merkle proof cells must have valid merkle hash
+            CellType::MerkleProof if !cell.verify_merkle_hash() => {
+                return Err(BocError::InvalidMerkleProof);
             }
-            CellType::PrunedBranch => {
-                // Pruned branches cannot have references
-                if !cell.references().is_empty() {
-                    return Err(BocError::InvalidPrunedBranch);
-                }
+            // Pruned branches cannot have references
+            CellType::PrunedBranch if !cell.references().is_empty() => {
+                return Err(BocError::InvalidPrunedBranch);
+            }
+            // Pruned branches carry the hash of the subtree they replace
+            CellType::PrunedBranch
+                if cell.committed_subtree().map(|(hash, _)| hash) != Some(*cell.merkle_hash()) =>
+            {
+                return Err(BocError::InvalidPrunedBranch);
             }
             _ => {}
         }
@@ -170,24 +231,10 @@ impl VerifyBOC {
     }
 }
 
-#[derive(Debug)]
-pub enum BocError {
-    TooManyCells,
-    NoRoots,
-    TotalSizeTooLarge,
-    CellDataTooLarge,
-    TooManyReferences,
-    InvalidReference { from: usize, to: usize },
-    InvalidRoot(usize),
-    InvalidMerkleProof,
-    InvalidPrunedBranch,
-    CycleDetected,
-    MaxDepthExceeded,
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::state::boc::cell::CellReference;
 
     fn create_test_verifier() -> VerifyBOC {
         VerifyBOC::new(1000, 1_000_000, 256)
@@ -311,6 +358,27 @@ mod tests {
             Err(BocError::InvalidMerkleProof)
         ));
     }
+    #[test]
+    fn test_pruned_branch_must_carry_its_hash() {
+        let mut cell = Cell::pruned_branch([7u8; 32], 2);
+        let boc = BOC {
+            cells: vec![cell.clone()],
+            roots: vec![0],
+        };
+        let verifier = create_test_verifier();
+        assert!(verifier.verify_boc(&boc).is_ok());
+
+        cell.set_merkle_hash([8u8; 32]);
+        let boc = BOC {
+            cells: vec![cell],
+            roots: vec![0],
+        };
+        assert!(matches!(
+            verifier.verify_boc(&boc),
+            Err(BocError::InvalidPrunedBranch)
+        ));
+    }
+
     #[test]
     fn test_invalid_pruned_branch() {
         let mut cell = Cell::new(vec![1], CellType::PrunedBranch).unwrap();
@@ -1,5 +1,4 @@
 use crate::core::error::errors::{SystemError, SystemErrorType};
-use crate::core::hierarchy::intermediate::sparse_merkle_tree_i::MerkleNode;
 use crate::core::types::boc::BOC;
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
@@ -57,6 +56,15 @@ pub struct DistributionState {
     pub state_metrics: StateMetrics,
 }
 
+/// Node of the in-memory trees the state manager walks.
+#[derive(Debug, Clone, Default)]
+pub struct MerkleNode {
+    pub left: Option<Box<MerkleNode>>,
+    pub right: Option<Box<MerkleNode>>,
+    pub hash: Option<[u8; 32]>,
+    pub data: Option<Vec<u8>>,
+}
+
 pub struct StateManager {
     wallet_proofs: RwLock<HashMap<[u8; 32], Vec<[u8; 32]>>>,
     intermediate_trees: RwLock<HashMap<[u8; 32], MerkleNode>>,
@@ -2,6 +2,7 @@
 
 pub mod boc;
 pub mod ovp_ops;
+pub mod rebalance;
 //pub mod ovp_types;
 // Re-exporting the modules for external use
 pub use ovp_ops::*;
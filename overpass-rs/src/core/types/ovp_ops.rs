@@ -1,4 +1,101 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Current version of the namespaced opcode encoding. No legacy opcode uses
+/// this byte, so a versioned payload never reads as a legacy one.
+pub const OPCODE_WIRE_VERSION: u8 = 0xFF;
+/// Version byte reserved for payloads that carry a bare legacy opcode. Also
+/// unused by every legacy opcode.
+pub const LEGACY_OPCODE_WIRE_VERSION: u8 = 0x00;
+
+/// Operation family; the first byte of a namespaced opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum OpFamily {
+    Root = 0x01,
+    Intermediate = 0x02,
+    Wallet = 0x03,
+    Channel = 0x04,
+    Storage = 0x05,
+}
+
+impl OpFamily {
+    pub const ALL: [OpFamily; 5] = [
+        OpFamily::Root,
+        OpFamily::Intermediate,
+        OpFamily::Wallet,
+        OpFamily::Channel,
+        OpFamily::Storage,
+    ];
+}
+
+impl From<OpFamily> for u8 {
+    fn from(family: OpFamily) -> Self {
+        family as u8
+    }
+}
+
+impl TryFrom<u8> for OpFamily {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(Self::Root),
+            0x02 => Ok(Self::Intermediate),
+            0x03 => Ok(Self::Wallet),
+            0x04 => Ok(Self::Channel),
+            0x05 => Ok(Self::Storage),
+            _ => Err("Invalid operation family"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpCodeError {
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    UnsupportedVersion(u8),
+    UnknownFamily(u8),
+    UnknownOp {
+        family: OpFamily,
+        op: u8,
+    },
+    UnknownLegacyOp(u8),
+    /// A legacy byte is valid in several families and no family was given.
+    AmbiguousLegacyOp {
+        op: u8,
+        candidates: Vec<OpFamily>,
+    },
+}
+
+impl fmt::Display for OpCodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated { expected, actual } => write!(
+                f,
+                "Opcode truncated: expected {} bytes, got {}",
+                expected, actual
+            ),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "Unsupported opcode wire version {}", version)
+            }
+            Self::UnknownFamily(family) => write!(f, "Unknown operation family {:#04x}", family),
+            Self::UnknownOp { family, op } => {
+                write!(f, "Unknown {:?} operation code {:#04x}", family, op)
+            }
+            Self::UnknownLegacyOp(op) => write!(f, "Unknown legacy operation code {:#04x}", op),
+            Self::AmbiguousLegacyOp { op, candidates } => write!(
+                f,
+                "Legacy operation code {:#04x} is ambiguous between {:?}",
+                op, candidates
+            ),
+        }
+    }
+}
+
+impl std::error::Error for OpCodeError {}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OpCode {
@@ -10,6 +107,121 @@ pub enum OpCode {
 }
 
 impl OpCode {
+    /// Length of a versioned opcode: version, family, op.
+    pub const ENCODED_LEN: usize = 3;
+
+    pub fn family(&self) -> OpFamily {
+        match self {
+            OpCode::Root(_) => OpFamily::Root,
+            OpCode::Intermediate(_) => OpFamily::Intermediate,
+            OpCode::Wallet(_) => OpFamily::Wallet,
+            OpCode::Channel(_) => OpFamily::Channel,
+            OpCode::Storage(_) => OpFamily::Storage,
+        }
+    }
+
+    /// The namespaced `(family, op)` pair.
+    pub fn to_bytes(&self) -> [u8; 2] {
+        [u8::from(self.family()), self.to_u8()]
+    }
+
+    /// Decodes a namespaced `(family, op)` pair.
+    pub fn from_bytes(bytes: [u8; 2]) -> Result<OpCode, OpCodeError> {
+        let family =
+            OpFamily::try_from(bytes[0]).map_err(|_| OpCodeError::UnknownFamily(bytes[0]))?;
+        Self::from_family(family, bytes[1])
+    }
+
+    /// Decodes `op` within a known family.
+    pub fn from_family(family: OpFamily, op: u8) -> Result<OpCode, OpCodeError> {
+        let decoded = match family {
+            OpFamily::Root => RootOpCode::try_from(op).map(OpCode::Root),
+            OpFamily::Intermediate => IntermediateOpCode::try_from(op).map(OpCode::Intermediate),
+            OpFamily::Wallet => WalletOpCode::try_from(op).map(OpCode::Wallet),
+            OpFamily::Channel => ChannelOpCode::try_from(op).map(OpCode::Channel),
+            OpFamily::Storage => StorageOpCode::try_from(op).map(OpCode::Storage),
+        };
+        decoded.map_err(|_| OpCodeError::UnknownOp { family, op })
+    }
+
+    /// Versioned wire form: `[OPCODE_WIRE_VERSION, family, op]`.
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let [family, op] = self.to_bytes();
+        [OPCODE_WIRE_VERSION, family, op]
+    }
+
+    /// Decodes the opcode at the start of a versioned payload and returns it
+    /// with the number of bytes consumed.
+    pub fn decode(payload: &[u8]) -> Result<(OpCode, usize), OpCodeError> {
+        let version = *payload.first().ok_or(OpCodeError::Truncated {
+            expected: 1,
+            actual: 0,
+        })?;
+        match version {
+            OPCODE_WIRE_VERSION => {
+                if payload.len() < Self::ENCODED_LEN {
+                    return Err(OpCodeError::Truncated {
+                        expected: Self::ENCODED_LEN,
+                        actual: payload.len(),
+                    });
+                }
+                let op = Self::from_bytes([payload[1], payload[2]])?;
+                Ok((op, Self::ENCODED_LEN))
+            }
+            LEGACY_OPCODE_WIRE_VERSION => {
+                let op_byte = *payload.get(1).ok_or(OpCodeError::Truncated {
+                    expected: 2,
+                    actual: payload.len(),
+                })?;
+                Ok((Self::from_legacy(op_byte, None)?, 2))
+            }
+            other => Err(OpCodeError::UnsupportedVersion(other)),
+        }
+    }
+
+    /// Families in which a legacy single-byte opcode is defined.
+    pub fn legacy_candidates(op: u8) -> Vec<OpFamily> {
+        OpFamily::ALL
+            .into_iter()
+            .filter(|family| Self::from_family(*family, op).is_ok())
+            .collect()
+    }
+
+    /// Decodes a legacy single-byte opcode. Bytes that exist in more than one
+    /// family are only decoded when `family` says which one was meant.
+    pub fn from_legacy(op: u8, family: Option<OpFamily>) -> Result<OpCode, OpCodeError> {
+        if let Some(family) = family {
+            return Self::from_family(family, op);
+        }
+        let candidates = Self::legacy_candidates(op);
+        match candidates.as_slice() {
+            [] => Err(OpCodeError::UnknownLegacyOp(op)),
+            [family] => Self::from_family(*family, op),
+            _ => Err(OpCodeError::AmbiguousLegacyOp { op, candidates }),
+        }
+    }
+
+    /// Rewrites a payload that starts with a bare legacy opcode into the
+    /// versioned encoding, keeping the rest of the payload unchanged.
+    /// `family` is required when the legacy byte is ambiguous; the sender's
+    /// layer usually tells which family it used.
+    pub fn migrate_legacy_payload(
+        payload: &[u8],
+        family: Option<OpFamily>,
+    ) -> Result<Vec<u8>, OpCodeError> {
+        let (&op_byte, rest) = payload.split_first().ok_or(OpCodeError::Truncated {
+            expected: 1,
+            actual: 0,
+        })?;
+        let op = Self::from_legacy(op_byte, family)?;
+        let mut migrated = Vec::with_capacity(Self::ENCODED_LEN + rest.len());
+        migrated.extend_from_slice(&op.encode());
+        migrated.extend_from_slice(rest);
+        Ok(migrated)
+    }
+
+    /// The op byte within its family. On its own this is ambiguous; use
+    /// [`encode`](Self::encode) for anything sent over the wire.
     #[inline]
     pub fn to_u8(&self) -> u8 {
         match self {
@@ -21,6 +233,10 @@ impl OpCode {
         }
     }
 
+    /// Legacy single-byte decoding. Overlapping bytes resolve by match order,
+    /// so this can pick the wrong family; prefer [`decode`](Self::decode) or
+    /// [`from_legacy`](Self::from_legacy) with a family. Bytes shared by the
+    /// storage and wallet families decode as storage operations.
     pub fn from_u8(value: u8) -> Option<OpCode> {
         // Try each conversion in order, being careful about overlapping ranges
         if let Ok(op) = RootOpCode::try_from(value) {
@@ -29,12 +245,12 @@ impl OpCode {
         if let Ok(op) = IntermediateOpCode::try_from(value) {
             return Some(OpCode::Intermediate(op));
         }
-        if let Ok(op) = WalletOpCode::try_from(value) {
-            return Some(OpCode::Wallet(op));
-        }
         if let Ok(op) = StorageOpCode::try_from(value) {
             return Some(OpCode::Storage(op));
         }
+        if let Ok(op) = WalletOpCode::try_from(value) {
+            return Some(OpCode::Wallet(op));
+        }
         if let Ok(op) = ChannelOpCode::try_from(value) {
             return Some(OpCode::Channel(op));
         }
@@ -426,6 +642,42 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_from_u8_prefers_storage_over_wallet() {
+        let mut shared = 0;
+        for byte in 0..=u8::MAX {
+            let (Ok(storage), Ok(wallet)) = (
+                OpCode::from_family(OpFamily::Storage, byte),
+                OpCode::from_family(OpFamily::Wallet, byte),
+            ) else {
+                continue;
+            };
+            shared += 1;
+            assert_eq!(OpCode::from_u8(byte), Some(storage));
+            assert_eq!(
+                OpCode::from_legacy(byte, Some(OpFamily::Wallet)),
+                Ok(wallet)
+            );
+        }
+        assert!(shared > 0);
+    }
+
+    #[test]
+    fn test_overlapping_legacy_ops_need_family() {
+        assert!(matches!(
+            OpCode::from_legacy(0xC0, Some(OpFamily::Storage)),
+            Ok(OpCode::Storage(StorageOpCode::ChargeNode))
+        ));
+        assert!(matches!(
+            OpCode::decode(&OpCode::Storage(StorageOpCode::PropagateState).encode()),
+            Ok((OpCode::Storage(StorageOpCode::PropagateState), _))
+        ));
+        assert_eq!(
+            OpCode::legacy_candidates(0xC0),
+            vec![OpFamily::Wallet, OpFamily::Storage]
+        );
+    }
+
     #[test]
     fn test_opcode_to_u8() {
         let channel_op = ChannelOpCode::CreatePayment;
@@ -447,4 +699,121 @@ mod tests {
         );
         assert_eq!(OpCode::from_u8(0xFF), None);
     }
+
+    #[test]
+    fn test_namespaced_encoding_is_unambiguous() {
+        let colliding = [
+            OpCode::Root(RootOpCode::RegisterIntermediate),
+            OpCode::Intermediate(IntermediateOpCode::RequestChannelOpen),
+            OpCode::Wallet(WalletOpCode::UpdateBalance),
+            OpCode::Channel(ChannelOpCode::ValidatePayment),
+            OpCode::Wallet(WalletOpCode::CreateTransaction),
+            OpCode::Storage(StorageOpCode::ChargeNode),
+            OpCode::Wallet(WalletOpCode::VerifyWalletProof),
+            OpCode::Channel(ChannelOpCode::ProcessPayment),
+            OpCode::Storage(StorageOpCode::SyncState),
+            OpCode::Channel(ChannelOpCode::InitiateSettlement),
+            OpCode::Storage(StorageOpCode::ReplicateState),
+        ];
+        for op in colliding {
+            assert_eq!(OpCode::from_bytes(op.to_bytes()), Ok(op));
+            assert_eq!(OpCode::decode(&op.encode()), Ok((op, 3)));
+        }
+    }
+
+    #[test]
+    fn test_versioned_decoder_errors() {
+        assert_eq!(
+            OpCode::decode(&[]),
+            Err(OpCodeError::Truncated {
+                expected: 1,
+                actual: 0
+            })
+        );
+        assert_eq!(
+            OpCode::decode(&[OPCODE_WIRE_VERSION, 0x01]),
+            Err(OpCodeError::Truncated {
+                expected: 3,
+                actual: 2
+            })
+        );
+        assert_eq!(
+            OpCode::decode(&[9, 0x01, 0x01]),
+            Err(OpCodeError::UnsupportedVersion(9))
+        );
+        assert_eq!(
+            OpCode::decode(&[OPCODE_WIRE_VERSION, 0x09, 0x01]),
+            Err(OpCodeError::UnknownFamily(0x09))
+        );
+        assert_eq!(
+            OpCode::decode(&[OPCODE_WIRE_VERSION, 0x01, 0xA0]),
+            Err(OpCodeError::UnknownOp {
+                family: OpFamily::Root,
+                op: 0xA0
+            })
+        );
+    }
+
+    #[test]
+    fn test_legacy_migration() {
+        // Unambiguous legacy bytes migrate on their own.
+        let migrated = OpCode::migrate_legacy_payload(&[0xA0, 7, 8], None).unwrap();
+        assert_eq!(migrated, vec![OPCODE_WIRE_VERSION, 0x04, 0xA0, 7, 8]);
+        assert_eq!(
+            OpCode::decode(&migrated),
+            Ok((OpCode::Channel(ChannelOpCode::CreatePayment), 3))
+        );
+
+        // Ambiguous ones need the sender's family.
+        assert_eq!(
+            OpCode::migrate_legacy_payload(&[0x20], None),
+            Err(OpCodeError::AmbiguousLegacyOp {
+                op: 0x20,
+                candidates: vec![OpFamily::Root, OpFamily::Intermediate]
+            })
+        );
+        assert_eq!(
+            OpCode::from_legacy(0xE0, Some(OpFamily::Storage)),
+            Ok(OpCode::Storage(StorageOpCode::ReplicateState))
+        );
+        assert_eq!(
+            OpCode::decode(&[LEGACY_OPCODE_WIRE_VERSION, 0x01]),
+            Ok((OpCode::Root(RootOpCode::SubmitEpoch), 2))
+        );
+        assert!(OpCode::decode(&[LEGACY_OPCODE_WIRE_VERSION, 0xC0]).is_err());
+    }
+
+    #[test]
+    fn test_every_legacy_opcode_decodes() {
+        let mut seen = 0;
+        for family in OpFamily::ALL {
+            for byte in 0..=u8::MAX {
+                let Ok(op) = OpCode::from_family(family, byte) else {
+                    continue;
+                };
+                seen += 1;
+                assert_ne!(byte, OPCODE_WIRE_VERSION);
+                assert_ne!(byte, LEGACY_OPCODE_WIRE_VERSION);
+
+                let candidates = OpCode::legacy_candidates(byte);
+                assert!(candidates.contains(&family));
+                let decoded = OpCode::decode(&[LEGACY_OPCODE_WIRE_VERSION, byte]);
+                if candidates.len() == 1 {
+                    assert_eq!(decoded, Ok((op, 2)));
+                } else {
+                    assert_eq!(
+                        decoded,
+                        Err(OpCodeError::AmbiguousLegacyOp {
+                            op: byte,
+                            candidates
+                        })
+                    );
+                }
+
+                let migrated = OpCode::migrate_legacy_payload(&[byte], Some(family)).unwrap();
+                assert_eq!(OpCode::decode(&migrated), Ok((op, OpCode::ENCODED_LEN)));
+            }
+        }
+        assert!(seen > 0);
+    }
 }
@@ -648,14 +648,7 @@ pub struct WalletExtension {
     pub proof_system: Arc<ZkProofSystem>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct RebalanceConfig {
-    pub enabled: bool,
-    pub min_imbalance_threshold: u64,
-    pub max_rebalance_amount: u64,
-    pub target_ratios: HashMap<[u8; 32], f64>,
-    pub rebalance_interval: u64,
-}
+pub use crate::core::types::rebalance::RebalanceConfig;
 
 #[derive(Clone, Debug)]
 pub struct RebalanceOperation {
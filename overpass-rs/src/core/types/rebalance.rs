@@ -0,0 +1,37 @@
+// ./src/core/types/rebalance.rs
+
+use serde::{Deserialize, Serialize};
+
+/// Rebalancing policy for the channels of one wallet, enforced by the
+/// intermediate's rebalance engine.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RebalanceConfig {
+    /// Balance every channel of a wallet is steered towards.
+    pub target_balance: u64,
+    /// Channels within `target_balance ± allowed_deviation` are left alone.
+    pub allowed_deviation: u64,
+    /// Channels below this are topped up first and skip the rate limit.
+    pub emergency_threshold: u64,
+    /// Largest amount a single transfer may move.
+    pub max_rebalance_amount: u64,
+    /// Non-emergency transfers executed per wallet within `rate_limit_window`.
+    pub max_transfers_per_window: usize,
+    /// Seconds covered by the rate limit.
+    pub rate_limit_window: u64,
+    /// Queue planned transfers automatically when balances are checked.
+    pub auto_rebalance: bool,
+}
+
+impl Default for RebalanceConfig {
+    fn default() -> Self {
+        Self {
+            target_balance: 1_000,
+            allowed_deviation: 200,
+            emergency_threshold: 100,
+            max_rebalance_amount: 1_000,
+            max_transfers_per_window: 4,
+            rate_limit_window: 60,
+            auto_rebalance: true,
+        }
+    }
+}
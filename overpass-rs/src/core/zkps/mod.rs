@@ -7,3 +7,4 @@ pub mod plonky2;
 pub mod proof;
 pub mod zkp;
 pub mod zkp_interface;
+pub mod wallet_root_circuit;
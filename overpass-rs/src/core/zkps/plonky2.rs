@@ -16,6 +16,8 @@ use plonky2::{
 };
 use plonky2_field::types::Field;
 use std::rc::Rc;
+
+use super::wallet_root_circuit::{limbs, LIMBS};
 use wasm_bindgen::prelude::*;
 
 const D: usize = 2;
@@ -45,16 +47,7 @@ impl Default for Plonky2SystemHandle {
 impl Plonky2SystemHandle {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Result<Plonky2SystemHandle, JsValue> {
-        let circuit_config = CircuitConfig::standard_recursion_config();
-        let builder = CircuitBuilder::<F, D>::new(circuit_config.clone());
-        let state_transition_circuit = build_state_transition_circuit(builder)
-            .map_err(|e| JsValue::from_str(&e.to_string()))?;
-
-        let system = Plonky2System {
-            circuit_config,
-            state_transition_circuit,
-        };
-
+        let system = Plonky2System::new().map_err(|e| JsValue::from_str(&e.to_string()))?;
         Ok(Plonky2SystemHandle(Rc::new(system)))
     }
 
@@ -94,6 +87,17 @@ impl Default for Plonky2System {
     }
 }
 impl Plonky2System {
+    /// Builds the state transition circuit.
+    pub fn new() -> Result<Self, PlonkyError> {
+        let circuit_config = CircuitConfig::standard_recursion_config();
+        let builder = CircuitBuilder::<F, D>::new(circuit_config.clone());
+        let state_transition_circuit = build_state_transition_circuit(builder)?;
+        Ok(Self {
+            circuit_config,
+            state_transition_circuit,
+        })
+    }
+
     pub fn generate_proof(
         &self,
         old_balance: u64,
@@ -101,6 +105,28 @@ impl Plonky2System {
         new_balance: u64,
         new_nonce: u64,
         transfer_amount: u64,
+    ) -> Result<Vec<u8>, PlonkyError> {
+        self.generate_committed_proof(
+            old_balance,
+            old_nonce,
+            new_balance,
+            new_nonce,
+            transfer_amount,
+            &[0u8; 32],
+        )
+    }
+
+    /// Like [`Self::generate_proof`], but also exposes `commitment` as public
+    /// inputs so a verifier can tell which root or digest the proof was made
+    /// for.
+    pub fn generate_committed_proof(
+        &self,
+        old_balance: u64,
+        old_nonce: u64,
+        new_balance: u64,
+        new_nonce: u64,
+        transfer_amount: u64,
+        commitment: &[u8; 32],
     ) -> Result<Vec<u8>, PlonkyError> {
         let circuit_data = &self.state_transition_circuit.circuit_data;
         let mut pw = PartialWitness::new();
@@ -113,6 +139,7 @@ impl Plonky2System {
             new_balance,
             new_nonce,
             transfer_amount,
+            commitment,
         )?;
 
         let proof = circuit_data
@@ -123,17 +150,50 @@ impl Plonky2System {
     }
 
     pub fn verify_proof(&self, proof_bytes: &[u8]) -> Result<(), PlonkyError> {
-        let circuit_data = &self.state_transition_circuit.circuit_data;
-        let common_data = &circuit_data.common;
-
-        let proof = ProofWithPublicInputs::<F, C, D>::from_bytes(proof_bytes.to_vec(), common_data)
-            .map_err(|e| PlonkyError::InvalidInput(e.to_string()))?;
+        let proof = self.decode_proof(proof_bytes)?;
+        self.state_transition_circuit
+            .circuit_data
+            .verify(proof)
+            .map_err(|e| PlonkyError::InvalidInput(e.to_string()))
+    }
 
-        circuit_data
+    /// Verifies the proof and checks that its commitment public inputs encode
+    /// `commitment`, so a valid proof made for another root is rejected.
+    pub fn verify_committed_proof(
+        &self,
+        proof_bytes: &[u8],
+        commitment: &[u8; 32],
+    ) -> Result<(), PlonkyError> {
+        let proof = self.decode_proof(proof_bytes)?;
+        let committed = proof
+            .public_inputs
+            .get(COMMITMENT_OFFSET..COMMITMENT_OFFSET + LIMBS)
+            .ok_or_else(|| PlonkyError::InvalidInput("Proof carries no commitment".into()))?;
+        if committed != limbs(commitment) {
+            return Err(PlonkyError::InvalidInput(
+                "Proof commits to a different value".into(),
+            ));
+        }
+        self.state_transition_circuit
+            .circuit_data
             .verify(proof)
             .map_err(|e| PlonkyError::InvalidInput(e.to_string()))
     }
+
+    fn decode_proof(
+        &self,
+        proof_bytes: &[u8],
+    ) -> Result<ProofWithPublicInputs<F, C, D>, PlonkyError> {
+        let common_data = &self.state_transition_circuit.circuit_data.common;
+        ProofWithPublicInputs::<F, C, D>::from_bytes(proof_bytes.to_vec(), common_data)
+            .map_err(|e| PlonkyError::InvalidInput(e.to_string()))
+    }
 }
+
+/// Index of the first commitment limb among the state transition circuit's
+/// public inputs, after the five balance and nonce values.
+const COMMITMENT_OFFSET: usize = 5;
+
 // Remove the Default derive macro as it conflicts with the manual implementation
 pub struct StateTransitionCircuitData {
     pub circuit_data: CircuitData<F, C, D>,
@@ -142,6 +202,7 @@ pub struct StateTransitionCircuitData {
     pub new_balance_target: Target,
     pub new_nonce_target: Target,
     pub transfer_amount_target: Target,
+    pub commitment_targets: [Target; LIMBS],
 }
 
 impl Default for StateTransitionCircuitData {
@@ -158,6 +219,7 @@ impl Default for StateTransitionCircuitData {
             new_balance_target: Target::default(),
             new_nonce_target: Target::default(),
             transfer_amount_target: Target::default(),
+            commitment_targets: [Target::default(); LIMBS],
         }
     }
 }
@@ -169,6 +231,11 @@ fn build_state_transition_circuit(
     let new_balance_target = builder.add_virtual_public_input();
     let new_nonce_target = builder.add_virtual_public_input();
     let transfer_amount_target = builder.add_virtual_public_input();
+    let commitment_targets: [Target; LIMBS] =
+        std::array::from_fn(|_| builder.add_virtual_public_input());
+    for &limb in &commitment_targets {
+        builder.range_check(limb, 32);
+    }
 
     let one = builder.one();
     let old_nonce_plus_one = builder.add(old_nonce_target, one);
@@ -191,6 +258,7 @@ fn build_state_transition_circuit(
         new_balance_target,
         new_nonce_target,
         transfer_amount_target,
+        commitment_targets,
     })
 }
 
@@ -202,6 +270,7 @@ fn fill_state_transition_witness(
     new_balance: u64,
     new_nonce: u64,
     transfer_amount: u64,
+    commitment: &[u8; 32],
 ) -> Result<(), PlonkyError> {
     let old_balance_f = F::from_canonical_u64(old_balance);
     let old_nonce_f = F::from_canonical_u64(old_nonce);
@@ -219,6 +288,10 @@ fn fill_state_transition_witness(
         .map_err(|e| PlonkyError::ProofGenerationError(e.to_string()))?;
     pw.set_target(circuit.transfer_amount_target, transfer_amount_f)
         .map_err(|e| PlonkyError::ProofGenerationError(e.to_string()))?;
+    for (&target, limb) in circuit.commitment_targets.iter().zip(limbs(commitment)) {
+        pw.set_target(target, limb)
+            .map_err(|e| PlonkyError::ProofGenerationError(e.to_string()))?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,476 @@
+// ./src/core/zkps/wallet_root_circuit.rs
+
+use crate::core::hierarchy::merkle_bits::get_bit;
+use crate::core::zkps::plonky2::PlonkyError;
+use plonky2::{
+    field::goldilocks_field::GoldilocksField,
+    hash::{
+        hash_types::{HashOut, HashOutTarget},
+        poseidon::PoseidonHash,
+    },
+    iop::{
+        target::{BoolTarget, Target},
+        witness::{PartialWitness, WitnessWrite},
+    },
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        config::{Hasher, PoseidonGoldilocksConfig},
+        proof::ProofWithPublicInputs,
+    },
+};
+use plonky2_field::types::{Field, Field64, PrimeField64};
+
+const D: usize = 2;
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+
+/// One level per bit of a wallet id.
+pub const WALLET_TREE_DEPTH: usize = 256;
+/// A 32-byte id or root enters the circuit as eight little-endian u32 limbs.
+pub(crate) const LIMBS: usize = 8;
+/// Old root (4) ‖ new root (4) ‖ epoch (1) ‖ update commitment (4).
+pub const PUBLIC_INPUT_COUNT: usize = 13;
+
+pub type WalletTreeHash = HashOut<F>;
+
+/// One wallet root change in a batch. An all-zero root means the wallet is
+/// not in the tree, so a zero `old_root` inserts and a zero `new_root`
+/// removes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalletRootUpdate {
+    pub wallet_id: [u8; 32],
+    pub old_root: [u8; 32],
+    pub new_root: [u8; 32],
+    /// Siblings of the wallet's leaf from the leaf level up, taken from the
+    /// tree as it stands after the previous updates in the batch.
+    pub siblings: Vec<[u8; 32]>,
+}
+
+pub(crate) fn limbs(bytes: &[u8; 32]) -> [F; LIMBS] {
+    std::array::from_fn(|i| {
+        F::from_canonical_u32(u32::from_le_bytes(
+            bytes[i * 4..i * 4 + 4].try_into().unwrap(),
+        ))
+    })
+}
+
+pub fn hash_to_bytes(hash: &WalletTreeHash) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    for (chunk, element) in bytes.chunks_exact_mut(8).zip(hash.elements) {
+        chunk.copy_from_slice(&element.to_canonical_u64().to_le_bytes());
+    }
+    bytes
+}
+
+pub fn hash_from_bytes(bytes: &[u8; 32]) -> Result<WalletTreeHash, PlonkyError> {
+    let mut elements = [F::ZERO; 4];
+    for (element, chunk) in elements.iter_mut().zip(bytes.chunks_exact(8)) {
+        let value = u64::from_le_bytes(chunk.try_into().unwrap());
+        if value >= F::ORDER {
+            return Err(PlonkyError::InvalidInput(
+                "Hash element is not a canonical field element".to_string(),
+            ));
+        }
+        *element = F::from_canonical_u64(value);
+    }
+    Ok(HashOut { elements })
+}
+
+pub fn wallet_leaf_hash(wallet_id: &[u8; 32], wallet_root: &[u8; 32]) -> WalletTreeHash {
+    if *wallet_root == [0u8; 32] {
+        return HashOut::ZERO;
+    }
+    let mut inputs = limbs(wallet_id).to_vec();
+    inputs.extend(limbs(wallet_root));
+    PoseidonHash::hash_no_pad(&inputs)
+}
+
+pub fn wallet_node_hash(left: &WalletTreeHash, right: &WalletTreeHash) -> WalletTreeHash {
+    let mut inputs = left.elements.to_vec();
+    inputs.extend(right.elements);
+    PoseidonHash::hash_no_pad(&inputs)
+}
+
+/// Hashes of empty subtrees, indexed by height above the leaves.
+pub fn empty_subtree_hashes() -> Vec<WalletTreeHash> {
+    let mut hashes = vec![HashOut::ZERO];
+    for height in 0..WALLET_TREE_DEPTH {
+        hashes.push(wallet_node_hash(&hashes[height], &hashes[height]));
+    }
+    hashes
+}
+
+/// Hashes `leaf` up one level per sibling, where `siblings[i]` sits at
+/// height `i`. With a full path this is the tree root.
+pub fn path_root(
+    wallet_id: &[u8; 32],
+    leaf: WalletTreeHash,
+    siblings: &[WalletTreeHash],
+) -> WalletTreeHash {
+    siblings
+        .iter()
+        .enumerate()
+        .fold(leaf, |node, (height, sibling)| {
+            if get_bit(wallet_id, WALLET_TREE_DEPTH - 1 - height) {
+                wallet_node_hash(sibling, &node)
+            } else {
+                wallet_node_hash(&node, sibling)
+            }
+        })
+}
+
+/// Chains one update into the running commitment over a batch's updates,
+/// starting from [`HashOut::ZERO`].
+pub fn update_commitment(
+    accumulator: &WalletTreeHash,
+    wallet_id: &[u8; 32],
+    old_root: &[u8; 32],
+    new_root: &[u8; 32],
+) -> WalletTreeHash {
+    let mut inputs = accumulator.elements.to_vec();
+    inputs.extend(limbs(wallet_id));
+    inputs.extend(limbs(old_root));
+    inputs.extend(limbs(new_root));
+    PoseidonHash::hash_no_pad(&inputs)
+}
+
+/// Public inputs of a wallet root batch proof.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalletRootPublicInputs {
+    pub old_root: [u8; 32],
+    pub new_root: [u8; 32],
+    pub epoch: u64,
+    pub commitment: [u8; 32],
+}
+
+impl WalletRootPublicInputs {
+    pub fn from_slice(inputs: &[u64]) -> Result<Self, PlonkyError> {
+        if inputs.len() != PUBLIC_INPUT_COUNT {
+            return Err(PlonkyError::InvalidInput(format!(
+                "Expected {} public inputs, got {}",
+                PUBLIC_INPUT_COUNT,
+                inputs.len()
+            )));
+        }
+        let hash = |elements: &[u64]| {
+            let mut bytes = [0u8; 32];
+            for (chunk, element) in bytes.chunks_exact_mut(8).zip(elements) {
+                chunk.copy_from_slice(&element.to_le_bytes());
+            }
+            bytes
+        };
+        Ok(Self {
+            old_root: hash(&inputs[0..4]),
+            new_root: hash(&inputs[4..8]),
+            epoch: inputs[8],
+            commitment: hash(&inputs[9..13]),
+        })
+    }
+}
+
+struct UpdateSlotTargets {
+    active: BoolTarget,
+    wallet_id: [Target; LIMBS],
+    old_root: [Target; LIMBS],
+    new_root: [Target; LIMBS],
+    siblings: Vec<HashOutTarget>,
+}
+
+/// Circuit proving that up to `max_updates` wallet root updates, applied in
+/// order, move the intermediate tree from `old_root` to `new_root`.
+///
+/// Each active slot recomputes the tree root along the wallet's path twice,
+/// once with the old leaf (which must give the current root) and once with
+/// the new leaf (which becomes the current root). Unused slots leave the
+/// root and the update commitment untouched.
+pub struct WalletRootBatchCircuit {
+    circuit_data: CircuitData<F, C, D>,
+    old_root: HashOutTarget,
+    epoch: Target,
+    slots: Vec<UpdateSlotTargets>,
+}
+
+fn witness_error(e: impl std::fmt::Display) -> PlonkyError {
+    PlonkyError::ProofGenerationError(e.to_string())
+}
+
+fn select_hash(
+    builder: &mut CircuitBuilder<F, D>,
+    condition: BoolTarget,
+    if_true: HashOutTarget,
+    if_false: HashOutTarget,
+) -> HashOutTarget {
+    HashOutTarget {
+        elements: std::array::from_fn(|i| {
+            builder.select(condition, if_true.elements[i], if_false.elements[i])
+        }),
+    }
+}
+
+fn leaf_target(
+    builder: &mut CircuitBuilder<F, D>,
+    wallet_id: &[Target; LIMBS],
+    wallet_root: &[Target; LIMBS],
+    empty: HashOutTarget,
+) -> HashOutTarget {
+    // Limbs are range checked to 32 bits, so their sum cannot wrap.
+    let sum = builder.add_many(wallet_root.iter().copied());
+    let zero = builder.zero();
+    let is_empty = builder.is_equal(sum, zero);
+    let mut inputs = wallet_id.to_vec();
+    inputs.extend(wallet_root);
+    let hash = builder.hash_n_to_hash_no_pad::<PoseidonHash>(inputs);
+    select_hash(builder, is_empty, empty, hash)
+}
+
+impl WalletRootBatchCircuit {
+    pub fn new(max_updates: usize) -> Self {
+        let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_config());
+        let old_root = builder.add_virtual_hash();
+        let epoch = builder.add_virtual_target();
+        let zero = builder.zero();
+        let empty = HashOutTarget {
+            elements: [zero; 4],
+        };
+
+        let mut root = old_root;
+        let mut commitment = empty;
+        let mut slots = Vec::with_capacity(max_updates);
+        for _ in 0..max_updates {
+            let active = builder.add_virtual_bool_target_safe();
+            let wallet_id: [Target; LIMBS] = std::array::from_fn(|_| builder.add_virtual_target());
+            let old_wallet_root: [Target; LIMBS] =
+                std::array::from_fn(|_| builder.add_virtual_target());
+            let new_wallet_root: [Target; LIMBS] =
+                std::array::from_fn(|_| builder.add_virtual_target());
+            let siblings: Vec<HashOutTarget> = (0..WALLET_TREE_DEPTH)
+                .map(|_| builder.add_virtual_hash())
+                .collect();
+
+            let limb_bits: Vec<Vec<BoolTarget>> = wallet_id
+                .iter()
+                .map(|limb| builder.split_le(*limb, 32))
+                .collect();
+            for limb in old_wallet_root.iter().chain(&new_wallet_root) {
+                builder.range_check(*limb, 32);
+            }
+
+            let mut old_node = leaf_target(&mut builder, &wallet_id, &old_wallet_root, empty);
+            let mut new_node = leaf_target(&mut builder, &wallet_id, &new_wallet_root, empty);
+            for (height, sibling) in siblings.iter().enumerate() {
+                // Bit `depth` of the id is bit `7 - depth % 8` of byte
+                // `depth / 8`, which sits in limb `depth / 32`.
+                let depth = WALLET_TREE_DEPTH - 1 - height;
+                let byte = depth / 8;
+                let goes_right = limb_bits[byte / 4][8 * (byte % 4) + 7 - depth % 8];
+                for node in [&mut old_node, &mut new_node] {
+                    let left = select_hash(&mut builder, goes_right, *sibling, *node);
+                    let right = select_hash(&mut builder, goes_right, *node, *sibling);
+                    let mut inputs = left.elements.to_vec();
+                    inputs.extend(right.elements);
+                    *node = builder.hash_n_to_hash_no_pad::<PoseidonHash>(inputs);
+                }
+            }
+
+            let expected_root = select_hash(&mut builder, active, old_node, root);
+            builder.connect_hashes(expected_root, root);
+            root = select_hash(&mut builder, active, new_node, root);
+
+            let mut inputs = commitment.elements.to_vec();
+            inputs.extend(wallet_id);
+            inputs.extend(old_wallet_root);
+            inputs.extend(new_wallet_root);
+            let chained = builder.hash_n_to_hash_no_pad::<PoseidonHash>(inputs);
+            commitment = select_hash(&mut builder, active, chained, commitment);
+
+            slots.push(UpdateSlotTargets {
+                active,
+                wallet_id,
+                old_root: old_wallet_root,
+                new_root: new_wallet_root,
+                siblings,
+            });
+        }
+
+        builder.register_public_inputs(&old_root.elements);
+        builder.register_public_inputs(&root.elements);
+        builder.register_public_input(epoch);
+        builder.register_public_inputs(&commitment.elements);
+
+        Self {
+            circuit_data: builder.build::<C>(),
+            old_root,
+            epoch,
+            slots,
+        }
+    }
+
+    pub fn max_updates(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Proves `updates` against `old_root`, returning the serialized proof
+    /// and its public inputs.
+    pub fn prove(
+        &self,
+        old_root: &[u8; 32],
+        epoch: u64,
+        updates: &[WalletRootUpdate],
+    ) -> Result<(Vec<u8>, Vec<u64>), PlonkyError> {
+        if updates.len() > self.slots.len() {
+            return Err(PlonkyError::InvalidInput(format!(
+                "Batch of {} updates exceeds circuit capacity of {}",
+                updates.len(),
+                self.slots.len()
+            )));
+        }
+        if epoch >= F::ORDER {
+            return Err(PlonkyError::InvalidInput(
+                "Epoch is not a canonical field element".to_string(),
+            ));
+        }
+
+        let mut pw = PartialWitness::new();
+        pw.set_hash_target(self.old_root, hash_from_bytes(old_root)?)
+            .map_err(witness_error)?;
+        pw.set_target(self.epoch, F::from_canonical_u64(epoch))
+            .map_err(witness_error)?;
+
+        let padding = WalletRootUpdate {
+            wallet_id: [0; 32],
+            old_root: [0; 32],
+            new_root: [0; 32],
+            siblings: vec![[0; 32]; WALLET_TREE_DEPTH],
+        };
+        for (i, slot) in self.slots.iter().enumerate() {
+            let (update, active) = match updates.get(i) {
+                Some(update) => (update, true),
+                None => (&padding, false),
+            };
+            if update.siblings.len() != WALLET_TREE_DEPTH {
+                return Err(PlonkyError::InvalidInput(format!(
+                    "Update path has {} siblings, expected {}",
+                    update.siblings.len(),
+                    WALLET_TREE_DEPTH
+                )));
+            }
+            pw.set_bool_target(slot.active, active)
+                .map_err(witness_error)?;
+            for (targets, bytes) in [
+                (&slot.wallet_id, &update.wallet_id),
+                (&slot.old_root, &update.old_root),
+                (&slot.new_root, &update.new_root),
+            ] {
+                for (target, value) in targets.iter().zip(limbs(bytes)) {
+                    pw.set_target(*target, value).map_err(witness_error)?;
+                }
+            }
+            for (target, sibling) in slot.siblings.iter().zip(&update.siblings) {
+                pw.set_hash_target(*target, hash_from_bytes(sibling)?)
+                    .map_err(witness_error)?;
+            }
+        }
+
+        let proof = self
+            .circuit_data
+            .prove(pw)
+            .map_err(|e| PlonkyError::ProofGenerationError(e.to_string()))?;
+        let public_inputs = proof
+            .public_inputs
+            .iter()
+            .map(|input| input.to_canonical_u64())
+            .collect();
+        Ok((proof.to_bytes(), public_inputs))
+    }
+
+    /// Verifies a proof from [`prove`](Self::prove) and returns the public
+    /// inputs it attests to.
+    pub fn verify(&self, proof_bytes: &[u8]) -> Result<WalletRootPublicInputs, PlonkyError> {
+        let proof = ProofWithPublicInputs::<F, C, D>::from_bytes(
+            proof_bytes.to_vec(),
+            &self.circuit_data.common,
+        )
+        .map_err(|e| PlonkyError::InvalidInput(e.to_string()))?;
+        let public_inputs: Vec<u64> = proof
+            .public_inputs
+            .iter()
+            .map(|input| input.to_canonical_u64())
+            .collect();
+        self.circuit_data
+            .verify(proof)
+            .map_err(|e| PlonkyError::InvalidInput(e.to_string()))?;
+        WalletRootPublicInputs::from_slice(&public_inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(
+        wallet_id: [u8; 32],
+        root: [u8; 32],
+        siblings: &[WalletTreeHash],
+    ) -> WalletRootUpdate {
+        WalletRootUpdate {
+            wallet_id,
+            old_root: [0; 32],
+            new_root: root,
+            siblings: siblings.iter().map(hash_to_bytes).collect(),
+        }
+    }
+
+    #[test]
+    fn test_batch_proof_moves_tree_to_new_root() {
+        let empty = empty_subtree_hashes();
+        let old_root = empty[WALLET_TREE_DEPTH];
+        let (alice, bob) = ([0x01; 32], [0x81; 32]);
+
+        // Alice and Bob sit in opposite halves of the tree, so after Alice's
+        // insert Bob's top sibling is Alice's half.
+        let first = insert(alice, [7; 32], &empty[..WALLET_TREE_DEPTH]);
+        let alice_leaf = wallet_leaf_hash(&alice, &[7; 32]);
+        let alice_half = path_root(&alice, alice_leaf, &empty[..WALLET_TREE_DEPTH - 1]);
+        let mut bob_siblings = empty[..WALLET_TREE_DEPTH - 1].to_vec();
+        bob_siblings.push(alice_half);
+        let second = insert(bob, [9; 32], &bob_siblings);
+        let expected = path_root(&bob, wallet_leaf_hash(&bob, &[9; 32]), &bob_siblings);
+
+        let circuit = WalletRootBatchCircuit::new(3);
+        let updates = [first, second];
+        let (proof, public_inputs) = circuit
+            .prove(&hash_to_bytes(&old_root), 4, &updates)
+            .unwrap();
+        let attested = circuit.verify(&proof).unwrap();
+
+        assert_eq!(
+            attested,
+            WalletRootPublicInputs::from_slice(&public_inputs).unwrap()
+        );
+        assert_eq!(attested.old_root, hash_to_bytes(&old_root));
+        assert_eq!(attested.new_root, hash_to_bytes(&expected));
+        assert_eq!(attested.epoch, 4);
+        let commitment = updates.iter().fold(HashOut::ZERO, |acc, update| {
+            update_commitment(&acc, &update.wallet_id, &update.old_root, &update.new_root)
+        });
+        assert_eq!(attested.commitment, hash_to_bytes(&commitment));
+    }
+
+    #[test]
+    fn test_stale_path_cannot_be_proven() {
+        let empty = empty_subtree_hashes();
+        let old_root = hash_to_bytes(&empty[WALLET_TREE_DEPTH]);
+        // Both updates use the empty tree's path, but the second runs against
+        // the root left by the first.
+        let updates = [
+            insert([0x01; 32], [7; 32], &empty[..WALLET_TREE_DEPTH]),
+            insert([0x81; 32], [9; 32], &empty[..WALLET_TREE_DEPTH]),
+        ];
+
+        let circuit = WalletRootBatchCircuit::new(2);
+        assert!(circuit.prove(&old_root, 0, &updates).is_err());
+        assert!(circuit.prove(&old_root, 0, &updates[..1]).is_ok());
+        assert!(circuit.prove(&[0x01; 32], 0, &updates[..1]).is_err());
+    }
+}
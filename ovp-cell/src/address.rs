@@ -0,0 +1,18 @@
+// ./src/address.rs
+
+/// A standard internal address (`addr_std` without anycast): a workchain
+/// and a 256-bit account id. Stored as 267 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Address {
+    pub workchain: i8,
+    pub hash: [u8; 32],
+}
+
+impl Address {
+    /// Bits taken by an `addr_std` with no anycast.
+    pub const BITS: usize = 2 + 1 + 8 + 256;
+
+    pub fn new(workchain: i8, hash: [u8; 32]) -> Self {
+        Self { workchain, hash }
+    }
+}
@@ -0,0 +1,259 @@
+// ./src/builder.rs
+
+use crate::address::Address;
+use crate::cell::{Cell, MAX_CELL_BITS, MAX_CELL_REFS};
+use crate::error::CellError;
+use std::sync::Arc;
+
+/// Writes fields into a cell bit by bit, most significant bit first, the
+/// way the TON VM lays them out.
+///
+/// Every store checks capacity and range before writing, so a failed
+/// store leaves the builder unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct CellBuilder {
+    data: Vec<u8>,
+    bit_len: usize,
+    references: Vec<Arc<Cell>>,
+}
+
+impl CellBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bits_used(&self) -> usize {
+        self.bit_len
+    }
+
+    pub fn bits_left(&self) -> usize {
+        MAX_CELL_BITS - self.bit_len
+    }
+
+    pub fn refs_left(&self) -> usize {
+        MAX_CELL_REFS - self.references.len()
+    }
+
+    pub fn store_bit(&mut self, bit: bool) -> Result<&mut Self, CellError> {
+        self.store_uint(bit as u128, 1)
+    }
+
+    /// Stores `value` as an unsigned integer of `n_bits` bits.
+    pub fn store_uint(&mut self, value: u128, n_bits: usize) -> Result<&mut Self, CellError> {
+        if n_bits > 128 {
+            return Err(CellError::InvalidWidth(n_bits));
+        }
+        if n_bits < 128 && value >> n_bits != 0 {
+            return Err(CellError::ValueTooLarge { n_bits });
+        }
+        self.ensure_bits(n_bits)?;
+        self.push_bits(value, n_bits);
+        Ok(self)
+    }
+
+    /// Stores `value` as a two's complement integer of `n_bits` bits.
+    pub fn store_int(&mut self, value: i128, n_bits: usize) -> Result<&mut Self, CellError> {
+        if n_bits == 0 || n_bits > 128 {
+            return Err(CellError::InvalidWidth(n_bits));
+        }
+        if n_bits < 128 {
+            let bound = 1i128 << (n_bits - 1);
+            if value < -bound || value >= bound {
+                return Err(CellError::ValueTooLarge { n_bits });
+            }
+        }
+        self.ensure_bits(n_bits)?;
+        let mask = if n_bits == 128 {
+            u128::MAX
+        } else {
+            (1u128 << n_bits) - 1
+        };
+        self.push_bits(value as u128 & mask, n_bits);
+        Ok(self)
+    }
+
+    pub fn store_bytes(&mut self, bytes: &[u8]) -> Result<&mut Self, CellError> {
+        self.ensure_bits(bytes.len() * 8)?;
+        for &byte in bytes {
+            self.push_bits(byte as u128, 8);
+        }
+        Ok(self)
+    }
+
+    /// Stores an amount as `VarUInteger 16`: a 4-bit byte length followed
+    /// by that many bytes, so amounts below 2^120.
+    pub fn store_coins(&mut self, amount: u128) -> Result<&mut Self, CellError> {
+        let byte_len = (128 - amount.leading_zeros() as usize).div_ceil(8);
+        if byte_len > 15 {
+            return Err(CellError::ValueTooLarge { n_bits: 120 });
+        }
+        self.ensure_bits(4 + byte_len * 8)?;
+        self.push_bits(byte_len as u128, 4);
+        self.push_bits(amount, byte_len * 8);
+        Ok(self)
+    }
+
+    /// Stores `address` as `addr_std$10 anycast:nothing workchain:int8
+    /// address:bits256`.
+    pub fn store_address(&mut self, address: &Address) -> Result<&mut Self, CellError> {
+        self.ensure_bits(Address::BITS)?;
+        self.push_bits(0b100, 3);
+        self.push_bits(address.workchain as u8 as u128, 8);
+        for &byte in &address.hash {
+            self.push_bits(byte as u128, 8);
+        }
+        Ok(self)
+    }
+
+    pub fn store_ref(&mut self, cell: impl Into<Arc<Cell>>) -> Result<&mut Self, CellError> {
+        if self.references.len() >= MAX_CELL_REFS {
+            return Err(CellError::RefsOverflow);
+        }
+        self.references.push(cell.into());
+        Ok(self)
+    }
+
+    /// Stores `bytes` of any length in a tree of cells hanging off one
+    /// reference. Each cell holds up to [`BLOB_CHUNK_BYTES`] bytes followed
+    /// by the cells holding the rest, in order, split evenly between its
+    /// children so long blobs stay shallow.
+    pub fn store_blob(&mut self, bytes: &[u8]) -> Result<&mut Self, CellError> {
+        let chunks: Vec<&[u8]> = bytes.chunks(BLOB_CHUNK_BYTES).collect();
+        self.store_ref(blob_tree(&chunks))
+    }
+
+    pub fn build(&self) -> Cell {
+        Cell::new(self.data.clone(), self.bit_len, self.references.clone())
+            .expect("the builder never exceeds cell limits")
+    }
+
+    fn ensure_bits(&self, n_bits: usize) -> Result<(), CellError> {
+        if n_bits > self.bits_left() {
+            return Err(CellError::BitsOverflow {
+                needed: n_bits,
+                available: self.bits_left(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Appends the low `n_bits` bits of `value`. Capacity must have been
+    /// checked.
+    fn push_bits(&mut self, value: u128, n_bits: usize) {
+        for i in (0..n_bits).rev() {
+            if self.bit_len % 8 == 0 {
+                self.data.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                let last = self.data.len() - 1;
+                self.data[last] |= 0x80 >> (self.bit_len % 8);
+            }
+            self.bit_len += 1;
+        }
+    }
+}
+
+/// Bytes held by each cell of a blob stored with
+/// [`CellBuilder::store_blob`].
+pub const BLOB_CHUNK_BYTES: usize = MAX_CELL_BITS / 8;
+
+/// Builds a cell holding the first of `chunks`, with the rest split evenly
+/// between up to four children.
+fn blob_tree(chunks: &[&[u8]]) -> Cell {
+    let (first, rest) = match chunks.split_first() {
+        Some((first, rest)) => (*first, rest),
+        None => (&[][..], &[][..]),
+    };
+    let references = match rest.len().div_ceil(MAX_CELL_REFS) {
+        0 => Vec::new(),
+        per_child => rest
+            .chunks(per_child)
+            .map(|group| Arc::new(blob_tree(group)))
+            .collect(),
+    };
+    Cell::new(first.to_vec(), first.len() * 8, references).expect("blob cells stay within limits")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields_are_packed_msb_first() {
+        let mut builder = CellBuilder::new();
+        builder
+            .store_uint(0b101, 3)
+            .unwrap()
+            .store_uint(0xff, 8)
+            .unwrap()
+            .store_bit(true)
+            .unwrap();
+        let cell = builder.build();
+        assert_eq!(cell.bit_len(), 12);
+        assert_eq!(cell.data(), &[0b1011_1111, 0b1111_0000]);
+    }
+
+    #[test]
+    fn test_out_of_range_values_rejected() {
+        let mut builder = CellBuilder::new();
+        assert_eq!(
+            builder.store_uint(256, 8).unwrap_err(),
+            CellError::ValueTooLarge { n_bits: 8 }
+        );
+        assert!(builder.store_int(-129, 8).is_err());
+        assert!(builder.store_int(-128, 8).is_ok());
+        assert!(builder.store_coins(1u128 << 120).is_err());
+        assert!(builder.store_uint(0, 129).is_err());
+        assert_eq!(builder.bits_used(), 8);
+    }
+
+    #[test]
+    fn test_blobs_round_trip_and_stay_shallow() {
+        fn depth(cell: &Cell) -> usize {
+            cell.references()
+                .iter()
+                .map(|child| depth(child) + 1)
+                .max()
+                .unwrap_or(0)
+        }
+
+        for len in [0, 1, BLOB_CHUNK_BYTES, BLOB_CHUNK_BYTES + 1, 100_000] {
+            let bytes: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let mut builder = CellBuilder::new();
+            builder
+                .store_uint(7, 3)
+                .unwrap()
+                .store_blob(&bytes)
+                .unwrap();
+            let cell = builder.build();
+
+            let mut slice = cell.parse();
+            assert_eq!(slice.load_uint(3).unwrap(), 7);
+            assert_eq!(slice.load_blob().unwrap(), bytes);
+            slice.end_parse().unwrap();
+            assert!(depth(&cell) <= 6);
+        }
+    }
+
+    #[test]
+    fn test_capacity_enforced() {
+        let mut builder = CellBuilder::new();
+        builder.store_bytes(&[0; 127]).unwrap();
+        builder.store_uint(0, 7).unwrap();
+        assert_eq!(
+            builder.store_bit(false).unwrap_err(),
+            CellError::BitsOverflow {
+                needed: 1,
+                available: 0
+            }
+        );
+
+        for _ in 0..MAX_CELL_REFS {
+            builder.store_ref(Cell::default()).unwrap();
+        }
+        assert_eq!(
+            builder.store_ref(Cell::default()).unwrap_err(),
+            CellError::RefsOverflow
+        );
+    }
+}
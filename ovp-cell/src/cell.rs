@@ -0,0 +1,138 @@
+// ./src/cell.rs
+
+use crate::error::CellError;
+use crate::slice::CellSlice;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+pub const MAX_CELL_BITS: usize = 1023;
+pub const MAX_CELL_REFS: usize = 4;
+
+/// An ordinary cell: up to 1023 data bits and up to four child cells.
+/// Children are shared, so the same subtree can hang off several parents.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Cell {
+    data: Vec<u8>,
+    bit_len: usize,
+    references: Vec<Arc<Cell>>,
+}
+
+impl Cell {
+    /// Creates a cell holding the first `bit_len` bits of `data`.
+    pub fn new(
+        mut data: Vec<u8>,
+        bit_len: usize,
+        references: Vec<Arc<Cell>>,
+    ) -> Result<Self, CellError> {
+        if bit_len > MAX_CELL_BITS {
+            return Err(CellError::BitsOverflow {
+                needed: bit_len,
+                available: MAX_CELL_BITS,
+            });
+        }
+        if references.len() > MAX_CELL_REFS {
+            return Err(CellError::RefsOverflow);
+        }
+        if data.len() < bit_len.div_ceil(8) {
+            return Err(CellError::BitsUnderflow {
+                needed: bit_len,
+                available: data.len() * 8,
+            });
+        }
+        data.truncate(bit_len.div_ceil(8));
+        // Bits past `bit_len` are not part of the cell.
+        if bit_len % 8 != 0 {
+            let last = data.len() - 1;
+            data[last] &= 0xff << (8 - bit_len % 8);
+        }
+        Ok(Self {
+            data,
+            bit_len,
+            references,
+        })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn bit_len(&self) -> usize {
+        self.bit_len
+    }
+
+    pub fn references(&self) -> &[Arc<Cell>] {
+        &self.references
+    }
+
+    /// Starts reading the cell from its first bit and first reference.
+    pub fn parse(&self) -> CellSlice<'_> {
+        CellSlice::new(self)
+    }
+
+    /// The representation hash TON gives this cell: SHA-256 of its two
+    /// descriptor bytes and padded data, followed by the depth and then the
+    /// hash of each child.
+    pub fn hash(&self) -> [u8; 32] {
+        self.depth_and_hash().1
+    }
+
+    /// Length of the longest path from this cell down to a leaf.
+    pub fn depth(&self) -> u16 {
+        self.depth_and_hash().0
+    }
+
+    fn depth_and_hash(&self) -> (u16, [u8; 32]) {
+        let children: Vec<_> = self
+            .references
+            .iter()
+            .map(|child| child.depth_and_hash())
+            .collect();
+
+        let mut hasher = Sha256::new();
+        let full_bytes = self.bit_len / 8;
+        hasher.update([
+            self.references.len() as u8,
+            (full_bytes + self.bit_len.div_ceil(8)) as u8,
+        ]);
+        hasher.update(&self.data[..full_bytes]);
+        if self.bit_len % 8 != 0 {
+            // The bit after the data is set to mark where it ends.
+            hasher.update([self.data[full_bytes] | 0x80 >> (self.bit_len % 8)]);
+        }
+        for (depth, _) in &children {
+            hasher.update(depth.to_be_bytes());
+        }
+        for (_, hash) in &children {
+            hasher.update(hash);
+        }
+
+        let depth = children
+            .iter()
+            .map(|(depth, _)| depth + 1)
+            .max()
+            .unwrap_or(0);
+        (depth, hasher.finalize().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_matches_ton() {
+        // The hash TON gives the empty cell.
+        const EMPTY_CELL_HASH: [u8; 32] = [
+            0x96, 0xa2, 0x96, 0xd2, 0x24, 0xf2, 0x85, 0xc6, 0x7b, 0xee, 0x93, 0xc3, 0x0f, 0x8a,
+            0x30, 0x91, 0x57, 0xf0, 0xda, 0xa3, 0x5d, 0xc5, 0xb8, 0x7e, 0x41, 0x0b, 0x78, 0x63,
+            0x0a, 0x09, 0xcf, 0xc7,
+        ];
+        assert_eq!(Cell::default().hash(), EMPTY_CELL_HASH);
+
+        let leaf = Arc::new(Cell::new(vec![0b1010_0000], 3, vec![]).unwrap());
+        let parent = Cell::new(vec![], 0, vec![leaf.clone(), leaf.clone()]).unwrap();
+        assert_eq!(parent.depth(), 1);
+        assert_ne!(parent.hash(), leaf.hash());
+        assert_ne!(leaf.hash(), Cell::default().hash());
+    }
+}
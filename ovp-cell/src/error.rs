@@ -0,0 +1,70 @@
+// ./src/error.rs
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CellError {
+    /// A store would take the cell past its bit capacity.
+    BitsOverflow {
+        needed: usize,
+        available: usize,
+    },
+    /// A fifth reference was stored.
+    RefsOverflow,
+    /// A load asked for more bits than the slice has left.
+    BitsUnderflow {
+        needed: usize,
+        available: usize,
+    },
+    /// A load asked for a reference the slice does not have.
+    RefsUnderflow,
+    /// The value needs more bits than the field was given.
+    ValueTooLarge {
+        n_bits: usize,
+    },
+    /// Integer fields are at most 128 bits wide.
+    InvalidWidth(usize),
+    InvalidAddress(String),
+    /// The slice still holds data after the last expected field.
+    TrailingData {
+        bits: usize,
+        refs: usize,
+    },
+    /// A cell of a blob holds a number of bits that is not a whole number
+    /// of bytes.
+    PartialByte(usize),
+}
+
+impl fmt::Display for CellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CellError::BitsOverflow { needed, available } => write!(
+                f,
+                "Cell overflow: {} bits needed, {} available",
+                needed, available
+            ),
+            CellError::RefsOverflow => write!(f, "Cell overflow: too many references"),
+            CellError::BitsUnderflow { needed, available } => write!(
+                f,
+                "Cell underflow: {} bits needed, {} available",
+                needed, available
+            ),
+            CellError::RefsUnderflow => write!(f, "Cell underflow: no reference left"),
+            CellError::ValueTooLarge { n_bits } => {
+                write!(f, "Value does not fit in {} bits", n_bits)
+            }
+            CellError::InvalidWidth(n_bits) => write!(f, "Invalid field width {}", n_bits),
+            CellError::InvalidAddress(reason) => write!(f, "Invalid address: {}", reason),
+            CellError::TrailingData { bits, refs } => write!(
+                f,
+                "Unread data left in cell: {} bits, {} references",
+                bits, refs
+            ),
+            CellError::PartialByte(bits) => {
+                write!(f, "Blob cell holds {} bits, not whole bytes", bits)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CellError {}
@@ -0,0 +1,17 @@
+// ./src/lib.rs
+
+//! Cell builder and slice reader shared by the OVP node (`overpass-rs`)
+//! and client (`ovp-client`), so both sides write and read cell fields
+//! with the same bit layout instead of hand-counted byte offsets.
+
+pub mod address;
+pub mod builder;
+pub mod cell;
+pub mod error;
+pub mod slice;
+
+pub use address::Address;
+pub use builder::{CellBuilder, BLOB_CHUNK_BYTES};
+pub use cell::{Cell, MAX_CELL_BITS, MAX_CELL_REFS};
+pub use error::CellError;
+pub use slice::CellSlice;
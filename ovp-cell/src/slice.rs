@@ -0,0 +1,237 @@
+// ./src/slice.rs
+
+use crate::address::Address;
+use crate::cell::Cell;
+use crate::error::CellError;
+
+/// Reads the fields of a [`Cell`] in the order a [`CellBuilder`] stored
+/// them.
+///
+/// Loads check the remaining bits and references first, so a failed load
+/// leaves the slice where it was.
+///
+/// [`CellBuilder`]: crate::CellBuilder
+#[derive(Debug, Clone)]
+pub struct CellSlice<'a> {
+    cell: &'a Cell,
+    bit_pos: usize,
+    ref_pos: usize,
+}
+
+impl<'a> CellSlice<'a> {
+    pub fn new(cell: &'a Cell) -> Self {
+        Self {
+            cell,
+            bit_pos: 0,
+            ref_pos: 0,
+        }
+    }
+
+    pub fn remaining_bits(&self) -> usize {
+        self.cell.bit_len() - self.bit_pos
+    }
+
+    pub fn remaining_refs(&self) -> usize {
+        self.cell.references().len() - self.ref_pos
+    }
+
+    pub fn load_bit(&mut self) -> Result<bool, CellError> {
+        Ok(self.load_uint(1)? == 1)
+    }
+
+    pub fn load_uint(&mut self, n_bits: usize) -> Result<u128, CellError> {
+        if n_bits > 128 {
+            return Err(CellError::InvalidWidth(n_bits));
+        }
+        self.ensure_bits(n_bits)?;
+        Ok(self.take_bits(n_bits))
+    }
+
+    pub fn load_int(&mut self, n_bits: usize) -> Result<i128, CellError> {
+        if n_bits == 0 || n_bits > 128 {
+            return Err(CellError::InvalidWidth(n_bits));
+        }
+        self.ensure_bits(n_bits)?;
+        // Shift the sign bit to the top, then back with sign extension.
+        let shift = 128 - n_bits;
+        Ok(((self.take_bits(n_bits) << shift) as i128) >> shift)
+    }
+
+    pub fn load_bytes(&mut self, len: usize) -> Result<Vec<u8>, CellError> {
+        self.ensure_bits(len * 8)?;
+        Ok((0..len).map(|_| self.take_bits(8) as u8).collect())
+    }
+
+    /// Loads a `VarUInteger 16` amount.
+    pub fn load_coins(&mut self) -> Result<u128, CellError> {
+        self.ensure_bits(4)?;
+        let byte_len = self.peek_bits(4) as usize;
+        self.ensure_bits(4 + byte_len * 8)?;
+        self.take_bits(4);
+        Ok(self.take_bits(byte_len * 8))
+    }
+
+    /// Loads an `addr_std` without anycast. Other address forms are
+    /// rejected.
+    pub fn load_address(&mut self) -> Result<Address, CellError> {
+        self.ensure_bits(3)?;
+        match self.peek_bits(3) {
+            0b100 => {}
+            0b101 => {
+                return Err(CellError::InvalidAddress(
+                    "anycast addresses are not supported".to_string(),
+                ))
+            }
+            tag => {
+                return Err(CellError::InvalidAddress(format!(
+                    "expected addr_std, found tag {:02b}",
+                    tag >> 1
+                )))
+            }
+        }
+        self.ensure_bits(Address::BITS)?;
+        self.take_bits(3);
+        let workchain = self.take_bits(8) as u8 as i8;
+        let mut hash = [0u8; 32];
+        for byte in &mut hash {
+            *byte = self.take_bits(8) as u8;
+        }
+        Ok(Address { workchain, hash })
+    }
+
+    pub fn load_ref(&mut self) -> Result<&'a Cell, CellError> {
+        let cell = self
+            .cell
+            .references()
+            .get(self.ref_pos)
+            .ok_or(CellError::RefsUnderflow)?;
+        self.ref_pos += 1;
+        Ok(cell)
+    }
+
+    /// Loads bytes stored with [`CellBuilder::store_blob`].
+    ///
+    /// [`CellBuilder::store_blob`]: crate::CellBuilder::store_blob
+    pub fn load_blob(&mut self) -> Result<Vec<u8>, CellError> {
+        let mut bytes = Vec::new();
+        read_blob(self.load_ref()?, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Checks that every bit and reference has been read, which catches a
+    /// reader that has fallen out of step with the writer.
+    pub fn end_parse(&self) -> Result<(), CellError> {
+        if self.remaining_bits() != 0 || self.remaining_refs() != 0 {
+            return Err(CellError::TrailingData {
+                bits: self.remaining_bits(),
+                refs: self.remaining_refs(),
+            });
+        }
+        Ok(())
+    }
+
+    fn ensure_bits(&self, n_bits: usize) -> Result<(), CellError> {
+        if n_bits > self.remaining_bits() {
+            return Err(CellError::BitsUnderflow {
+                needed: n_bits,
+                available: self.remaining_bits(),
+            });
+        }
+        Ok(())
+    }
+
+    fn peek_bits(&self, n_bits: usize) -> u128 {
+        let data = self.cell.data();
+        (self.bit_pos..self.bit_pos + n_bits).fold(0, |value, pos| {
+            (value << 1) | ((data[pos / 8] >> (7 - pos % 8)) & 1) as u128
+        })
+    }
+
+    /// Reads `n_bits` bits as an unsigned value. Availability must have
+    /// been checked.
+    fn take_bits(&mut self, n_bits: usize) -> u128 {
+        let value = self.peek_bits(n_bits);
+        self.bit_pos += n_bits;
+        value
+    }
+}
+
+/// Appends the bytes of the blob cell `cell` and of its children, in order.
+fn read_blob(cell: &Cell, bytes: &mut Vec<u8>) -> Result<(), CellError> {
+    if cell.bit_len() % 8 != 0 {
+        return Err(CellError::PartialByte(cell.bit_len()));
+    }
+    bytes.extend_from_slice(cell.data());
+    for child in cell.references() {
+        read_blob(child, bytes)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::CellBuilder;
+
+    #[test]
+    fn test_round_trip_all_field_types() {
+        let child = CellBuilder::new().store_uint(7, 3).unwrap().build();
+        let address = Address::new(-1, [0xab; 32]);
+        let mut builder = CellBuilder::new();
+        builder
+            .store_uint(42, 7)
+            .unwrap()
+            .store_int(-5, 9)
+            .unwrap()
+            .store_coins(1_000_000_000)
+            .unwrap()
+            .store_address(&address)
+            .unwrap()
+            .store_bytes(b"ovp")
+            .unwrap()
+            .store_ref(child.clone())
+            .unwrap();
+        let cell = builder.build();
+
+        let mut slice = cell.parse();
+        assert_eq!(slice.load_uint(7).unwrap(), 42);
+        assert_eq!(slice.load_int(9).unwrap(), -5);
+        assert_eq!(slice.load_coins().unwrap(), 1_000_000_000);
+        assert_eq!(slice.load_address().unwrap(), address);
+        assert_eq!(slice.load_bytes(3).unwrap(), b"ovp");
+        assert_eq!(slice.load_ref().unwrap(), &child);
+        slice.end_parse().unwrap();
+    }
+
+    #[test]
+    fn test_coins_encoding() {
+        // Zero is just the 4-bit length.
+        let zero = CellBuilder::new().store_coins(0).unwrap().build();
+        assert_eq!(zero.bit_len(), 4);
+        let one_ton = CellBuilder::new()
+            .store_coins(1_000_000_000)
+            .unwrap()
+            .build();
+        assert_eq!(one_ton.bit_len(), 4 + 32);
+        assert_eq!(one_ton.data(), &[0x43, 0xb9, 0xac, 0xa0, 0x00]);
+    }
+
+    #[test]
+    fn test_underflow_leaves_slice_unchanged() {
+        let cell = CellBuilder::new().store_uint(0xabc, 12).unwrap().build();
+        let mut slice = cell.parse();
+        assert_eq!(
+            slice.load_uint(16).unwrap_err(),
+            CellError::BitsUnderflow {
+                needed: 16,
+                available: 12
+            }
+        );
+        assert_eq!(slice.load_ref().unwrap_err(), CellError::RefsUnderflow);
+        assert!(matches!(
+            slice.end_parse(),
+            Err(CellError::TrailingData { bits: 12, refs: 0 })
+        ));
+        assert_eq!(slice.load_uint(12).unwrap(), 0xabc);
+    }
+}
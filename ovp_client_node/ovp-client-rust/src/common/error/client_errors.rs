@@ -39,6 +39,11 @@ impl From<io::Error> for ChannelError {
         ChannelError::new(ChannelErrorType::InvalidOperation, err.to_string())
     }
 }
+impl From<ovp_cell::CellError> for ChannelError {
+    fn from(err: ovp_cell::CellError) -> Self {
+        ChannelError::new(ChannelErrorType::InvalidArgument, err.to_string())
+    }
+}
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ClientErrorType {
     InvalidTransaction,
@@ -413,6 +418,12 @@ impl From<CellError> for Error {
     }
 }
 
+impl From<ovp_cell::CellError> for Error {
+    fn from(err: ovp_cell::CellError) -> Self {
+        Error::CellError(Box::new(Error::CustomError(err.to_string())))
+    }
+}
+
 impl From<ZkProofError> for Error {
     fn from(err: ZkProofError) -> Self {
         Error::ZkProofError(err)
@@ -1,5 +1,6 @@
 // ./src/common/types/mod.rs
 
+pub mod builder;
 pub mod dag_boc;
 pub mod ops;
 pub mod state_boc;
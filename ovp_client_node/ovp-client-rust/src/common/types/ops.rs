@@ -76,6 +76,22 @@ impl From<ContractOpCode> for u8 {
         code as u8
     }
 }
+
+impl TryFrom<u8> for ContractOpCode {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0xA0 => Ok(Self::CreatePayment),
+            0xA1 => Ok(Self::UpdateState),
+            0xA2 => Ok(Self::FinalizeState),
+            0xA3 => Ok(Self::DisputeState),
+            0xA4 => Ok(Self::InitChannel),
+            _ => Err("Invalid contract operation code"),
+        }
+    }
+}
+
 impl OpCode {
     #[inline]
     pub fn to_u8(&self) -> u8 {
@@ -1,9 +1,9 @@
 use crate::common::error::client_errors::{ChannelError, ChannelErrorType};
+use crate::common::types::builder::{Cell as StateCell, CellBuilder, CellSlice};
 use crate::common::types::ops::ContractOpCode;
 use crate::common::types::state_boc::STATEBOC;
 use js_sys::Uint8Array;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 pub type ChannelId = String;
@@ -199,7 +199,7 @@ impl ChannelContract {
         let mut boc = STATEBOC::new();
         let state_hash = self.calculate_state_hash()?;
         let state_cell = Cell::new(
-            self.serialize_state()?,
+            self.serialize_state()?.data().to_vec(),
             Vec::new(),
             Vec::new(),
             CellType::Ordinary,
@@ -210,28 +210,23 @@ impl ChannelContract {
         boc.add_root(0);
         Ok(boc)
     }
-    fn serialize_state(&self) -> Result<Vec<u8>, ChannelError> {
-        let mut data = Vec::new();
-        data.extend_from_slice(self.id.as_bytes());
-        data.extend_from_slice(&self.balance.to_le_bytes());
-        data.extend_from_slice(&self.nonce.to_le_bytes());
-        data.extend_from_slice(&self.seqno.to_le_bytes());
-        data.push(u8::from(self.op_code));
 
-        let state_bytes = self.state.as_bytes();
-        data.extend_from_slice(&(state_bytes.len() as u32).to_le_bytes());
-        data.extend_from_slice(state_bytes);
-
-        Ok(data)
+    /// The channel state as a cell: balance, nonce and seqno as uint64, the
+    /// op code as uint8, then the id and the private state as blobs.
+    fn serialize_state(&self) -> Result<StateCell, ChannelError> {
+        let mut builder = CellBuilder::new();
+        builder
+            .store_uint(self.balance as u128, 64)?
+            .store_uint(self.nonce as u128, 64)?
+            .store_uint(self.seqno as u128, 64)?
+            .store_uint(u8::from(self.op_code) as u128, 8)?
+            .store_blob(self.id.as_bytes())?
+            .store_blob(self.state.as_bytes())?;
+        Ok(builder.build())
     }
 
     fn calculate_state_hash(&self) -> Result<[u8; 32], ChannelError> {
-        let mut hasher = Sha256::new();
-        hasher.update(&self.serialize_state()?);
-        let hash = hasher.finalize();
-        let mut hash_array = [0u8; 32];
-        hash_array.copy_from_slice(&hash);
-        Ok(hash_array)
+        Ok(self.serialize_state()?.hash())
     }
 
     #[wasm_bindgen]
@@ -404,6 +399,35 @@ impl ChannelContract {
         }
     }
 }
+impl ChannelContract {
+    /// Restores a contract from a cell written by `serialize_state`. The
+    /// status and dispute fields are not part of the cell and start fresh.
+    pub fn from_state_cell(cell: &StateCell) -> Result<ChannelContract, ChannelError> {
+        let mut state = CellSlice::new(cell);
+        let balance = state.load_uint(64)? as u64;
+        let nonce = state.load_uint(64)? as u64;
+        let seqno = state.load_uint(64)? as u64;
+        let op_code = ContractOpCode::try_from(state.load_uint(8)? as u8)
+            .map_err(|e| ChannelError::new(ChannelErrorType::InvalidOperation, e.to_string()))?;
+        let id = utf8(state.load_blob()?)?;
+        let private_state = utf8(state.load_blob()?)?;
+        state.end_parse()?;
+
+        let mut contract = ChannelContract::new(&id);
+        contract.state = private_state;
+        contract.balance = balance;
+        contract.nonce = nonce;
+        contract.seqno = seqno;
+        contract.op_code = op_code;
+        Ok(contract)
+    }
+}
+
+fn utf8(bytes: Vec<u8>) -> Result<String, ChannelError> {
+    String::from_utf8(bytes)
+        .map_err(|e| ChannelError::new(ChannelErrorType::InvalidArgument, e.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -455,6 +479,37 @@ mod tests {
         assert!(contract.validate_transaction(&tx).is_err());
     }
 
+    #[test]
+    fn test_state_cell_round_trip() {
+        let mut contract = ChannelContract::new("test_channel");
+        contract.update_balance(1000).unwrap();
+        let tx = create_test_transaction("test_channel", 1, 1, 400);
+        contract.process_transaction(&tx).unwrap();
+        contract.state = "x".repeat(300);
+
+        let cell = contract.serialize_state().unwrap();
+        let restored = ChannelContract::from_state_cell(&cell).unwrap();
+        assert_eq!(restored.id(), contract.id());
+        assert_eq!(restored.balance(), 600);
+        assert_eq!(restored.nonce(), 1);
+        assert_eq!(restored.seqno(), 1);
+        assert_eq!(restored.op_code(), contract.op_code());
+        assert_eq!(restored.state, contract.state);
+        assert_eq!(
+            restored.calculate_state_hash().unwrap(),
+            contract.calculate_state_hash().unwrap()
+        );
+
+        let mut bad = CellBuilder::new();
+        bad.store_uint(0, 128)
+            .unwrap()
+            .store_uint(0, 64)
+            .unwrap()
+            .store_uint(0xFF, 8)
+            .unwrap();
+        assert!(ChannelContract::from_state_cell(&bad.build()).is_err());
+    }
+
     #[test]
     fn test_spending_limit() {
         let mut contract = ChannelContract::new("test_channel");
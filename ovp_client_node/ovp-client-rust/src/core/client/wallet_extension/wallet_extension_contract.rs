@@ -2,6 +2,7 @@ use sha2::Digest;
 use std::error::Error;
 use crate::core::client::wallet_extension::channel_manager::ChannelConfig;
 use crate::core::client::wallet_extension::user::ChannelState;
+use crate::core::client::wallet_extension::wallet_extension_types::RebalanceConfig;
 use crate::core::client::channel::channel_contract::ChannelContract;
 use crate::core::state::sparse_merkle_tree_wasm::SparseMerkleTreeWasm;
 use serde::{Deserialize, Serialize};
@@ -69,13 +70,6 @@ pub struct WalletExtension {
     channel_id: HashMap<ByteArray32Local, ChannelContract>,
 }
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
-pub struct RebalanceConfig {
-    pub min_balance: u64,
-    pub max_balance: u64,
-    pub target_balance: u64,
-}
-
 impl WalletExtension {
     pub fn new() -> Result<Self, Box<dyn Error>> {
         let proof_system = Arc::new(Plonky2SystemHandle::new());